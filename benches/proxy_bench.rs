@@ -66,11 +66,197 @@ fn metrics_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
+/// Loopback QUIC connection pair, with the endpoints that keep it alive
+fn quic_pair(
+    rt: &tokio::runtime::Runtime,
+) -> (quinn::Connection, quinn::Connection, quinn::Endpoint, quinn::Endpoint) {
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use std::sync::Arc;
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_der = CertificateDer::from(cert.cert);
+    let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert_der.clone()).unwrap();
+
+    rt.block_on(async {
+        let server_config =
+            quinn::ServerConfig::with_single_cert(vec![cert_der], key_der).unwrap();
+        let server =
+            quinn::Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut client = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        client.set_default_client_config(
+            quinn::ClientConfig::with_root_certificates(Arc::new(roots)).unwrap(),
+        );
+
+        let connecting = client.connect(server.local_addr().unwrap(), "localhost").unwrap();
+        let (client_conn, server_conn) = tokio::join!(connecting, async {
+            server.accept().await.unwrap().await
+        });
+        (client_conn.unwrap(), server_conn.unwrap(), client, server)
+    })
+}
+
+/// An origin streaming 64KB in 64-byte writes, like chatty interactive traffic
+fn tcp_coalesce_benchmark(c: &mut Criterion) {
+    use mytunnel_server::proxy::TcpProxy;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    const WRITE: usize = 64;
+    const TOTAL: usize = 64 * 1024;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let (client, server, _client_endpoint, _server_endpoint) = quic_pair(&rt);
+    let origin_addr = rt.block_on(async {
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = origin.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = origin.accept().await {
+                tokio::spawn(async move {
+                    socket.set_nodelay(true).unwrap();
+                    let chunk = [0u8; WRITE];
+                    for _ in 0..TOTAL / WRITE {
+                        socket.write_all(&chunk).await.unwrap();
+                    }
+                });
+            }
+        });
+        addr
+    });
+
+    let mut group = c.benchmark_group("tcp_small_writes");
+    group.throughput(Throughput::Bytes(TOTAL as u64));
+
+    for (name, coalesce_bytes) in [("per_read", 0), ("coalesce_16k", 16384)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let (mut send, mut recv) = client.open_bi().await.unwrap();
+                    send.finish().unwrap();
+                    let (server_send, server_recv) = server.accept_bi().await.unwrap();
+                    let proxy = TcpProxy::new(BufferPool::new(10, 10, 10))
+                        .with_coalesce(coalesce_bytes, Duration::from_millis(1));
+                    let origin_addr = origin_addr.clone();
+                    tokio::spawn(async move {
+                        proxy.proxy_stream(server_send, server_recv, &origin_addr).await
+                    });
+                    black_box(recv.read_to_end(TOTAL).await.unwrap().len());
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// An origin streaming 4MB in 16KB writes, comparing splice with userspace reads
+#[cfg(target_os = "linux")]
+fn tcp_splice_benchmark(c: &mut Criterion) {
+    use mytunnel_server::proxy::TcpProxy;
+    use tokio::io::AsyncWriteExt;
+
+    const WRITE: usize = 16 * 1024;
+    const TOTAL: usize = 4 * 1024 * 1024;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let (client, server, _client_endpoint, _server_endpoint) = quic_pair(&rt);
+    let origin_addr = rt.block_on(async {
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = origin.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = origin.accept().await {
+                tokio::spawn(async move {
+                    let chunk = [0u8; WRITE];
+                    for _ in 0..TOTAL / WRITE {
+                        socket.write_all(&chunk).await.unwrap();
+                    }
+                });
+            }
+        });
+        addr
+    });
+
+    let mut group = c.benchmark_group("tcp_bulk");
+    group.throughput(Throughput::Bytes(TOTAL as u64));
+
+    for (name, splice) in [("userspace", false), ("splice", true)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let (mut send, mut recv) = client.open_bi().await.unwrap();
+                    send.finish().unwrap();
+                    let (server_send, server_recv) = server.accept_bi().await.unwrap();
+                    let proxy = TcpProxy::new(BufferPool::new(10, 10, 10)).with_splice(splice);
+                    let origin_addr = origin_addr.clone();
+                    tokio::spawn(async move {
+                        proxy.proxy_stream(server_send, server_recv, &origin_addr).await
+                    });
+                    black_box(recv.read_to_end(TOTAL).await.unwrap().len());
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+#[cfg(target_os = "linux")]
+fn udp_send_benchmark(c: &mut Criterion) {
+    use mytunnel_server::proxy::BatchedUdpSender;
+    use std::net::UdpSocket;
+    use std::os::unix::io::AsRawFd;
+
+    // Nothing reads the receiver; loopback drops what overflows its buffer
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let target = receiver.local_addr().unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let payload = [0u8; 512];
+    let packets: Vec<_> = (0..64).map(|_| (target, &payload[..])).collect();
+
+    let mut group = c.benchmark_group("udp_send");
+    group.throughput(Throughput::Elements(packets.len() as u64));
+
+    group.bench_function("per_packet_64", |b| {
+        b.iter(|| {
+            for (addr, data) in &packets {
+                black_box(socket.send_to(data, addr).unwrap());
+            }
+        })
+    });
+
+    group.bench_function("sendmmsg_64", |b| {
+        let sender = BatchedUdpSender::from_raw_fd(socket.as_raw_fd());
+        b.iter(|| {
+            let mut sent = 0;
+            while sent < packets.len() {
+                sent += sender.send_batch(&packets[sent..]).unwrap();
+            }
+            black_box(sent);
+        })
+    });
+
+    group.finish();
+}
+
+#[cfg(target_os = "linux")]
+criterion_group!(
+    benches,
+    buffer_pool_benchmark,
+    connection_slab_benchmark,
+    metrics_benchmark,
+    tcp_coalesce_benchmark,
+    tcp_splice_benchmark,
+    udp_send_benchmark,
+);
+#[cfg(not(target_os = "linux"))]
 criterion_group!(
     benches,
     buffer_pool_benchmark,
     connection_slab_benchmark,
     metrics_benchmark,
+    tcp_coalesce_benchmark,
 );
 criterion_main!(benches);
 
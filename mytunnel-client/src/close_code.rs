@@ -0,0 +1,134 @@
+//! Connection close codes
+//!
+//! Application error codes the server closes QUIC connections with. The
+//! client crate keeps an identical copy so it builds and packages on its
+//! own; `test_copies_match` fails when they drift apart. Edit both, and
+//! keep them free of anything but `quinn` and `std`.
+
+use quinn::{Connection, ConnectionError, VarInt};
+use std::fmt;
+
+/// Why the server closed a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    /// Planned shutdown or drain
+    ServerShutdown = 0,
+    /// No free connection slots
+    Capacity = 1,
+    /// No traffic for longer than the server allows
+    IdleTimeout = 2,
+    /// Missing or invalid auth token
+    AuthFailed = 3,
+    /// Closed by an operator through the API
+    Disconnected = 4,
+    /// The client broke server policy
+    PolicyViolation = 5,
+}
+
+impl CloseCode {
+    /// Every code, in wire order
+    pub const ALL: [CloseCode; 6] = [
+        CloseCode::ServerShutdown,
+        CloseCode::Capacity,
+        CloseCode::IdleTimeout,
+        CloseCode::AuthFailed,
+        CloseCode::Disconnected,
+        CloseCode::PolicyViolation,
+    ];
+
+    /// Wire value of this code
+    pub fn code(self) -> VarInt {
+        VarInt::from_u32(self as u32)
+    }
+
+    /// Code with wire value `code`, if it is one of ours
+    pub fn from_code(code: VarInt) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.code() == code)
+    }
+
+    /// Reason phrase sent in the close frame
+    pub fn reason(self) -> &'static str {
+        match self {
+            CloseCode::ServerShutdown => "server shutdown",
+            CloseCode::Capacity => "server at capacity",
+            CloseCode::IdleTimeout => "idle timeout",
+            CloseCode::AuthFailed => "authentication failed",
+            CloseCode::Disconnected => "disconnected by operator",
+            CloseCode::PolicyViolation => "policy violation",
+        }
+    }
+
+    /// Close `connection` with this code and its reason phrase
+    pub fn close(self, connection: &Connection) {
+        connection.close(self.code(), self.reason().as_bytes());
+    }
+}
+
+/// Human-readable cause, for logs on the receiving side
+impl fmt::Display for CloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CloseCode::ServerShutdown => "the server is shutting down",
+            CloseCode::Capacity => "the server is at capacity",
+            CloseCode::IdleTimeout => "the connection was idle too long",
+            CloseCode::AuthFailed => "the auth token was rejected",
+            CloseCode::Disconnected => "an operator disconnected this client",
+            CloseCode::PolicyViolation => "the client violated server policy",
+        })
+    }
+}
+
+/// Human-readable cause of a connection closing
+///
+/// Our own codes are spelled out; anything else falls back to the code,
+/// reason phrase or transport error as reported by quinn.
+pub fn describe_close(error: &ConnectionError) -> String {
+    match error {
+        ConnectionError::ApplicationClosed(close) => match CloseCode::from_code(close.error_code) {
+            Some(code) => code.to_string(),
+            None => format!(
+                "application error {} ({})",
+                close.error_code,
+                String::from_utf8_lossy(&close.reason)
+            ),
+        },
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_close_codes() {
+        for (i, code) in CloseCode::ALL.into_iter().enumerate() {
+            assert_eq!(code.code(), VarInt::from_u32(i as u32));
+            assert_eq!(CloseCode::from_code(code.code()), Some(code));
+        }
+        assert_eq!(CloseCode::from_code(VarInt::from_u32(99)), None);
+
+        let close = |code: u32, reason: &'static [u8]| {
+            ConnectionError::ApplicationClosed(quinn::ApplicationClose {
+                error_code: VarInt::from_u32(code),
+                reason: reason.into(),
+            })
+        };
+        assert_eq!(describe_close(&close(0, b"")), "the server is shutting down");
+        assert_eq!(describe_close(&close(99, b"oops")), "application error 99 (oops)");
+        assert_eq!(describe_close(&ConnectionError::TimedOut), "timed out");
+    }
+
+    #[test]
+    fn test_copies_match() {
+        // The other crate's copy, seen from the server or from the client;
+        // absent when a crate is built on its own
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        let ours = std::fs::read_to_string(dir.join("src/close_code.rs")).unwrap();
+        for other in ["mytunnel-client/src/close_code.rs", "../src/close_code.rs"] {
+            if let Ok(theirs) = std::fs::read_to_string(dir.join(other)) {
+                assert!(ours == theirs, "{other} differs; keep both copies identical");
+            }
+        }
+    }
+}
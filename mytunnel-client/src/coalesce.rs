@@ -0,0 +1,93 @@
+//! Coalescing of small reads before they are written to a QUIC stream
+//!
+//! Only the direction that reads a local socket and writes to the tunnel
+//! is coalesced: origin to client on the server, local application to
+//! server on the client. Data read from a QUIC stream is written out as
+//! it arrives.
+//!
+//! The client crate keeps an identical copy so it builds and packages on
+//! its own; `test_copies_match` fails when they drift apart. Edit both,
+//! and keep them free of anything but `tokio` and `std`.
+
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Batching of small local reads into fewer QUIC stream writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coalesce {
+    /// Flush once this many bytes are buffered
+    pub bytes: usize,
+    /// Flush once the first buffered byte has waited this long
+    pub delay: Duration,
+}
+
+impl Coalesce {
+    /// Keep reading into `buf` past its first `filled` bytes until the
+    /// threshold is reached or the delay passes
+    ///
+    /// Returns the bytes buffered and whether `reader` ended meanwhile.
+    pub(crate) async fn fill<R: AsyncRead + Unpin>(
+        &self,
+        reader: &mut R,
+        buf: &mut [u8],
+        mut filled: usize,
+    ) -> (usize, bool) {
+        let limit = self.bytes.min(buf.len());
+        let deadline = tokio::time::Instant::now() + self.delay;
+        while filled < limit {
+            match tokio::time::timeout_at(deadline, reader.read(&mut buf[filled..limit])).await {
+                Ok(Ok(0)) | Ok(Err(_)) => return (filled, true),
+                Ok(Ok(n)) => filled += n,
+                Err(_) => break,
+            }
+        }
+        (filled, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_coalesce_fill() {
+        let coalesce = Coalesce { bytes: 6, delay: Duration::from_secs(5) };
+        let (mut reader, mut writer) = tokio::io::duplex(64);
+        let mut buf = [0u8; 16];
+
+        // Reads are gathered until the threshold
+        buf[0] = b'a';
+        writer.write_all(b"bc").await.unwrap();
+        let write = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            writer.write_all(b"defgh").await.unwrap();
+        };
+        let ((filled, ended), ()) = tokio::join!(coalesce.fill(&mut reader, &mut buf, 1), write);
+        assert_eq!((&buf[..filled], ended), (&b"abcdef"[..], false));
+
+        // The delay flushes a short batch
+        let coalesce = Coalesce { bytes: 16, delay: Duration::from_millis(20) };
+        let (filled, ended) = coalesce.fill(&mut reader, &mut buf, 0).await;
+        assert_eq!((&buf[..filled], ended), (&b"gh"[..], false));
+
+        // So does the end of the reader
+        writer.write_all(b"ij").await.unwrap();
+        drop(writer);
+        let (filled, ended) = coalesce.fill(&mut reader, &mut buf, 0).await;
+        assert_eq!((&buf[..filled], ended), (&b"ij"[..], true));
+    }
+
+    #[test]
+    fn test_copies_match() {
+        // The other crate's copy, seen from the server or from the client;
+        // absent when a crate is built on its own
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        let ours = std::fs::read_to_string(dir.join("src/coalesce.rs")).unwrap();
+        for other in ["mytunnel-client/src/coalesce.rs", "../src/coalesce.rs"] {
+            if let Ok(theirs) = std::fs::read_to_string(dir.join(other)) {
+                assert!(ours == theirs, "{other} differs; keep both copies identical");
+            }
+        }
+    }
+}
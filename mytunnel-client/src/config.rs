@@ -3,7 +3,7 @@
 //! Handles loading and validating client configuration from TOML files.
 
 use anyhow::{Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use std::net::SocketAddr;
 use std::path::Path;
 
@@ -16,33 +16,119 @@ pub struct Config {
     pub quic: QuicConfig,
     #[serde(default)]
     pub logging: LoggingConfig,
+    /// Shared token sent to servers that require authentication
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+}
+
+/// Shared-secret authentication
+#[derive(Clone, Deserialize)]
+pub struct AuthConfig {
+    /// Token matching the server's `[auth] token` (1-255 bytes)
+    pub token: String,
+}
+
+impl std::fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthConfig").field("token", &"<redacted>").finish()
+    }
 }
 
 /// Server connection configuration
 #[derive(Debug, Clone, Deserialize)]
 pub struct ServerConfig {
-    /// Server address (host:port)
-    pub address: String,
+    /// Server address (host:port), or a list of them for failover
+    #[serde(rename = "address", deserialize_with = "one_or_many")]
+    pub addresses: Vec<String>,
+    /// How to pick among multiple server addresses
+    #[serde(default)]
+    pub failover_strategy: FailoverStrategy,
     /// Server name for TLS SNI (defaults to host from address)
     pub server_name: Option<String>,
     /// Skip TLS certificate verification (insecure, dev only)
     #[serde(default)]
     pub insecure: bool,
+    /// Seconds to wait before reconnecting after the server reports it is at capacity
+    #[serde(default = "default_capacity_backoff")]
+    pub capacity_backoff_secs: u64,
+    /// Client certificate presented to servers requiring mutual TLS (PEM format)
+    #[serde(default)]
+    pub client_cert_path: Option<String>,
+    /// Private key for `client_cert_path` (PEM format)
+    #[serde(default)]
+    pub client_key_path: Option<String>,
+    /// Backoff between reconnect attempts
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    /// Maximum QUIC connections opened to the server; streams go to the
+    /// least-loaded one and extra connections are opened on demand
+    #[serde(default = "default_connection_pool_size")]
+    pub connection_pool_size: usize,
+}
+
+/// Reconnect backoff configuration
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReconnectConfig {
+    /// Delay ceiling after the first failed attempt, in milliseconds
+    #[serde(default = "default_reconnect_initial")]
+    pub initial_ms: u64,
+    /// Largest delay ceiling, in milliseconds
+    #[serde(default = "default_reconnect_max")]
+    pub max_ms: u64,
+    /// Factor the ceiling grows by after each consecutive failure
+    #[serde(default = "default_reconnect_multiplier")]
+    pub multiplier: f64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_ms: default_reconnect_initial(),
+            max_ms: default_reconnect_max(),
+            multiplier: default_reconnect_multiplier(),
+        }
+    }
 }
 
 impl ServerConfig {
-    /// Get the server name for TLS SNI
-    pub fn get_server_name(&self) -> &str {
+    /// Get the server name for TLS SNI when connecting to `address`
+    pub fn server_name_for<'a>(&'a self, address: &'a str) -> &'a str {
         self.server_name.as_deref().unwrap_or_else(|| {
-            // Extract host from address (strip port)
-            self.address
+            // Extract host from address (strip port and IPv6 brackets)
+            address
                 .rsplit_once(':')
-                .map(|(host, _)| host)
-                .unwrap_or(&self.address)
+                .map(|(host, _)| host.trim_start_matches('[').trim_end_matches(']'))
+                .unwrap_or(address)
         })
     }
 }
 
+/// Order in which multiple server addresses are tried
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailoverStrategy {
+    /// Prefer the last server that worked, falling back in list order
+    #[default]
+    Ordered,
+    /// Spread successive connections across the servers
+    RoundRobin,
+}
+
+/// Accept either a single string or a list of strings
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(address) => vec![address],
+        OneOrMany::Many(addresses) => addresses,
+    })
+}
+
 /// Local proxy configuration
 #[derive(Debug, Clone, Deserialize)]
 pub struct ProxyConfig {
@@ -58,6 +144,108 @@ pub struct ProxyConfig {
     /// Enable HTTP proxy
     #[serde(default = "default_true")]
     pub http_enabled: bool,
+    /// Require SOCKS5 username/password authentication (RFC 1929)
+    #[serde(default)]
+    pub socks5_auth: Option<ProxyCredentials>,
+    /// Reassemble fragmented SOCKS5 UDP datagrams instead of dropping them
+    #[serde(default)]
+    pub socks5_udp_reassembly: bool,
+    /// Serve SOCKS5 BIND through a one-shot listener on the server
+    #[serde(default)]
+    pub socks5_allow_bind: bool,
+    /// Require HTTP Basic proxy authentication
+    #[serde(default)]
+    pub http_auth: Option<ProxyCredentials>,
+    /// Static port forwards, each tunneling a local port to a fixed target
+    #[serde(default)]
+    pub forwards: Vec<ForwardConfig>,
+    /// Reverse forwards, each taking connections to a server port to a local target
+    #[serde(default)]
+    pub reverse_forwards: Vec<ReverseForwardConfig>,
+    /// Batch local data into stream writes of up to this many bytes (0 = off)
+    #[serde(default)]
+    pub coalesce_bytes: usize,
+    /// Longest a batch waits for more data, in microseconds
+    #[serde(default = "default_coalesce_delay")]
+    pub coalesce_delay_us: u64,
+}
+
+/// One static port forward
+#[derive(Debug, Clone, Deserialize)]
+pub struct ForwardConfig {
+    /// Local address to listen on
+    pub local: SocketAddr,
+    /// Target every accepted connection is tunneled to, as `host:port`
+    pub remote: String,
+}
+
+impl ForwardConfig {
+    /// Host and port of the remote target
+    ///
+    /// IPv6 literals may be bracketed (`[::1]:5432`).
+    pub fn target(&self) -> Option<(&str, u16)> {
+        split_host_port(&self.remote)
+    }
+}
+
+/// One reverse forward, like `ssh -R`; the server must allow reverse tunnels
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReverseForwardConfig {
+    /// Server port to listen on (0 = any free port, logged once bound)
+    pub remote_port: u16,
+    /// Target every connection is made to from this side, as `host:port`
+    pub local: String,
+}
+
+impl ReverseForwardConfig {
+    /// Host and port of the local target
+    pub fn target(&self) -> Option<(&str, u16)> {
+        split_host_port(&self.local)
+    }
+}
+
+/// Split `host:port`, unbracketing IPv6 literals (`[::1]:5432`)
+fn split_host_port(address: &str) -> Option<(&str, u16)> {
+    let (host, port) = address.rsplit_once(':')?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    let port = port.parse().ok()?;
+    (!host.is_empty()).then_some((host, port))
+}
+
+/// Username/password credentials for a local proxy
+#[derive(Clone, Deserialize)]
+pub struct ProxyCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl ProxyCredentials {
+    /// Check supplied credentials in constant time
+    pub fn verify(&self, username: &[u8], password: &[u8]) -> bool {
+        // Compare both so a wrong username costs as much as a wrong password
+        constant_time_eq(self.username.as_bytes(), username)
+            & constant_time_eq(self.password.as_bytes(), password)
+    }
+}
+
+impl std::fmt::Debug for ProxyCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxyCredentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Compare two byte strings without short-circuiting on the first mismatch
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
 }
 
 /// QUIC protocol configuration
@@ -113,10 +301,34 @@ fn default_http_bind() -> SocketAddr {
     "127.0.0.1:8080".parse().unwrap()
 }
 
+fn default_coalesce_delay() -> u64 {
+    1000
+}
+
 fn default_true() -> bool {
     true
 }
 
+fn default_capacity_backoff() -> u64 {
+    30
+}
+
+fn default_connection_pool_size() -> usize {
+    1
+}
+
+fn default_reconnect_initial() -> u64 {
+    500
+}
+
+fn default_reconnect_max() -> u64 {
+    30_000
+}
+
+fn default_reconnect_multiplier() -> f64 {
+    2.0
+}
+
 fn default_idle_timeout() -> u64 {
     30
 }
@@ -148,15 +360,59 @@ impl Config {
 
     /// Validate configuration values
     fn validate(&self) -> Result<()> {
-        if self.server.address.is_empty() {
+        if self.server.addresses.is_empty() || self.server.addresses.iter().any(|a| a.is_empty()) {
             anyhow::bail!("server.address must not be empty");
         }
+        if self.server.client_cert_path.is_some() != self.server.client_key_path.is_some() {
+            anyhow::bail!("server.client_cert_path and server.client_key_path must be set together");
+        }
+        if self.server.connection_pool_size == 0 {
+            anyhow::bail!("server.connection_pool_size must be at least 1");
+        }
+        if let Some(auth) = &self.auth {
+            if auth.token.is_empty() || auth.token.len() > 255 {
+                anyhow::bail!("auth.token must be 1-255 bytes");
+            }
+        }
+        let reconnect = &self.server.reconnect;
+        if reconnect.initial_ms == 0 || reconnect.max_ms < reconnect.initial_ms {
+            anyhow::bail!("server.reconnect requires 0 < initial_ms <= max_ms");
+        }
+        if !(reconnect.multiplier >= 1.0 && reconnect.multiplier.is_finite()) {
+            anyhow::bail!("server.reconnect.multiplier must be >= 1.0");
+        }
         if self.quic.idle_timeout_secs == 0 {
             anyhow::bail!("quic.idle_timeout_secs must be > 0");
         }
         if self.quic.max_streams == 0 {
             anyhow::bail!("quic.max_streams must be > 0");
         }
+        if let Some(auth) = &self.proxy.socks5_auth {
+            // RFC 1929 length fields are a single byte
+            for (name, value) in [("username", &auth.username), ("password", &auth.password)] {
+                if value.is_empty() || value.len() > 255 {
+                    anyhow::bail!("proxy.socks5_auth.{} must be 1-255 bytes", name);
+                }
+            }
+        }
+        if let Some(auth) = &self.proxy.http_auth {
+            if auth.username.is_empty() || auth.username.contains(':') {
+                anyhow::bail!("proxy.http_auth.username must be non-empty and contain no ':'");
+            }
+        }
+        for forward in &self.proxy.forwards {
+            if forward.target().is_none() {
+                anyhow::bail!("proxy.forwards remote {:?} must be host:port", forward.remote);
+            }
+        }
+        for forward in &self.proxy.reverse_forwards {
+            if forward.target().is_none() {
+                anyhow::bail!(
+                    "proxy.reverse_forwards local {:?} must be host:port",
+                    forward.local
+                );
+            }
+        }
         Ok(())
     }
 }
@@ -168,18 +424,132 @@ mod tests {
     #[test]
     fn test_server_name_extraction() {
         let config = ServerConfig {
-            address: "example.com:443".to_string(),
+            addresses: vec!["example.com:443".to_string()],
+            failover_strategy: FailoverStrategy::Ordered,
             server_name: None,
             insecure: false,
+            capacity_backoff_secs: 30,
+            client_cert_path: None,
+            client_key_path: None,
+            reconnect: ReconnectConfig::default(),
+            connection_pool_size: 1,
         };
-        assert_eq!(config.get_server_name(), "example.com");
+        assert_eq!(config.server_name_for("example.com:443"), "example.com");
+        assert_eq!(config.server_name_for("backup.example.com:443"), "backup.example.com");
+        assert_eq!(config.server_name_for("[2001:db8::1]:443"), "2001:db8::1");
 
         let config_with_name = ServerConfig {
-            address: "example.com:443".to_string(),
+            addresses: vec!["example.com:443".to_string()],
+            failover_strategy: FailoverStrategy::Ordered,
             server_name: Some("custom.example.com".to_string()),
             insecure: false,
+            capacity_backoff_secs: 30,
+            client_cert_path: None,
+            client_key_path: None,
+            reconnect: ReconnectConfig::default(),
+            connection_pool_size: 1,
         };
-        assert_eq!(config_with_name.get_server_name(), "custom.example.com");
+        assert_eq!(
+            config_with_name.server_name_for("example.com:443"),
+            "custom.example.com"
+        );
+    }
+
+    #[test]
+    fn test_address_list() {
+        let single: Config = toml::from_str(
+            "[server]\naddress = \"a.example.com:443\"\n[proxy]\n",
+        )
+        .unwrap();
+        assert_eq!(single.server.addresses, ["a.example.com:443"]);
+        assert_eq!(single.server.failover_strategy, FailoverStrategy::Ordered);
+
+        let list: Config = toml::from_str(
+            r#"
+            [server]
+            address = ["a.example.com:443", "b.example.com:443"]
+            failover_strategy = "round_robin"
+            [proxy]
+        "#,
+        )
+        .unwrap();
+        assert!(list.validate().is_ok());
+        assert_eq!(list.server.addresses, ["a.example.com:443", "b.example.com:443"]);
+        assert_eq!(list.server.failover_strategy, FailoverStrategy::RoundRobin);
+
+        let empty: Config = toml::from_str("[server]\naddress = []\n[proxy]\n").unwrap();
+        assert!(empty.validate().is_err());
+    }
+
+    #[test]
+    fn test_socks5_auth() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            address = "example.com:443"
+            [proxy]
+            [proxy.socks5_auth]
+            username = "alice"
+            password = "secret"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let auth = config.proxy.socks5_auth.unwrap();
+        assert!(auth.verify(b"alice", b"secret"));
+        assert!(!auth.verify(b"alice", b"secreT"));
+        assert!(!auth.verify(b"bob", b"secret"));
+        assert!(!auth.verify(b"alice", b"secret2"));
+        assert!(!format!("{:?}", auth).contains("secret"));
+    }
+
+    #[test]
+    fn test_forwards() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            address = "example.com:443"
+            [proxy]
+            forwards = [
+                { local = "127.0.0.1:5432", remote = "db.internal:5432" },
+                { local = "[::1]:6379", remote = "[2001:db8::7]:6379" },
+            ]
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let targets: Vec<_> = config.proxy.forwards.iter().map(|f| f.target().unwrap()).collect();
+        assert_eq!(targets, [("db.internal", 5432), ("2001:db8::7", 6379)]);
+
+        let mut config = config;
+        config.proxy.forwards[0].remote = "db.internal".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_reverse_forwards() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [server]
+            address = "example.com:443"
+            [[proxy.reverse_forwards]]
+            remote_port = 8080
+            local = "127.0.0.1:3000"
+            [[proxy.reverse_forwards]]
+            remote_port = 0
+            local = "[::1]:22"
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let forwards = &config.proxy.reverse_forwards;
+        assert_eq!(forwards[0].remote_port, 8080);
+        assert_eq!(forwards[0].target(), Some(("127.0.0.1", 3000)));
+        assert_eq!(forwards[1].target(), Some(("::1", 22)));
+
+        config.proxy.reverse_forwards[1].local = "localhost".to_string();
+        assert!(config.validate().is_err());
     }
 
     #[test]
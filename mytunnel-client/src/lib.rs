@@ -2,14 +2,45 @@
 //!
 //! A QUIC-based tunnel client with SOCKS5 and HTTP proxy support.
 
+/// Close codes shared with the server
+pub mod close_code;
+/// Read coalescing shared with the server
+pub mod coalesce;
 pub mod config;
 pub mod protocol;
 pub mod proxy;
 pub mod tunnel;
 
+#[cfg(test)]
+mod testing;
+
+pub use close_code::CloseCode;
 pub use config::Config;
 pub use tunnel::TunnelClient;
 
 /// Client version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Install the ring crypto provider as the process default for rustls
+///
+/// Safe to call more than once: a provider that is already installed, by
+/// an earlier call or by an embedding application, is kept. The client
+/// calls this itself before building its TLS config.
+pub fn init_crypto() {
+    if rustls::crypto::ring::default_provider().install_default().is_err() {
+        tracing::debug!("rustls crypto provider already installed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_crypto_idempotent() {
+        init_crypto();
+        init_crypto();
+        assert!(rustls::crypto::CryptoProvider::get_default().is_some());
+    }
+}
+
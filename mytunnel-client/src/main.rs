@@ -9,6 +9,7 @@ use std::sync::Arc;
 use tokio::signal;
 use tracing::{error, info};
 
+use mytunnel_client::tunnel::AddressProbe;
 use mytunnel_client::{Config, TunnelClient, VERSION};
 
 /// MyTunnel Client - QUIC tunnel with SOCKS5/HTTP proxy
@@ -40,9 +41,7 @@ enum Commands {
 #[tokio::main]
 async fn main() -> Result<()> {
     // Install the ring crypto provider for rustls
-    rustls::crypto::ring::default_provider()
-        .install_default()
-        .expect("Failed to install rustls crypto provider");
+    mytunnel_client::init_crypto();
 
     let cli = Cli::parse();
 
@@ -72,7 +71,7 @@ async fn run_client(config_path: PathBuf) -> Result<()> {
     let client = TunnelClient::new(config.clone()).await?;
 
     info!(
-        server = %config.server.address,
+        servers = ?config.server.addresses,
         socks5 = %config.proxy.socks5_bind,
         http = %config.proxy.http_bind,
         "Client started"
@@ -107,22 +106,63 @@ async fn test_connection(config_path: PathBuf) -> Result<()> {
         .init();
 
     info!(
-        server = %config.server.address,
+        servers = ?config.server.addresses,
         "Testing connection to server"
     );
 
     let config = Arc::new(config);
 
-    // Try to establish connection
-    match TunnelClient::test_connection(config.clone()).await {
-        Ok(()) => {
-            info!("Connection test successful!");
-            Ok(())
-        }
+    // Try every resolved address at once
+    let probes = match TunnelClient::test_connection(config.clone()).await {
+        Ok(probes) => probes,
         Err(e) => {
             error!(error = %e, "Connection test failed");
-            Err(e)
+            return Err(e);
         }
+    };
+    print_probes(&probes);
+
+    let ok = probes.iter().filter(|probe| probe.is_ok()).count();
+    if ok == 0 {
+        error!("Connection test failed: no server address accepted the connection");
+        anyhow::bail!("no server address reachable");
+    }
+    info!(reachable = ok, total = probes.len(), "Connection test successful!");
+    Ok(())
+}
+
+/// Print one row per probed address
+///
+/// Failed rows carry the error in place of the handshake details.
+fn print_probes(probes: &[AddressProbe]) {
+    let addr = |probe: &AddressProbe| match probe.addr {
+        Some(addr) => addr.to_string(),
+        None => "-".to_string(),
+    };
+    let server_width = probes.iter().map(|p| p.server.len()).max().unwrap_or(0).max(6);
+    let addr_width = probes.iter().map(|p| addr(p).len()).max().unwrap_or(0).max(7);
+
+    println!(
+        "{:server_width$}  {:addr_width$}  {:6}  {:>9}  {:8}  {:7}  SUBJECT",
+        "SERVER", "ADDRESS", "RESULT", "HANDSHAKE", "ALPN", "RESUMED"
+    );
+    for probe in probes {
+        let details = match &probe.result {
+            Ok(info) => format!(
+                "{:6}  {:>9}  {:8}  {:7}  {}",
+                "ok",
+                format!("{:.1} ms", info.handshake.as_secs_f64() * 1000.0),
+                info.alpn.as_deref().unwrap_or("-"),
+                match info.resumed {
+                    Some(true) => "yes",
+                    Some(false) => "no",
+                    None => "-",
+                },
+                info.server_subject.as_deref().unwrap_or("-"),
+            ),
+            Err(e) => format!("{:6}  {}", "failed", e),
+        };
+        println!("{:server_width$}  {:addr_width$}  {}", probe.server, addr(probe), details);
     }
 }
 
@@ -1,23 +1,97 @@
 //! Wire protocol encoding/decoding
 //!
 //! Implements the tunnel protocol matching the server format:
-//! - TCP Tunnel Request: [Type(1)][Port(2)][HostLen(1)][Host(N)]
-//! - UDP Relay: [Port(2)][HostLen(1)][Host(N)][Payload]
+//! - TCP Tunnel Request: [Type(1)][Port(2)][Address]
+//!   - Type 0x01 (domain): Address is [HostLen(1)][Host(N)]
+//!   - Type 0x02 (IPv4): Address is 4 octets
+//!   - Type 0x03 (IPv6): Address is 16 octets
+//! - Reverse Bind: [0x10][Port(2)], answered by [Status(1)][BoundPort(2)] on success
+//! - Reverse Connect (server-opened stream): [0x11][BoundPort(2)], answered by [Status(1)]
+//! - One-shot Bind: [0x12][Port(2)], answered like Reverse Bind and then, once
+//!   a peer connects, by [Status(1)][Type(1)][Address][PeerPort(2)]
+//! - UDP Relay: [FlowId(4)][Port(2)][HostLen(1)][Host(N)][Payload]
+//! - Auth (first uni stream): [Type(1)][TokenLen(1)][Token(N)]
 
 use anyhow::{bail, Result};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fmt;
+use std::net::IpAddr;
 
-/// Request types for TCP tunneling
+/// Request types for TCP tunneling, one per target address type
 pub const TCP_CONNECT: u8 = 0x01;
+pub const TCP_CONNECT_IPV4: u8 = 0x02;
+pub const TCP_CONNECT_IPV6: u8 = 0x03;
+
+/// Ask the server to listen on a port and hand its connections back
+pub const BIND_REMOTE: u8 = 0x10;
+/// Server-opened stream carrying a connection to a bound port
+pub const REVERSE_CONNECT: u8 = 0x11;
+/// Ask the server to listen for one connection and carry it on the same stream
+pub const BIND_ONCE: u8 = 0x12;
+/// One-shot bind peer type: take a connection from any address
+pub const BIND_PEER_ANY: u8 = 0x00;
+
+/// Control frame carrying the shared auth token
+pub const AUTH: u8 = 0x02;
 
 /// Response status codes
 pub const STATUS_OK: u8 = 0x00;
+pub const STATUS_OUTBOUND_LIMIT: u8 = 0xFD;
+pub const STATUS_RATE_LIMITED: u8 = 0xFE;
 pub const STATUS_ERROR: u8 = 0xFF;
 
+/// Failure reasons sent after `STATUS_ERROR`
+pub const REASON_UNSPECIFIED: u8 = 0x00;
+pub const REASON_HOST_UNREACHABLE: u8 = 0x01;
+pub const REASON_CONNECTION_REFUSED: u8 = 0x02;
+pub const REASON_POLICY_DENIED: u8 = 0x03;
+pub const REASON_TIMEOUT: u8 = 0x04;
+pub const REASON_STREAM_LIMIT: u8 = 0x05;
+pub const REASON_SERVER_BUSY: u8 = 0x06;
+
+/// Encode the auth frame sent on the first unidirectional stream
+///
+/// Format: [Type(1)][TokenLen(1)][Token(N)]
+pub fn encode_auth_frame(token: &str) -> Result<Vec<u8>> {
+    let token_bytes = token.as_bytes();
+    if token_bytes.is_empty() || token_bytes.len() > 255 {
+        bail!("Auth token must be 1-255 bytes");
+    }
+
+    let mut buf = Vec::with_capacity(2 + token_bytes.len());
+    buf.push(AUTH);
+    buf.push(token_bytes.len() as u8);
+    buf.extend_from_slice(token_bytes);
+
+    Ok(buf)
+}
+
 /// Encode a TCP tunnel request
 ///
-/// Format: [Type(1)][Port(2 BE)][HostLen(1)][Host(N)]
+/// IP address literals are sent as raw addresses so the server can skip
+/// resolving them; anything else is sent as a host name.
+///
+/// Format: [Type(1)][Port(2 BE)] followed by [HostLen(1)][Host(N)],
+/// 4 IPv4 octets or 16 IPv6 octets depending on the type.
 pub fn encode_tcp_request(host: &str, port: u16) -> Result<Vec<u8>> {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            let mut buf = Vec::with_capacity(7);
+            buf.push(TCP_CONNECT_IPV4);
+            buf.put_u16(port);
+            buf.extend_from_slice(&ip.octets());
+            return Ok(buf);
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let mut buf = Vec::with_capacity(19);
+            buf.push(TCP_CONNECT_IPV6);
+            buf.put_u16(port);
+            buf.extend_from_slice(&ip.octets());
+            return Ok(buf);
+        }
+        Err(_) => {}
+    }
+
     let host_bytes = host.as_bytes();
     if host_bytes.len() > 255 {
         bail!("Host name too long (max 255 bytes)");
@@ -32,32 +106,140 @@ pub fn encode_tcp_request(host: &str, port: u16) -> Result<Vec<u8>> {
     Ok(buf)
 }
 
+/// Encode a reverse tunnel bind request for server port `port` (0 = any)
+///
+/// Format: [Type(1)][Port(2 BE)]
+pub fn encode_bind_remote(port: u16) -> [u8; 3] {
+    let [hi, lo] = port.to_be_bytes();
+    [BIND_REMOTE, hi, lo]
+}
+
+/// Encode a one-shot bind request for server port `port` (0 = any),
+/// taking a connection only from `peer` if given
+///
+/// Format: [Type(1)][Port(2 BE)][PeerType(1)][Peer(0, 4 or 16)], with the
+/// peer typed like a TCP connect request or [`BIND_PEER_ANY`].
+pub fn encode_bind_once(port: u16, peer: Option<IpAddr>) -> Vec<u8> {
+    let mut buf = vec![BIND_ONCE];
+    buf.put_u16(port);
+    match peer.map(|ip| ip.to_canonical()) {
+        None => buf.push(BIND_PEER_ANY),
+        Some(IpAddr::V4(ip)) => {
+            buf.push(TCP_CONNECT_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        Some(IpAddr::V6(ip)) => {
+            buf.push(TCP_CONNECT_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf
+}
+
+/// Why the server failed a TCP tunnel request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureReason {
+    /// No reason given, or one this client doesn't know
+    Unspecified,
+    HostUnreachable,
+    ConnectionRefused,
+    PolicyDenied,
+    Timeout,
+    /// The tunnel connection already has as many streams as the server allows
+    StreamLimit,
+    /// The server is running as many streams as it allows across all clients
+    ServerBusy,
+}
+
+impl FailureReason {
+    fn from_code(code: u8) -> Self {
+        match code {
+            REASON_HOST_UNREACHABLE => Self::HostUnreachable,
+            REASON_CONNECTION_REFUSED => Self::ConnectionRefused,
+            REASON_POLICY_DENIED => Self::PolicyDenied,
+            REASON_TIMEOUT => Self::Timeout,
+            REASON_STREAM_LIMIT => Self::StreamLimit,
+            REASON_SERVER_BUSY => Self::ServerBusy,
+            _ => Self::Unspecified,
+        }
+    }
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Unspecified => "unspecified error",
+            Self::HostUnreachable => "host unreachable",
+            Self::ConnectionRefused => "connection refused",
+            Self::PolicyDenied => "denied by policy",
+            Self::Timeout => "timed out",
+            Self::StreamLimit => "too many streams on the tunnel connection",
+            Self::ServerBusy => "server busy",
+        })
+    }
+}
+
+/// Decoded TCP tunnel response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpResponse {
+    Ok,
+    OutboundLimit,
+    RateLimited,
+    Failed(FailureReason),
+}
+
+impl fmt::Display for TcpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ok => f.write_str("OK"),
+            Self::OutboundLimit => f.write_str("Server reached its outbound connection limit"),
+            Self::RateLimited => f.write_str("Server rate limited the request"),
+            Self::Failed(reason) => write!(f, "Server returned error: {}", reason),
+        }
+    }
+}
+
+/// Returned when the server answers a TCP tunnel request with anything but OK
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("{0}")]
+pub struct TunnelRejected(pub TcpResponse);
+
 /// Decode a TCP tunnel response
 ///
-/// Format: [Status(1)]
-/// Returns Ok(()) if status is OK, Err otherwise
-pub fn decode_tcp_response(data: &[u8]) -> Result<()> {
+/// Format: [Status(1)], plus [Reason(1)] when the status is `STATUS_ERROR`.
+/// Older servers send no reason, which decodes as unspecified.
+pub fn decode_tcp_response(data: &[u8]) -> Result<TcpResponse> {
     if data.is_empty() {
         bail!("Empty response");
     }
 
     match data[0] {
-        STATUS_OK => Ok(()),
-        STATUS_ERROR => bail!("Server returned error"),
+        STATUS_OK => Ok(TcpResponse::Ok),
+        STATUS_OUTBOUND_LIMIT => Ok(TcpResponse::OutboundLimit),
+        STATUS_RATE_LIMITED => Ok(TcpResponse::RateLimited),
+        STATUS_ERROR => {
+            let reason = data.get(1).map_or(FailureReason::Unspecified, |&code| {
+                FailureReason::from_code(code)
+            });
+            Ok(TcpResponse::Failed(reason))
+        }
         status => bail!("Unknown status code: {}", status),
     }
 }
 
 /// Encode a UDP datagram for relay
 ///
-/// Format: [Port(2 BE)][HostLen(1)][Host(N)][Payload]
-pub fn encode_udp_packet(host: &str, port: u16, payload: &[u8]) -> Result<Vec<u8>> {
+/// Format: [FlowId(4 BE)][Port(2 BE)][HostLen(1)][Host(N)][Payload]
+///
+/// The server echoes `flow_id` in every response on the flow.
+pub fn encode_udp_packet(flow_id: u32, host: &str, port: u16, payload: &[u8]) -> Result<Vec<u8>> {
     let host_bytes = host.as_bytes();
     if host_bytes.len() > 255 {
         bail!("Host name too long (max 255 bytes)");
     }
 
-    let mut buf = Vec::with_capacity(3 + host_bytes.len() + payload.len());
+    let mut buf = Vec::with_capacity(7 + host_bytes.len() + payload.len());
+    buf.put_u32(flow_id);
     buf.put_u16(port);
     buf.push(host_bytes.len() as u8);
     buf.extend_from_slice(host_bytes);
@@ -69,6 +251,7 @@ pub fn encode_udp_packet(host: &str, port: u16, payload: &[u8]) -> Result<Vec<u8
 /// Decoded UDP packet
 #[derive(Debug)]
 pub struct UdpPacket {
+    pub flow_id: u32,
     pub host: String,
     pub port: u16,
     pub payload: Bytes,
@@ -76,13 +259,14 @@ pub struct UdpPacket {
 
 /// Decode a UDP datagram response
 ///
-/// Format: [Port(2 BE)][HostLen(1)][Host(N)][Payload]
+/// Format: [FlowId(4 BE)][Port(2 BE)][HostLen(1)][Host(N)][Payload]
 pub fn decode_udp_packet(data: Bytes) -> Result<UdpPacket> {
-    if data.len() < 4 {
+    if data.len() < 7 {
         bail!("UDP packet too short");
     }
 
     let mut buf = data;
+    let flow_id = buf.get_u32();
     let port = buf.get_u16();
     let host_len = buf.get_u8() as usize;
 
@@ -94,6 +278,7 @@ pub fn decode_udp_packet(data: Bytes) -> Result<UdpPacket> {
     let payload = buf;
 
     Ok(UdpPacket {
+        flow_id,
         host,
         port,
         payload,
@@ -110,6 +295,11 @@ pub mod socks5 {
     pub const AUTH_USERPASS: u8 = 0x02;
     pub const AUTH_NO_ACCEPTABLE: u8 = 0xFF;
 
+    /// Username/password sub-negotiation (RFC 1929)
+    pub const USERPASS_VERSION: u8 = 0x01;
+    pub const USERPASS_SUCCESS: u8 = 0x00;
+    pub const USERPASS_FAILURE: u8 = 0x01;
+
     /// Commands
     pub const CMD_CONNECT: u8 = 0x01;
     pub const CMD_BIND: u8 = 0x02;
@@ -132,7 +322,7 @@ pub mod socks5 {
     pub const REP_ATYP_NOT_SUPPORTED: u8 = 0x08;
 
     use super::*;
-    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 
     /// Parse SOCKS5 address from buffer
     pub fn parse_address(data: &mut BytesMut) -> Result<(String, u16)> {
@@ -204,9 +394,16 @@ pub mod socks5 {
         buf
     }
 
-    /// Create a "zero" bind address for replies where we don't have a real address
-    pub fn zero_bind_addr_v4() -> SocketAddr {
-        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))
+    /// Create a "zero" bind address for replies where we don't have a real
+    /// address, in the same family as `like`
+    ///
+    /// IPv4-mapped IPv6 addresses count as IPv4, so clients reaching a
+    /// dual-stack listener over IPv4 get an IPv4 reply.
+    pub fn zero_bind_addr(like: SocketAddr) -> SocketAddr {
+        match like.ip().to_canonical() {
+            IpAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            IpAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+        }
     }
 }
 
@@ -214,6 +411,13 @@ pub mod socks5 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_encode_auth_frame() {
+        assert_eq!(encode_auth_frame("s3cret").unwrap(), b"\x02\x06s3cret");
+        assert!(encode_auth_frame("").is_err());
+        assert!(encode_auth_frame(&"a".repeat(256)).is_err());
+    }
+
     #[test]
     fn test_encode_tcp_request() {
         let req = encode_tcp_request("example.com", 443).unwrap();
@@ -223,29 +427,123 @@ mod tests {
         assert_eq!(&req[4..], b"example.com");
     }
 
+    #[test]
+    fn test_encode_tcp_request_ip() {
+        let req = encode_tcp_request("192.168.1.1", 8080).unwrap();
+        assert_eq!(req, [TCP_CONNECT_IPV4, 0x1F, 0x90, 192, 168, 1, 1]);
+
+        let req = encode_tcp_request("2001:db8::1", 443).unwrap();
+        assert_eq!(req.len(), 19);
+        assert_eq!(req[0], TCP_CONNECT_IPV6);
+        assert_eq!(u16::from_be_bytes([req[1], req[2]]), 443);
+        assert_eq!(&req[3..], "2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+
+        // Bracketed literals aren't addresses; they go out as host names
+        assert_eq!(encode_tcp_request("[::1]", 443).unwrap()[0], TCP_CONNECT);
+    }
+
+    #[test]
+    fn test_encode_bind_remote() {
+        assert_eq!(encode_bind_remote(8080), [BIND_REMOTE, 0x1f, 0x90]);
+        assert_eq!(encode_bind_remote(0), [BIND_REMOTE, 0, 0]);
+    }
+
+    #[test]
+    fn test_encode_bind_once() {
+        assert_eq!(encode_bind_once(0, None), [BIND_ONCE, 0, 0, BIND_PEER_ANY]);
+        let peer = "::ffff:10.0.0.5".parse().ok();
+        assert_eq!(encode_bind_once(0, peer), [BIND_ONCE, 0, 0, TCP_CONNECT_IPV4, 10, 0, 0, 5]);
+        let bind = encode_bind_once(21, "2001:db8::1".parse().ok());
+        assert_eq!(bind[..4], [BIND_ONCE, 0, 21, TCP_CONNECT_IPV6]);
+        assert_eq!(bind.len(), 20);
+    }
+
     #[test]
     fn test_decode_tcp_response() {
-        assert!(decode_tcp_response(&[STATUS_OK]).is_ok());
-        assert!(decode_tcp_response(&[STATUS_ERROR]).is_err());
+        assert_eq!(decode_tcp_response(&[STATUS_OK]).unwrap(), TcpResponse::Ok);
+        assert_eq!(
+            decode_tcp_response(&[STATUS_RATE_LIMITED]).unwrap(),
+            TcpResponse::RateLimited
+        );
+        assert_eq!(
+            decode_tcp_response(&[STATUS_OUTBOUND_LIMIT]).unwrap(),
+            TcpResponse::OutboundLimit
+        );
+        assert!(decode_tcp_response(&[0x42]).is_err());
         assert!(decode_tcp_response(&[]).is_err());
     }
 
+    #[test]
+    fn test_decode_tcp_response_reason() {
+        let cases = [
+            (REASON_UNSPECIFIED, FailureReason::Unspecified),
+            (REASON_HOST_UNREACHABLE, FailureReason::HostUnreachable),
+            (REASON_CONNECTION_REFUSED, FailureReason::ConnectionRefused),
+            (REASON_POLICY_DENIED, FailureReason::PolicyDenied),
+            (REASON_TIMEOUT, FailureReason::Timeout),
+            (REASON_STREAM_LIMIT, FailureReason::StreamLimit),
+            (REASON_SERVER_BUSY, FailureReason::ServerBusy),
+            (0x42, FailureReason::Unspecified),
+        ];
+        for (code, reason) in cases {
+            assert_eq!(
+                decode_tcp_response(&[STATUS_ERROR, code]).unwrap(),
+                TcpResponse::Failed(reason)
+            );
+        }
+
+        // Servers predating reason codes send the status alone
+        assert_eq!(
+            decode_tcp_response(&[STATUS_ERROR]).unwrap(),
+            TcpResponse::Failed(FailureReason::Unspecified)
+        );
+    }
+
     #[test]
     fn test_encode_udp_packet() {
-        let packet = encode_udp_packet("dns.google", 53, b"test").unwrap();
-        assert_eq!(u16::from_be_bytes([packet[0], packet[1]]), 53);
-        assert_eq!(packet[2], 10); // "dns.google".len()
-        assert_eq!(&packet[3..13], b"dns.google");
-        assert_eq!(&packet[13..], b"test");
+        let packet = encode_udp_packet(7, "dns.google", 53, b"test").unwrap();
+        assert_eq!(&packet[..4], &7u32.to_be_bytes());
+        assert_eq!(u16::from_be_bytes([packet[4], packet[5]]), 53);
+        assert_eq!(packet[6], 10); // "dns.google".len()
+        assert_eq!(&packet[7..17], b"dns.google");
+        assert_eq!(&packet[17..], b"test");
     }
 
     #[test]
     fn test_decode_udp_packet() {
-        let data = encode_udp_packet("test.com", 8080, b"payload").unwrap();
+        let data = encode_udp_packet(0x0102_0304, "test.com", 8080, b"payload").unwrap();
         let packet = decode_udp_packet(Bytes::from(data)).unwrap();
+        assert_eq!(packet.flow_id, 0x0102_0304);
         assert_eq!(packet.host, "test.com");
         assert_eq!(packet.port, 8080);
         assert_eq!(&packet.payload[..], b"payload");
     }
-}
 
+    #[test]
+    fn test_encode_reply_families() {
+        use socks5::*;
+
+        let v4: std::net::SocketAddr = "127.0.0.1:1080".parse().unwrap();
+        assert_eq!(
+            encode_reply(REP_SUCCESS, v4),
+            [VERSION, REP_SUCCESS, 0, ATYP_IPV4, 127, 0, 0, 1, 0x04, 0x38]
+        );
+
+        let v6: std::net::SocketAddr = "[::1]:1080".parse().unwrap();
+        let reply = encode_reply(REP_SUCCESS, v6);
+        assert_eq!(reply.len(), 22);
+        assert_eq!(&reply[..4], [VERSION, REP_SUCCESS, 0, ATYP_IPV6]);
+        assert_eq!(&reply[4..20], std::net::Ipv6Addr::LOCALHOST.octets());
+        assert_eq!(&reply[20..], [0x04, 0x38]);
+    }
+
+    #[test]
+    fn test_zero_bind_addr_family() {
+        use socks5::zero_bind_addr;
+
+        let zero = |addr: &str| zero_bind_addr(addr.parse().unwrap()).to_string();
+        assert_eq!(zero("192.0.2.1:5000"), "0.0.0.0:0");
+        assert_eq!(zero("[2001:db8::1]:5000"), "[::]:0");
+        assert_eq!(zero("[::ffff:192.0.2.1]:5000"), "0.0.0.0:0");
+    }
+}
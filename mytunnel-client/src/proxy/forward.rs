@@ -0,0 +1,178 @@
+//! Static port forwarding
+//!
+//! Listens on fixed local ports and tunnels every accepted connection
+//! straight to a fixed target, like `ssh -L`. No proxy protocol is spoken
+//! on the local side.
+
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+
+use crate::config::ForwardConfig;
+use crate::tunnel::stream::{establish_tcp_tunnel, proxy_bidirectional};
+use crate::tunnel::TunnelClientHandle;
+
+/// Port forwarder for the configured `[proxy] forwards`
+pub struct ForwardProxy {
+    tunnel: Arc<TunnelClientHandle>,
+    forwards: Vec<ForwardConfig>,
+}
+
+impl ForwardProxy {
+    /// Create a forwarder for `forwards`
+    pub fn new(tunnel: Arc<TunnelClientHandle>, forwards: Vec<ForwardConfig>) -> Self {
+        Self { tunnel, forwards }
+    }
+
+    /// Run one listener per forward until any of them fails
+    pub async fn run(&self) -> Result<()> {
+        let mut listeners = JoinSet::new();
+        for forward in &self.forwards {
+            let (host, port) = forward
+                .target()
+                .with_context(|| format!("Invalid forward target {}", forward.remote))?;
+            let listener = TcpListener::bind(forward.local)
+                .await
+                .with_context(|| format!("Failed to bind forward to {}", forward.local))?;
+
+            info!(bind = %forward.local, remote = %forward.remote, "Port forward listening");
+            listeners.spawn(serve(listener, self.tunnel.clone(), host.to_string(), port));
+        }
+
+        match listeners.join_next().await {
+            Some(result) => result.context("Port forward task failed")?,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Accept connections on `listener`, tunneling each to `host:port`
+async fn serve(
+    listener: TcpListener,
+    tunnel: Arc<TunnelClientHandle>,
+    host: String,
+    port: u16,
+) -> Result<()> {
+    let host: Arc<str> = host.into();
+    loop {
+        match listener.accept().await {
+            Ok((stream, client_addr)) => {
+                debug!(client = %client_addr, host = %host, port, "New forwarded connection");
+                let tunnel = tunnel.clone();
+                let host = host.clone();
+
+                tokio::spawn(async move {
+                    if let Err(e) = forward_connection(stream, &tunnel, &host, port).await {
+                        debug!(error = %e, client = %client_addr, "Forwarded connection error");
+                    }
+                });
+            }
+            Err(e) => {
+                error!(error = %e, "Failed to accept connection");
+            }
+        }
+    }
+}
+
+/// Tunnel one accepted connection to `host:port`
+///
+/// A connection the tunnel can't reach is simply closed.
+async fn forward_connection(
+    stream: TcpStream,
+    tunnel: &TunnelClientHandle,
+    host: &str,
+    port: u16,
+) -> Result<()> {
+    let (quic_send, quic_recv, _lease) = tunnel.open_stream().await?;
+    let (quic_send, quic_recv) = match establish_tcp_tunnel(quic_send, quic_recv, host, port).await
+    {
+        Ok(s) => s,
+        Err(e) => {
+            warn!(error = %e, host = %host, port = %port, "Failed to establish tunnel");
+            return Err(e);
+        }
+    };
+
+    let (local_read, local_write) = stream.into_split();
+    let coalesce = tunnel.coalesce();
+    let (tx, rx) =
+        proxy_bidirectional(local_read, local_write, quic_send, quic_recv, coalesce).await?;
+
+    debug!(tx_bytes = %tx, rx_bytes = %rx, "Forwarded connection completed");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol;
+    use crate::testing::{test_config, test_server};
+    use crate::tunnel::TunnelClient;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_forward_to_echo() {
+        // Loopback echo server behind the tunnel
+        let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = echo.accept().await {
+                tokio::spawn(async move {
+                    let (mut read, mut write) = stream.into_split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+
+        // Tunnel server that checks the target and splices it to the echo server
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            while let Ok((mut send, mut recv)) = conn.accept_bi().await {
+                tokio::spawn(async move {
+                    // [0x01][Port(2)][HostLen(1)][Host(N)]
+                    let mut header = [0u8; 4];
+                    recv.read_exact(&mut header).await.unwrap();
+                    let mut host = vec![0u8; header[3] as usize];
+                    recv.read_exact(&mut host).await.unwrap();
+                    assert_eq!(host, b"echo.internal");
+                    assert_eq!(u16::from_be_bytes([header[1], header[2]]), 7);
+
+                    let stream = TcpStream::connect(echo_addr).await.unwrap();
+                    send.write_all(&[protocol::STATUS_OK]).await.unwrap();
+                    let (mut read, mut write) = stream.into_split();
+                    tokio::join!(
+                        async {
+                            let _ = tokio::io::copy(&mut recv, &mut write).await;
+                            let _ = write.shutdown().await;
+                        },
+                        async {
+                            let _ = tokio::io::copy(&mut read, &mut send).await;
+                            let _ = send.finish();
+                        },
+                    );
+                });
+            }
+        });
+
+        let client = TunnelClient::new(Arc::new(test_config(addr, ""))).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, client.handle(), "echo.internal".to_string(), 7));
+
+        // Each local connection gets a tunnel of its own
+        for message in [&b"first"[..], b"second"] {
+            let mut stream = TcpStream::connect(local_addr).await.unwrap();
+            stream.write_all(message).await.unwrap();
+            stream.shutdown().await.unwrap();
+            let mut echoed = Vec::new();
+            stream.read_to_end(&mut echoed).await.unwrap();
+            assert_eq!(echoed, message);
+        }
+    }
+}
@@ -1,27 +1,69 @@
-//! HTTP CONNECT proxy server implementation
+//! HTTP proxy server implementation
 //!
-//! Implements HTTP CONNECT tunneling for TCP proxying.
+//! Implements HTTP CONNECT tunneling for TCP proxying, plus plain
+//! forwarding of absolute-URI requests (`GET http://host/path`).
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use quinn::{RecvStream, SendStream};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
 use tokio::net::{TcpListener, TcpStream};
 use tracing::{debug, error, info, warn};
 
+use crate::config::ProxyCredentials;
+use crate::protocol::{FailureReason, TcpResponse, TunnelRejected};
+use crate::tunnel::pool::StreamLease;
 use crate::tunnel::stream::{establish_tcp_tunnel, proxy_bidirectional};
 use crate::tunnel::TunnelClientHandle;
 
-/// HTTP CONNECT proxy server
+/// Headers that only apply to the client-proxy hop and aren't forwarded
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "upgrade",
+];
+
+/// How a message body is delimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFraming {
+    None,
+    Length(u64),
+    Chunked,
+    /// Runs until the origin closes; responses only
+    UntilClose,
+}
+
+/// HTTP proxy server
 pub struct HttpProxy {
     tunnel: Arc<TunnelClientHandle>,
     bind_addr: SocketAddr,
+    /// Required credentials, if authentication is enabled
+    auth: Option<Arc<ProxyCredentials>>,
 }
 
 impl HttpProxy {
     /// Create a new HTTP proxy
     pub fn new(tunnel: Arc<TunnelClientHandle>, bind_addr: SocketAddr) -> Self {
-        Self { tunnel, bind_addr }
+        Self {
+            tunnel,
+            bind_addr,
+            auth: None,
+        }
+    }
+
+    /// Require HTTP Basic proxy authentication
+    pub fn with_auth(mut self, auth: Option<ProxyCredentials>) -> Self {
+        self.auth = auth.map(Arc::new);
+        self
     }
 
     /// Run the HTTP proxy server
@@ -37,9 +79,10 @@ impl HttpProxy {
                 Ok((stream, client_addr)) => {
                     debug!(client = %client_addr, "New HTTP connection");
                     let tunnel = self.tunnel.clone();
+                    let auth = self.auth.clone();
 
                     tokio::spawn(async move {
-                        if let Err(e) = handle_http_client(stream, tunnel).await {
+                        if let Err(e) = handle_http_client(stream, tunnel, auth.as_deref()).await {
                             debug!(error = %e, client = %client_addr, "HTTP client error");
                         }
                     });
@@ -53,78 +96,318 @@ impl HttpProxy {
 }
 
 /// Handle a single HTTP client connection
-async fn handle_http_client(stream: TcpStream, tunnel: Arc<TunnelClientHandle>) -> Result<()> {
+///
+/// Forwarded requests may be followed by more on the same connection; a
+/// CONNECT hands the connection over to the tunnel for good.
+async fn handle_http_client(
+    stream: TcpStream,
+    tunnel: Arc<TunnelClientHandle>,
+    auth: Option<&ProxyCredentials>,
+) -> Result<()> {
     let (reader, mut writer) = stream.into_split();
     let mut reader = BufReader::new(reader);
+    let mut upstream: Option<Upstream> = None;
 
-    // Read the request line
-    let mut request_line = String::new();
-    reader.read_line(&mut request_line).await?;
+    loop {
+        // Read the request line
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).await? == 0 {
+            // Client closed between requests
+            return Ok(());
+        }
 
-    let parts: Vec<&str> = request_line.trim().split_whitespace().collect();
-    if parts.len() < 3 {
-        send_error(&mut writer, 400, "Bad Request").await?;
-        return Err(anyhow::anyhow!("Invalid request line"));
-    }
+        let parts: Vec<&str> = request_line.split_whitespace().collect();
+        if parts.len() < 3 {
+            send_error(&mut writer, 400, "Bad Request").await?;
+            return Err(anyhow::anyhow!("Invalid request line"));
+        }
+
+        let method = parts[0];
+        let target = parts[1];
+        let version = parts[2];
+
+        let headers = read_headers(&mut reader).await?;
 
-    let method = parts[0];
-    let target = parts[1];
-    let _version = parts[2];
+        if let Some(auth) = auth {
+            let authorization = header_value(&headers, "proxy-authorization");
+            if !check_basic_auth(authorization, auth) {
+                writer
+                    .write_all(
+                        b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+                          Proxy-Authenticate: Basic realm=\"mytunnel\"\r\n\
+                          Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .await?;
+                return Err(anyhow::anyhow!("Proxy authentication failed"));
+            }
+        }
+
+        if method == "CONNECT" {
+            return handle_connect(reader, writer, &tunnel, target).await;
+        }
 
-    // Only support CONNECT method
-    if method != "CONNECT" {
-        send_error(&mut writer, 405, "Method Not Allowed").await?;
-        return Err(anyhow::anyhow!("Only CONNECT method supported, got: {}", method));
+        let request = ForwardRequest {
+            method,
+            uri: target,
+            version,
+            headers: &headers,
+        };
+        if !forward_request(&mut reader, &mut writer, &tunnel, &mut upstream, request).await? {
+            writer.shutdown().await?;
+            return Ok(());
+        }
     }
+}
 
+/// Tunnel a CONNECT request to `target` for the rest of the connection
+async fn handle_connect<R, W>(
+    reader: R,
+    mut writer: W,
+    tunnel: &TunnelClientHandle,
+    target: &str,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
     // Parse target (host:port)
     let (host, port) = parse_connect_target(target)?;
 
-    // Read and discard headers until empty line
-    loop {
-        let mut line = String::new();
-        reader.read_line(&mut line).await?;
-        if line.trim().is_empty() {
-            break;
+    debug!(host = %host, port = %port, "HTTP CONNECT request");
+
+    let (quic_send, quic_recv, _lease) = open_tunnel(tunnel, &mut writer, &host, port).await?;
+
+    // Send success response
+    writer
+        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+        .await?;
+
+    debug!(host = %host, port = %port, "HTTP CONNECT established");
+
+    // Proxy data bidirectionally
+    let (tx, rx) =
+        proxy_bidirectional(reader, writer, quic_send, quic_recv, tunnel.coalesce()).await?;
+
+    debug!(tx_bytes = %tx, rx_bytes = %rx, "HTTP CONNECT completed");
+
+    Ok(())
+}
+
+/// Request head of a forwarded (absolute-URI) request
+struct ForwardRequest<'a> {
+    method: &'a str,
+    uri: &'a str,
+    version: &'a str,
+    headers: &'a [(String, String)],
+}
+
+/// Tunnel stream left open to an origin between keep-alive requests
+struct Upstream {
+    host: String,
+    port: u16,
+    send: SendStream,
+    recv: BufReader<RecvStream>,
+    _lease: StreamLease,
+}
+
+impl Upstream {
+    /// Whether the stream can take another request to `host:port`
+    ///
+    /// An origin that has since closed its side, or sent something
+    /// unasked for, can't be reused.
+    async fn reusable_for(&mut self, host: &str, port: u16) -> bool {
+        if self.host != host || self.port != port {
+            return false;
         }
+        // Pending means idle and still open
+        tokio::time::timeout(Duration::ZERO, self.recv.fill_buf())
+            .await
+            .is_err()
     }
+}
 
-    debug!(host = %host, port = %port, "HTTP CONNECT request");
+/// Forward one absolute-URI request to its origin and relay the response
+///
+/// A kept-alive tunnel stream to the same origin is reused. Returns whether
+/// the client connection can take another request: both the client and the
+/// response must allow keep-alive, and the response's end must be known
+/// without the origin closing.
+async fn forward_request<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    tunnel: &TunnelClientHandle,
+    upstream: &mut Option<Upstream>,
+    request: ForwardRequest<'_>,
+) -> Result<bool>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let target = parse_absolute_uri(request.uri).and_then(|target| {
+        body_framing(request.headers).map(|framing| (target, framing))
+    });
+    let ((host, port, path), framing) = match target {
+        Ok(target) => target,
+        Err(e) => {
+            send_error(writer, 400, "Bad Request").await?;
+            return Err(e);
+        }
+    };
+    let client_keep_alive = wants_keep_alive(request.version, request.headers);
+
+    debug!(method = %request.method, host = %host, port = %port, "HTTP forward request");
+
+    let reusable = match upstream {
+        Some(origin) => origin.reusable_for(&host, port).await,
+        None => false,
+    };
+    let origin = if reusable {
+        debug!(host = %host, port = %port, "Reusing tunnel stream");
+        upstream.as_mut().expect("checked above")
+    } else {
+        *upstream = None;
+        let (send, recv, lease) = open_tunnel(tunnel, writer, &host, port).await?;
+        upstream.insert(Upstream {
+            host: host.clone(),
+            port,
+            send,
+            recv: BufReader::new(recv),
+            _lease: lease,
+        })
+    };
 
+    let head = encode_request_head(&request, &path, &host, port, client_keep_alive);
+    origin.send.write_all(head.as_bytes()).await?;
+    copy_body(reader, &mut origin.send, framing).await?;
+
+    // Interim 1xx responses are relayed ahead of the final one
+    let (status_line, status, headers) = loop {
+        let (status_line, status, headers) = read_response_head(&mut origin.recv).await?;
+        if !(100..200).contains(&status) {
+            break (status_line, status, headers);
+        }
+        writer
+            .write_all(encode_response_head(&status_line, &headers, None).as_bytes())
+            .await?;
+    };
+
+    let framing = response_framing(request.method, status, &headers);
+    let keep_alive = client_keep_alive && framing != BodyFraming::UntilClose;
+    let response_version = status_line.split_whitespace().next().unwrap_or_default();
+    let origin_keep_alive = keep_alive && wants_keep_alive(response_version, &headers);
+
+    let connection = if keep_alive { "keep-alive" } else { "close" };
+    writer
+        .write_all(encode_response_head(&status_line, &headers, Some(connection)).as_bytes())
+        .await?;
+    copy_body(&mut origin.recv, writer, framing).await?;
+
+    if !origin_keep_alive {
+        *upstream = None;
+    }
+
+    debug!(status, keep_alive, reuse = origin_keep_alive, "HTTP forward completed");
+
+    Ok(keep_alive)
+}
+
+/// Open a tunnel stream to `host:port`, answering the client on failure
+async fn open_tunnel<W>(
+    tunnel: &TunnelClientHandle,
+    writer: &mut W,
+    host: &str,
+    port: u16,
+) -> Result<(SendStream, RecvStream, StreamLease)>
+where
+    W: AsyncWrite + Unpin,
+{
     // Open QUIC stream
-    let (quic_send, quic_recv) = match tunnel.open_stream().await {
+    let (quic_send, quic_recv, lease) = match tunnel.open_stream().await {
         Ok(s) => s,
         Err(e) => {
             warn!(error = %e, "Failed to open tunnel stream");
-            send_error(&mut writer, 502, "Bad Gateway").await?;
+            send_error(writer, 502, "Bad Gateway").await?;
             return Err(e);
         }
     };
 
     // Establish TCP tunnel
-    let (quic_send, quic_recv) = match establish_tcp_tunnel(quic_send, quic_recv, &host, port).await
-    {
-        Ok(s) => s,
+    match establish_tcp_tunnel(quic_send, quic_recv, host, port).await {
+        Ok((quic_send, quic_recv)) => Ok((quic_send, quic_recv, lease)),
         Err(e) => {
             warn!(error = %e, host = %host, port = %port, "Failed to establish tunnel");
-            send_error(&mut writer, 502, "Bad Gateway").await?;
-            return Err(e);
+            let (status, message) = connect_failure_status(&e);
+            send_error(writer, status, message).await?;
+            Err(e)
         }
-    };
+    }
+}
 
-    // Send success response
-    writer
-        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
-        .await?;
+/// Read header lines up to the blank line ending the request head
+async fn read_headers<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            bail!("Connection closed in request headers");
+        }
+        if line.trim().is_empty() {
+            return Ok(headers);
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+}
 
-    debug!(host = %host, port = %port, "HTTP CONNECT established");
+/// Read a response's status line and headers from the origin
+async fn read_response_head<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<(String, u16, Vec<(String, String)>)> {
+    let mut status_line = String::new();
+    if reader.read_line(&mut status_line).await? == 0 {
+        bail!("Origin closed without responding");
+    }
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .with_context(|| format!("Invalid status line: {}", status_line.trim_end()))?;
+    let headers = read_headers(reader).await?;
+    Ok((status_line, status, headers))
+}
 
-    // Proxy data bidirectionally
-    let (tx, rx) = proxy_bidirectional(reader, writer, quic_send, quic_recv).await?;
+/// Whether the sender of `headers` wants the connection kept open
+///
+/// HTTP/1.1 defaults to keep-alive and HTTP/1.0 to close; a `Connection`
+/// (or legacy `Proxy-Connection`) header overrides either.
+fn wants_keep_alive(version: &str, headers: &[(String, String)]) -> bool {
+    let mut tokens = headers
+        .iter()
+        .filter(|(name, _)| {
+            name.eq_ignore_ascii_case("connection") || name.eq_ignore_ascii_case("proxy-connection")
+        })
+        .flat_map(|(_, value)| value.split(','))
+        .map(str::trim);
 
-    debug!(tx_bytes = %tx, rx_bytes = %rx, "HTTP CONNECT completed");
+    let mut keep_alive = version.eq_ignore_ascii_case("HTTP/1.1");
+    for token in &mut tokens {
+        if token.eq_ignore_ascii_case("close") {
+            return false;
+        }
+        if token.eq_ignore_ascii_case("keep-alive") {
+            keep_alive = true;
+        }
+    }
+    keep_alive
+}
 
-    Ok(())
+/// First value of the header `name`, matched case-insensitively
+fn header_value<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
 }
 
 /// Parse CONNECT target (host:port)
@@ -155,6 +438,251 @@ fn parse_connect_target(target: &str) -> Result<(String, u16)> {
     Ok((host, port))
 }
 
+/// Split an absolute `http://` URI into host, port and origin-form path
+///
+/// The port defaults to 80; `https://` targets must use CONNECT instead.
+fn parse_absolute_uri(uri: &str) -> Result<(String, u16, String)> {
+    let rest = match uri.get(..7) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("http://") => &uri[7..],
+        _ => bail!("Unsupported request URI: {}", uri),
+    };
+
+    let path_start = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(path_start);
+    let path = match path {
+        "" => "/".to_string(),
+        p if p.starts_with('?') => format!("/{}", p),
+        p => p.to_string(),
+    };
+
+    // Drop any userinfo; it's never sent on to the origin
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    if authority.is_empty() {
+        bail!("Missing host in request URI: {}", uri);
+    }
+
+    let (host, port) = if authority.starts_with('[') || authority.contains(':') {
+        if authority.ends_with(']') {
+            (authority.trim_start_matches('[').trim_end_matches(']').to_string(), 80)
+        } else if authority.starts_with('[') || authority.matches(':').count() == 1 {
+            parse_connect_target(authority)?
+        } else {
+            bail!("Invalid host in request URI: {}", uri);
+        }
+    } else {
+        (authority.to_string(), 80)
+    };
+
+    Ok((host, port, path))
+}
+
+/// Work out how a message body is delimited from its headers
+///
+/// Transfer-Encoding wins over Content-Length, per RFC 9112, and must
+/// end in chunked for the body's end to be known.
+fn body_framing(headers: &[(String, String)]) -> Result<BodyFraming> {
+    let last_coding = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("transfer-encoding"))
+        .flat_map(|(_, value)| value.split(','))
+        .last();
+    if let Some(coding) = last_coding {
+        if !coding.trim().eq_ignore_ascii_case("chunked") {
+            bail!("Unsupported transfer coding: {}", coding.trim());
+        }
+        return Ok(BodyFraming::Chunked);
+    }
+
+    match header_value(headers, "content-length") {
+        Some(len) => {
+            let len: u64 = len.parse().context("Invalid Content-Length")?;
+            Ok(BodyFraming::Length(len))
+        }
+        None => Ok(BodyFraming::None),
+    }
+}
+
+/// Work out how a response body is delimited
+///
+/// Responses to HEAD, 1xx, 204 and 304 have no body. When the headers
+/// don't pin the length down, the body runs until the origin closes.
+fn response_framing(method: &str, status: u16, headers: &[(String, String)]) -> BodyFraming {
+    if method.eq_ignore_ascii_case("HEAD") || status < 200 || status == 204 || status == 304 {
+        return BodyFraming::None;
+    }
+    match body_framing(headers) {
+        Ok(BodyFraming::None) | Err(_) => BodyFraming::UntilClose,
+        Ok(framing) => framing,
+    }
+}
+
+/// Build the request head sent to the origin
+///
+/// Hop-by-hop headers are dropped, Host is added if the client left it
+/// out, and `Connection` asks to keep the stream open only if the client
+/// wants its own connection kept.
+fn encode_request_head(
+    request: &ForwardRequest<'_>,
+    path: &str,
+    host: &str,
+    port: u16,
+    keep_alive: bool,
+) -> String {
+    let mut head = format!("{} {} {}\r\n", request.method, path, request.version);
+
+    if header_value(request.headers, "host").is_none() {
+        let host = if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host.to_string()
+        };
+        match port {
+            80 => head.push_str(&format!("Host: {}\r\n", host)),
+            port => head.push_str(&format!("Host: {}:{}\r\n", host, port)),
+        }
+    }
+
+    push_end_to_end_headers(&mut head, request.headers);
+    let connection = if keep_alive { "keep-alive" } else { "close" };
+    head.push_str(&format!("Connection: {}\r\n\r\n", connection));
+    head
+}
+
+/// Build the response head relayed to the client
+///
+/// Hop-by-hop headers are dropped and replaced by `connection`, if given.
+fn encode_response_head(
+    status_line: &str,
+    headers: &[(String, String)],
+    connection: Option<&str>,
+) -> String {
+    let mut head = format!("{}\r\n", status_line.trim_end());
+    push_end_to_end_headers(&mut head, headers);
+    if let Some(connection) = connection {
+        head.push_str(&format!("Connection: {}\r\n", connection));
+    }
+    head.push_str("\r\n");
+    head
+}
+
+/// Append every header that isn't hop-by-hop
+///
+/// Headers named in `Connection` are hop-by-hop too (RFC 9110 section
+/// 7.6.1). With Transfer-Encoding present, Content-Length is dropped so
+/// the next hop can't frame the body differently (request smuggling).
+fn push_end_to_end_headers(head: &mut String, headers: &[(String, String)]) {
+    let connection_options: Vec<&str> = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(str::trim)
+        .collect();
+    let chunked = header_value(headers, "transfer-encoding").is_some();
+
+    for (name, value) in headers {
+        let hop_by_hop = HOP_BY_HOP_HEADERS
+            .iter()
+            .chain(&connection_options)
+            .any(|h| name.eq_ignore_ascii_case(h));
+        let conflicting_length = chunked && name.eq_ignore_ascii_case("content-length");
+        if !hop_by_hop && !conflicting_length {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+}
+
+/// Copy a message body from `reader` to `writer`
+///
+/// Chunked bodies are passed through still encoded, trailers included.
+async fn copy_body<R, W>(reader: &mut R, writer: &mut W, framing: BodyFraming) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    match framing {
+        BodyFraming::None => Ok(()),
+        BodyFraming::Length(len) => copy_exact(reader, writer, len).await,
+        BodyFraming::UntilClose => {
+            tokio::io::copy(reader, writer).await?;
+            Ok(())
+        }
+        BodyFraming::Chunked => loop {
+            let line = copy_line(reader, writer).await?;
+            let size = line.trim_end().split(';').next().unwrap_or("").trim();
+            let size = u64::from_str_radix(size, 16).context("Invalid chunk size")?;
+
+            if size == 0 {
+                // Trailer section ends with a blank line
+                while !copy_line(reader, writer).await?.trim().is_empty() {}
+                return Ok(());
+            }
+
+            // Chunk data plus its trailing CRLF
+            copy_exact(reader, writer, size + 2).await?;
+        },
+    }
+}
+
+/// Copy one line from `reader` to `writer`, returning it
+async fn copy_line<R, W>(reader: &mut R, writer: &mut W) -> Result<String>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        bail!("Connection closed in message body");
+    }
+    writer.write_all(line.as_bytes()).await?;
+    Ok(line)
+}
+
+/// Copy exactly `len` bytes from `reader` to `writer`
+async fn copy_exact<R, W>(reader: &mut R, writer: &mut W, len: u64) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let copied = tokio::io::copy(&mut reader.take(len), writer).await?;
+    if copied < len {
+        bail!("Connection closed in message body");
+    }
+    Ok(())
+}
+
+/// Check a `Proxy-Authorization` header value against the configured credentials
+fn check_basic_auth(header: Option<&str>, auth: &ProxyCredentials) -> bool {
+    let Some((scheme, encoded)) = header.and_then(|h| h.split_once(' ')) else {
+        return false;
+    };
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return false;
+    }
+    let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(encoded.trim()) else {
+        return false;
+    };
+    let Some(colon) = decoded.iter().position(|&b| b == b':') else {
+        return false;
+    };
+    auth.verify(&decoded[..colon], &decoded[colon + 1..])
+}
+
+/// HTTP status for a tunnel that couldn't be established
+fn connect_failure_status(err: &anyhow::Error) -> (u16, &'static str) {
+    match err.downcast_ref::<TunnelRejected>() {
+        Some(TunnelRejected(TcpResponse::Failed(FailureReason::PolicyDenied))) => {
+            (403, "Forbidden")
+        }
+        Some(TunnelRejected(TcpResponse::Failed(FailureReason::Timeout))) => {
+            (504, "Gateway Timeout")
+        }
+        Some(TunnelRejected(TcpResponse::Failed(
+            FailureReason::StreamLimit | FailureReason::ServerBusy,
+        ))) => (503, "Service Unavailable"),
+        _ => (502, "Bad Gateway"),
+    }
+}
+
 /// Send HTTP error response
 async fn send_error<W: AsyncWriteExt + Unpin>(
     writer: &mut W,
@@ -172,6 +700,9 @@ async fn send_error<W: AsyncWriteExt + Unpin>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{test_config, test_server};
+    use crate::tunnel::TunnelClient;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[test]
     fn test_parse_connect_target() {
@@ -187,5 +718,335 @@ mod tests {
         assert_eq!(host, "::1");
         assert_eq!(port, 443);
     }
+
+    #[test]
+    fn test_check_basic_auth() {
+        let auth = ProxyCredentials {
+            username: "alice".to_string(),
+            password: "se:cret".to_string(),
+        };
+
+        // "alice:se:cret"
+        assert!(check_basic_auth(Some("Basic YWxpY2U6c2U6Y3JldA=="), &auth));
+        assert!(check_basic_auth(Some("basic YWxpY2U6c2U6Y3JldA=="), &auth));
+
+        // "alice:wrong"
+        assert!(!check_basic_auth(Some("Basic YWxpY2U6d3Jvbmc="), &auth));
+        assert!(!check_basic_auth(Some("Bearer YWxpY2U6c2U6Y3JldA=="), &auth));
+        assert!(!check_basic_auth(Some("Basic not-base64!"), &auth));
+        assert!(!check_basic_auth(Some("Basic"), &auth));
+        assert!(!check_basic_auth(None, &auth));
+    }
+
+    #[test]
+    fn test_parse_absolute_uri() {
+        let parse = |uri| parse_absolute_uri(uri).unwrap();
+
+        assert_eq!(parse("http://example.com/a?b=c"), ("example.com".into(), 80, "/a?b=c".into()));
+        assert_eq!(parse("HTTP://example.com:8080"), ("example.com".into(), 8080, "/".into()));
+        assert_eq!(parse("http://example.com?q"), ("example.com".into(), 80, "/?q".into()));
+        assert_eq!(parse("http://user:pw@example.com/"), ("example.com".into(), 80, "/".into()));
+        assert_eq!(parse("http://[::1]:81/x"), ("::1".into(), 81, "/x".into()));
+        assert_eq!(parse("http://[::1]/x"), ("::1".into(), 80, "/x".into()));
+
+        assert!(parse_absolute_uri("https://example.com/").is_err());
+        assert!(parse_absolute_uri("/relative").is_err());
+        assert!(parse_absolute_uri("http:///path").is_err());
+        assert!(parse_absolute_uri("http://example.com:http/").is_err());
+    }
+
+    #[test]
+    fn test_body_framing() {
+        let headers = |list: &[(&str, &str)]| -> Vec<(String, String)> {
+            list.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+        };
+
+        assert_eq!(body_framing(&headers(&[])).unwrap(), BodyFraming::None);
+        assert_eq!(
+            body_framing(&headers(&[("Content-Length", "12")])).unwrap(),
+            BodyFraming::Length(12)
+        );
+        assert_eq!(
+            body_framing(&headers(&[
+                ("Content-Length", "12"),
+                ("Transfer-Encoding", "gzip, chunked"),
+            ]))
+            .unwrap(),
+            BodyFraming::Chunked
+        );
+        assert!(body_framing(&headers(&[("content-length", "lots")])).is_err());
+        assert!(body_framing(&headers(&[("Transfer-Encoding", "gzip")])).is_err());
+    }
+
+    #[test]
+    fn test_encode_request_head() {
+        let mut headers = vec![
+            ("User-Agent".to_string(), "curl".to_string()),
+            ("Proxy-Connection".to_string(), "keep-alive".to_string()),
+            ("Proxy-Authorization".to_string(), "Basic xyz".to_string()),
+        ];
+        let request = ForwardRequest {
+            method: "GET",
+            uri: "http://example.com:8080/index.html",
+            version: "HTTP/1.1",
+            headers: &headers,
+        };
+
+        let head = encode_request_head(&request, "/index.html", "example.com", 8080, true);
+        assert_eq!(
+            head,
+            "GET /index.html HTTP/1.1\r\nHost: example.com:8080\r\nUser-Agent: curl\r\n\
+             Connection: keep-alive\r\n\r\n"
+        );
+
+        headers.push(("host".to_string(), "example.com".to_string()));
+        let request = ForwardRequest {
+            method: "GET",
+            uri: "http://[::1]/",
+            version: "HTTP/1.0",
+            headers: &headers,
+        };
+        let head = encode_request_head(&request, "/", "::1", 80, false);
+        assert!(!head.contains("Host: [::1]"));
+        assert!(head.contains("host: example.com\r\n"));
+        assert!(head.ends_with("Connection: close\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_encode_request_head_drops_hop_by_hop() {
+        let headers: Vec<_> = [
+            ("Connection", "Upgrade, X-Session"),
+            ("Upgrade", "websocket"),
+            ("X-Session", "abc"),
+            ("TE", "trailers"),
+            ("Trailer", "Expires"),
+            ("X-Kept", "yes"),
+        ]
+        .iter()
+        .map(|(n, v)| (n.to_string(), v.to_string()))
+        .collect();
+        let request = ForwardRequest {
+            method: "GET",
+            uri: "http://example.com/",
+            version: "HTTP/1.1",
+            headers: &headers,
+        };
+
+        let head = encode_request_head(&request, "/", "example.com", 80, true);
+        assert_eq!(
+            head,
+            "GET / HTTP/1.1\r\nHost: example.com\r\nX-Kept: yes\r\n\
+             Connection: keep-alive\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_request_head_transfer_encoding_wins() {
+        // Both framings at once is the classic request smuggling setup
+        let headers: Vec<_> = [("Content-Length", "4"), ("Transfer-Encoding", "chunked")]
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect();
+        let request = ForwardRequest {
+            method: "POST",
+            uri: "http://example.com/",
+            version: "HTTP/1.1",
+            headers: &headers,
+        };
+
+        assert_eq!(body_framing(&headers).unwrap(), BodyFraming::Chunked);
+        let head = encode_request_head(&request, "/", "example.com", 80, true);
+        assert!(!head.to_ascii_lowercase().contains("content-length"));
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+    }
+
+    #[test]
+    fn test_encode_response_head() {
+        let headers = vec![
+            ("Content-Length".to_string(), "2".to_string()),
+            ("Connection".to_string(), "close".to_string()),
+            ("Keep-Alive".to_string(), "timeout=5".to_string()),
+        ];
+
+        assert_eq!(
+            encode_response_head("HTTP/1.1 200 OK\r\n", &headers, Some("keep-alive")),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: keep-alive\r\n\r\n"
+        );
+        assert_eq!(
+            encode_response_head("HTTP/1.1 100 Continue\r\n", &[], None),
+            "HTTP/1.1 100 Continue\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_wants_keep_alive() {
+        let connection = |value: &str| vec![("Connection".to_string(), value.to_string())];
+
+        assert!(wants_keep_alive("HTTP/1.1", &[]));
+        assert!(!wants_keep_alive("HTTP/1.0", &[]));
+        assert!(!wants_keep_alive("HTTP/1.1", &connection("close")));
+        assert!(!wants_keep_alive("HTTP/1.1", &connection("keep-alive, Close")));
+        assert!(wants_keep_alive("HTTP/1.0", &connection("Keep-Alive")));
+        assert!(wants_keep_alive(
+            "HTTP/1.0",
+            &[("Proxy-Connection".to_string(), "keep-alive".to_string())]
+        ));
+    }
+
+    #[test]
+    fn test_response_framing() {
+        let length = vec![("Content-Length".to_string(), "5".to_string())];
+        let gzip = vec![("Transfer-Encoding".to_string(), "gzip".to_string())];
+
+        assert_eq!(response_framing("GET", 200, &length), BodyFraming::Length(5));
+        assert_eq!(response_framing("HEAD", 200, &length), BodyFraming::None);
+        assert_eq!(response_framing("GET", 204, &[]), BodyFraming::None);
+        assert_eq!(response_framing("GET", 304, &length), BodyFraming::None);
+
+        // Nothing pins the length down, so the origin's close ends it
+        assert_eq!(response_framing("GET", 200, &[]), BodyFraming::UntilClose);
+        assert_eq!(response_framing("GET", 200, &gzip), BodyFraming::UntilClose);
+    }
+
+    #[tokio::test]
+    async fn test_copy_body() {
+        let mut out = Vec::new();
+        let mut body: &[u8] = b"hello world, and then the next request";
+        copy_body(&mut body, &mut out, BodyFraming::Length(11)).await.unwrap();
+        assert_eq!(out, b"hello world");
+
+        let chunked = b"5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\nGET /next";
+        let mut out = Vec::new();
+        let mut body: &[u8] = chunked;
+        copy_body(&mut body, &mut out, BodyFraming::Chunked).await.unwrap();
+        assert_eq!(out, &chunked[..chunked.len() - 9]);
+        assert_eq!(body, b"GET /next");
+
+        let mut body: &[u8] = b"short";
+        assert!(copy_body(&mut body, &mut Vec::new(), BodyFraming::Length(10)).await.is_err());
+        let mut body: &[u8] = b"zz\r\n";
+        assert!(copy_body(&mut body, &mut Vec::new(), BodyFraming::Chunked).await.is_err());
+    }
+
+    /// Tunnel server answering every stream as an HTTP origin
+    ///
+    /// Each response body is the request path. Returns the proxy address
+    /// and a count of tunnel streams opened.
+    async fn forward_proxy() -> (SocketAddr, Arc<AtomicUsize>) {
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        let streams = Arc::new(AtomicUsize::new(0));
+
+        let opened = streams.clone();
+        tokio::spawn(async move {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            while let Ok((mut send, recv)) = conn.accept_bi().await {
+                opened.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut recv = BufReader::new(recv);
+                    // [0x01][Port(2)][HostLen(1)][Host(N)]
+                    let mut header = [0u8; 4];
+                    recv.read_exact(&mut header).await.unwrap();
+                    let mut host = vec![0u8; header[3] as usize];
+                    recv.read_exact(&mut host).await.unwrap();
+                    send.write_all(&[crate::protocol::STATUS_OK]).await.unwrap();
+
+                    let mut line = String::new();
+                    while recv.read_line(&mut line).await.unwrap() > 0 {
+                        let path = line.split_whitespace().nth(1).unwrap().to_string();
+                        let headers = read_headers(&mut recv).await.unwrap();
+                        let close = !wants_keep_alive("HTTP/1.1", &headers);
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                            path.len(),
+                            path
+                        );
+                        send.write_all(response.as_bytes()).await.unwrap();
+                        if close {
+                            break;
+                        }
+                        line.clear();
+                    }
+                    let _ = send.finish();
+                });
+            }
+        });
+
+        let client = TunnelClient::new(Arc::new(test_config(addr, ""))).await.unwrap();
+        let tunnel = client.handle();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _client = client;
+            while let Ok((stream, _)) = listener.accept().await {
+                let tunnel = tunnel.clone();
+                tokio::spawn(async move {
+                    let _ = handle_http_client(stream, tunnel, None).await;
+                });
+            }
+        });
+
+        (proxy_addr, streams)
+    }
+
+    /// Read one response, returning its Connection header and body
+    async fn read_response<R: AsyncBufRead + Unpin>(reader: &mut R) -> (String, String) {
+        let (_, status, headers) = read_response_head(reader).await.unwrap();
+        assert_eq!(status, 200);
+        let len: usize = header_value(&headers, "content-length").unwrap().parse().unwrap();
+        let mut body = vec![0u8; len];
+        reader.read_exact(&mut body).await.unwrap();
+        (
+            header_value(&headers, "connection").unwrap().to_string(),
+            String::from_utf8(body).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_forward_keep_alive_reuses_stream() {
+        let (proxy_addr, streams) = forward_proxy().await;
+        let stream = TcpStream::connect(proxy_addr).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        for path in ["/one", "/two"] {
+            let request = format!("GET http://example.com{} HTTP/1.1\r\n\r\n", path);
+            writer.write_all(request.as_bytes()).await.unwrap();
+            let (connection, body) = read_response(&mut reader).await;
+            assert_eq!(connection, "keep-alive");
+            assert_eq!(body, path);
+        }
+        assert_eq!(streams.load(Ordering::SeqCst), 1);
+
+        // A different origin needs a stream of its own
+        writer
+            .write_all(b"GET http://example.org/three HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(read_response(&mut reader).await.1, "/three");
+        assert_eq!(streams.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_forward_connection_close() {
+        let (proxy_addr, streams) = forward_proxy().await;
+        let stream = TcpStream::connect(proxy_addr).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        writer
+            .write_all(b"GET http://example.com/bye HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let (connection, body) = read_response(&mut reader).await;
+        assert_eq!(connection, "close");
+        assert_eq!(body, "/bye");
+
+        // The proxy closes the client connection after the response
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(streams.load(Ordering::SeqCst), 1);
+    }
 }
 
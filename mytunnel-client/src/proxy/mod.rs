@@ -1,10 +1,13 @@
 //! Local proxy servers
 //!
-//! Provides SOCKS5 and HTTP CONNECT proxy interfaces.
+//! Provides SOCKS5 and HTTP CONNECT proxy interfaces, plus static port
+//! forwards.
 
+pub mod forward;
 pub mod http;
 pub mod socks5;
 
+pub use forward::ForwardProxy;
 pub use http::HttpProxy;
 pub use socks5::Socks5Proxy;
 
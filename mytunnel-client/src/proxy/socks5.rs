@@ -1,30 +1,65 @@
 //! SOCKS5 proxy server implementation
 //!
-//! Implements RFC 1928 SOCKS5 protocol with CONNECT and UDP ASSOCIATE support.
+//! Implements RFC 1928 SOCKS5 protocol with CONNECT and UDP ASSOCIATE
+//! support, plus BIND when enabled.
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bytes::BytesMut;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tracing::{debug, error, info, warn};
 
+use crate::config::ProxyCredentials;
 use crate::protocol::socks5::*;
+use crate::protocol::{FailureReason, TcpResponse, TunnelRejected};
 use crate::tunnel::datagram::UdpAssociation;
-use crate::tunnel::stream::{establish_tcp_tunnel, proxy_bidirectional};
+use crate::tunnel::stream::{
+    accept_bind_once, bind_once, establish_tcp_tunnel, proxy_bidirectional,
+};
 use crate::tunnel::TunnelClientHandle;
 
 /// SOCKS5 proxy server
 pub struct Socks5Proxy {
     tunnel: Arc<TunnelClientHandle>,
     bind_addr: SocketAddr,
+    /// Required credentials, if authentication is enabled
+    auth: Option<Arc<ProxyCredentials>>,
+    /// Reassemble fragmented UDP datagrams
+    udp_reassembly: bool,
+    /// Serve BIND through a one-shot listener on the server
+    bind: bool,
 }
 
 impl Socks5Proxy {
     /// Create a new SOCKS5 proxy
     pub fn new(tunnel: Arc<TunnelClientHandle>, bind_addr: SocketAddr) -> Self {
-        Self { tunnel, bind_addr }
+        Self {
+            tunnel,
+            bind_addr,
+            auth: None,
+            udp_reassembly: false,
+            bind: false,
+        }
+    }
+
+    /// Require username/password authentication
+    pub fn with_auth(mut self, auth: Option<ProxyCredentials>) -> Self {
+        self.auth = auth.map(Arc::new);
+        self
+    }
+
+    /// Reassemble fragmented UDP ASSOCIATE datagrams instead of dropping them
+    pub fn with_udp_reassembly(mut self, enabled: bool) -> Self {
+        self.udp_reassembly = enabled;
+        self
+    }
+
+    /// Serve BIND requests; the server must allow reverse tunnels
+    pub fn with_bind(mut self, enabled: bool) -> Self {
+        self.bind = enabled;
+        self
     }
 
     /// Run the SOCKS5 proxy server
@@ -40,9 +75,21 @@ impl Socks5Proxy {
                 Ok((stream, client_addr)) => {
                     debug!(client = %client_addr, "New SOCKS5 connection");
                     let tunnel = self.tunnel.clone();
+                    let auth = self.auth.clone();
+                    let udp_reassembly = self.udp_reassembly;
+                    let bind = self.bind;
 
                     tokio::spawn(async move {
-                        if let Err(e) = handle_socks5_client(stream, tunnel, client_addr).await {
+                        let result = handle_socks5_client(
+                            stream,
+                            tunnel,
+                            auth.as_deref(),
+                            udp_reassembly,
+                            bind,
+                            client_addr,
+                        )
+                        .await;
+                        if let Err(e) = result {
                             debug!(error = %e, client = %client_addr, "SOCKS5 client error");
                         }
                     });
@@ -59,33 +106,12 @@ impl Socks5Proxy {
 async fn handle_socks5_client(
     mut stream: TcpStream,
     tunnel: Arc<TunnelClientHandle>,
+    auth: Option<&ProxyCredentials>,
+    udp_reassembly: bool,
+    bind: bool,
     client_addr: SocketAddr,
 ) -> Result<()> {
-    // Read version and auth methods
-    let mut header = [0u8; 2];
-    stream.read_exact(&mut header).await?;
-
-    if header[0] != VERSION {
-        return Err(anyhow::anyhow!("Invalid SOCKS version: {}", header[0]));
-    }
-
-    let nmethods = header[1] as usize;
-    let mut methods = vec![0u8; nmethods];
-    stream.read_exact(&mut methods).await?;
-
-    // We only support no authentication
-    let method = if methods.contains(&AUTH_NONE) {
-        AUTH_NONE
-    } else {
-        AUTH_NO_ACCEPTABLE
-    };
-
-    // Send method selection
-    stream.write_all(&[VERSION, method]).await?;
-
-    if method == AUTH_NO_ACCEPTABLE {
-        return Err(anyhow::anyhow!("No acceptable auth method"));
-    }
+    negotiate_auth(&mut stream, auth).await?;
 
     // Read request
     let mut request_header = [0u8; 4];
@@ -128,7 +154,7 @@ async fn handle_socks5_client(
         }
         _ => {
             // Send error reply
-            let reply = encode_reply(REP_ATYP_NOT_SUPPORTED, zero_bind_addr_v4());
+            let reply = encode_reply(REP_ATYP_NOT_SUPPORTED, zero_bind_addr(client_addr));
             stream.write_all(&reply).await?;
             return Err(anyhow::anyhow!("Unsupported address type: {}", atyp));
         }
@@ -140,19 +166,27 @@ async fn handle_socks5_client(
 
     match cmd {
         CMD_CONNECT => {
-            handle_connect(stream, tunnel, &host, port).await?;
+            handle_connect(stream, tunnel, &host, port, client_addr).await?;
         }
         CMD_UDP_ASSOCIATE => {
-            handle_udp_associate(stream, tunnel, client_addr).await?;
+            handle_udp_associate(stream, tunnel, udp_reassembly, client_addr).await?;
+        }
+        CMD_BIND if bind => {
+            handle_bind(stream, tunnel, &host, port, client_addr).await?;
         }
         CMD_BIND => {
-            // BIND not supported
-            let reply = encode_reply(REP_CMD_NOT_SUPPORTED, zero_bind_addr_v4());
+            // Logged so operators can see whether clients want BIND
+            debug!(
+                client = %client_addr,
+                host = %host,
+                port = %port,
+                "SOCKS5 BIND rejected: disabled"
+            );
+            let reply = encode_reply(REP_CMD_NOT_SUPPORTED, zero_bind_addr(client_addr));
             stream.write_all(&reply).await?;
-            return Err(anyhow::anyhow!("BIND command not supported"));
         }
         _ => {
-            let reply = encode_reply(REP_CMD_NOT_SUPPORTED, zero_bind_addr_v4());
+            let reply = encode_reply(REP_CMD_NOT_SUPPORTED, zero_bind_addr(client_addr));
             stream.write_all(&reply).await?;
             return Err(anyhow::anyhow!("Unknown command: {}", cmd));
         }
@@ -161,19 +195,83 @@ async fn handle_socks5_client(
     Ok(())
 }
 
+/// Perform method selection and, if required, username/password authentication
+///
+/// Without credentials only `AUTH_NONE` is offered; with credentials only
+/// `AUTH_USERPASS` is. On failure the error reply has already been sent.
+async fn negotiate_auth<S>(stream: &mut S, auth: Option<&ProxyCredentials>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Read version and auth methods
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+
+    if header[0] != VERSION {
+        return Err(anyhow::anyhow!("Invalid SOCKS version: {}", header[0]));
+    }
+
+    let nmethods = header[1] as usize;
+    let mut methods = vec![0u8; nmethods];
+    stream.read_exact(&mut methods).await?;
+
+    let wanted = if auth.is_some() { AUTH_USERPASS } else { AUTH_NONE };
+    let method = if methods.contains(&wanted) {
+        wanted
+    } else {
+        AUTH_NO_ACCEPTABLE
+    };
+
+    // Send method selection
+    stream.write_all(&[VERSION, method]).await?;
+
+    if method == AUTH_NO_ACCEPTABLE {
+        return Err(anyhow::anyhow!("No acceptable auth method"));
+    }
+
+    let Some(auth) = auth else {
+        return Ok(());
+    };
+
+    // RFC 1929: VER | ULEN | UNAME | PLEN | PASSWD
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != USERPASS_VERSION {
+        stream.write_all(&[USERPASS_VERSION, USERPASS_FAILURE]).await?;
+        return Err(anyhow::anyhow!("Invalid auth version: {}", header[0]));
+    }
+
+    let mut username = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut username).await?;
+
+    let mut plen = [0u8; 1];
+    stream.read_exact(&mut plen).await?;
+    let mut password = vec![0u8; plen[0] as usize];
+    stream.read_exact(&mut password).await?;
+
+    if !auth.verify(&username, &password) {
+        stream.write_all(&[USERPASS_VERSION, USERPASS_FAILURE]).await?;
+        return Err(anyhow::anyhow!("Invalid SOCKS5 credentials"));
+    }
+
+    stream.write_all(&[USERPASS_VERSION, USERPASS_SUCCESS]).await?;
+    Ok(())
+}
+
 /// Handle CONNECT command
 async fn handle_connect(
     mut stream: TcpStream,
     tunnel: Arc<TunnelClientHandle>,
     host: &str,
     port: u16,
+    client_addr: SocketAddr,
 ) -> Result<()> {
     // Open QUIC stream
-    let (quic_send, quic_recv) = match tunnel.open_stream().await {
+    let (quic_send, quic_recv, _lease) = match tunnel.open_stream().await {
         Ok(s) => s,
         Err(e) => {
             warn!(error = %e, "Failed to open tunnel stream");
-            let reply = encode_reply(REP_GENERAL_FAILURE, zero_bind_addr_v4());
+            let reply = encode_reply(REP_GENERAL_FAILURE, zero_bind_addr(client_addr));
             stream.write_all(&reply).await?;
             return Err(e);
         }
@@ -185,14 +283,14 @@ async fn handle_connect(
         Ok(s) => s,
         Err(e) => {
             warn!(error = %e, host = %host, port = %port, "Failed to establish tunnel");
-            let reply = encode_reply(REP_HOST_UNREACHABLE, zero_bind_addr_v4());
+            let reply = encode_reply(connect_failure_reply(&e), zero_bind_addr(client_addr));
             stream.write_all(&reply).await?;
             return Err(e);
         }
     };
 
     // Send success reply
-    let reply = encode_reply(REP_SUCCESS, zero_bind_addr_v4());
+    let reply = encode_reply(REP_SUCCESS, zero_bind_addr(client_addr));
     stream.write_all(&reply).await?;
 
     debug!(host = %host, port = %port, "SOCKS5 CONNECT established");
@@ -200,28 +298,104 @@ async fn handle_connect(
     // Split the TCP stream and proxy data
     let (local_read, local_write) = stream.into_split();
 
-    let (tx, rx) = proxy_bidirectional(local_read, local_write, quic_send, quic_recv).await?;
+    let coalesce = tunnel.coalesce();
+    let (tx, rx) =
+        proxy_bidirectional(local_read, local_write, quic_send, quic_recv, coalesce).await?;
 
     debug!(tx_bytes = %tx, rx_bytes = %rx, "SOCKS5 CONNECT completed");
 
     Ok(())
 }
 
+/// Handle BIND command
+///
+/// The server listens on an ephemeral port: the first reply carries its
+/// address, the second the peer that connected to it, after which that
+/// connection is proxied. When `host` is an IP address the server takes
+/// the connection only from it; a domain name or the unspecified address
+/// takes any peer. `port` isn't checked, since peers such as active FTP
+/// servers connect from a port of their own choosing.
+async fn handle_bind(
+    mut stream: TcpStream,
+    tunnel: Arc<TunnelClientHandle>,
+    host: &str,
+    port: u16,
+    client_addr: SocketAddr,
+) -> Result<()> {
+    let connection = match tunnel.get_connection().await {
+        Ok(connection) => connection,
+        Err(e) => {
+            warn!(error = %e, "Failed to reach tunnel server for BIND");
+            let reply = encode_reply(REP_GENERAL_FAILURE, zero_bind_addr(client_addr));
+            stream.write_all(&reply).await?;
+            return Err(e);
+        }
+    };
+    let expected = host.parse::<IpAddr>().ok().filter(|ip| !ip.is_unspecified());
+    let (bound_port, quic_send, mut quic_recv) = match bind_once(&connection, expected).await {
+        Ok(bound) => bound,
+        Err(e) => {
+            warn!(error = %e, host = %host, port = %port, "Failed to bind on server");
+            let reply = encode_reply(connect_failure_reply(&e), zero_bind_addr(client_addr));
+            stream.write_all(&reply).await?;
+            return Err(e);
+        }
+    };
+
+    // Peers reach the listener at the address we reach the server at
+    let bound = SocketAddr::new(connection.remote_address().ip().to_canonical(), bound_port);
+    stream.write_all(&encode_reply(REP_SUCCESS, bound)).await?;
+    debug!(bound = %bound, expected_peer = %host, "SOCKS5 BIND listening");
+
+    let peer = tokio::select! {
+        peer = accept_bind_once(&mut quic_recv) => peer,
+        closed = wait_for_tcp_close(&stream) => {
+            if let Err(e) = closed {
+                let reply = encode_reply(REP_GENERAL_FAILURE, zero_bind_addr(client_addr));
+                stream.write_all(&reply).await?;
+                return Err(e);
+            }
+            debug!(bound = %bound, "SOCKS5 client left before BIND connection arrived");
+            return Ok(());
+        }
+    };
+    let peer = match peer {
+        Ok(peer) => peer,
+        Err(e) => {
+            let reply = encode_reply(connect_failure_reply(&e), zero_bind_addr(client_addr));
+            stream.write_all(&reply).await?;
+            return Err(e);
+        }
+    };
+    stream.write_all(&encode_reply(REP_SUCCESS, peer)).await?;
+    debug!(bound = %bound, peer = %peer, "SOCKS5 BIND connected");
+
+    let (local_read, local_write) = stream.into_split();
+    let coalesce = tunnel.coalesce();
+    let (tx, rx) =
+        proxy_bidirectional(local_read, local_write, quic_send, quic_recv, coalesce).await?;
+
+    debug!(tx_bytes = %tx, rx_bytes = %rx, "SOCKS5 BIND completed");
+
+    Ok(())
+}
+
 /// Handle UDP ASSOCIATE command
 async fn handle_udp_associate(
     mut stream: TcpStream,
     tunnel: Arc<TunnelClientHandle>,
-    _client_addr: SocketAddr,
+    reassembly: bool,
+    client_addr: SocketAddr,
 ) -> Result<()> {
-    // Create UDP association
-    // Bind to ephemeral port on localhost
-    let bind_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    // Create UDP association on an ephemeral port of the address the
+    // client reached us at, so the reply matches its address family
+    let bind_addr = udp_relay_bind_addr(stream.local_addr()?);
 
     let association = match UdpAssociation::new(tunnel, bind_addr).await {
-        Ok(a) => a,
+        Ok(a) => a.with_reassembly(reassembly),
         Err(e) => {
             warn!(error = %e, "Failed to create UDP association");
-            let reply = encode_reply(REP_GENERAL_FAILURE, zero_bind_addr_v4());
+            let reply = encode_reply(REP_GENERAL_FAILURE, zero_bind_addr(client_addr));
             stream.write_all(&reply).await?;
             return Err(e);
         }
@@ -242,18 +416,231 @@ async fn handle_udp_associate(
                 debug!(error = %e, "UDP association error");
             }
         }
-        _ = wait_for_tcp_close(&mut stream) => {
-            debug!("SOCKS5 TCP connection closed, ending UDP association");
+        closed = wait_for_tcp_close(&stream) => {
+            if let Err(e) = closed {
+                debug!(error = %e, "Ending UDP association");
+            } else {
+                debug!("SOCKS5 TCP connection closed, ending UDP association");
+            }
         }
     }
 
     Ok(())
 }
 
-/// Wait for TCP connection to close (used for UDP ASSOCIATE lifecycle)
-async fn wait_for_tcp_close(stream: &mut TcpStream) {
+/// Address to bind a UDP relay for a client connected to `local_addr`
+///
+/// IPv4-mapped addresses from a dual-stack listener are bound as plain
+/// IPv4 so the reply carries an IPv4 relay address.
+fn udp_relay_bind_addr(local_addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(local_addr.ip().to_canonical(), 0)
+}
+
+/// Wait for the SOCKS5 client to close its TCP connection, while a BIND
+/// or UDP ASSOCIATE waits on it
+///
+/// The client may not send anything at this point, so data fails with an
+/// error rather than counting as a close. It is peeked, not consumed.
+async fn wait_for_tcp_close(stream: &TcpStream) -> Result<()> {
     let mut buf = [0u8; 1];
-    // When the client closes the TCP connection, this will return
-    let _ = stream.read(&mut buf).await;
+    match stream.peek(&mut buf).await {
+        Ok(0) | Err(_) => Ok(()),
+        Ok(_) => bail!("SOCKS5 client sent data before the request completed"),
+    }
+}
+
+/// SOCKS5 reply code for a tunnel that couldn't be established
+///
+/// Timeouts map to "TTL expired", as most SOCKS5 servers report them.
+fn connect_failure_reply(err: &anyhow::Error) -> u8 {
+    match err.downcast_ref::<TunnelRejected>() {
+        Some(TunnelRejected(TcpResponse::Failed(reason))) => match reason {
+            FailureReason::HostUnreachable => REP_HOST_UNREACHABLE,
+            FailureReason::ConnectionRefused => REP_CONN_REFUSED,
+            FailureReason::PolicyDenied => REP_CONN_NOT_ALLOWED,
+            FailureReason::Timeout => REP_TTL_EXPIRED,
+            FailureReason::Unspecified
+            | FailureReason::StreamLimit
+            | FailureReason::ServerBusy => REP_GENERAL_FAILURE,
+        },
+        _ => REP_GENERAL_FAILURE,
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{self, STATUS_OK, TCP_CONNECT_IPV4};
+    use crate::testing::{test_config, test_server};
+    use crate::tunnel::TunnelClient;
+
+    fn credentials() -> ProxyCredentials {
+        ProxyCredentials {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+        }
+    }
+
+    /// Run negotiation against `client_bytes`, returning the result and server replies
+    async fn negotiate(client_bytes: &[u8], auth: Option<&ProxyCredentials>) -> (bool, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(client_bytes).await.unwrap();
+
+        let ok = negotiate_auth(&mut server, auth).await.is_ok();
+        drop(server);
+
+        let mut replies = Vec::new();
+        client.read_to_end(&mut replies).await.unwrap();
+        (ok, replies)
+    }
+
+    #[tokio::test]
+    async fn test_no_auth() {
+        let (ok, replies) = negotiate(&[VERSION, 1, AUTH_NONE], None).await;
+        assert!(ok);
+        assert_eq!(replies, [VERSION, AUTH_NONE]);
+    }
+
+    #[tokio::test]
+    async fn test_userpass_accepted() {
+        let mut hello = vec![VERSION, 2, AUTH_NONE, AUTH_USERPASS, USERPASS_VERSION, 5];
+        hello.extend_from_slice(b"alice");
+        hello.push(6);
+        hello.extend_from_slice(b"secret");
+
+        let (ok, replies) = negotiate(&hello, Some(&credentials())).await;
+        assert!(ok);
+        assert_eq!(replies, [VERSION, AUTH_USERPASS, USERPASS_VERSION, USERPASS_SUCCESS]);
+    }
+
+    #[tokio::test]
+    async fn test_userpass_rejected() {
+        let mut hello = vec![VERSION, 1, AUTH_USERPASS, USERPASS_VERSION, 5];
+        hello.extend_from_slice(b"alice");
+        hello.push(5);
+        hello.extend_from_slice(b"wrong");
+
+        let (ok, replies) = negotiate(&hello, Some(&credentials())).await;
+        assert!(!ok);
+        assert_eq!(replies, [VERSION, AUTH_USERPASS, USERPASS_VERSION, USERPASS_FAILURE]);
+    }
+
+    #[tokio::test]
+    async fn test_auth_required_but_not_offered() {
+        let (ok, replies) = negotiate(&[VERSION, 1, AUTH_NONE], Some(&credentials())).await;
+        assert!(!ok);
+        assert_eq!(replies, [VERSION, AUTH_NO_ACCEPTABLE]);
+    }
+
+    /// Serve one SOCKS5 client over loopback, returning the client's socket
+    async fn socks_client(tunnel: Arc<TunnelClientHandle>, bind: bool) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, client_addr) = listener.accept().await.unwrap();
+        tokio::spawn(async move {
+            let _ = handle_socks5_client(stream, tunnel, None, false, bind, client_addr).await;
+        });
+        client
+    }
+
+    #[tokio::test]
+    async fn test_bind() {
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        let client = TunnelClient::new(Arc::new(test_config(addr, ""))).await.unwrap();
+        let request = [VERSION, 1, AUTH_NONE, VERSION, CMD_BIND, 0, ATYP_IPV4, 10, 0, 0, 5, 0, 21];
+
+        // Refused when disabled
+        let mut socks = socks_client(client.handle(), false).await;
+        socks.write_all(&request).await.unwrap();
+        let mut reply = [0u8; 12];
+        socks.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[2..4], [VERSION, REP_CMD_NOT_SUPPORTED]);
+
+        let mut socks = socks_client(client.handle(), true).await;
+        socks.write_all(&request).await.unwrap();
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let (mut send, mut recv) = conn.accept_bi().await.unwrap();
+        let mut bind = [0u8; 3];
+        recv.read_exact(&mut bind).await.unwrap();
+        assert_eq!(bind, protocol::encode_bind_once(0, "10.0.0.5".parse().ok())[..3]);
+        let mut peer = [0u8; 5];
+        recv.read_exact(&mut peer).await.unwrap();
+        assert_eq!(peer, [TCP_CONNECT_IPV4, 10, 0, 0, 5]);
+        send.write_all(&[STATUS_OK, 0x1f, 0x90]).await.unwrap();
+
+        // The first reply is where the server listens...
+        let mut reply = [0u8; 12];
+        socks.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[2..], encode_reply(REP_SUCCESS, "127.0.0.1:8080".parse().unwrap()));
+
+        // ...the second who connected, and then the connection is proxied
+        send.write_all(&[STATUS_OK, TCP_CONNECT_IPV4, 10, 0, 0, 5, 0x4e, 0x20])
+            .await
+            .unwrap();
+        let mut reply = [0u8; 10];
+        socks.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[..], encode_reply(REP_SUCCESS, "10.0.0.5:20000".parse().unwrap()));
+
+        socks.write_all(b"ping").await.unwrap();
+        let mut data = [0u8; 4];
+        recv.read_exact(&mut data).await.unwrap();
+        assert_eq!(&data, b"ping");
+        send.write_all(b"pong").await.unwrap();
+        send.finish().unwrap();
+        socks.read_exact(&mut data).await.unwrap();
+        assert_eq!(&data, b"pong");
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_tcp_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        // Data is an error, and is left unread
+        client.write_all(b"x").await.unwrap();
+        assert!(wait_for_tcp_close(&stream).await.is_err());
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"x");
+
+        drop(client);
+        assert!(wait_for_tcp_close(&stream).await.is_ok());
+    }
+
+    #[test]
+    fn test_udp_relay_bind_addr() {
+        let bind = |addr: &str| udp_relay_bind_addr(addr.parse().unwrap()).to_string();
+        assert_eq!(bind("127.0.0.1:1080"), "127.0.0.1:0");
+        assert_eq!(bind("[::1]:1080"), "[::1]:0");
+        assert_eq!(bind("[::ffff:127.0.0.1]:1080"), "127.0.0.1:0");
+    }
+
+    #[test]
+    fn test_connect_failure_reply() {
+        let failed = |reason| anyhow::Error::from(TunnelRejected(TcpResponse::Failed(reason)));
+
+        assert_eq!(
+            connect_failure_reply(&failed(FailureReason::HostUnreachable)),
+            REP_HOST_UNREACHABLE
+        );
+        assert_eq!(
+            connect_failure_reply(&failed(FailureReason::ConnectionRefused)),
+            REP_CONN_REFUSED
+        );
+        assert_eq!(
+            connect_failure_reply(&failed(FailureReason::PolicyDenied)),
+            REP_CONN_NOT_ALLOWED
+        );
+        assert_eq!(connect_failure_reply(&failed(FailureReason::Timeout)), REP_TTL_EXPIRED);
+        assert_eq!(
+            connect_failure_reply(&failed(FailureReason::Unspecified)),
+            REP_GENERAL_FAILURE
+        );
+
+        let rate_limited = anyhow::Error::from(TunnelRejected(TcpResponse::RateLimited));
+        assert_eq!(connect_failure_reply(&rate_limited), REP_GENERAL_FAILURE);
+        assert_eq!(connect_failure_reply(&anyhow::anyhow!("stream reset")), REP_GENERAL_FAILURE);
+    }
+}
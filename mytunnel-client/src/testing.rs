@@ -0,0 +1,43 @@
+//! Shared helpers for unit tests
+
+use quinn::{Endpoint, ServerConfig};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::config::Config;
+
+/// Start a loopback QUIC server endpoint with a throwaway certificate
+pub(crate) fn test_server() -> Endpoint {
+    test_server_on("127.0.0.1:0".parse().unwrap())
+}
+
+/// Start a QUIC server endpoint on `addr` with a throwaway certificate
+pub(crate) fn test_server_on(addr: SocketAddr) -> Endpoint {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_der = CertificateDer::from(cert.cert);
+    let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+
+    let mut tls_config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert_der], key_der)
+        .unwrap();
+    tls_config.alpn_protocols = vec![b"mytunnel".to_vec()];
+    tls_config.max_early_data_size = u32::MAX;
+
+    let server_config = ServerConfig::with_crypto(Arc::new(
+        quinn::crypto::rustls::QuicServerConfig::try_from(tls_config).unwrap(),
+    ));
+    Endpoint::server(server_config, addr).unwrap()
+}
+
+/// Client config pointing at `addr` with certificate checks disabled
+///
+/// `extra` is appended to the `[server]` table.
+pub(crate) fn test_config(addr: SocketAddr, extra: &str) -> Config {
+    toml::from_str(&format!(
+        "[server]\naddress = \"{}\"\ninsecure = true\n{}\n[proxy]\n",
+        addr, extra
+    ))
+    .unwrap()
+}
@@ -0,0 +1,224 @@
+//! Reconnect backoff
+//!
+//! Decides how long the client waits before reconnecting after the
+//! server closes the connection.
+
+use parking_lot::Mutex;
+use quinn::ConnectionError;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::time::{Duration, Instant};
+
+use crate::config::ReconnectConfig;
+use crate::close_code::CloseCode;
+
+/// Holds off reconnects after the server rejects us for capacity
+pub struct CapacityBackoff {
+    /// How long to wait after a capacity rejection
+    delay: Duration,
+    /// Earliest time a reconnect may be attempted
+    retry_at: Mutex<Option<Instant>>,
+}
+
+impl CapacityBackoff {
+    /// Create a backoff that waits `delay` after each capacity rejection
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            retry_at: Mutex::new(None),
+        }
+    }
+
+    /// Record why a connection closed
+    ///
+    /// Returns true if the server closed it with the capacity code, in
+    /// which case reconnects are held off for the configured delay.
+    pub fn observe(&self, reason: &ConnectionError) -> bool {
+        if !is_capacity_close(reason) {
+            return false;
+        }
+        *self.retry_at.lock() = Some(Instant::now() + self.delay);
+        true
+    }
+
+    /// Time left before a reconnect is allowed, if any
+    pub fn remaining(&self) -> Option<Duration> {
+        let mut retry_at = self.retry_at.lock();
+        let now = Instant::now();
+        match *retry_at {
+            Some(at) if at > now => Some(at - now),
+            _ => {
+                *retry_at = None;
+                None
+            }
+        }
+    }
+}
+
+/// Exponential backoff with full jitter between failed reconnect attempts
+pub struct ReconnectBackoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    state: Mutex<ReconnectState>,
+}
+
+#[derive(Default)]
+struct ReconnectState {
+    /// Consecutive failed attempts
+    failures: u32,
+    /// Earliest time the next attempt may be made
+    retry_at: Option<Instant>,
+}
+
+impl ReconnectBackoff {
+    /// Create a backoff from the `[server.reconnect]` settings
+    pub fn new(config: &ReconnectConfig) -> Self {
+        Self {
+            initial: Duration::from_millis(config.initial_ms),
+            max: Duration::from_millis(config.max_ms),
+            multiplier: config.multiplier,
+            state: Mutex::new(ReconnectState::default()),
+        }
+    }
+
+    /// Record a failed attempt and return how long to wait before the next
+    pub fn record_failure(&self) -> Duration {
+        let mut state = self.state.lock();
+        let delay = jitter(self.ceiling(state.failures));
+        state.failures = state.failures.saturating_add(1);
+        state.retry_at = Some(Instant::now() + delay);
+        delay
+    }
+
+    /// Record a successful attempt, resetting the delay
+    pub fn record_success(&self) {
+        *self.state.lock() = ReconnectState::default();
+    }
+
+    /// Number of consecutive failed attempts
+    pub fn failures(&self) -> u32 {
+        self.state.lock().failures
+    }
+
+    /// Time left before the next attempt is allowed, if any
+    pub fn remaining(&self) -> Option<Duration> {
+        let state = self.state.lock();
+        let now = Instant::now();
+        state.retry_at.filter(|&at| at > now).map(|at| at - now)
+    }
+
+    /// Upper bound on the delay after `failures` consecutive failures
+    ///
+    /// Clamped in f64, so a product too large for `Duration` yields `max`.
+    fn ceiling(&self, failures: u32) -> Duration {
+        let factor = self.multiplier.powi(failures.min(64) as i32);
+        Duration::try_from_secs_f64(self.initial.as_secs_f64() * factor)
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+/// Pick a uniformly random duration in `[0, ceiling]`
+fn jitter(ceiling: Duration) -> Duration {
+    let mut bytes = [0u8; 8];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return ceiling;
+    }
+    ceiling.mul_f64(u64::from_le_bytes(bytes) as f64 / u64::MAX as f64)
+}
+
+/// Check whether the server closed the connection because it is full
+pub fn is_capacity_close(reason: &ConnectionError) -> bool {
+    matches!(
+        reason,
+        ConnectionError::ApplicationClosed(close)
+            if close.error_code == CloseCode::Capacity.code()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use quinn::{ApplicationClose, VarInt};
+
+    fn app_close(code: u32) -> ConnectionError {
+        ConnectionError::ApplicationClosed(ApplicationClose {
+            error_code: VarInt::from_u32(code),
+            reason: Bytes::from_static(b""),
+        })
+    }
+
+    #[test]
+    fn test_capacity_close_sets_backoff() {
+        let backoff = CapacityBackoff::new(Duration::from_secs(30));
+        assert!(backoff.remaining().is_none());
+
+        assert!(backoff.observe(&app_close(CloseCode::Capacity as u32)));
+        let remaining = backoff.remaining().unwrap();
+        assert!(remaining > Duration::from_secs(29));
+    }
+
+    #[test]
+    fn test_other_close_does_not_back_off() {
+        let backoff = CapacityBackoff::new(Duration::from_secs(30));
+        assert!(!backoff.observe(&app_close(0)));
+        assert!(!backoff.observe(&ConnectionError::TimedOut));
+        assert!(backoff.remaining().is_none());
+    }
+
+    fn reconnect_backoff() -> ReconnectBackoff {
+        ReconnectBackoff::new(&ReconnectConfig {
+            initial_ms: 100,
+            max_ms: 1000,
+            multiplier: 2.0,
+        })
+    }
+
+    #[test]
+    fn test_reconnect_delay_grows_to_max() {
+        let backoff = reconnect_backoff();
+        let ceilings: Vec<_> = (0..6).map(|n| backoff.ceiling(n).as_millis()).collect();
+        assert_eq!(ceilings, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(backoff.ceiling(u32::MAX), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_reconnect_ceiling_overflow() {
+        let backoff = ReconnectBackoff::new(&ReconnectConfig {
+            initial_ms: u64::MAX,
+            max_ms: u64::MAX,
+            multiplier: 1000.0,
+        });
+        assert_eq!(backoff.ceiling(u32::MAX), Duration::from_millis(u64::MAX));
+        assert!(backoff.record_failure() <= Duration::from_millis(u64::MAX));
+    }
+
+    #[test]
+    fn test_reconnect_jitter_within_ceiling() {
+        let backoff = reconnect_backoff();
+        for n in 0..20 {
+            let delay = backoff.record_failure();
+            assert!(delay <= backoff.ceiling(n));
+        }
+        assert_eq!(backoff.failures(), 20);
+    }
+
+    #[test]
+    fn test_reconnect_success_resets() {
+        let backoff = reconnect_backoff();
+        backoff.record_failure();
+        backoff.record_failure();
+        assert_eq!(backoff.failures(), 2);
+
+        backoff.record_success();
+        assert_eq!(backoff.failures(), 0);
+        assert!(backoff.remaining().is_none());
+    }
+
+    #[test]
+    fn test_backoff_expires() {
+        let backoff = CapacityBackoff::new(Duration::ZERO);
+        backoff.observe(&app_close(CloseCode::Capacity as u32));
+        assert!(backoff.remaining().is_none());
+    }
+}
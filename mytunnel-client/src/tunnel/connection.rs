@@ -5,22 +5,90 @@
 use anyhow::{Context, Result};
 use bytes::Bytes;
 use parking_lot::RwLock;
-use quinn::{Connection, Endpoint, RecvStream, SendStream};
-use rustls::pki_types::ServerName;
-use std::net::SocketAddr;
+use quinn::{Connecting, Connection, Endpoint, RecvStream, SendStream};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{Ipv6Addr, SocketAddr, UdpSocket};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::broadcast;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinSet;
 use tracing::{debug, error, info, warn};
 
+use crate::close_code::describe_close;
 use crate::config::Config;
-use crate::proxy::{HttpProxy, Socks5Proxy};
+use crate::coalesce::Coalesce;
+use crate::protocol::{self, UdpPacket};
+use crate::proxy::{ForwardProxy, HttpProxy, Socks5Proxy};
+
+use super::backoff::{CapacityBackoff, ReconnectBackoff};
+use super::datagram::{FlowRoutes, TunnelUdpSocket};
+use super::failover::ServerSelector;
+use super::pool::{ConnectionPool, StreamLease};
+use super::reverse::ReverseForwarder;
+use super::stream::{establish_tcp_tunnel, TunnelStream};
+
+/// How often the monitor checks the connection while it is healthy
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tunnel connection health as seen by the local proxies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// A live connection to the server is available
+    Connected,
+    /// The connection is gone and the next request will try to reconnect
+    Reconnecting,
+    /// Reconnects are held off by backoff; requests fail immediately
+    Down,
+}
+
+/// How long a probe waits after its first handshake for the server's
+/// session tickets, at least, before trying to resume
+const RESUME_TICKET_WAIT: Duration = Duration::from_millis(50);
+
+/// Outcome of probing one resolved server address
+#[derive(Debug, Clone)]
+pub struct AddressProbe {
+    /// Server address as configured
+    pub server: String,
+    /// Resolved address; None if the name didn't resolve
+    pub addr: Option<SocketAddr>,
+    /// What the handshake negotiated, or why the connect failed
+    pub result: Result<HandshakeInfo, String>,
+}
+
+/// What a successful probe learned about the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeInfo {
+    /// Time from starting the connect until the connection was ready
+    pub handshake: Duration,
+    /// Negotiated ALPN protocol
+    pub alpn: Option<String>,
+    /// Subject of the server's leaf certificate
+    pub server_subject: Option<String>,
+    /// Whether a second connection resumed the session with 0-RTT; None
+    /// when 0-RTT is disabled
+    pub resumed: Option<bool>,
+}
+
+impl AddressProbe {
+    /// Whether the address accepted the connection
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+}
 
 /// Tunnel client that manages the QUIC connection and local proxies
 pub struct TunnelClient {
     config: Arc<Config>,
     endpoint: Endpoint,
-    connection: Arc<RwLock<Option<Connection>>>,
+    pool: Arc<ConnectionPool>,
+    backoff: Arc<CapacityBackoff>,
+    reconnect: Arc<ReconnectBackoff>,
+    servers: Arc<ServerSelector>,
+    /// UDP flow ids and their owners, shared by every handle so flows
+    /// never collide
+    flows: Arc<FlowRoutes>,
     shutdown_tx: broadcast::Sender<()>,
 }
 
@@ -30,91 +98,118 @@ impl TunnelClient {
         let endpoint = create_client_endpoint(&config)?;
 
         let (shutdown_tx, _) = broadcast::channel(1);
+        let backoff = Arc::new(CapacityBackoff::new(Duration::from_secs(
+            config.server.capacity_backoff_secs,
+        )));
+        let reconnect = Arc::new(ReconnectBackoff::new(&config.server.reconnect));
+        let servers = Arc::new(server_selector(&config));
+        let pool = Arc::new(ConnectionPool::new(config.server.connection_pool_size));
 
         Ok(Self {
             config,
             endpoint,
-            connection: Arc::new(RwLock::new(None)),
+            pool,
+            backoff,
+            reconnect,
+            servers,
+            flows: Arc::new(FlowRoutes::default()),
             shutdown_tx,
         })
     }
 
+    /// Current health of the tunnel connection
+    pub fn state(&self) -> ConnectionState {
+        connection_state(&self.pool, &self.backoff, &self.reconnect)
+    }
+
     /// Test connection to the server
-    pub async fn test_connection(config: Arc<Config>) -> Result<()> {
+    ///
+    /// Resolves every configured server and connects to all of their
+    /// addresses at once, so a broken primary isn't masked by a working
+    /// fallback. Returns one probe per address, in configuration and
+    /// resolution order; the test passed if any of them is ok. With 0-RTT
+    /// enabled each address that answers is connected to a second time to
+    /// check that the session resumes. Connections are closed right away.
+    pub async fn test_connection(config: Arc<Config>) -> Result<Vec<AddressProbe>> {
         let endpoint = create_client_endpoint(&config)?;
 
-        // Resolve server address
-        let server_addr = resolve_address(&config.server.address).await?;
-        let server_name = config.server.get_server_name().to_string();
-
-        info!(addr = %server_addr, name = %server_name, "Connecting to server");
-
-        // Connect to server
-        let connection = endpoint
-            .connect(server_addr, &server_name)?
-            .await
-            .context("Failed to establish QUIC connection")?;
-
-        info!(
-            "Connected! Remote address: {}, Protocol: {:?}",
-            connection.remote_address(),
-            connection.handshake_data()
-                .and_then(|h| h.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
-                .and_then(|h| h.protocol.map(|p| String::from_utf8_lossy(&p).to_string()))
-        );
+        let mut probes = Vec::new();
+        for server in &config.server.addresses {
+            match resolve_addresses(server).await {
+                Ok(addrs) => probes.extend(addrs.into_iter().map(|addr| AddressProbe {
+                    server: server.clone(),
+                    addr: Some(addr),
+                    result: Err("not attempted".to_string()),
+                })),
+                Err(e) => probes.push(AddressProbe {
+                    server: server.clone(),
+                    addr: None,
+                    result: Err(format!("{:#}", e)),
+                }),
+            }
+        }
 
-        // Close connection gracefully
-        connection.close(quinn::VarInt::from_u32(0), b"test complete");
+        let mut attempts = JoinSet::new();
+        for (index, probe) in probes.iter().enumerate() {
+            let Some(addr) = probe.addr else { continue };
+            let endpoint = endpoint.clone();
+            let config = config.clone();
+            let server = probe.server.clone();
+            attempts.spawn(async move {
+                let result = probe_addr(&endpoint, &config, &server, addr).await;
+                (index, result.map_err(|e| format!("{:#}", e)))
+            });
+        }
+        while let Some(joined) = attempts.join_next().await {
+            let (index, result) = joined.context("Connection test task failed")?;
+            probes[index].result = result;
+        }
 
-        Ok(())
+        Ok(probes)
     }
 
-    /// Connect to the server
+    /// Connect to the first server that answers
     async fn connect(&self) -> Result<Connection> {
-        let server_addr = resolve_address(&self.config.server.address).await?;
-        let server_name = self.config.server.get_server_name().to_string();
+        let (connection, address) =
+            connect_any(&self.endpoint, &self.config, &self.servers).await?;
 
-        debug!(addr = %server_addr, name = %server_name, "Connecting to server");
-
-        let connection = self
-            .endpoint
-            .connect(server_addr, &server_name)?
-            .await
-            .context("Failed to establish QUIC connection")?;
-
-        info!(addr = %connection.remote_address(), "Connected to server");
+        info!(server = %address, addr = %connection.remote_address(), "Connected to server");
 
         Ok(connection)
     }
 
-    /// Get or establish connection
+    /// Get or establish the primary connection
     pub async fn get_connection(&self) -> Result<Connection> {
-        // Check existing connection
-        {
-            let conn = self.connection.read();
-            if let Some(ref c) = *conn {
-                if !c.close_reason().is_some() {
-                    return Ok(c.clone());
-                }
-            }
+        if let Some(c) = live_connection(&self.pool.primary().connection, &self.backoff)? {
+            return Ok(c);
         }
 
         // Need to establish new connection
-        let new_conn = self.connect().await?;
-
-        {
-            let mut conn = self.connection.write();
-            *conn = Some(new_conn.clone());
-        }
-
-        Ok(new_conn)
+        let new_conn = reconnect_with_backoff(
+            &self.endpoint,
+            &self.config,
+            &self.servers,
+            &self.reconnect,
+        )
+        .await?;
+
+        Ok(self.pool.install(0, new_conn))
     }
 
     /// Open a bidirectional stream for TCP tunneling
-    pub async fn open_stream(&self) -> Result<(SendStream, RecvStream)> {
-        let conn = self.get_connection().await?;
-        let (send, recv) = conn.open_bi().await.context("Failed to open stream")?;
-        Ok((send, recv))
+    ///
+    /// The stream goes to the least-loaded pooled connection; keep the
+    /// returned lease alive for as long as the stream is in use.
+    pub async fn open_stream(&self) -> Result<(SendStream, RecvStream, StreamLease)> {
+        open_pooled_stream(
+            &self.pool,
+            &self.backoff,
+            &self.endpoint,
+            &self.config,
+            &self.servers,
+            &self.reconnect,
+        )
+        .await
     }
 
     /// Send a datagram for UDP relay
@@ -135,27 +230,36 @@ impl TunnelClient {
         Ok(data)
     }
 
+    /// Shared handle for proxies to open streams through this client
+    pub fn handle(&self) -> Arc<TunnelClientHandle> {
+        Arc::new(TunnelClientHandle {
+            pool: self.pool.clone(),
+            backoff: self.backoff.clone(),
+            reconnect: self.reconnect.clone(),
+            servers: self.servers.clone(),
+            config: self.config.clone(),
+            endpoint: self.endpoint.clone(),
+            flows: self.flows.clone(),
+        })
+    }
+
     /// Run the tunnel client with local proxy servers
     pub async fn run(&self) -> Result<()> {
         // Establish initial connection
         let conn = self.connect().await?;
-        {
-            let mut c = self.connection.write();
-            *c = Some(conn);
-        }
+        self.pool.install(0, conn);
 
         // Create shared client reference for proxies
-        let client = Arc::new(TunnelClientHandle {
-            connection: self.connection.clone(),
-            config: self.config.clone(),
-            endpoint: self.endpoint.clone(),
-        });
+        let client = self.handle();
 
         let mut handles = Vec::new();
 
         // Start SOCKS5 proxy if enabled
         if self.config.proxy.socks5_enabled {
-            let socks5 = Socks5Proxy::new(client.clone(), self.config.proxy.socks5_bind);
+            let socks5 = Socks5Proxy::new(client.clone(), self.config.proxy.socks5_bind)
+                .with_auth(self.config.proxy.socks5_auth.clone())
+                .with_udp_reassembly(self.config.proxy.socks5_udp_reassembly)
+                .with_bind(self.config.proxy.socks5_allow_bind);
             let mut shutdown_rx = self.shutdown_tx.subscribe();
 
             handles.push(tokio::spawn(async move {
@@ -176,7 +280,8 @@ impl TunnelClient {
 
         // Start HTTP proxy if enabled
         if self.config.proxy.http_enabled {
-            let http = HttpProxy::new(client.clone(), self.config.proxy.http_bind);
+            let http = HttpProxy::new(client.clone(), self.config.proxy.http_bind)
+                .with_auth(self.config.proxy.http_auth.clone());
             let mut shutdown_rx = self.shutdown_tx.subscribe();
 
             handles.push(tokio::spawn(async move {
@@ -195,35 +300,91 @@ impl TunnelClient {
             info!(bind = %self.config.proxy.http_bind, "HTTP proxy started");
         }
 
-        // Monitor connection health
-        let connection = self.connection.clone();
+        // Start static port forwards, if any
+        if !self.config.proxy.forwards.is_empty() {
+            let forwards = ForwardProxy::new(client.clone(), self.config.proxy.forwards.clone());
+            let mut shutdown_rx = self.shutdown_tx.subscribe();
+
+            handles.push(tokio::spawn(async move {
+                tokio::select! {
+                    result = forwards.run() => {
+                        if let Err(e) = result {
+                            error!(error = %e, "Port forward error");
+                        }
+                    }
+                    _ = shutdown_rx.recv() => {
+                        debug!("Port forwards shutting down");
+                    }
+                }
+            }));
+
+            info!(count = self.config.proxy.forwards.len(), "Port forwards started");
+        }
+
+        // Bind reverse forwards on the server, if any
+        if !self.config.proxy.reverse_forwards.is_empty() {
+            let reverse = ReverseForwarder::new(
+                client.clone(),
+                self.config.proxy.reverse_forwards.clone(),
+            );
+            let mut shutdown_rx = self.shutdown_tx.subscribe();
+
+            handles.push(tokio::spawn(async move {
+                tokio::select! {
+                    result = reverse.run() => {
+                        if let Err(e) = result {
+                            error!(error = %e, "Reverse forward error");
+                        }
+                    }
+                    _ = shutdown_rx.recv() => {
+                        debug!("Reverse forwards shutting down");
+                    }
+                }
+            }));
+
+            info!(count = self.config.proxy.reverse_forwards.len(), "Reverse forwards started");
+        }
+
+        // Monitor the primary connection; other pool slots refill on demand
+        let pool = self.pool.clone();
+        let backoff = self.backoff.clone();
+        let reconnect = self.reconnect.clone();
+        let servers = self.servers.clone();
         let config = self.config.clone();
         let endpoint = self.endpoint.clone();
         let mut shutdown_rx = self.shutdown_tx.subscribe();
 
         handles.push(tokio::spawn(async move {
             loop {
+                // Wake up when the reconnect backoff expires, if one is running
+                let wait = reconnect.remaining().unwrap_or(HEALTH_CHECK_INTERVAL);
+
                 tokio::select! {
-                    _ = tokio::time::sleep(Duration::from_secs(5)) => {
+                    _ = tokio::time::sleep(wait) => {
                         // Check connection health
-                        let needs_reconnect = {
-                            let conn = connection.read();
-                            match &*conn {
-                                Some(c) => c.close_reason().is_some(),
-                                None => true,
+                        let primary = &pool.primary().connection;
+                        let needs_reconnect = match live_connection(primary, &backoff) {
+                            Ok(Some(_)) => false,
+                            Ok(None) => true,
+                            Err(e) => {
+                                debug!(error = %e, "Holding off reconnect");
+                                false
                             }
                         };
 
                         if needs_reconnect {
-                            warn!("Connection lost, attempting reconnect");
-                            match reconnect(&endpoint, &config).await {
+                            if reconnect.failures() == 0 {
+                                warn!("Connection lost, attempting reconnect");
+                            }
+                            let result =
+                                reconnect_with_backoff(&endpoint, &config, &servers, &reconnect)
+                                    .await;
+                            match result {
                                 Ok(new_conn) => {
-                                    let mut conn = connection.write();
-                                    *conn = Some(new_conn);
-                                    info!("Reconnected to server");
+                                    pool.install(0, new_conn);
                                 }
                                 Err(e) => {
-                                    error!(error = %e, "Reconnection failed");
+                                    debug!(error = %e, "Reconnect attempt failed");
                                 }
                             }
                         }
@@ -248,26 +409,104 @@ impl TunnelClient {
     pub async fn shutdown(&self) {
         let _ = self.shutdown_tx.send(());
 
-        // Close connection
-        if let Some(conn) = self.connection.write().take() {
-            conn.close(quinn::VarInt::from_u32(0), b"client shutdown");
-        }
+        // Close connections
+        self.pool.close_all(b"client shutdown");
     }
 }
 
 /// Shared handle for proxy servers to access the tunnel
 pub struct TunnelClientHandle {
-    connection: Arc<RwLock<Option<Connection>>>,
+    pool: Arc<ConnectionPool>,
+    backoff: Arc<CapacityBackoff>,
+    reconnect: Arc<ReconnectBackoff>,
+    servers: Arc<ServerSelector>,
     config: Arc<Config>,
     endpoint: Endpoint,
+    flows: Arc<FlowRoutes>,
 }
 
 impl TunnelClientHandle {
-    /// Open a bidirectional stream
-    pub async fn open_stream(&self) -> Result<(SendStream, RecvStream)> {
-        let conn = self.get_connection().await?;
-        let (send, recv) = conn.open_bi().await.context("Failed to open stream")?;
-        Ok((send, recv))
+    /// Current health of the tunnel connection
+    ///
+    /// Proxies can check for [`ConnectionState::Down`] to reject new
+    /// requests without waiting on a reconnect.
+    pub fn state(&self) -> ConnectionState {
+        connection_state(&self.pool, &self.backoff, &self.reconnect)
+    }
+
+    /// Write batching for proxied connections, from `[proxy] coalesce_bytes`
+    pub fn coalesce(&self) -> Option<Coalesce> {
+        let proxy = &self.config.proxy;
+        (proxy.coalesce_bytes > 0).then(|| Coalesce {
+            bytes: proxy.coalesce_bytes,
+            delay: Duration::from_micros(proxy.coalesce_delay_us),
+        })
+    }
+
+    /// Open a bidirectional stream on the least-loaded pooled connection
+    ///
+    /// Keep the returned lease alive for as long as the stream is in use.
+    pub async fn open_stream(&self) -> Result<(SendStream, RecvStream, StreamLease)> {
+        open_pooled_stream(
+            &self.pool,
+            &self.backoff,
+            &self.endpoint,
+            &self.config,
+            &self.servers,
+            &self.reconnect,
+        )
+        .await
+    }
+
+    /// Open a TCP connection to `host:port` through the tunnel
+    ///
+    /// A refusal from the server fails with [`TunnelRejected`](crate::protocol::TunnelRejected).
+    pub async fn connect_tcp(&self, host: &str, port: u16) -> Result<TunnelStream> {
+        let (send, recv, lease) = self.open_stream().await?;
+        let (send, recv) = establish_tcp_tunnel(send, recv, host, port).await?;
+        Ok(TunnelStream::new(send, recv, lease))
+    }
+
+    /// Open a UDP flow to `host:port` through the tunnel
+    pub fn connect_udp(self: &Arc<Self>, host: &str, port: u16) -> Result<TunnelUdpSocket> {
+        TunnelUdpSocket::new(self.clone(), host, port)
+    }
+
+    /// Allocate an id for a new UDP flow
+    ///
+    /// Ids are unique across the client until the counter wraps.
+    pub fn next_flow_id(&self) -> u32 {
+        self.flows.next_id()
+    }
+
+    /// Send responses on flow `flow_id` to `tx`
+    pub(crate) fn route_flow(&self, flow_id: u32, tx: mpsc::Sender<UdpPacket>) {
+        self.flows.add(flow_id, tx);
+    }
+
+    /// Stop routing responses on flow `flow_id`
+    pub(crate) fn unroute_flow(&self, flow_id: u32) {
+        self.flows.remove(flow_id);
+    }
+
+    /// Wait for a response routed to `rx`
+    ///
+    /// Reads tunnel datagrams meanwhile and routes each to the owner of its
+    /// flow, so any number of owners can wait at once.
+    pub(crate) async fn recv_routed(
+        &self,
+        rx: &mut mpsc::Receiver<UdpPacket>,
+    ) -> Result<UdpPacket> {
+        loop {
+            tokio::select! {
+                biased;
+                packet = rx.recv() => return packet.context("UDP flow closed"),
+                data = self.recv_datagram() => match protocol::decode_udp_packet(data?) {
+                    Ok(packet) => self.flows.deliver(packet),
+                    Err(e) => debug!(error = %e, "Failed to decode UDP response"),
+                },
+            }
+        }
     }
 
     /// Send a datagram
@@ -279,6 +518,9 @@ impl TunnelClientHandle {
     }
 
     /// Receive a datagram
+    ///
+    /// This bypasses flow routing: prefer [`connect_udp`](Self::connect_udp)
+    /// when other UDP flows may share the tunnel.
     pub async fn recv_datagram(&self) -> Result<Bytes> {
         let conn = self.get_connection().await?;
         let data = conn
@@ -288,27 +530,172 @@ impl TunnelClientHandle {
         Ok(data)
     }
 
-    /// Get the current connection
-    async fn get_connection(&self) -> Result<Connection> {
-        // Check existing connection
-        {
-            let conn = self.connection.read();
-            if let Some(ref c) = *conn {
-                if !c.close_reason().is_some() {
-                    return Ok(c.clone());
-                }
-            }
+    /// Get the primary connection, used for datagrams and reverse forwards
+    pub(crate) async fn get_connection(&self) -> Result<Connection> {
+        if let Some(c) = live_connection(&self.pool.primary().connection, &self.backoff)? {
+            return Ok(c);
         }
 
         // Need to reconnect
-        let new_conn = reconnect(&self.endpoint, &self.config).await?;
+        let new_conn = reconnect_with_backoff(
+            &self.endpoint,
+            &self.config,
+            &self.servers,
+            &self.reconnect,
+        )
+        .await?;
+
+        Ok(self.pool.install(0, new_conn))
+    }
+}
+
+/// Return the current connection if it is still open
+///
+/// A closed connection is cleared from the slot and its close reason fed
+/// to the backoff. Fails while a capacity backoff is in effect so callers
+/// don't hammer a full server.
+fn live_connection(
+    connection: &RwLock<Option<Connection>>,
+    backoff: &CapacityBackoff,
+) -> Result<Option<Connection>> {
+    {
+        let conn = connection.read();
+        if let Some(c) = conn.as_ref() {
+            if c.close_reason().is_none() {
+                return Ok(Some(c.clone()));
+            }
+        }
+    }
+
+    // Re-check under the write lock in case another task already reconnected
+    let reason = {
+        let mut conn = connection.write();
+        match conn.as_ref().map(|c| c.close_reason()) {
+            Some(None) => return Ok(conn.clone()),
+            Some(Some(reason)) => {
+                *conn = None;
+                warn!(cause = %describe_close(&reason), "Tunnel connection closed");
+                Some(reason)
+            }
+            None => None,
+        }
+    };
+
+    if let Some(reason) = reason {
+        if backoff.observe(&reason) {
+            warn!(
+                delay_secs = backoff.remaining().unwrap_or_default().as_secs(),
+                "Server at capacity, backing off before reconnecting"
+            );
+        }
+    }
+
+    if let Some(remaining) = backoff.remaining() {
+        anyhow::bail!("Server at capacity, retrying in {}s", remaining.as_secs() + 1);
+    }
+
+    Ok(None)
+}
+
+/// Derive the tunnel state from the connection pool and both backoffs
+fn connection_state(
+    pool: &ConnectionPool,
+    capacity: &CapacityBackoff,
+    reconnect: &ReconnectBackoff,
+) -> ConnectionState {
+    if pool.is_connected() {
+        ConnectionState::Connected
+    } else if capacity.remaining().is_some() || reconnect.remaining().is_some() {
+        ConnectionState::Down
+    } else {
+        ConnectionState::Reconnecting
+    }
+}
 
-        {
-            let mut conn = self.connection.write();
-            *conn = Some(new_conn.clone());
+/// Open a stream on the least-loaded live connection in the pool
+///
+/// A new connection is opened in an empty or dead slot when every live
+/// connection already carries streams. If that fails, the stream falls
+/// back to the least-loaded live connection.
+async fn open_pooled_stream(
+    pool: &ConnectionPool,
+    backoff: &CapacityBackoff,
+    endpoint: &Endpoint,
+    config: &Config,
+    servers: &ServerSelector,
+    reconnect: &ReconnectBackoff,
+) -> Result<(SendStream, RecvStream, StreamLease)> {
+    let mut least_loaded: Option<(usize, Connection, usize)> = None;
+    let mut empty = None;
+    let mut held_off = None;
+
+    for (index, slot) in pool.slots().iter().enumerate() {
+        match live_connection(&slot.connection, backoff) {
+            Ok(Some(conn)) => {
+                let streams = slot.streams();
+                if least_loaded.as_ref().map_or(true, |(_, _, min)| streams < *min) {
+                    least_loaded = Some((index, conn, streams));
+                }
+            }
+            Ok(None) => {
+                empty.get_or_insert(index);
+            }
+            Err(e) => held_off = Some(e),
+        }
+    }
+
+    let (index, conn) = match (least_loaded, empty) {
+        (Some((index, conn, 0)), _) | (Some((index, conn, _)), None) => (index, conn),
+        (least_loaded, Some(empty)) => {
+            match reconnect_with_backoff(endpoint, config, servers, reconnect).await {
+                Ok(new_conn) => {
+                    debug!(slot = empty, "Opened pooled connection");
+                    (empty, pool.install(empty, new_conn))
+                }
+                Err(e) => match least_loaded {
+                    Some((index, conn, _)) => (index, conn),
+                    None => return Err(e),
+                },
+            }
         }
+        (None, None) => {
+            return Err(held_off.unwrap_or_else(|| anyhow::anyhow!("No connection available")))
+        }
+    };
+
+    let lease = pool.slots()[index].lease();
+    let (send, recv) = conn.open_bi().await.context("Failed to open stream")?;
+    Ok((send, recv, lease))
+}
 
-        Ok(new_conn)
+/// Reconnect unless a previous failure's backoff is still running
+///
+/// Failures extend the backoff; a success resets it.
+async fn reconnect_with_backoff(
+    endpoint: &Endpoint,
+    config: &Config,
+    servers: &ServerSelector,
+    backoff: &ReconnectBackoff,
+) -> Result<Connection> {
+    if let Some(remaining) = backoff.remaining() {
+        anyhow::bail!("Server unreachable, retrying in {}ms", remaining.as_millis());
+    }
+
+    match reconnect(endpoint, config, servers).await {
+        Ok(connection) => {
+            backoff.record_success();
+            Ok(connection)
+        }
+        Err(e) => {
+            let delay = backoff.record_failure();
+            warn!(
+                error = %e,
+                failures = backoff.failures(),
+                delay_ms = delay.as_millis() as u64,
+                "Reconnect failed, backing off"
+            );
+            Err(e)
+        }
     }
 }
 
@@ -324,18 +711,25 @@ fn create_client_endpoint(config: &Config) -> Result<Endpoint> {
         root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
     }
 
-    let mut tls_config = if config.server.insecure {
+    crate::init_crypto();
+    let builder = if config.server.insecure {
         rustls::ClientConfig::builder()
             .dangerous()
             .with_custom_certificate_verifier(Arc::new(InsecureServerVerifier))
-            .with_no_client_auth()
     } else {
-        rustls::ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth()
+        rustls::ClientConfig::builder().with_root_certificates(root_store)
+    };
+
+    let mut tls_config = match load_client_identity(config)? {
+        Some((certs, key)) => builder
+            .with_client_auth_cert(certs, key)
+            .context("Invalid client certificate")?,
+        None => builder.with_no_client_auth(),
     };
 
     tls_config.alpn_protocols = vec![b"mytunnel".to_vec()];
+    // Resumed sessions may send the auth frame as early data
+    tls_config.enable_early_data = config.quic.enable_0rtt;
 
     // Configure QUIC
     let mut transport = quinn::TransportConfig::default();
@@ -352,17 +746,74 @@ fn create_client_endpoint(config: &Config) -> Result<Endpoint> {
     client_config.transport_config(Arc::new(transport));
 
     // Create endpoint
-    let mut endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
+    let mut endpoint = Endpoint::new(
+        quinn::EndpointConfig::default(),
+        None,
+        bind_client_socket()?,
+        Arc::new(quinn::TokioRuntime),
+    )?;
     endpoint.set_default_client_config(client_config);
 
     Ok(endpoint)
 }
 
+/// Bind a dual-stack UDP socket on `[::]:0`, so one endpoint reaches
+/// servers of either address family
+///
+/// Falls back to an IPv4-only socket on hosts without IPv6.
+fn bind_client_socket() -> Result<UdpSocket> {
+    let dual_stack = || -> std::io::Result<UdpSocket> {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(false)?;
+        socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)).into())?;
+        Ok(socket.into())
+    };
+
+    match dual_stack() {
+        Ok(socket) => Ok(socket),
+        Err(e) => {
+            debug!(error = %e, "IPv6 unavailable, binding an IPv4-only socket");
+            UdpSocket::bind("0.0.0.0:0").context("Failed to bind client socket")
+        }
+    }
+}
+
+/// Load the client certificate chain and key for mutual TLS, if configured
+fn load_client_identity(
+    config: &Config,
+) -> Result<Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>> {
+    let (Some(cert_path), Some(key_path)) = (
+        config.server.client_cert_path.as_deref(),
+        config.server.client_key_path.as_deref(),
+    ) else {
+        return Ok(None);
+    };
+
+    let cert_pem = std::fs::read(cert_path)
+        .with_context(|| format!("Failed to read client certificate: {}", cert_path))?;
+    let key_pem = std::fs::read(key_path)
+        .with_context(|| format!("Failed to read client key: {}", key_path))?;
+
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to parse client certificate")?;
+    let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+        .context("Failed to parse client key")?
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {}", key_path))?;
+
+    Ok(Some((certs, key)))
+}
+
 /// Resolve server address
 async fn resolve_address(address: &str) -> Result<SocketAddr> {
+    Ok(resolve_addresses(address).await?[0])
+}
+
+/// Resolve a server address to every address it names, never empty
+async fn resolve_addresses(address: &str) -> Result<Vec<SocketAddr>> {
     // Try parsing as socket address first
     if let Ok(addr) = address.parse::<SocketAddr>() {
-        return Ok(addr);
+        return Ok(vec![addr]);
     }
 
     // DNS resolution
@@ -371,25 +822,210 @@ async fn resolve_address(address: &str) -> Result<SocketAddr> {
         .with_context(|| format!("Failed to resolve {}", address))?
         .collect();
 
-    addrs
-        .into_iter()
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("No addresses found for {}", address))
+    if addrs.is_empty() {
+        anyhow::bail!("No addresses found for {}", address);
+    }
+    Ok(addrs)
 }
 
-/// Reconnect to server
-async fn reconnect(endpoint: &Endpoint, config: &Config) -> Result<Connection> {
-    let server_addr = resolve_address(&config.server.address).await?;
-    let server_name = config.server.get_server_name().to_string();
-
-    let connection = endpoint
-        .connect(server_addr, &server_name)?
-        .await
-        .context("Failed to reconnect")?;
+/// Build the server selector for the configured addresses
+fn server_selector(config: &Config) -> ServerSelector {
+    ServerSelector::new(
+        config.server.addresses.clone(),
+        config.server.failover_strategy,
+    )
+}
 
+/// Connect and authenticate to a single server address
+async fn connect_to(endpoint: &Endpoint, config: &Config, address: &str) -> Result<Connection> {
+    let server_addr = resolve_address(address).await?;
+    let (connection, _) = connect_addr(endpoint, config, address, server_addr).await?;
     Ok(connection)
 }
 
+/// Connect and authenticate to `server_addr`, one of the addresses the
+/// configured `address` resolved to
+///
+/// Also returns whether the session was resumed with 0-RTT.
+async fn connect_addr(
+    endpoint: &Endpoint,
+    config: &Config,
+    address: &str,
+    server_addr: SocketAddr,
+) -> Result<(Connection, bool)> {
+    let server_name = config.server.server_name_for(address).to_string();
+
+    debug!(addr = %server_addr, name = %server_name, "Connecting to server");
+
+    let connecting = endpoint.connect(server_addr, &server_name)?;
+    if config.quic.enable_0rtt {
+        let (connection, early) = connect_early(connecting, config)
+            .await
+            .with_context(|| format!("Failed to establish QUIC connection to {}", address))?;
+        if early {
+            debug!(addr = %server_addr, "Resumed session with 0-RTT");
+        }
+        Ok((connection, early))
+    } else {
+        let connection = connecting
+            .await
+            .with_context(|| format!("Failed to establish QUIC connection to {}", address))?;
+        authenticate(&connection, config).await?;
+        Ok((connection, false))
+    }
+}
+
+/// Connect to `server_addr` for `test_connection` and describe the handshake
+async fn probe_addr(
+    endpoint: &Endpoint,
+    config: &Config,
+    address: &str,
+    server_addr: SocketAddr,
+) -> Result<HandshakeInfo> {
+    let started = Instant::now();
+    let (connection, _) = connect_addr(endpoint, config, address, server_addr).await?;
+    let handshake = started.elapsed();
+
+    let alpn = connection
+        .handshake_data()
+        .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+        .and_then(|data| data.protocol)
+        .map(|protocol| String::from_utf8_lossy(&protocol).into_owned());
+    let server_subject = connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .and_then(|chain| certificate_subject(chain.first()?));
+
+    let resumed = if config.quic.enable_0rtt {
+        // Session tickets follow the handshake; give them a moment to land
+        tokio::time::sleep(handshake.max(RESUME_TICKET_WAIT)).await;
+        connection.close(quinn::VarInt::from_u32(0), b"test complete");
+        let resumed = connect_addr(endpoint, config, address, server_addr).await;
+        Some(match resumed {
+            Ok((connection, early)) => {
+                connection.close(quinn::VarInt::from_u32(0), b"test complete");
+                early
+            }
+            Err(_) => false,
+        })
+    } else {
+        connection.close(quinn::VarInt::from_u32(0), b"test complete");
+        None
+    };
+
+    Ok(HandshakeInfo {
+        handshake,
+        alpn,
+        server_subject,
+        resumed,
+    })
+}
+
+/// Distinguished name of a DER certificate's subject
+fn certificate_subject(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    Some(cert.subject().to_string())
+}
+
+/// Complete a handshake, sending the auth frame as 0-RTT early data when
+/// the session can be resumed
+///
+/// Early data can be replayed, so only the idempotent auth frame is sent
+/// before the handshake completes; tunnel requests wait for it. Returns
+/// whether the server accepted the early data. If it didn't, the auth
+/// frame is sent again.
+async fn connect_early(connecting: Connecting, config: &Config) -> Result<(Connection, bool)> {
+    let (connection, accepted) = match connecting.into_0rtt() {
+        Ok(early) => early,
+        Err(connecting) => {
+            let connection = connecting.await?;
+            authenticate(&connection, config).await?;
+            return Ok((connection, false));
+        }
+    };
+
+    let early_auth = authenticate(&connection, config).await;
+    let accepted = accepted.await;
+    if !accepted || early_auth.is_err() {
+        authenticate(&connection, config).await?;
+    }
+
+    Ok((connection, accepted))
+}
+
+/// Try every configured server in turn until one accepts the connection
+///
+/// Returns the connection and the address it was made to.
+async fn connect_any(
+    endpoint: &Endpoint,
+    config: &Config,
+    servers: &ServerSelector,
+) -> Result<(Connection, String)> {
+    let mut last_error = None;
+
+    for (index, address) in servers.candidates() {
+        match connect_to(endpoint, config, address).await {
+            Ok(connection) => {
+                servers.record_success(index);
+                return Ok((connection, address.to_string()));
+            }
+            Err(e) => {
+                warn!(server = %address, error = %e, "Server unreachable, trying next");
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.expect("at least one server address"))
+}
+
+/// Reconnect to the current server
+///
+/// Repeated failures move the selector on to the next server, so later
+/// attempts fail over instead of retrying a dead server forever.
+async fn reconnect(
+    endpoint: &Endpoint,
+    config: &Config,
+    servers: &ServerSelector,
+) -> Result<Connection> {
+    let (index, address) = servers.current();
+
+    match connect_to(endpoint, config, address).await {
+        Ok(connection) => {
+            servers.record_success(index);
+            info!(server = %address, "Reconnected to server");
+            Ok(connection)
+        }
+        Err(e) => {
+            if let Some(next) = servers.record_failure(index) {
+                warn!(from = %address, to = %next, "Failing over to next server");
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Send the shared auth token on a fresh connection, if one is configured
+///
+/// The server reads it from the first unidirectional stream and closes the
+/// connection if it doesn't match.
+async fn authenticate(connection: &Connection, config: &Config) -> Result<()> {
+    let Some(auth) = &config.auth else {
+        return Ok(());
+    };
+
+    let frame = protocol::encode_auth_frame(&auth.token)?;
+    let mut send = connection
+        .open_uni()
+        .await
+        .context("Failed to open auth stream")?;
+    send.write_all(&frame)
+        .await
+        .context("Failed to send auth token")?;
+    send.finish().context("Failed to send auth token")?;
+    Ok(())
+}
+
 /// Insecure TLS verifier for development
 #[derive(Debug)]
 struct InsecureServerVerifier;
@@ -440,3 +1076,320 @@ impl rustls::client::danger::ServerCertVerifier for InsecureServerVerifier {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::close_code::CloseCode;
+    use crate::testing::{test_config, test_server, test_server_on};
+    use quinn::VarInt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_auth_token_sent_on_connect() {
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        let received = tokio::spawn(async move {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            let mut recv = conn.accept_uni().await.unwrap();
+            recv.read_to_end(64).await.unwrap()
+        });
+
+        let mut config = test_config(addr, "");
+        config.auth = Some(crate::config::AuthConfig {
+            token: "s3cret".to_string(),
+        });
+        let client = TunnelClient::new(Arc::new(config)).await.unwrap();
+        client.get_connection().await.unwrap();
+
+        assert_eq!(received.await.unwrap(), b"\x02\x06s3cret");
+    }
+
+    #[tokio::test]
+    async fn test_resumed_connection_uses_0rtt() {
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        let (token_tx, mut token_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(incoming) = server.accept().await {
+                let (conn, handshake) = incoming.accept().unwrap().into_0rtt().unwrap();
+                let mut recv = conn.accept_uni().await.unwrap();
+                token_tx.send(recv.read_to_end(64).await.unwrap()).unwrap();
+                handshake.await;
+                conn.close(VarInt::from_u32(0), b"done");
+            }
+        });
+
+        let mut config = test_config(addr, "");
+        config.auth = Some(crate::config::AuthConfig {
+            token: "s3cret".to_string(),
+        });
+        let endpoint = create_client_endpoint(&config).unwrap();
+        let connect = || async {
+            let connecting = endpoint.connect(addr, "localhost").unwrap();
+            let (connection, early) = connect_early(connecting, &config).await.unwrap();
+            // Session tickets arrive before the server's close
+            connection.closed().await;
+            early
+        };
+
+        // The first connection has no session to resume
+        assert!(!connect().await);
+        assert_eq!(token_rx.recv().await.unwrap(), b"\x02\x06s3cret");
+
+        // The resumed one sends its auth frame in the first flight
+        assert!(connect().await);
+        assert_eq!(token_rx.recv().await.unwrap(), b"\x02\x06s3cret");
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_to_next_server() {
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Some(incoming) = server.accept().await {
+                let _ = incoming.await;
+            }
+        });
+
+        let mut config = test_config(addr, "");
+        config.server.addresses = vec![
+            silent.local_addr().unwrap().to_string(),
+            addr.to_string(),
+        ];
+        config.quic.idle_timeout_secs = 1;
+        let config = Arc::new(config);
+
+        // Both servers are probed; only the live one succeeds
+        let probes = TunnelClient::test_connection(config.clone()).await.unwrap();
+        assert_eq!(probes.len(), 2);
+        assert_eq!(probes[0].addr, Some(silent.local_addr().unwrap()));
+        assert!(!probes[0].is_ok());
+        assert_eq!(probes[1].addr, Some(addr));
+        assert!(probes[1].is_ok());
+
+        // The client remembers the server that worked
+        let client = TunnelClient::new(config).await.unwrap();
+        client.connect().await.unwrap();
+        assert_eq!(client.servers.current(), (1, addr.to_string().as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_probes_both_families() {
+        let v4 = test_server();
+        let v6 = test_server_on("[::1]:0".parse().unwrap());
+        let addrs = [v4.local_addr().unwrap(), v6.local_addr().unwrap()];
+        for server in [v4, v6] {
+            tokio::spawn(async move {
+                while let Some(incoming) = server.accept().await {
+                    let _ = incoming.await;
+                }
+            });
+        }
+
+        let mut config = test_config(addrs[0], "");
+        config.server.addresses = addrs.iter().map(ToString::to_string).collect();
+        let config = Arc::new(config);
+
+        // One endpoint reaches servers of either family
+        let probes = TunnelClient::test_connection(config.clone()).await.unwrap();
+        assert_eq!(probes.len(), 2);
+        for (probe, addr) in probes.iter().zip(addrs) {
+            assert_eq!(probe.addr, Some(addr));
+            assert!(probe.is_ok(), "{addr}: {:?}", probe.result);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe_reports_handshake() {
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Some(incoming) = server.accept().await {
+                tokio::spawn(async move {
+                    let (conn, _) = incoming.accept().unwrap().into_0rtt().unwrap();
+                    conn.closed().await;
+                });
+            }
+        });
+
+        let probes = TunnelClient::test_connection(Arc::new(test_config(addr, "")))
+            .await
+            .unwrap();
+        let info = probes[0].result.clone().unwrap();
+        assert!(info.handshake > Duration::ZERO);
+        assert_eq!(info.alpn.as_deref(), Some("mytunnel"));
+        assert_eq!(info.server_subject.as_deref(), Some("CN=rcgen self signed cert"));
+        assert_eq!(info.resumed, Some(true));
+
+        // Without 0-RTT resumption isn't tried
+        let mut config = test_config(addr, "");
+        config.quic.enable_0rtt = false;
+        let probes = TunnelClient::test_connection(Arc::new(config)).await.unwrap();
+        assert_eq!(probes[0].result.clone().unwrap().resumed, None);
+    }
+
+    #[tokio::test]
+    async fn test_pool_spreads_streams() {
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+
+        let accepted_clone = accepted.clone();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Some(incoming) = server.accept().await {
+                accepted_clone.fetch_add(1, Ordering::SeqCst);
+                if let Ok(conn) = incoming.await {
+                    connections.push(conn);
+                }
+            }
+        });
+
+        let config = Arc::new(test_config(addr, "connection_pool_size = 2"));
+        let client = TunnelClient::new(config).await.unwrap();
+
+        // A busy connection makes the next stream open a second one
+        let (_, _, first) = client.open_stream().await.unwrap();
+        let (_, _, second) = client.open_stream().await.unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+        assert_eq!(client.pool.primary().streams(), 1);
+        assert_eq!(client.pool.slots()[1].streams(), 1);
+
+        // With the pool full, streams go to the least-loaded connection
+        drop(first);
+        let (_, _, _third) = client.open_stream().await.unwrap();
+        let (_, _, _fourth) = client.open_stream().await.unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+        assert_eq!(client.pool.primary().streams(), 2);
+        assert_eq!(client.pool.slots()[1].streams(), 1);
+
+        // A dead connection is replaced on demand
+        let dead = client.pool.slots()[1].connection.read().clone().unwrap();
+        dead.close(VarInt::from_u32(0), b"test");
+        drop(second);
+        let (_, _, _fifth) = client.open_stream().await.unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 3);
+        assert_eq!(client.pool.slots()[1].streams(), 1);
+        assert_eq!(client.state(), ConnectionState::Connected);
+    }
+
+    #[tokio::test]
+    async fn test_failed_reconnect_reports_down() {
+        // A bound socket that never answers the handshake
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = silent.local_addr().unwrap();
+
+        let mut config = test_config(
+            addr,
+            "[server.reconnect]\ninitial_ms = 3600000\nmax_ms = 3600000",
+        );
+        config.quic.idle_timeout_secs = 1;
+        let client = TunnelClient::new(Arc::new(config)).await.unwrap();
+        assert_eq!(client.state(), ConnectionState::Reconnecting);
+
+        assert!(client.get_connection().await.is_err());
+        assert_eq!(client.reconnect.failures(), 1);
+        assert_eq!(client.state(), ConnectionState::Down);
+
+        // Further requests fail fast instead of waiting on another handshake
+        let start = std::time::Instant::now();
+        let err = client.get_connection().await.unwrap_err();
+        assert!(err.to_string().contains("retrying"));
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(client.reconnect.failures(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_capacity_close_holds_off_reconnect() {
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+
+        let accepted_clone = accepted.clone();
+        tokio::spawn(async move {
+            while let Some(incoming) = server.accept().await {
+                accepted_clone.fetch_add(1, Ordering::SeqCst);
+                if let Ok(conn) = incoming.await {
+                    CloseCode::Capacity.close(&conn);
+                }
+            }
+        });
+
+        let config = Arc::new(test_config(addr, "capacity_backoff_secs = 30"));
+        let client = TunnelClient::new(config).await.unwrap();
+
+        let conn = client.get_connection().await.unwrap();
+        conn.closed().await;
+
+        // The next attempt must fail fast instead of reconnecting
+        let err = client.get_connection().await.unwrap_err();
+        assert!(err.to_string().contains("capacity"));
+        assert!(client.backoff.remaining().unwrap() > Duration::from_secs(25));
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_presented() {
+        use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+        use rustls::pki_types::PrivatePkcs8KeyDer;
+
+        // Client CA and a client certificate it signed
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().unwrap();
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+        let mut client_params = CertificateParams::new(vec!["client".to_string()]).unwrap();
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = client_params.signed_by(&client_key, &ca_cert, &ca_key).unwrap();
+
+        let dir = std::env::temp_dir()
+            .join(format!("mytunnel-client-mtls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("client.pem");
+        let key_path = dir.join("client.key");
+        std::fs::write(&cert_path, client_cert.pem()).unwrap();
+        std::fs::write(&key_path, client_key.serialize_pem()).unwrap();
+
+        // Server that only accepts certificates from the CA
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(ca_cert.der().clone()).unwrap();
+        let verifier = rustls::server::WebPkiClientVerifier::builder(Arc::new(roots))
+            .build()
+            .unwrap();
+        let server_cert =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let server_key = PrivatePkcs8KeyDer::from(server_cert.key_pair.serialize_der());
+        let mut tls = rustls::ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(vec![server_cert.cert.der().clone()], server_key.into())
+            .unwrap();
+        tls.alpn_protocols = vec![b"mytunnel".to_vec()];
+        let server = Endpoint::server(
+            quinn::ServerConfig::with_crypto(Arc::new(
+                quinn::crypto::rustls::QuicServerConfig::try_from(tls).unwrap(),
+            )),
+            "127.0.0.1:0".parse().unwrap(),
+        )
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let config = Arc::new(test_config(
+            addr,
+            &format!(
+                "client_cert_path = \"{}\"\nclient_key_path = \"{}\"",
+                cert_path.display(),
+                key_path.display()
+            ),
+        ));
+        let accept = tokio::spawn(async move { server.accept().await.unwrap().await });
+        let client = TunnelClient::new(config).await.unwrap();
+        client.get_connection().await.unwrap();
+
+        let server_conn = accept.await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(server_conn.is_ok());
+    }
+}
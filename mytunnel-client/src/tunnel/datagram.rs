@@ -1,26 +1,328 @@
 //! Datagram handling for UDP relay
 //!
-//! Handles QUIC datagrams for UDP packet relay.
+//! Handles QUIC datagrams for UDP packet relay. Responses are routed by
+//! flow id to whichever association or [`TunnelUdpSocket`] owns the flow.
 
 use anyhow::Result;
 use bytes::Bytes;
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use parking_lot::Mutex;
 use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
 use tracing::{debug, warn};
 
-use crate::protocol;
+use crate::protocol::{self, UdpPacket};
 use crate::tunnel::connection::TunnelClientHandle;
 
+/// Responses queued for a flow owner before further ones are dropped
+const ROUTE_QUEUE: usize = 256;
+
+/// Flow ids and the owner each flow's responses go to
+///
+/// Every owner waiting for a response reads tunnel datagrams and hands
+/// each to the owner of its flow, so associations and sockets share the
+/// connection without taking each other's responses.
+#[derive(Default)]
+pub(crate) struct FlowRoutes {
+    next_id: AtomicU32,
+    routes: Mutex<HashMap<u32, mpsc::Sender<UdpPacket>>>,
+}
+
+impl FlowRoutes {
+    /// Allocate an id for a new flow
+    pub(crate) fn next_id(&self) -> u32 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Send responses on `flow_id` to `tx`
+    pub(crate) fn add(&self, flow_id: u32, tx: mpsc::Sender<UdpPacket>) {
+        let mut routes = self.routes.lock();
+        // Drop routes of owners that went away without removing them
+        routes.retain(|_, tx| !tx.is_closed());
+        routes.insert(flow_id, tx);
+    }
+
+    /// Stop routing responses on `flow_id`
+    pub(crate) fn remove(&self, flow_id: u32) {
+        self.routes.lock().remove(&flow_id);
+    }
+
+    /// Hand `packet` to the owner of its flow
+    pub(crate) fn deliver(&self, packet: UdpPacket) {
+        let flow_id = packet.flow_id;
+        let mut routes = self.routes.lock();
+        let Some(tx) = routes.get(&flow_id) else {
+            debug!(flow_id, "UDP response for unknown flow");
+            return;
+        };
+        match tx.try_send(packet) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                debug!(flow_id, "UDP flow queue full, dropping response");
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                routes.remove(&flow_id);
+            }
+        }
+    }
+}
+
+/// A UDP flow through the tunnel to one target
+///
+/// Returned by [`TunnelClientHandle::connect_udp`]. Sent payloads go to
+/// the target and [`recv`](Self::recv) yields its responses.
+pub struct TunnelUdpSocket {
+    tunnel: Arc<TunnelClientHandle>,
+    flow_id: u32,
+    host: String,
+    port: u16,
+    rx: mpsc::Receiver<UdpPacket>,
+}
+
+impl TunnelUdpSocket {
+    pub(crate) fn new(tunnel: Arc<TunnelClientHandle>, host: &str, port: u16) -> Result<Self> {
+        if host.len() > 255 {
+            anyhow::bail!("Host name too long (max 255 bytes)");
+        }
+
+        let flow_id = tunnel.next_flow_id();
+        let (tx, rx) = mpsc::channel(ROUTE_QUEUE);
+        tunnel.route_flow(flow_id, tx);
+
+        Ok(Self {
+            tunnel,
+            flow_id,
+            host: host.to_string(),
+            port,
+            rx,
+        })
+    }
+
+    /// Id of the flow, unique within the client
+    pub fn flow_id(&self) -> u32 {
+        self.flow_id
+    }
+
+    /// Send one datagram to the target
+    pub async fn send(&self, payload: &[u8]) -> Result<()> {
+        let packet = protocol::encode_udp_packet(self.flow_id, &self.host, self.port, payload)?;
+        self.tunnel.send_datagram(Bytes::from(packet)).await
+    }
+
+    /// Receive the next datagram from the target
+    pub async fn recv(&mut self) -> Result<Bytes> {
+        Ok(self.tunnel.recv_routed(&mut self.rx).await?.payload)
+    }
+}
+
+impl Drop for TunnelUdpSocket {
+    fn drop(&mut self) {
+        self.tunnel.unroute_flow(self.flow_id);
+    }
+}
+
+/// Flows unused for this long are forgotten
+const FLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Fragment sequences incomplete after this long are abandoned
+///
+/// RFC 1928 requires at least 5 seconds.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest datagram reassembled from fragments
+const MAX_REASSEMBLED_LEN: usize = 65_535;
+
+/// FRAG bit marking the last fragment of a sequence
+const FRAG_END: u8 = 0x80;
+
+/// Fragments dropped across all associations
+static UDP_FRAGMENTS_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// SOCKS5 UDP fragments dropped so far, either because reassembly is
+/// disabled or because their sequence was abandoned
+pub fn udp_fragments_dropped() -> u64 {
+    UDP_FRAGMENTS_DROPPED.load(Ordering::Relaxed)
+}
+
+/// Count `count` dropped fragments from `client_addr`
+fn drop_fragments(client_addr: SocketAddr, count: u64, reason: &str) {
+    let total = UDP_FRAGMENTS_DROPPED.fetch_add(count, Ordering::Relaxed) + count;
+    warn!(
+        client = %client_addr,
+        fragments = count,
+        reason,
+        udp_fragments_dropped = total,
+        "Dropped fragmented SOCKS5 UDP datagram"
+    );
+}
+
+/// A fragment sequence being reassembled
+struct Fragments {
+    target: (String, u16),
+    /// Position of the last fragment received
+    position: u8,
+    data: Vec<u8>,
+    started: Instant,
+}
+
+/// Sequential reassembly of fragmented SOCKS5 UDP datagrams (RFC 1928 §7)
+///
+/// Each local client has one sequence in flight. Fragments must arrive in
+/// order starting at position 1; anything else abandons the sequence.
+#[derive(Default)]
+struct Reassembler {
+    pending: HashMap<SocketAddr, Fragments>,
+}
+
+impl Reassembler {
+    /// Add a fragment, returning the whole datagram once the last one arrives
+    fn push(
+        &mut self,
+        client_addr: SocketAddr,
+        frag: u8,
+        host: &str,
+        port: u16,
+        payload: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.expire(REASSEMBLY_TIMEOUT);
+
+        let position = frag & !FRAG_END;
+        let target = (host.to_string(), port);
+        let mut fragments = match self.pending.remove(&client_addr) {
+            Some(f) if f.position + 1 == position && f.target == target => f,
+            previous => {
+                if let Some(f) = previous {
+                    drop_fragments(client_addr, f.position as u64, "fragment out of sequence");
+                }
+                if position != 1 {
+                    drop_fragments(client_addr, 1, "fragment out of sequence");
+                    return None;
+                }
+                Fragments {
+                    target,
+                    position: 0,
+                    data: Vec::new(),
+                    started: Instant::now(),
+                }
+            }
+        };
+
+        if fragments.data.len() + payload.len() > MAX_REASSEMBLED_LEN {
+            drop_fragments(client_addr, position as u64, "reassembled datagram too large");
+            return None;
+        }
+        fragments.data.extend_from_slice(payload);
+        fragments.position = position;
+
+        if frag & FRAG_END != 0 {
+            return Some(fragments.data);
+        }
+        self.pending.insert(client_addr, fragments);
+        None
+    }
+
+    /// Abandon sequences started longer than `timeout` ago
+    fn expire(&mut self, timeout: Duration) {
+        self.pending.retain(|client_addr, f| {
+            let live = f.started.elapsed() < timeout;
+            if !live {
+                drop_fragments(*client_addr, f.position as u64, "reassembly timed out");
+            }
+            live
+        });
+    }
+}
+
+/// A local client talking to one target
+struct Flow {
+    client_addr: SocketAddr,
+    key: FlowKey,
+    /// SOCKS5 UDP header the client addressed the target with, echoed on
+    /// every response so it sees the address it sent to
+    header: Vec<u8>,
+    last_active: Instant,
+}
+
+/// Local client and target a flow is for
+type FlowKey = (SocketAddr, String, u16);
+
+/// Flows of one association, so responses reach the client that asked
+///
+/// Each `(client, host, port)` gets its own flow id, which the server
+/// echoes back; two clients querying the same target don't collide.
+#[derive(Default)]
+struct FlowTable {
+    ids: HashMap<FlowKey, u32>,
+    flows: HashMap<u32, Flow>,
+}
+
+impl FlowTable {
+    /// Id of the flow from `client_addr` to `host:port`, opening it with
+    /// `allocate` if needed
+    ///
+    /// `header` is the SOCKS5 UDP header of the request, kept for replies.
+    fn open(
+        &mut self,
+        client_addr: SocketAddr,
+        host: &str,
+        port: u16,
+        header: &[u8],
+        allocate: impl FnOnce() -> u32,
+    ) -> u32 {
+        let key = (client_addr, host.to_string(), port);
+        let id = *self.ids.entry(key.clone()).or_insert_with(allocate);
+        self.flows
+            .entry(id)
+            .or_insert_with(|| Flow {
+                client_addr,
+                key,
+                header: header.to_vec(),
+                last_active: Instant::now(),
+            })
+            .last_active = Instant::now();
+        id
+    }
+
+    /// Local client a response on flow `id` belongs to, and the SOCKS5
+    /// datagram carrying `payload` back to it
+    fn reply(&mut self, id: u32, payload: &[u8]) -> Option<(SocketAddr, Vec<u8>)> {
+        let flow = self.flows.get_mut(&id)?;
+        flow.last_active = Instant::now();
+
+        let mut response = Vec::with_capacity(flow.header.len() + payload.len());
+        response.extend_from_slice(&flow.header);
+        response.extend_from_slice(payload);
+        Some((flow.client_addr, response))
+    }
+
+    /// Forget flows idle for longer than `timeout`, returning their ids
+    fn expire(&mut self, timeout: Duration) -> Vec<u32> {
+        let ids = &mut self.ids;
+        let mut expired = Vec::new();
+        self.flows.retain(|&id, flow| {
+            let live = flow.last_active.elapsed() < timeout;
+            if !live {
+                ids.remove(&flow.key);
+                expired.push(id);
+            }
+            live
+        });
+        expired
+    }
+}
+
 /// UDP association for SOCKS5 UDP ASSOCIATE
 pub struct UdpAssociation {
     /// Local UDP socket for client communication
     local_socket: Arc<UdpSocket>,
     /// Tunnel client handle
     tunnel: Arc<TunnelClientHandle>,
+    /// Reassemble fragmented datagrams instead of dropping them
+    reassembly: bool,
 }
 
 impl UdpAssociation {
@@ -34,9 +336,16 @@ impl UdpAssociation {
         Ok(Self {
             local_socket: Arc::new(local_socket),
             tunnel,
+            reassembly: false,
         })
     }
 
+    /// Reassemble fragmented datagrams (FRAG != 0) instead of dropping them
+    pub fn with_reassembly(mut self, enabled: bool) -> Self {
+        self.reassembly = enabled;
+        self
+    }
+
     /// Get the local bound address
     pub fn local_addr(&self) -> Result<SocketAddr> {
         Ok(self.local_socket.local_addr()?)
@@ -47,13 +356,14 @@ impl UdpAssociation {
         let socket = self.local_socket.clone();
         let tunnel = self.tunnel.clone();
         
-        // Track pending requests for matching responses
-        let pending: Arc<Mutex<HashMap<(String, u16), (SocketAddr, Instant)>>> =
-            Arc::new(Mutex::new(HashMap::new()));
+        // Track flows for matching responses
+        let flows: Arc<Mutex<FlowTable>> = Arc::new(Mutex::new(FlowTable::default()));
+        let (routes_tx, mut routes_rx) = mpsc::channel(ROUTE_QUEUE);
 
-        let pending_clone = pending.clone();
+        let flows_clone = flows.clone();
         let tunnel_clone = tunnel.clone();
         let socket_clone = socket.clone();
+        let mut reassembler = self.reassembly.then(Reassembler::default);
 
         // Task to receive from local clients and forward to tunnel
         let local_to_tunnel = async move {
@@ -69,11 +379,6 @@ impl UdpAssociation {
 
                         // SOCKS5 UDP header: RSV(2) | FRAG(1) | ATYP(1) | DST.ADDR | DST.PORT | DATA
                         let frag = buf[2];
-                        if frag != 0 {
-                            // We don't support fragmentation
-                            warn!("UDP fragmentation not supported");
-                            continue;
-                        }
 
                         let atyp = buf[3];
                         let (host, port, data_start) = match atyp {
@@ -114,19 +419,41 @@ impl UdpAssociation {
                             _ => continue,
                         };
 
-                        let payload = &buf[data_start..len];
+                        let reassembled;
+                        let payload = if frag == 0 {
+                            &buf[data_start..len]
+                        } else if let Some(reassembler) = reassembler.as_mut() {
+                            let fragment = &buf[data_start..len];
+                            match reassembler.push(client_addr, frag, &host, port, fragment) {
+                                Some(datagram) => {
+                                    reassembled = datagram;
+                                    &reassembled
+                                }
+                                None => continue,
+                            }
+                        } else {
+                            drop_fragments(client_addr, 1, "reassembly disabled");
+                            continue;
+                        };
 
-                        // Store pending request info
-                        {
-                            let mut p = pending.lock();
-                            p.insert((host.clone(), port), (client_addr, Instant::now()));
-                            
-                            // Cleanup old entries
-                            p.retain(|_, (_, t)| t.elapsed() < Duration::from_secs(30));
-                        }
+                        // Replies are never fragmented
+                        let mut header = buf[..data_start].to_vec();
+                        header[2] = 0;
+
+                        let flow_id = {
+                            let mut flows = flows.lock();
+                            for id in flows.expire(FLOW_IDLE_TIMEOUT) {
+                                tunnel.unroute_flow(id);
+                            }
+                            flows.open(client_addr, &host, port, &header, || {
+                                let id = tunnel.next_flow_id();
+                                tunnel.route_flow(id, routes_tx.clone());
+                                id
+                            })
+                        };
 
                         // Encode and send through tunnel
-                        match protocol::encode_udp_packet(&host, port, payload) {
+                        match protocol::encode_udp_packet(flow_id, &host, port, payload) {
                             Ok(packet) => {
                                 if let Err(e) = tunnel.send_datagram(Bytes::from(packet)).await {
                                     debug!(error = %e, "Failed to send UDP datagram");
@@ -148,35 +475,15 @@ impl UdpAssociation {
         // Task to receive from tunnel and forward to local clients
         let tunnel_to_local = async move {
             loop {
-                match tunnel_clone.recv_datagram().await {
-                    Ok(data) => {
-                        // Decode the response
-                        match protocol::decode_udp_packet(data) {
-                            Ok(packet) => {
-                                // Find the client that sent this request
-                                let client_addr = {
-                                    let p = pending_clone.lock();
-                                    p.get(&(packet.host.clone(), packet.port))
-                                        .map(|(addr, _)| *addr)
-                                };
+                match tunnel_clone.recv_routed(&mut routes_rx).await {
+                    Ok(packet) => {
+                        // Route by flow id alone: the echoed host and
+                        // port can't tell two clients apart
+                        let reply = flows_clone.lock().reply(packet.flow_id, &packet.payload);
 
-                                if let Some(client_addr) = client_addr {
-                                    // Build SOCKS5 UDP response
-                                    let mut response = Vec::new();
-                                    response.extend_from_slice(&[0, 0, 0]); // RSV, FRAG
-                                    response.push(0x03); // Domain type
-                                    response.push(packet.host.len() as u8);
-                                    response.extend_from_slice(packet.host.as_bytes());
-                                    response.extend_from_slice(&packet.port.to_be_bytes());
-                                    response.extend_from_slice(&packet.payload);
-
-                                    if let Err(e) = socket_clone.send_to(&response, client_addr).await {
-                                        debug!(error = %e, "Failed to send UDP response to client");
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                debug!(error = %e, "Failed to decode UDP response");
+                        if let Some((client_addr, response)) = reply {
+                            if let Err(e) = socket_clone.send_to(&response, client_addr).await {
+                                debug!(error = %e, "Failed to send UDP response to client");
                             }
                         }
                     }
@@ -197,3 +504,223 @@ impl UdpAssociation {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flows_to_same_target() {
+        let mut table = FlowTable::default();
+        let mut next = 0;
+        let mut allocate = || {
+            next += 1;
+            next
+        };
+
+        let a: SocketAddr = "127.0.0.1:1000".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:2000".parse().unwrap();
+        let header = [0, 0, 0, 0x01, 8, 8, 8, 8, 0, 53];
+        let flow_a = table.open(a, "8.8.8.8", 53, &header, &mut allocate);
+        let flow_b = table.open(b, "8.8.8.8", 53, &header, &mut allocate);
+        assert_ne!(flow_a, flow_b);
+        assert_eq!(table.open(a, "8.8.8.8", 53, &header, &mut allocate), flow_a);
+
+        let (client, response) = table.reply(flow_a, b"x").unwrap();
+        assert_eq!(client, a);
+        assert_eq!(response, [&header[..], b"x"].concat());
+        assert_eq!(table.reply(flow_b, b"x").unwrap().0, b);
+        assert!(table.reply(99, b"x").is_none());
+
+        table.expire(Duration::ZERO);
+        assert!(table.reply(flow_a, b"x").is_none());
+        assert_ne!(table.open(a, "8.8.8.8", 53, &header, &mut allocate), flow_a);
+    }
+
+    #[test]
+    fn test_reassembly() {
+        let client: SocketAddr = "127.0.0.1:1000".parse().unwrap();
+        let mut reassembler = Reassembler::default();
+
+        assert_eq!(reassembler.push(client, 1, "a", 53, b"one "), None);
+        assert_eq!(reassembler.push(client, 2, "a", 53, b"two "), None);
+        let datagram = reassembler.push(client, 3 | FRAG_END, "a", 53, b"three");
+        assert_eq!(datagram.as_deref(), Some(&b"one two three"[..]));
+        assert!(reassembler.pending.is_empty());
+
+        // A gap abandons the sequence, and a fresh one can start after it
+        let dropped = udp_fragments_dropped();
+        assert_eq!(reassembler.push(client, 1, "a", 53, b"x"), None);
+        assert_eq!(reassembler.push(client, 3 | FRAG_END, "a", 53, b"z"), None);
+        assert!(udp_fragments_dropped() >= dropped + 2);
+        assert_eq!(reassembler.push(client, 1, "a", 53, b"x"), None);
+        assert_eq!(reassembler.push(client, 2 | FRAG_END, "a", 53, b"y").unwrap(), b"xy");
+
+        // A different target mid-sequence abandons it too
+        assert_eq!(reassembler.push(client, 1, "a", 53, b"x"), None);
+        assert_eq!(reassembler.push(client, 2 | FRAG_END, "b", 53, b"y"), None);
+
+        assert_eq!(reassembler.push(client, 1, "a", 53, b"x"), None);
+        reassembler.expire(Duration::ZERO);
+        assert!(reassembler.pending.is_empty());
+    }
+
+    /// SOCKS5 UDP header for 10.0.0.53:53
+    const SOCKS5_HEADER: [u8; 10] = [0, 0, 0, 0x01, 10, 0, 0, 53, 0, 53];
+
+    /// SOCKS5 UDP request to 10.0.0.53:53
+    fn socks5_packet(payload: &[u8]) -> Vec<u8> {
+        [&SOCKS5_HEADER[..], payload].concat()
+    }
+
+    /// Fragment `frag` of a SOCKS5 UDP request to 10.0.0.53:53
+    fn socks5_fragment(frag: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = socks5_packet(payload);
+        packet[2] = frag;
+        packet
+    }
+
+    /// Start a tunnel server that answers datagrams in batches of `batch`,
+    /// newest first, prefixing each payload with "re:"
+    fn start_tunnel_server(batch: usize) -> SocketAddr {
+        use crate::testing::test_server;
+
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(incoming) = server.accept().await {
+                let conn = incoming.await.unwrap();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    while let Ok(data) = conn.read_datagram().await {
+                        let _ = tx.send((conn.clone(), data));
+                    }
+                });
+            }
+        });
+        tokio::spawn(async move {
+            let mut pending = Vec::new();
+            while let Some(received) = rx.recv().await {
+                pending.push(received);
+                if pending.len() < batch {
+                    continue;
+                }
+                for (conn, data) in pending.drain(..).rev() {
+                    let packet = protocol::decode_udp_packet(data).unwrap();
+                    let reply = [&b"re:"[..], &packet.payload].concat();
+                    let response = protocol::encode_udp_packet(
+                        packet.flow_id,
+                        &packet.host,
+                        packet.port,
+                        &reply,
+                    )
+                    .unwrap();
+                    conn.send_datagram(Bytes::from(response)).unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    /// Start a tunnel server as in [`start_tunnel_server`] and a UDP
+    /// association relaying through it
+    async fn start_relay(batch: usize) -> SocketAddr {
+        let client = tunnel_client(start_tunnel_server(batch)).await;
+        start_association(&client).await
+    }
+
+    async fn tunnel_client(addr: SocketAddr) -> crate::tunnel::TunnelClient {
+        use crate::testing::test_config;
+
+        crate::tunnel::TunnelClient::new(Arc::new(test_config(addr, ""))).await.unwrap()
+    }
+
+    /// Start a UDP association over `client`, returning its relay address
+    async fn start_association(client: &crate::tunnel::TunnelClient) -> SocketAddr {
+        let association = UdpAssociation::new(client.handle(), "127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap()
+            .with_reassembly(true);
+        let relay_addr = association.local_addr().unwrap();
+        tokio::spawn(association.run());
+        relay_addr
+    }
+
+    async fn recv_reply(socket: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0u8; 128];
+        let n = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        buf[..n].to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_flows_demultiplexed() {
+        let relay_addr = start_relay(1).await;
+
+        // Two local clients query the same target at once
+        let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        a.send_to(&socks5_packet(b"from-a"), relay_addr).await.unwrap();
+        b.send_to(&socks5_packet(b"from-b"), relay_addr).await.unwrap();
+
+        for (socket, expected) in [(&a, &b"re:from-a"[..]), (&b, b"re:from-b")] {
+            let reply = recv_reply(socket).await;
+            assert!(reply.ends_with(expected), "{:?}", reply);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_responses() {
+        let relay_addr = start_relay(2).await;
+
+        let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        a.send_to(&socks5_packet(b"from-a"), relay_addr).await.unwrap();
+        b.send_to(&socks5_packet(b"from-b"), relay_addr).await.unwrap();
+
+        // b's response arrives first; each still reaches its own client,
+        // addressed the way the client addressed the target
+        assert_eq!(recv_reply(&b).await, socks5_packet(b"re:from-b"));
+        assert_eq!(recv_reply(&a).await, socks5_packet(b"re:from-a"));
+    }
+
+    #[tokio::test]
+    async fn test_fragments_reassembled() {
+        let relay_addr = start_relay(1).await;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.send_to(&socks5_fragment(1, b"one,"), relay_addr).await.unwrap();
+        socket.send_to(&socks5_fragment(2 | FRAG_END, b"two"), relay_addr).await.unwrap();
+
+        // One datagram reaches the server, and the reply is unfragmented
+        assert_eq!(recv_reply(&socket).await, socks5_packet(b"re:one,two"));
+    }
+
+    #[tokio::test]
+    async fn test_connect_udp_alongside_association() {
+        let client = tunnel_client(start_tunnel_server(1)).await;
+        let relay_addr = start_association(&client).await;
+        let handle = client.handle();
+        let mut first = handle.connect_udp("10.0.0.53", 53).unwrap();
+        let mut second = handle.connect_udp("10.0.0.53", 53).unwrap();
+        assert_ne!(first.flow_id(), second.flow_id());
+
+        // Every owner gets its own responses, whoever reads the datagram
+        let local = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        local.send_to(&socks5_packet(b"local"), relay_addr).await.unwrap();
+        first.send(b"first").await.unwrap();
+        second.send(b"second").await.unwrap();
+
+        let timeout = Duration::from_secs(5);
+        let (a, b) = tokio::join!(
+            tokio::time::timeout(timeout, first.recv()),
+            tokio::time::timeout(timeout, second.recv()),
+        );
+        assert_eq!(&a.unwrap().unwrap()[..], b"re:first");
+        assert_eq!(&b.unwrap().unwrap()[..], b"re:second");
+        assert_eq!(recv_reply(&local).await, socks5_packet(b"re:local"));
+    }
+}
@@ -0,0 +1,149 @@
+//! Upstream server selection
+//!
+//! Chooses which configured server to connect to and moves on to the
+//! next one when the current server keeps failing.
+
+use parking_lot::Mutex;
+
+use crate::config::FailoverStrategy;
+
+/// Consecutive reconnect failures before moving to the next server
+const FAILOVER_AFTER_FAILURES: u32 = 3;
+
+/// Tracks the preferred upstream server
+pub struct ServerSelector {
+    addresses: Vec<String>,
+    strategy: FailoverStrategy,
+    state: Mutex<SelectorState>,
+}
+
+#[derive(Default)]
+struct SelectorState {
+    /// Index of the server the next reconnect goes to
+    current: usize,
+    /// Consecutive failures against `current`
+    failures: u32,
+}
+
+impl ServerSelector {
+    /// Create a selector over `addresses` (must not be empty)
+    pub fn new(addresses: Vec<String>, strategy: FailoverStrategy) -> Self {
+        assert!(!addresses.is_empty(), "at least one server address required");
+        Self {
+            addresses,
+            strategy,
+            state: Mutex::new(SelectorState::default()),
+        }
+    }
+
+    /// Every server in the order a fresh connection should try them
+    ///
+    /// Starts at the last good server; with round-robin the next call
+    /// starts one server further along.
+    pub fn candidates(&self) -> Vec<(usize, &str)> {
+        let mut state = self.state.lock();
+        let start = state.current;
+        if self.strategy == FailoverStrategy::RoundRobin {
+            state.current = (start + 1) % self.addresses.len();
+            state.failures = 0;
+        }
+
+        (0..self.addresses.len())
+            .map(|i| (start + i) % self.addresses.len())
+            .map(|i| (i, self.addresses[i].as_str()))
+            .collect()
+    }
+
+    /// The server a single reconnect attempt should use
+    pub fn current(&self) -> (usize, &str) {
+        let mut state = self.state.lock();
+        let index = state.current;
+        if self.strategy == FailoverStrategy::RoundRobin {
+            state.current = (index + 1) % self.addresses.len();
+            state.failures = 0;
+        }
+        (index, self.addresses[index].as_str())
+    }
+
+    /// Remember `index` as the last good server
+    pub fn record_success(&self, index: usize) {
+        let mut state = self.state.lock();
+        if self.strategy == FailoverStrategy::Ordered {
+            state.current = index;
+        }
+        state.failures = 0;
+    }
+
+    /// Count a failure against `index`
+    ///
+    /// Returns the server failed over to, if this failure moved the
+    /// selector off the current server.
+    pub fn record_failure(&self, index: usize) -> Option<&str> {
+        let mut state = self.state.lock();
+        if index != state.current || self.addresses.len() == 1 {
+            return None;
+        }
+
+        state.failures += 1;
+        if state.failures < FAILOVER_AFTER_FAILURES {
+            return None;
+        }
+
+        state.current = (index + 1) % self.addresses.len();
+        state.failures = 0;
+        Some(self.addresses[state.current].as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selector(strategy: FailoverStrategy) -> ServerSelector {
+        let addresses = ["a:443", "b:443", "c:443"].map(String::from).to_vec();
+        ServerSelector::new(addresses, strategy)
+    }
+
+    fn order(selector: &ServerSelector) -> Vec<usize> {
+        selector.candidates().into_iter().map(|(i, _)| i).collect()
+    }
+
+    #[test]
+    fn test_ordered_remembers_last_good() {
+        let selector = selector(FailoverStrategy::Ordered);
+        assert_eq!(order(&selector), [0, 1, 2]);
+        assert_eq!(order(&selector), [0, 1, 2]);
+
+        selector.record_success(1);
+        assert_eq!(order(&selector), [1, 2, 0]);
+        assert_eq!(selector.current(), (1, "b:443"));
+    }
+
+    #[test]
+    fn test_round_robin_rotates() {
+        let selector = selector(FailoverStrategy::RoundRobin);
+        assert_eq!(order(&selector), [0, 1, 2]);
+        assert_eq!(order(&selector), [1, 2, 0]);
+        assert_eq!(selector.current().0, 2);
+        assert_eq!(selector.current().0, 0);
+    }
+
+    #[test]
+    fn test_fails_over_after_repeated_failures() {
+        let selector = selector(FailoverStrategy::Ordered);
+        assert_eq!(selector.record_failure(0), None);
+        assert_eq!(selector.record_failure(0), None);
+        assert_eq!(selector.record_failure(0), Some("b:443"));
+        assert_eq!(selector.current(), (1, "b:443"));
+
+        // A success resets the count
+        selector.record_failure(1);
+        selector.record_failure(1);
+        selector.record_success(1);
+        assert_eq!(selector.record_failure(1), None);
+        assert_eq!(selector.current().0, 1);
+
+        // Failures against a server that is no longer current don't count
+        assert_eq!(selector.record_failure(0), None);
+    }
+}
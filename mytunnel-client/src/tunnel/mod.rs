@@ -2,9 +2,19 @@
 //!
 //! Handles QUIC connection to the server and manages streams/datagrams.
 
+pub mod backoff;
 pub mod connection;
 pub mod datagram;
+pub mod failover;
+pub mod pool;
+pub mod reverse;
 pub mod stream;
 
-pub use connection::{TunnelClient, TunnelClientHandle};
+pub use connection::{
+    AddressProbe, ConnectionState, HandshakeInfo, TunnelClient, TunnelClientHandle,
+};
+pub use datagram::TunnelUdpSocket;
+pub use reverse::ReverseForwarder;
+pub use crate::coalesce::Coalesce;
+pub use stream::TunnelStream;
 
@@ -0,0 +1,139 @@
+//! QUIC connection pool
+//!
+//! Keeps up to N connections to the server so streams are spread across
+//! them and one lost connection doesn't take every stream down with it.
+
+use parking_lot::RwLock;
+use quinn::Connection;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Fixed set of connection slots, filled lazily
+pub struct ConnectionPool {
+    slots: Box<[PoolSlot]>,
+}
+
+/// One pooled connection and the number of streams using it
+pub struct PoolSlot {
+    /// The connection, if one has been established
+    pub connection: RwLock<Option<Connection>>,
+    streams: Arc<AtomicUsize>,
+}
+
+impl ConnectionPool {
+    /// Create a pool with `size` empty slots (at least one)
+    pub fn new(size: usize) -> Self {
+        let slots = (0..size.max(1))
+            .map(|_| PoolSlot {
+                connection: RwLock::new(None),
+                streams: Arc::new(AtomicUsize::new(0)),
+            })
+            .collect();
+        Self { slots }
+    }
+
+    /// All slots, primary first
+    pub fn slots(&self) -> &[PoolSlot] {
+        &self.slots
+    }
+
+    /// The primary slot, which datagrams always use
+    ///
+    /// UDP responses come back on the connection the request went out on,
+    /// so datagrams stick to one connection instead of being balanced.
+    pub fn primary(&self) -> &PoolSlot {
+        &self.slots[0]
+    }
+
+    /// Store a freshly established connection in slot `index`
+    ///
+    /// If another task filled the slot first, the new connection is closed
+    /// and the existing one returned instead.
+    pub fn install(&self, index: usize, connection: Connection) -> Connection {
+        let mut slot = self.slots[index].connection.write();
+        match slot.as_ref() {
+            Some(existing) if existing.close_reason().is_none() => {
+                connection.close(quinn::VarInt::from_u32(0), b"duplicate");
+                existing.clone()
+            }
+            _ => {
+                *slot = Some(connection.clone());
+                connection
+            }
+        }
+    }
+
+    /// Whether any slot holds an open connection
+    pub fn is_connected(&self) -> bool {
+        self.slots.iter().any(|slot| {
+            slot.connection
+                .read()
+                .as_ref()
+                .is_some_and(|c| c.close_reason().is_none())
+        })
+    }
+
+    /// Close and clear every pooled connection
+    pub fn close_all(&self, reason: &[u8]) {
+        for slot in self.slots.iter() {
+            if let Some(conn) = slot.connection.write().take() {
+                conn.close(quinn::VarInt::from_u32(0), reason);
+            }
+        }
+    }
+}
+
+impl PoolSlot {
+    /// Number of streams currently open on this slot
+    pub fn streams(&self) -> usize {
+        self.streams.load(Ordering::Relaxed)
+    }
+
+    /// Count a stream against this slot until the lease is dropped
+    pub fn lease(&self) -> StreamLease {
+        self.streams.fetch_add(1, Ordering::Relaxed);
+        StreamLease {
+            streams: self.streams.clone(),
+        }
+    }
+}
+
+/// Keeps a pooled connection's stream count up while a stream is in use
+///
+/// Hold it for as long as the stream from `open_stream` is alive.
+pub struct StreamLease {
+    streams: Arc<AtomicUsize>,
+}
+
+impl Drop for StreamLease {
+    fn drop(&mut self) {
+        self.streams.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_counts_streams() {
+        let pool = ConnectionPool::new(2);
+        let slot = &pool.slots()[1];
+
+        let first = slot.lease();
+        let second = slot.lease();
+        assert_eq!(slot.streams(), 2);
+        assert_eq!(pool.primary().streams(), 0);
+
+        drop(first);
+        assert_eq!(slot.streams(), 1);
+        drop(second);
+        assert_eq!(slot.streams(), 0);
+    }
+
+    #[test]
+    fn test_pool_has_at_least_one_slot() {
+        assert_eq!(ConnectionPool::new(0).slots().len(), 1);
+        assert!(!ConnectionPool::new(3).is_connected());
+    }
+}
@@ -0,0 +1,241 @@
+//! Reverse port forwarding
+//!
+//! Asks the server to listen on ports on our behalf, like `ssh -R`, and
+//! connects every connection it hands back to a local target. The server
+//! must allow reverse tunnels.
+
+use anyhow::{bail, Context, Result};
+use quinn::{Connection, RecvStream, SendStream};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+use crate::coalesce::Coalesce;
+use crate::config::ReverseForwardConfig;
+use crate::protocol::{self, TcpResponse, TunnelRejected};
+
+use super::stream::proxy_bidirectional;
+use super::TunnelClientHandle;
+
+/// Wait before binding again after the tunnel connection is lost
+const REBIND_DELAY: Duration = Duration::from_secs(5);
+
+/// Local targets by the server port they were bound on
+type Targets = HashMap<u16, (String, u16)>;
+
+/// Reverse forwarder for the configured `[proxy] reverse_forwards`
+pub struct ReverseForwarder {
+    tunnel: Arc<TunnelClientHandle>,
+    forwards: Vec<ReverseForwardConfig>,
+}
+
+impl ReverseForwarder {
+    /// Create a forwarder for `forwards`
+    pub fn new(tunnel: Arc<TunnelClientHandle>, forwards: Vec<ReverseForwardConfig>) -> Self {
+        Self { tunnel, forwards }
+    }
+
+    /// Bind every forward and serve its connections, binding again
+    /// whenever the tunnel reconnects
+    pub async fn run(&self) -> Result<()> {
+        for forward in &self.forwards {
+            forward
+                .target()
+                .with_context(|| format!("Invalid reverse forward target {}", forward.local))?;
+        }
+
+        loop {
+            if let Err(e) = self.serve().await {
+                warn!(error = %e, "Reverse forwards interrupted");
+            }
+            tokio::time::sleep(REBIND_DELAY).await;
+        }
+    }
+
+    /// Bind the forwards on the current connection and serve them until it closes
+    async fn serve(&self) -> Result<()> {
+        let connection = self.tunnel.get_connection().await?;
+
+        // The bind streams hold the server listeners open until they drop
+        let mut targets = Targets::new();
+        let mut binds = Vec::new();
+        for forward in &self.forwards {
+            let Some((host, port)) = forward.target() else {
+                continue;
+            };
+            match bind_remote(&connection, forward.remote_port).await {
+                Ok((bound, bind)) => {
+                    info!(remote_port = bound, local = %forward.local, "Reverse forward bound");
+                    targets.insert(bound, (host.to_string(), port));
+                    binds.push(bind);
+                }
+                Err(e) => {
+                    warn!(
+                        error = %e,
+                        remote_port = forward.remote_port,
+                        local = %forward.local,
+                        "Failed to bind reverse forward"
+                    );
+                }
+            }
+        }
+        if targets.is_empty() {
+            bail!("No reverse forward could be bound");
+        }
+
+        let targets = Arc::new(targets);
+        loop {
+            let (send, recv) = connection
+                .accept_bi()
+                .await
+                .context("Tunnel connection lost")?;
+            let targets = targets.clone();
+            let coalesce = self.tunnel.coalesce();
+            tokio::spawn(async move {
+                if let Err(e) = handle_reverse(send, recv, &targets, coalesce).await {
+                    debug!(error = %e, "Reverse connection error");
+                }
+            });
+        }
+    }
+}
+
+/// Ask the server to listen on `port`, returning the port it bound and the
+/// stream that keeps the listener open
+///
+/// A refusal from the server fails with [`TunnelRejected`].
+async fn bind_remote(
+    connection: &Connection,
+    port: u16,
+) -> Result<(u16, (SendStream, RecvStream))> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.write_all(&protocol::encode_bind_remote(port))
+        .await
+        .context("Failed to send bind request")?;
+
+    let mut status = [0u8; 2];
+    recv.read_exact(&mut status[..1])
+        .await
+        .context("Failed to read bind response")?;
+    if status[0] != protocol::STATUS_OK {
+        let len = if recv.read_exact(&mut status[1..]).await.is_ok() { 2 } else { 1 };
+        let rejected = match protocol::decode_tcp_response(&status[..len])? {
+            TcpResponse::Ok => bail!("Malformed bind response: status {:#04x}", status[0]),
+            rejected => rejected,
+        };
+        return Err(TunnelRejected(rejected).into());
+    }
+
+    let mut bound = [0u8; 2];
+    recv.read_exact(&mut bound)
+        .await
+        .context("Failed to read bound port")?;
+    Ok((u16::from_be_bytes(bound), (send, recv)))
+}
+
+/// Connect a server-opened stream to the local target of its bound port
+async fn handle_reverse(
+    mut send: SendStream,
+    mut recv: RecvStream,
+    targets: &Targets,
+    coalesce: Option<Coalesce>,
+) -> Result<()> {
+    // [0x11][BoundPort(2)]
+    let mut header = [0u8; 3];
+    recv.read_exact(&mut header)
+        .await
+        .context("Failed to read reverse request")?;
+    if header[0] != protocol::REVERSE_CONNECT {
+        bail!("Unexpected server stream type {:#04x}", header[0]);
+    }
+    let bound = u16::from_be_bytes([header[1], header[2]]);
+
+    let Some((host, port)) = targets.get(&bound) else {
+        send.write_all(&[protocol::STATUS_ERROR, protocol::REASON_POLICY_DENIED])
+            .await?;
+        let _ = send.finish();
+        bail!("No reverse forward for server port {}", bound);
+    };
+
+    let stream = match TcpStream::connect((host.as_str(), *port)).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            let reason = match e.kind() {
+                std::io::ErrorKind::ConnectionRefused => protocol::REASON_CONNECTION_REFUSED,
+                std::io::ErrorKind::TimedOut => protocol::REASON_TIMEOUT,
+                _ => protocol::REASON_HOST_UNREACHABLE,
+            };
+            send.write_all(&[protocol::STATUS_ERROR, reason]).await?;
+            let _ = send.finish();
+            return Err(e).with_context(|| format!("Failed to connect to {}:{}", host, port));
+        }
+    };
+    send.write_all(&[protocol::STATUS_OK]).await?;
+
+    let (local_read, local_write) = stream.into_split();
+    let (tx, rx) = proxy_bidirectional(local_read, local_write, send, recv, coalesce).await?;
+    debug!(remote_port = bound, tx_bytes = %tx, rx_bytes = %rx, "Reverse connection completed");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_config, test_server};
+    use crate::tunnel::TunnelClient;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_reverse_to_local() {
+        // Local service the server's connections end up at
+        let local = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = local.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = local.accept().await.unwrap();
+            let mut request = [0u8; 4];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"ping");
+            stream.write_all(b"pong").await.unwrap();
+        });
+
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        let client = TunnelClient::new(Arc::new(test_config(addr, ""))).await.unwrap();
+        let forwarder = ReverseForwarder::new(
+            client.handle(),
+            vec![ReverseForwardConfig {
+                remote_port: 0,
+                local: local_addr.to_string(),
+            }],
+        );
+        tokio::spawn(async move { forwarder.run().await });
+
+        // The server answers the bind with a port of its choosing
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let (mut bind_send, mut bind_recv) = conn.accept_bi().await.unwrap();
+        let mut request = [0u8; 3];
+        bind_recv.read_exact(&mut request).await.unwrap();
+        assert_eq!(request, protocol::encode_bind_remote(0));
+        bind_send.write_all(&[protocol::STATUS_OK, 0x1f, 0x90]).await.unwrap();
+
+        // Then hands back a connection to that port
+        let (mut send, mut recv) = conn.open_bi().await.unwrap();
+        send.write_all(&[protocol::REVERSE_CONNECT, 0x1f, 0x90]).await.unwrap();
+        let mut status = [0u8; 1];
+        recv.read_exact(&mut status).await.unwrap();
+        assert_eq!(status, [protocol::STATUS_OK]);
+        send.write_all(b"ping").await.unwrap();
+        send.finish().unwrap();
+        assert_eq!(recv.read_to_end(64).await.unwrap(), b"pong");
+
+        // A port nothing was bound on is refused
+        let (mut send, mut recv) = conn.open_bi().await.unwrap();
+        send.write_all(&[protocol::REVERSE_CONNECT, 0x1f, 0x91]).await.unwrap();
+        let reply = recv.read_to_end(8).await.unwrap();
+        assert_eq!(reply, [protocol::STATUS_ERROR, protocol::REASON_POLICY_DENIED]);
+    }
+}
@@ -2,14 +2,71 @@
 //!
 //! Handles bidirectional QUIC streams for TCP proxy requests.
 
-use anyhow::{Context, Result};
-use quinn::{RecvStream, SendStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use anyhow::{bail, Context, Result};
+use quinn::{Connection, RecvStream, SendStream};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tracing::debug;
 
-use crate::protocol;
+use crate::coalesce::Coalesce;
+use crate::protocol::{self, TcpResponse, TunnelRejected};
+use crate::tunnel::pool::StreamLease;
+
+/// A TCP connection through the tunnel
+///
+/// Returned by [`TunnelClientHandle::connect_tcp`](crate::tunnel::TunnelClientHandle::connect_tcp).
+/// Reads and writes go to the target; shutting down the write side
+/// finishes the QUIC stream.
+pub struct TunnelStream {
+    send: SendStream,
+    recv: RecvStream,
+    _lease: StreamLease,
+}
+
+impl TunnelStream {
+    pub(crate) fn new(send: SendStream, recv: RecvStream, lease: StreamLease) -> Self {
+        Self {
+            send,
+            recv,
+            _lease: lease,
+        }
+    }
+}
+
+impl AsyncRead for TunnelStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        AsyncRead::poll_read(Pin::new(&mut self.get_mut().recv), cx, buf)
+    }
+}
+
+impl AsyncWrite for TunnelStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.get_mut().send), cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.get_mut().send), cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_shutdown(Pin::new(&mut self.get_mut().send), cx)
+    }
+}
 
 /// Establish a TCP tunnel through a QUIC stream
+///
+/// A refusal from the server fails with [`TunnelRejected`].
 pub async fn establish_tcp_tunnel(
     mut send: SendStream,
     mut recv: RecvStream,
@@ -21,26 +78,89 @@ pub async fn establish_tcp_tunnel(
     send.write_all(&request)
         .await
         .context("Failed to send tunnel request")?;
+    read_status(&mut recv).await?;
+
+    debug!(host = %host, port = %port, "TCP tunnel established");
+
+    Ok((send, recv))
+}
 
-    // Read response
-    let mut response = [0u8; 1];
-    recv.read_exact(&mut response)
+/// Ask the server to listen on an ephemeral port for one connection from
+/// `peer` (None = anyone), returning the port it bound and the stream that
+/// connection will use
+///
+/// A refusal from the server fails with [`TunnelRejected`].
+pub async fn bind_once(
+    connection: &Connection,
+    peer: Option<IpAddr>,
+) -> Result<(u16, SendStream, RecvStream)> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.write_all(&protocol::encode_bind_once(0, peer))
         .await
-        .context("Failed to read tunnel response")?;
+        .context("Failed to send bind request")?;
+    read_status(&mut recv).await?;
 
-    protocol::decode_tcp_response(&response)?;
+    let mut bound = [0u8; 2];
+    recv.read_exact(&mut bound)
+        .await
+        .context("Failed to read bound port")?;
+    Ok((u16::from_be_bytes(bound), send, recv))
+}
 
-    debug!(host = %host, port = %port, "TCP tunnel established");
+/// Wait for the connection to a one-shot bind, returning the peer's address
+///
+/// The stream carries that connection afterwards. A bind nobody connected
+/// to in time fails with [`TunnelRejected`].
+pub async fn accept_bind_once(recv: &mut RecvStream) -> Result<SocketAddr> {
+    read_status(recv).await?;
 
-    Ok((send, recv))
+    let ip = match recv.read_u8().await? {
+        protocol::TCP_CONNECT_IPV4 => {
+            let mut octets = [0u8; 4];
+            recv.read_exact(&mut octets).await?;
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        protocol::TCP_CONNECT_IPV6 => {
+            let mut octets = [0u8; 16];
+            recv.read_exact(&mut octets).await?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        other => bail!("Unknown bind peer address type {:#04x}", other),
+    };
+    let port = recv.read_u16().await?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Read a response status, failing with [`TunnelRejected`] unless it is OK
+///
+/// Errors carry a reason byte unless the server predates them.
+async fn read_status(recv: &mut RecvStream) -> Result<()> {
+    let mut response = [0u8; 2];
+    recv.read_exact(&mut response[..1])
+        .await
+        .context("Failed to read tunnel response")?;
+    let mut len = 1;
+    if response[0] == protocol::STATUS_ERROR && recv.read_exact(&mut response[1..]).await.is_ok()
+    {
+        len = 2;
+    }
+
+    match protocol::decode_tcp_response(&response[..len])? {
+        TcpResponse::Ok => Ok(()),
+        rejected => Err(TunnelRejected(rejected).into()),
+    }
 }
 
 /// Proxy data between a local TCP stream and QUIC stream
+///
+/// With `coalesce`, small local reads are batched before being written to
+/// the tunnel. Each direction half-closes its destination at EOF.
 pub async fn proxy_bidirectional<R, W>(
     mut local_read: R,
     mut local_write: W,
     mut quic_send: SendStream,
     mut quic_recv: RecvStream,
+    coalesce: Option<Coalesce>,
 ) -> Result<(u64, u64)>
 where
     R: tokio::io::AsyncRead + Unpin,
@@ -54,10 +174,17 @@ where
             match local_read.read(&mut buf).await {
                 Ok(0) => break, // EOF
                 Ok(n) => {
+                    let (n, ended) = match &coalesce {
+                        Some(coalesce) => coalesce.fill(&mut local_read, &mut buf, n).await,
+                        None => (n, false),
+                    };
                     if quic_send.write_all(&buf[..n]).await.is_err() {
                         break;
                     }
                     total += n as u64;
+                    if ended {
+                        break;
+                    }
                 }
                 Err(_) => break,
             }
@@ -90,3 +217,72 @@ where
     Ok((tx, rx))
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_config, test_server};
+    use crate::tunnel::TunnelClient;
+    use std::sync::Arc;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn test_connect_tcp_echo() {
+        // Loopback echo server the tunnel connects to
+        let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = echo.accept().await {
+                tokio::spawn(async move {
+                    let (mut read, mut write) = stream.into_split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+
+        // Tunnel server that dials the requested target and splices it through
+        let server = test_server();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let conn = server.accept().await.unwrap().await.unwrap();
+            while let Ok((mut send, mut recv)) = conn.accept_bi().await {
+                tokio::spawn(async move {
+                    // [0x01][Port(2)][HostLen(1)][Host(N)]
+                    let mut header = [0u8; 4];
+                    recv.read_exact(&mut header).await.unwrap();
+                    let mut host = vec![0u8; header[3] as usize];
+                    recv.read_exact(&mut host).await.unwrap();
+                    let port = u16::from_be_bytes([header[1], header[2]]);
+                    let target = format!("{}:{}", String::from_utf8(host).unwrap(), port);
+
+                    let stream = TcpStream::connect(target).await.unwrap();
+                    send.write_all(&[protocol::STATUS_OK]).await.unwrap();
+                    let (mut read, mut write) = stream.into_split();
+                    tokio::join!(
+                        async {
+                            let _ = tokio::io::copy(&mut recv, &mut write).await;
+                            let _ = write.shutdown().await;
+                        },
+                        async {
+                            let _ = tokio::io::copy(&mut read, &mut send).await;
+                            let _ = send.finish();
+                        },
+                    );
+                });
+            }
+        });
+
+        let client = TunnelClient::new(Arc::new(test_config(addr, ""))).await.unwrap();
+        let mut stream = client
+            .handle()
+            .connect_tcp("localhost", echo_addr.port())
+            .await
+            .unwrap();
+
+        stream.write_all(b"hello through the tunnel").await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut echoed = Vec::new();
+        stream.read_to_end(&mut echoed).await.unwrap();
+        assert_eq!(echoed, b"hello through the tunnel");
+    }
+}
@@ -3,9 +3,16 @@
 //! Handles loading and validating server configuration from TOML files.
 
 use anyhow::{Context, Result};
-use serde::Deserialize;
-use std::net::SocketAddr;
+use ipnet::IpNet;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::time::Duration;
+
+use crate::pool::BufferSize;
 
 /// Root configuration structure
 #[derive(Debug, Clone, Deserialize)]
@@ -18,16 +25,48 @@ pub struct Config {
     pub logging: LoggingConfig,
     #[serde(default)]
     pub limits: LimitsConfig,
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub egress: EgressConfig,
+    #[serde(default)]
+    pub dns: DnsConfig,
+    /// Require clients to present a shared token (disabled when absent)
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    /// POST connection events to a webhook (disabled when absent)
+    #[serde(default)]
+    pub notify: Option<NotifyConfig>,
+    #[serde(default)]
+    pub features: FeaturesConfig,
 }
 
 /// Server configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct ServerConfig {
-    /// Address to bind the QUIC listener
-    pub bind_addr: SocketAddr,
+    /// Addresses to bind the QUIC listener, one endpoint each
+    #[serde(rename = "bind_addr", deserialize_with = "one_or_many")]
+    pub bind_addrs: Vec<SocketAddr>,
     /// Number of worker threads (0 = auto)
     #[serde(default)]
     pub workers: usize,
+    /// Maximum QUIC handshakes in progress at once
+    #[serde(default = "default_max_concurrent_handshakes")]
+    pub max_concurrent_handshakes: usize,
+    /// SO_REUSEPORT sockets (and endpoints) per bind address (0 = one per worker)
+    #[serde(default = "default_reuseport_sockets")]
+    pub reuseport_sockets: usize,
+    /// Let listener sockets share their port (SO_REUSEPORT / SO_REUSEADDR)
+    #[serde(default = "default_true")]
+    pub reuse_port: bool,
+    /// DSCP value (0-63) marking proxied traffic to origins
+    #[serde(default)]
+    pub dscp: Option<u8>,
+    /// Seconds a shutdown waits for connections to drain before closing them
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout_secs: u64,
 }
 
 impl ServerConfig {
@@ -39,10 +78,48 @@ impl ServerConfig {
             self.workers
         }
     }
+
+    /// Get effective sockets per bind address (one per worker if 0)
+    ///
+    /// Always one when `reuse_port` is off.
+    pub fn effective_reuseport_sockets(&self) -> usize {
+        if !self.reuse_port {
+            1
+        } else if self.reuseport_sockets == 0 {
+            self.effective_workers()
+        } else {
+            self.reuseport_sockets
+        }
+    }
+}
+
+/// Accept either a single value or a list of them
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
 }
 
+/// Supported values for `quic.congestion_control`
+pub const CONGESTION_CONTROLLERS: &[&str] = &["bbr", "cubic", "newreno"];
+
+/// Supported values for `dns.mode`
+pub const DNS_MODES: &[&str] = &["system", "doh", "dot"];
+
 /// QUIC protocol configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct QuicConfig {
     /// Maximum concurrent connections
     #[serde(default = "default_max_connections")]
@@ -56,16 +133,20 @@ pub struct QuicConfig {
     /// Maximum UDP payload size
     #[serde(default = "default_max_udp_payload")]
     pub max_udp_payload: u16,
+    /// Keep UDP flows open for this many idle seconds and forward every
+    /// response; 0 relays a single response per request
+    #[serde(default)]
+    pub udp_flow_idle_timeout_secs: u64,
     /// Enable 0-RTT
     #[serde(default = "default_true")]
     pub enable_0rtt: bool,
-    /// Congestion control algorithm
+    /// Congestion control algorithm (one of [`CONGESTION_CONTROLLERS`])
     #[serde(default = "default_congestion_control")]
     pub congestion_control: String,
 }
 
 /// TLS configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct TlsConfig {
     /// Path to certificate file
     pub cert_path: String,
@@ -74,10 +155,39 @@ pub struct TlsConfig {
     /// Auto-generate self-signed cert if missing
     #[serde(default)]
     pub auto_generate: bool,
+    /// Require clients to present a certificate signed by `client_ca_path`
+    #[serde(default)]
+    pub require_client_cert: bool,
+    /// CA bundle used to verify client certificates (PEM format)
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+    /// Seconds between checks of the certificate files for changes (0 = never)
+    #[serde(default = "default_cert_reload_interval")]
+    pub reload_interval_secs: u64,
+    /// Certificates presented to clients asking for a specific server name;
+    /// others get `cert_path`
+    #[serde(default)]
+    pub certs: Vec<SniCertConfig>,
+    /// File holding the secret session ticket keys are derived from (they
+    /// rotate every 12 hours), created if missing; unset uses per-process
+    /// keys, so tickets don't survive a restart
+    #[serde(default)]
+    pub session_ticket_key_path: Option<String>,
+}
+
+/// A certificate selected by the SNI server name, from `[[tls.certs]]`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SniCertConfig {
+    /// Server name, or `*.domain` to match any single label under it
+    pub sni: String,
+    /// Path to certificate file
+    pub cert_path: String,
+    /// Path to private key file
+    pub key_path: String,
 }
 
 /// Memory pool configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct PoolConfig {
     /// Number of 4KB buffers
     #[serde(default = "default_buffer_count_4k")]
@@ -88,13 +198,71 @@ pub struct PoolConfig {
     /// Number of 64KB buffers
     #[serde(default = "default_buffer_count_64k")]
     pub buffer_count_64k: usize,
+    /// Extra 4KB buffers allocated on demand once the pool is empty
+    #[serde(default)]
+    pub max_overflow_4k: usize,
+    /// Extra 16KB buffers allocated on demand once the pool is empty
+    #[serde(default)]
+    pub max_overflow_16k: usize,
+    /// Extra 64KB buffers allocated on demand once the pool is empty
+    #[serde(default)]
+    pub max_overflow_64k: usize,
+    /// Custom buffer tiers; when set they replace the 4KB/16KB/64KB tiers
+    #[serde(default)]
+    pub tiers: Vec<BufferTierConfig>,
     /// Maximum connection slots
     #[serde(default = "default_connection_slots")]
     pub connection_slots: usize,
+    /// Zero buffers when they return to the pool
+    #[serde(default)]
+    pub zero_on_release: bool,
+    /// How long a TCP stream waits for its buffer pair before it is refused
+    #[serde(default = "default_acquire_timeout_ms")]
+    pub acquire_timeout_ms: u64,
+}
+
+/// One buffer pool size tier
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BufferTierConfig {
+    /// Size of each buffer in bytes
+    pub size: usize,
+    /// Number of preallocated buffers
+    pub count: usize,
+    /// Extra buffers allocated on demand once the tier is empty
+    #[serde(default)]
+    pub max_overflow: usize,
+}
+
+impl PoolConfig {
+    /// Tiers to build the buffer pool from
+    ///
+    /// Without custom `tiers` this is the fixed 4KB/16KB/64KB layout.
+    pub fn buffer_tiers(&self) -> Vec<BufferTierConfig> {
+        if !self.tiers.is_empty() {
+            return self.tiers.clone();
+        }
+        vec![
+            BufferTierConfig {
+                size: 4096,
+                count: self.buffer_count_4k,
+                max_overflow: self.max_overflow_4k,
+            },
+            BufferTierConfig {
+                size: 16384,
+                count: self.buffer_count_16k,
+                max_overflow: self.max_overflow_16k,
+            },
+            BufferTierConfig {
+                size: 65536,
+                count: self.buffer_count_64k,
+                max_overflow: self.max_overflow_64k,
+            },
+        ]
+    }
 }
 
 /// Metrics configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct MetricsConfig {
     /// Enable metrics endpoint
     #[serde(default)]
@@ -105,10 +273,13 @@ pub struct MetricsConfig {
     /// API server bind address (for /connections, /stats endpoints)
     #[serde(default = "default_api_addr")]
     pub api_bind_addr: SocketAddr,
+    /// Allow `POST /stats/reset` on the API server to zero the counters
+    #[serde(default)]
+    pub allow_reset: bool,
 }
 
 /// Logging configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct LoggingConfig {
     /// Log level
     #[serde(default = "default_log_level")]
@@ -116,40 +287,343 @@ pub struct LoggingConfig {
     /// Output format: "json" or "pretty"
     #[serde(default = "default_log_format")]
     pub format: String,
+    /// Log one INFO event per TCP stream with its target, outcome, bytes and duration
+    #[serde(default = "default_true")]
+    pub access_log: bool,
 }
 
 /// Resource limits configuration
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Default)]
 pub struct LimitsConfig {
     /// Max bandwidth per connection (bytes/sec, 0 = unlimited)
     #[serde(default)]
     pub max_bandwidth_per_conn: u64,
-    /// Max new connections per second
+    /// Max bandwidth shared by all connections from one client IP
+    /// (bytes/sec, 0 = unlimited)
+    #[serde(default)]
+    pub max_bandwidth_per_ip: u64,
+    /// Max new connections per second (0 = unlimited)
     #[serde(default = "default_max_new_conn")]
     pub max_new_conn_per_sec: u32,
+    /// Max new connections per second from one client IP (0 = unlimited)
+    #[serde(default)]
+    pub max_new_conn_per_sec_per_ip: u32,
     /// Max memory usage in MB (0 = unlimited)
     #[serde(default)]
     pub max_memory_mb: usize,
+    /// Max open origin connections across the server (0 = unlimited)
+    #[serde(default)]
+    pub max_outbound_connections: u64,
+    /// Max TCP streams handled at once across the server (0 = unlimited)
+    #[serde(default)]
+    pub max_concurrent_streams: usize,
+}
+
+/// Routing policy configuration
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RoutingConfig {
+    /// Allow requests that match no rule
+    #[serde(default = "default_true")]
+    pub default_allow: bool,
+    /// Blocked hosts (exact match)
+    #[serde(default)]
+    pub blocked_hosts: Vec<String>,
+    /// Blocked domains; `example.com` also covers subdomains, `*.example.com`
+    /// covers only subdomains
+    #[serde(default)]
+    pub blocked_domains: Vec<String>,
+    /// Blocked IP ranges, matched against IP-literal targets
+    #[serde(default)]
+    pub blocked_cidrs: Vec<IpNet>,
+    /// Refuse targets that are, or resolve to, loopback, RFC 1918,
+    /// link-local or unique-local addresses
+    #[serde(default)]
+    pub block_private_ranges: bool,
+    /// Private ranges still reachable with `block_private_ranges`
+    #[serde(default)]
+    pub allowed_private_cidrs: Vec<IpNet>,
+    /// Blocked ports
+    #[serde(default)]
+    pub blocked_ports: Vec<u16>,
+    /// Allowed ports only (empty = all allowed)
+    #[serde(default)]
+    pub allowed_ports: Vec<u16>,
+    /// Per-client overrides, first matching rule wins
+    #[serde(default)]
+    pub source_rules: Vec<SourceRule>,
+    /// Egress interface for allowed requests, a name from `[egress] interfaces`
+    #[serde(default)]
+    pub egress: Option<String>,
+}
+
+/// Routing overrides for clients connecting from one address range
+///
+/// Unset fields fall back to the global `[routing]` values.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SourceRule {
+    /// Client address range the rule applies to
+    pub source: IpNet,
+    /// Allow requests that match no rule
+    #[serde(default)]
+    pub default_allow: Option<bool>,
+    /// Blocked hosts (exact match)
+    #[serde(default)]
+    pub blocked_hosts: Option<Vec<String>>,
+    /// Blocked ports
+    #[serde(default)]
+    pub blocked_ports: Option<Vec<u16>>,
+    /// Allowed ports only (empty = all allowed)
+    #[serde(default)]
+    pub allowed_ports: Option<Vec<u16>>,
+    /// Egress interface for allowed requests
+    #[serde(default)]
+    pub egress: Option<String>,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            default_allow: true,
+            blocked_hosts: vec![],
+            blocked_domains: vec![],
+            blocked_cidrs: vec![],
+            block_private_ranges: false,
+            allowed_private_cidrs: vec![],
+            blocked_ports: vec![],
+            allowed_ports: vec![],
+            source_rules: vec![],
+            egress: None,
+        }
+    }
+}
+
+/// Named local addresses outbound traffic can be steered through
+#[derive(Debug, Clone, PartialEq, Deserialize, Default)]
+pub struct EgressConfig {
+    /// Interface name -> local source IP, e.g. `wan1 = "192.0.2.10"`
+    #[serde(default)]
+    pub interfaces: HashMap<String, IpAddr>,
+}
+
+/// Resolution of proxy and relay targets
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DnsConfig {
+    /// `system`, or `doh`/`dot` to resolve through `upstream` over TLS
+    #[serde(default = "default_dns_mode")]
+    pub mode: String,
+    /// `https://host[:port]/path` for `doh`, `host[:port]` for `dot`
+    #[serde(default)]
+    pub upstream: Option<String>,
+    /// Keep resolved addresses this long (0 = resolve every time)
+    ///
+    /// Failed lookups are kept for at most 5 seconds.
+    #[serde(default)]
+    pub cache_ttl_secs: u64,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            mode: default_dns_mode(),
+            upstream: None,
+            cache_ttl_secs: 0,
+        }
+    }
+}
+
+/// Shared-secret client authentication
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct AuthConfig {
+    /// Token clients must send before tunneling (1-255 bytes)
+    pub token: String,
+}
+
+impl std::fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthConfig").field("token", &"<redacted>").finish()
+    }
+}
+
+/// Webhook notifications of client connects and disconnects
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NotifyConfig {
+    /// `http://host[:port]/path` receiving `{"events": [...]}` POSTs
+    pub webhook_url: String,
+    /// Only report clients in these networks (empty = all clients)
+    #[serde(default)]
+    pub client_cidrs: Vec<IpNet>,
+    /// Collect events for this many seconds before posting them together
+    #[serde(default = "default_notify_batch_interval")]
+    pub batch_interval_secs: u64,
+    /// Post early once this many events are waiting
+    #[serde(default = "default_notify_max_batch")]
+    pub max_batch: usize,
+    /// Give up on one delivery attempt after this many seconds
+    #[serde(default = "default_notify_timeout")]
+    pub timeout_secs: u64,
+    /// Further attempts after a failed delivery before the batch is dropped
+    #[serde(default = "default_notify_retries")]
+    pub retries: u32,
+}
+
+/// Optional protocol features
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FeaturesConfig {
+    /// Let clients listen on server ports and take the connections they get (`ssh -R`)
+    #[serde(default)]
+    pub allow_reverse: bool,
+    /// Address reverse tunnel listeners bind to
+    #[serde(default = "default_reverse_bind_ip")]
+    pub reverse_bind_ip: IpAddr,
+    /// Lowest port reverse tunnel listeners may bind
+    #[serde(default = "default_reverse_port_min")]
+    pub reverse_port_min: u16,
+    /// Highest port reverse tunnel listeners may bind
+    #[serde(default = "default_reverse_port_max")]
+    pub reverse_port_max: u16,
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self {
+            allow_reverse: false,
+            reverse_bind_ip: default_reverse_bind_ip(),
+            reverse_port_min: default_reverse_port_min(),
+            reverse_port_max: default_reverse_port_max(),
+        }
+    }
+}
+
+impl FeaturesConfig {
+    /// Ports clients may ask reverse tunnel listeners to bind
+    pub fn reverse_port_range(&self) -> RangeInclusive<u16> {
+        self.reverse_port_min..=self.reverse_port_max
+    }
+}
+
+/// Outbound proxy configuration
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ProxyConfig {
+    /// Lowest local port to bind for outbound TCP connections
+    #[serde(default)]
+    pub egress_port_min: Option<u16>,
+    /// Highest local port to bind for outbound TCP connections
+    #[serde(default)]
+    pub egress_port_max: Option<u16>,
+    /// Wait for the client to acknowledge all stream data before closing
+    #[serde(default)]
+    pub confirm_delivery: bool,
+    /// Prepend a PROXY protocol v2 header with the client address to origin connections
+    #[serde(default)]
+    pub send_proxy_protocol: bool,
+    /// Give up connecting to a target after this many seconds
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout_secs: u64,
+    /// Refuse a stream whose request header hasn't fully arrived after this
+    /// many milliseconds
+    #[serde(default = "default_header_timeout")]
+    pub header_timeout_ms: u64,
+    /// Close a TCP stream after this many seconds without traffic either way
+    /// (default: `quic.idle_timeout_secs`, 0 = never)
+    #[serde(default)]
+    pub stream_idle_timeout_secs: Option<u64>,
+    /// Batch origin data into stream writes of up to this many bytes (0 = off)
+    #[serde(default)]
+    pub coalesce_bytes: usize,
+    /// Longest a batch waits for more data, in microseconds
+    #[serde(default = "default_coalesce_delay")]
+    pub coalesce_delay_us: u64,
+    /// Send the first tunneled bytes in the SYN to targets (TCP Fast Open, Linux)
+    ///
+    /// The connect returns before the target answers, so clients lose the
+    /// unreachable, refused and timeout reasons (they get OK, then a reset),
+    /// `connect_timeout_secs` no longer applies, only the first resolved
+    /// address is tried, and targets that speak first stall until the client
+    /// sends something.
+    #[serde(default)]
+    pub tcp_fast_open: bool,
+    /// Read targets through io_uring splice instead of userspace reads (Linux)
+    #[serde(default)]
+    pub splice: bool,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            egress_port_min: None,
+            egress_port_max: None,
+            confirm_delivery: false,
+            send_proxy_protocol: false,
+            connect_timeout_secs: default_connect_timeout(),
+            header_timeout_ms: default_header_timeout(),
+            stream_idle_timeout_secs: None,
+            coalesce_bytes: 0,
+            coalesce_delay_us: default_coalesce_delay(),
+            tcp_fast_open: false,
+            splice: false,
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// Local port range for outbound TCP connections, if configured
+    pub fn egress_port_range(&self) -> Option<RangeInclusive<u16>> {
+        Some(self.egress_port_min?..=self.egress_port_max?)
+    }
+}
+
+/// What a config reload changes, from [`Config::reload_diff`]
+#[derive(Debug, Default)]
+pub struct ReloadDiff {
+    /// Applied settings, as `section.field: old -> new`
+    pub changes: Vec<String>,
+    /// Sections that changed but only take effect after a restart
+    pub ignored: Vec<&'static str>,
 }
 
 // Default value functions
+fn default_max_concurrent_handshakes() -> usize { 1024 }
+fn default_reuseport_sockets() -> usize { 1 }
+fn default_drain_timeout() -> u64 { 30 }
 fn default_max_connections() -> u32 { 100_000 }
 fn default_max_streams() -> u32 { 100 }
 fn default_idle_timeout() -> u64 { 30 }
 fn default_max_udp_payload() -> u16 { 1350 }
 fn default_true() -> bool { true }
+fn default_cert_reload_interval() -> u64 { 60 }
 fn default_congestion_control() -> String { "bbr".to_string() }
 fn default_buffer_count_4k() -> usize { 16384 }
 fn default_buffer_count_16k() -> usize { 4096 }
 fn default_buffer_count_64k() -> usize { 1024 }
 fn default_connection_slots() -> usize { 100_000 }
+fn default_acquire_timeout_ms() -> u64 { 100 }
 fn default_metrics_addr() -> SocketAddr { "127.0.0.1:9090".parse().unwrap() }
 fn default_api_addr() -> SocketAddr { "127.0.0.1:9091".parse().unwrap() }
 fn default_log_level() -> String { "info".to_string() }
 fn default_log_format() -> String { "json".to_string() }
 fn default_max_new_conn() -> u32 { 10_000 }
+fn default_connect_timeout() -> u64 { 10 }
+fn default_header_timeout() -> u64 { 5000 }
+fn default_coalesce_delay() -> u64 { 1000 }
+fn default_dns_mode() -> String { "system".to_string() }
+fn default_notify_batch_interval() -> u64 { 1 }
+fn default_notify_max_batch() -> usize { 100 }
+fn default_notify_timeout() -> u64 { 5 }
+fn default_notify_retries() -> u32 { 3 }
+fn default_reverse_bind_ip() -> IpAddr { IpAddr::from([0, 0, 0, 0]) }
+fn default_reverse_port_min() -> u16 { 1024 }
+fn default_reverse_port_max() -> u16 { 65535 }
 
 impl Config {
+    /// How long a TCP stream may go without traffic, `None` for no limit
+    pub fn stream_idle_timeout(&self) -> Option<Duration> {
+        match self.proxy.stream_idle_timeout_secs {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => Some(Duration::from_secs(self.quic.idle_timeout_secs)),
+        }
+    }
+
     /// Load configuration from a TOML file
     pub fn load(path: &Path) -> Result<Self> {
         let contents = std::fs::read_to_string(path)
@@ -162,8 +636,141 @@ impl Config {
         Ok(config)
     }
 
+    /// Compare a freshly loaded config against the running one
+    ///
+    /// Only `[routing]` and `[limits]` can change at runtime. Changing the
+    /// bind address or TLS settings is rejected; other sections are
+    /// reported as ignored until restart.
+    pub fn reload_diff(&self, new: &Config) -> Result<ReloadDiff> {
+        if self.server.bind_addrs != new.server.bind_addrs {
+            anyhow::bail!("server.bind_addr change requires restart");
+        }
+        if self.tls != new.tls {
+            anyhow::bail!("tls change requires restart");
+        }
+
+        let mut diff = ReloadDiff::default();
+        let (old_r, new_r) = (&self.routing, &new.routing);
+        diff.field("routing.default_allow", &old_r.default_allow, &new_r.default_allow);
+        diff.field("routing.blocked_hosts", &old_r.blocked_hosts, &new_r.blocked_hosts);
+        diff.field("routing.blocked_domains", &old_r.blocked_domains, &new_r.blocked_domains);
+        diff.field("routing.blocked_cidrs", &old_r.blocked_cidrs, &new_r.blocked_cidrs);
+        diff.field(
+            "routing.block_private_ranges",
+            &old_r.block_private_ranges,
+            &new_r.block_private_ranges,
+        );
+        diff.field(
+            "routing.allowed_private_cidrs",
+            &old_r.allowed_private_cidrs,
+            &new_r.allowed_private_cidrs,
+        );
+        diff.field("routing.blocked_ports", &old_r.blocked_ports, &new_r.blocked_ports);
+        diff.field("routing.allowed_ports", &old_r.allowed_ports, &new_r.allowed_ports);
+        diff.field("routing.source_rules", &old_r.source_rules, &new_r.source_rules);
+        diff.field("routing.egress", &old_r.egress, &new_r.egress);
+
+        let (old_l, new_l) = (&self.limits, &new.limits);
+        diff.field(
+            "limits.max_bandwidth_per_conn",
+            &old_l.max_bandwidth_per_conn,
+            &new_l.max_bandwidth_per_conn,
+        );
+        diff.field(
+            "limits.max_bandwidth_per_ip",
+            &old_l.max_bandwidth_per_ip,
+            &new_l.max_bandwidth_per_ip,
+        );
+        diff.field(
+            "limits.max_new_conn_per_sec",
+            &old_l.max_new_conn_per_sec,
+            &new_l.max_new_conn_per_sec,
+        );
+        diff.field(
+            "limits.max_new_conn_per_sec_per_ip",
+            &old_l.max_new_conn_per_sec_per_ip,
+            &new_l.max_new_conn_per_sec_per_ip,
+        );
+        diff.field("limits.max_memory_mb", &old_l.max_memory_mb, &new_l.max_memory_mb);
+        diff.field(
+            "limits.max_outbound_connections",
+            &old_l.max_outbound_connections,
+            &new_l.max_outbound_connections,
+        );
+
+        let sections = [
+            ("server", self.server != new.server),
+            ("quic", self.quic != new.quic),
+            ("pool", self.pool != new.pool),
+            ("metrics", self.metrics != new.metrics),
+            ("logging", self.logging != new.logging),
+            ("proxy", self.proxy != new.proxy),
+            ("egress", self.egress != new.egress),
+            ("dns", self.dns != new.dns),
+            ("auth", self.auth != new.auth),
+            ("notify", self.notify != new.notify),
+            ("features", self.features != new.features),
+        ];
+        diff.ignored = sections
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| name)
+            .collect();
+        // The stream limiter is sized once at startup
+        if old_l.max_concurrent_streams != new_l.max_concurrent_streams {
+            diff.ignored.push("limits.max_concurrent_streams");
+        }
+
+        Ok(diff)
+    }
+
     /// Validate configuration values
     fn validate(&self) -> Result<()> {
+        if self.server.bind_addrs.is_empty() {
+            anyhow::bail!("server.bind_addr must list at least one address");
+        }
+        for (i, addr) in self.server.bind_addrs.iter().enumerate() {
+            if self.server.bind_addrs[..i].contains(addr) {
+                anyhow::bail!("server.bind_addr lists {} more than once", addr);
+            }
+        }
+        if self.server.max_concurrent_handshakes == 0 {
+            anyhow::bail!("max_concurrent_handshakes must be > 0");
+        }
+        if !self.server.reuse_port && self.server.reuseport_sockets > 1 {
+            anyhow::bail!("server.reuseport_sockets > 1 requires server.reuse_port");
+        }
+        if matches!(self.server.dscp, Some(dscp) if dscp > 63) {
+            anyhow::bail!("server.dscp must be 0-63");
+        }
+        if let Some(auth) = &self.auth {
+            if auth.token.is_empty() || auth.token.len() > 255 {
+                anyhow::bail!("auth.token must be 1-255 bytes");
+            }
+        }
+        if let Some(notify) = &self.notify {
+            let authority = notify
+                .webhook_url
+                .strip_prefix("http://")
+                .map(|rest| rest.split('/').next().unwrap_or_default());
+            if authority.map_or(true, str::is_empty) {
+                anyhow::bail!("notify.webhook_url must be an http:// URL with a host");
+            }
+            if notify.max_batch == 0 || notify.timeout_secs == 0 {
+                anyhow::bail!("notify.max_batch and notify.timeout_secs must be > 0");
+            }
+        }
+        if self.tls.require_client_cert && self.tls.client_ca_path.is_none() {
+            anyhow::bail!("tls.require_client_cert requires tls.client_ca_path");
+        }
+        for (i, cert) in self.tls.certs.iter().enumerate() {
+            if cert.sni.is_empty() {
+                anyhow::bail!("tls.certs sni must not be empty");
+            }
+            if self.tls.certs[..i].iter().any(|c| c.sni.eq_ignore_ascii_case(&cert.sni)) {
+                anyhow::bail!("tls.certs lists sni {:?} more than once", cert.sni);
+            }
+        }
         if self.quic.max_connections == 0 {
             anyhow::bail!("max_connections must be > 0");
         }
@@ -173,13 +780,88 @@ impl Config {
         if self.quic.idle_timeout_secs == 0 {
             anyhow::bail!("idle_timeout_secs must be > 0");
         }
+        if self.proxy.connect_timeout_secs == 0 {
+            anyhow::bail!("connect_timeout_secs must be > 0");
+        }
+        if self.proxy.header_timeout_ms == 0 {
+            anyhow::bail!("header_timeout_ms must be > 0");
+        }
+        if self.quic.max_udp_payload == 0 {
+            anyhow::bail!("max_udp_payload must be > 0");
+        }
+        if !CONGESTION_CONTROLLERS.contains(&self.quic.congestion_control.as_str()) {
+            anyhow::bail!(
+                "unknown congestion_control {:?} (supported: {})",
+                self.quic.congestion_control,
+                CONGESTION_CONTROLLERS.join(", ")
+            );
+        }
+        if !DNS_MODES.contains(&self.dns.mode.as_str()) {
+            anyhow::bail!(
+                "unknown dns.mode {:?} (supported: {})",
+                self.dns.mode,
+                DNS_MODES.join(", ")
+            );
+        }
+        match (self.dns.mode.as_str(), self.dns.upstream.as_deref()) {
+            ("system", _) => {}
+            ("doh", Some(url)) if !url.starts_with("https://") => {
+                anyhow::bail!("dns.upstream must be an https:// URL in doh mode");
+            }
+            (mode, None) => anyhow::bail!("dns.mode = {:?} requires dns.upstream", mode),
+            _ => {}
+        }
         if self.pool.connection_slots == 0 {
             anyhow::bail!("connection_slots must be > 0");
         }
+        for (i, tier) in self.pool.tiers.iter().enumerate() {
+            if tier.size == 0 {
+                anyhow::bail!("pool.tiers size must be > 0");
+            }
+            if self.pool.tiers[..i].iter().any(|t| t.size == tier.size) {
+                anyhow::bail!("pool.tiers lists size {} more than once", tier.size);
+            }
+        }
+        // TCP streams copy through buffers of at least this size
+        let stream_buffer = BufferSize::Medium.as_usize();
+        if !self.pool.tiers.is_empty()
+            && self.pool.tiers.iter().all(|tier| tier.size < stream_buffer)
+        {
+            anyhow::bail!("pool.tiers needs a tier of at least {} bytes", stream_buffer);
+        }
+        if let Some(port) = self
+            .routing
+            .allowed_ports
+            .iter()
+            .find(|p| self.routing.blocked_ports.contains(p))
+        {
+            anyhow::bail!("routing port {} is both allowed and blocked", port);
+        }
+        match (self.proxy.egress_port_min, self.proxy.egress_port_max) {
+            (None, None) => {}
+            (Some(min), Some(max)) if min > 0 && min <= max => {}
+            (Some(_), Some(_)) => {
+                anyhow::bail!("egress_port_min must be > 0 and <= egress_port_max");
+            }
+            _ => anyhow::bail!("egress_port_min and egress_port_max must be set together"),
+        }
+        if self.features.reverse_port_min == 0
+            || self.features.reverse_port_min > self.features.reverse_port_max
+        {
+            anyhow::bail!("reverse_port_min must be > 0 and <= reverse_port_max");
+        }
         Ok(())
     }
 }
 
+impl ReloadDiff {
+    fn field<T: PartialEq + std::fmt::Debug>(&mut self, name: &str, old: &T, new: &T) {
+        if old != new {
+            self.changes.push(format!("{}: {:?} -> {:?}", name, old, new));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -187,10 +869,324 @@ mod tests {
     #[test]
     fn test_default_workers() {
         let config = ServerConfig {
-            bind_addr: "0.0.0.0:443".parse().unwrap(),
+            bind_addrs: vec!["0.0.0.0:443".parse().unwrap()],
             workers: 0,
+            max_concurrent_handshakes: 1024,
+            reuseport_sockets: 0,
+            reuse_port: true,
+            dscp: None,
+            drain_timeout_secs: 30,
         };
         assert!(config.effective_workers() > 0);
+        assert_eq!(config.effective_reuseport_sockets(), config.effective_workers());
+
+        let config = ServerConfig {
+            reuse_port: false,
+            ..config
+        };
+        assert_eq!(config.effective_reuseport_sockets(), 1);
+    }
+
+    fn parse(extra: &str) -> Config {
+        let base = r#"
+            [server]
+            bind_addr = "127.0.0.1:4433"
+            [quic]
+            [tls]
+            cert_path = "cert.pem"
+            key_path = "key.pem"
+            [pool]
+            [metrics]
+            [logging]
+        "#;
+        toml::from_str(&format!("{}\n{}", base, extra)).unwrap()
+    }
+
+    #[test]
+    fn test_bind_addr_list() {
+        let config = parse("");
+        assert_eq!(config.server.bind_addrs, vec!["127.0.0.1:4433".parse().unwrap()]);
+
+        let mut config: Config = toml::from_str(
+            r#"
+            [server]
+            bind_addr = ["0.0.0.0:443", "[::]:443"]
+            [quic]
+            [tls]
+            cert_path = "cert.pem"
+            key_path = "key.pem"
+            [pool]
+            [metrics]
+            [logging]
+        "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.server.bind_addrs.len(), 2);
+        assert!(config.server.bind_addrs[1].is_ipv6());
+
+        config.server.bind_addrs.push("0.0.0.0:443".parse().unwrap());
+        assert!(config.validate().is_err());
+
+        config.server.bind_addrs.clear();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_pool_tiers() {
+        let config = parse(
+            r#"
+            [[pool.tiers]]
+            size = 9000
+            count = 8
+
+            [[pool.tiers]]
+            size = 16384
+            count = 8
+        "#,
+        );
+        assert!(config.validate().is_ok());
+        assert_eq!(config.pool.buffer_tiers().len(), 2);
+
+        let mut dup = config.clone();
+        dup.pool.tiers[1].size = 9000;
+        assert!(dup.validate().is_err());
+
+        // Streams would fall back to unpooled buffers
+        let mut small = config;
+        small.pool.tiers.pop();
+        assert!(small.validate().unwrap_err().to_string().contains("at least 16384"));
+    }
+
+    #[test]
+    fn test_dscp_range() {
+        let mut config = parse("");
+        assert_eq!(config.server.dscp, None);
+
+        config.server.dscp = Some(63);
+        assert!(config.validate().is_ok());
+        config.server.dscp = Some(64);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_sni_certs() {
+        let config = parse(
+            r#"
+            [[tls.certs]]
+            sni = "a.example.com"
+            cert_path = "a.pem"
+            key_path = "a.key"
+
+            [[tls.certs]]
+            sni = "*.example.org"
+            cert_path = "org.pem"
+            key_path = "org.key"
+        "#,
+        );
+        assert!(config.validate().is_ok());
+        assert_eq!(config.tls.certs.len(), 2);
+        assert_eq!(config.tls.certs[1].sni, "*.example.org");
+
+        let mut dup = config.clone();
+        dup.tls.certs[1].sni = "A.example.com".to_string();
+        assert!(dup.validate().is_err());
+
+        let mut empty = config;
+        empty.tls.certs[0].sni.clear();
+        assert!(empty.validate().is_err());
+    }
+
+    #[test]
+    fn test_notify_section() {
+        assert!(parse("").notify.is_none());
+
+        let config = parse(
+            r#"
+            [notify]
+            webhook_url = "http://hooks.internal:8080/mytunnel"
+            client_cidrs = ["10.0.0.0/8"]
+        "#,
+        );
+        assert!(config.validate().is_ok());
+        let notify = config.notify.as_ref().unwrap();
+        assert_eq!(notify.batch_interval_secs, 1);
+        assert_eq!(notify.retries, 3);
+        assert_eq!(notify.client_cidrs, vec!["10.0.0.0/8".parse::<IpNet>().unwrap()]);
+
+        for url in ["https://hooks.internal/", "http:///path", "hooks.internal"] {
+            let mut bad = config.clone();
+            bad.notify.as_mut().unwrap().webhook_url = url.to_string();
+            assert!(bad.validate().is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_dns_section() {
+        let config = parse("");
+        assert_eq!(config.dns.mode, "system");
+        assert_eq!(config.dns.cache_ttl_secs, 0);
+        assert_eq!(parse("[dns]\ncache_ttl_secs = 30").dns.cache_ttl_secs, 30);
+
+        let mut config = parse("[dns]\nmode = \"doh\"\nupstream = \"https://1.1.1.1/dns-query\"");
+        assert!(config.validate().is_ok());
+        config.dns.upstream = Some("1.1.1.1".to_string());
+        assert!(config.validate().is_err());
+        config.dns.mode = "dot".to_string();
+        assert!(config.validate().is_ok());
+        config.dns.upstream = None;
+        assert!(config.validate().is_err());
+        config.dns.mode = "mdns".to_string();
+        assert!(config.validate().unwrap_err().to_string().contains("system, doh, dot"));
+    }
+
+    #[test]
+    fn test_features_section() {
+        let config = parse("");
+        assert!(!config.features.allow_reverse);
+        assert_eq!(config.features.reverse_bind_ip, IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(config.features.reverse_port_range(), 1024..=65535);
+
+        let config = parse("[features]\nallow_reverse = true\nreverse_bind_ip = \"::1\"");
+        assert!(config.features.allow_reverse);
+        assert_eq!(config.features.reverse_bind_ip, "::1".parse::<IpAddr>().unwrap());
+
+        let ports = parse("[features]\nreverse_port_min = 8000\nreverse_port_max = 8099");
+        assert_eq!(ports.features.reverse_port_range(), 8000..=8099);
+        assert!(ports.validate().is_ok());
+        assert!(parse("[features]\nreverse_port_min = 0").validate().is_err());
+        assert!(parse("[features]\nreverse_port_min = 9000\nreverse_port_max = 8000")
+            .validate()
+            .is_err());
+
+        // Listeners are only opened for new requests, so a reload can't apply it
+        let diff = parse("").reload_diff(&config).unwrap();
+        assert_eq!(diff.ignored, ["features"]);
+    }
+
+    #[test]
+    fn test_routing_section() {
+        let config = parse(
+            r#"
+            [routing]
+            default_allow = false
+            blocked_hosts = ["blocked.com"]
+            allowed_ports = [80, 443]
+        "#,
+        );
+        assert!(config.validate().is_ok());
+        assert!(!config.routing.default_allow);
+        assert_eq!(config.routing.blocked_hosts, vec!["blocked.com"]);
+        assert_eq!(config.routing.allowed_ports, vec![80, 443]);
+
+        // Section is optional
+        assert!(parse("").routing.default_allow);
+    }
+
+    #[test]
+    fn test_routing_port_overlap_rejected() {
+        let config = parse(
+            r#"
+            [routing]
+            blocked_ports = [25, 443]
+            allowed_ports = [443]
+        "#,
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_congestion_control() {
+        let mut config = parse("");
+        assert_eq!(config.quic.congestion_control, "bbr");
+
+        for name in CONGESTION_CONTROLLERS {
+            config.quic.congestion_control = name.to_string();
+            assert!(config.validate().is_ok());
+        }
+
+        config.quic.congestion_control = "vegas".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("vegas"));
+        assert!(err.contains("bbr, cubic, newreno"));
+    }
+
+    #[test]
+    fn test_reload_diff() {
+        let old = parse("[limits]");
+
+        let new = parse("[routing]\nblocked_ports = [25]\n[limits]\nmax_new_conn_per_sec = 50");
+        let diff = old.reload_diff(&new).unwrap();
+        assert_eq!(
+            diff.changes,
+            vec![
+                "routing.blocked_ports: [] -> [25]",
+                "limits.max_new_conn_per_sec: 10000 -> 50",
+            ]
+        );
+        assert!(diff.ignored.is_empty());
+
+        let new = parse("[routing]\nblock_private_ranges = true\n[limits]");
+        let diff = old.reload_diff(&new).unwrap();
+        assert_eq!(diff.changes, vec!["routing.block_private_ranges: false -> true"]);
+
+        let new = parse("[routing]\nblocked_cidrs = [\"10.0.0.0/8\"]\n[limits]");
+        let diff = old.reload_diff(&new).unwrap();
+        assert_eq!(diff.changes, vec!["routing.blocked_cidrs: [] -> [10.0.0.0/8]"]);
+
+        let new = parse("[routing]\nblocked_domains = [\"*.example.com\"]\n[limits]");
+        let diff = old.reload_diff(&new).unwrap();
+        assert_eq!(diff.changes, vec!["routing.blocked_domains: [] -> [\"*.example.com\"]"]);
+
+        let new = parse("[[routing.source_rules]]\nsource = \"10.1.0.0/16\"\n[limits]");
+        let diff = old.reload_diff(&new).unwrap();
+        assert_eq!(diff.changes.len(), 1);
+        assert!(diff.changes[0].starts_with("routing.source_rules: [] -> [SourceRule"));
+
+        let mut new = parse("[limits]");
+        new.quic.idle_timeout_secs = 60;
+        let diff = old.reload_diff(&new).unwrap();
+        assert!(diff.changes.is_empty());
+        assert_eq!(diff.ignored, vec!["quic"]);
+
+        let mut new = old.clone();
+        new.limits.max_concurrent_streams = 5000;
+        let diff = old.reload_diff(&new).unwrap();
+        assert!(diff.changes.is_empty());
+        assert_eq!(diff.ignored, vec!["limits.max_concurrent_streams"]);
+
+        let mut new = old.clone();
+        new.limits.max_bandwidth_per_ip = 125_000;
+        new.limits.max_new_conn_per_sec_per_ip = 5;
+        let diff = old.reload_diff(&new).unwrap();
+        assert_eq!(
+            diff.changes,
+            vec![
+                "limits.max_bandwidth_per_ip: 0 -> 125000",
+                "limits.max_new_conn_per_sec_per_ip: 0 -> 5",
+            ]
+        );
+
+        let mut new = old.clone();
+        new.tls.cert_path = "other.pem".to_string();
+        assert!(old.reload_diff(&new).is_err());
+
+        let mut new = old.clone();
+        new.server.bind_addrs = vec!["127.0.0.1:4434".parse().unwrap()];
+        assert!(old.reload_diff(&new).is_err());
+    }
+
+    #[test]
+    fn test_egress_port_range() {
+        let config = parse("[proxy]\negress_port_min = 40000\negress_port_max = 40010");
+        assert!(config.validate().is_ok());
+        assert_eq!(config.proxy.egress_port_range(), Some(40000..=40010));
+
+        assert_eq!(parse("").proxy.egress_port_range(), None);
+        assert!(parse("[proxy]\negress_port_min = 40000").validate().is_err());
+        assert!(parse("[proxy]\negress_port_min = 2\negress_port_max = 1")
+            .validate()
+            .is_err());
     }
 }
 
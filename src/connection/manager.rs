@@ -3,17 +3,62 @@
 //! Manages connection lifecycle and provides fast lookup.
 
 use dashmap::DashMap;
+use quinn::Connection;
+use serde::Serialize;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, Notify};
 use tracing::{debug, info, warn};
 
-use super::state::{ConnectionId, ConnectionInfo, ConnectionState};
+use super::query::{ConnectionPage, ConnectionQuery};
+use super::state::{
+    ConnectionId, ConnectionInfo, ConnectionState, StreamDistribution, UserPolicy,
+};
+use crate::close_code::CloseCode;
 use crate::metrics::METRICS;
 use crate::pool::{ConnectionSlab, SlabHandle};
 
+/// Events buffered per subscriber before the slowest one starts missing them
+const EVENT_CAPACITY: usize = 1024;
+
+/// How often a drain logs the connections it is still waiting for
+const DRAIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Connection lifecycle event, from [`ConnectionManager::subscribe_events`]
+///
+/// Serializes with an `event` field naming the variant in snake case.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ConnectionEvent {
+    /// Registered, handshake in progress
+    Opened {
+        /// Connection ID (hex string)
+        id: String,
+        client_addr: SocketAddr,
+    },
+    /// Handshake complete
+    Activated {
+        /// Connection ID (hex string)
+        id: String,
+    },
+    /// Finishing in-flight streams during shutdown
+    Draining {
+        /// Connection ID (hex string)
+        id: String,
+    },
+    /// Unregistered, with its final totals
+    Closed {
+        /// Connection ID (hex string)
+        id: String,
+        client_addr: SocketAddr,
+        bytes_rx: u64,
+        bytes_tx: u64,
+        duration_secs: f64,
+    },
+}
+
 /// Connection manager configuration
 pub struct ConnectionManagerConfig {
     /// Maximum concurrent connections
@@ -26,7 +71,7 @@ pub struct ConnectionManagerConfig {
 pub struct ConnectionManager {
     /// Connection state slab
     connections: ConnectionSlab<ConnectionState>,
-    /// Fast lookup by connection ID
+    /// Slot lookup by connection ID; iteration goes through the slab
     id_to_handle: DashMap<ConnectionId, SlabHandle>,
     /// ID generator
     next_id: AtomicU64,
@@ -34,12 +79,19 @@ pub struct ConnectionManager {
     config: ConnectionManagerConfig,
     /// Shutdown signal sender
     shutdown_tx: broadcast::Sender<()>,
+    /// Notified whenever a connection is unregistered
+    unregistered: Notify,
+    /// Lifecycle events for API and embedder subscribers
+    events_tx: broadcast::Sender<ConnectionEvent>,
+    /// Open connections per authenticated user
+    user_connections: DashMap<String, u32>,
 }
 
 impl ConnectionManager {
     /// Create a new connection manager
     pub fn new(config: ConnectionManagerConfig) -> Arc<Self> {
         let (shutdown_tx, _) = broadcast::channel(1);
+        let (events_tx, _) = broadcast::channel(EVENT_CAPACITY);
         
         Arc::new(Self {
             connections: ConnectionSlab::new(config.max_connections),
@@ -47,16 +99,19 @@ impl ConnectionManager {
             next_id: AtomicU64::new(1),
             config,
             shutdown_tx,
+            unregistered: Notify::new(),
+            events_tx,
+            user_connections: DashMap::new(),
         })
     }
 
     /// Register a new connection
-    pub fn register(&self, client_addr: SocketAddr) -> Option<ConnectionId> {
+    pub fn register(&self, client_addr: SocketAddr, connection: Connection) -> Option<ConnectionId> {
         // Generate unique ID
         let id = ConnectionId::from_raw(self.next_id.fetch_add(1, Ordering::Relaxed));
         
         // Create connection state
-        let state = ConnectionState::new(id, client_addr);
+        let state = ConnectionState::new(id, client_addr, connection);
 
         // Insert into slab
         let handle = self.connections.insert(state)?;
@@ -66,6 +121,10 @@ impl ConnectionManager {
 
         METRICS.connection_opened();
         info!(conn_id = %id, %client_addr, "User connected");
+        let _ = self.events_tx.send(ConnectionEvent::Opened {
+            id: id.to_string(),
+            client_addr,
+        });
 
         Some(id)
     }
@@ -76,14 +135,60 @@ impl ConnectionManager {
             if let Some(mut state) = self.connections.get_mut(*handle) {
                 state.set_active();
                 debug!(conn_id = %id, "Connection activated");
+                let _ = self.events_tx.send(ConnectionEvent::Activated { id: id.to_string() });
             }
         }
     }
 
+    /// Attach the limits of the user a connection authenticated as
+    ///
+    /// Returns false, leaving the connection without a policy, if the
+    /// user already has `max_connections` open. Counted until the
+    /// connection is unregistered.
+    pub fn set_policy(&self, id: ConnectionId, policy: Arc<UserPolicy>) -> bool {
+        let Some(mut state) = self.get_mut(id) else {
+            return false;
+        };
+        let mut count = self.user_connections.entry(policy.user.clone()).or_insert(0);
+        if matches!(policy.max_connections, Some(max) if max > 0 && *count >= max) {
+            return false;
+        }
+        *count += 1;
+        state.policy = Some(policy);
+        true
+    }
+
+    /// Number of open connections authenticated as `user`
+    pub fn user_connection_count(&self, user: &str) -> u32 {
+        self.user_connections.get(user).map_or(0, |count| *count)
+    }
+
+    /// Forcibly close a connection
+    ///
+    /// Returns false if the ID is unknown. The connection is unregistered
+    /// by its handler once the close is observed.
+    pub fn disconnect(&self, id: ConnectionId) -> bool {
+        // Clone out so the slab lock isn't held while closing
+        let Some(connection) = self.get(id).map(|state| state.connection.clone()) else {
+            return false;
+        };
+
+        CloseCode::Disconnected.close(&connection);
+        info!(conn_id = %id, "Connection closed by operator");
+        true
+    }
+
     /// Unregister a connection
     pub fn unregister(&self, id: ConnectionId) {
         if let Some((_, handle)) = self.id_to_handle.remove(&id) {
             if let Some(state) = self.connections.remove(handle) {
+                if let Some(policy) = &state.policy {
+                    self.user_connections
+                        .remove_if_mut(&policy.user, |_, count| {
+                            *count -= 1;
+                            *count == 0
+                        });
+                }
                 METRICS.connection_closed();
                 info!(
                     conn_id = %id,
@@ -93,6 +198,14 @@ impl ConnectionManager {
                     bytes_tx = state.bytes_tx,
                     "User disconnected"
                 );
+                let _ = self.events_tx.send(ConnectionEvent::Closed {
+                    id: id.to_string(),
+                    client_addr: state.client_addr,
+                    bytes_rx: state.bytes_rx,
+                    bytes_tx: state.bytes_tx,
+                    duration_secs: state.duration().as_secs_f64(),
+                });
+                self.unregistered.notify_waiters();
             }
         }
     }
@@ -110,21 +223,72 @@ impl ConnectionManager {
     }
 
     /// Update connection activity and record traffic
+    ///
+    /// Only the per-connection totals change; the global byte counters
+    /// are updated by the proxies as data moves.
     pub fn record_traffic(&self, id: ConnectionId, rx: u64, tx: u64) {
         if let Some(handle) = self.id_to_handle.get(&id) {
             if let Some(mut state) = self.connections.get_mut(*handle) {
                 if rx > 0 {
                     state.record_rx(rx);
-                    METRICS.bytes_rx(rx);
                 }
                 if tx > 0 {
                     state.record_tx(tx);
-                    METRICS.bytes_tx(tx);
                 }
             }
         }
     }
 
+    /// Record the client's current address, as seen by QUIC
+    ///
+    /// Returns true, logging the move and counting it, if the connection
+    /// migrated to a new path since the last check.
+    pub fn update_client_addr(&self, id: ConnectionId, client_addr: SocketAddr) -> bool {
+        let Some(mut state) = self.get_mut(id) else {
+            return false;
+        };
+        if state.client_addr == client_addr {
+            return false;
+        }
+
+        let old_addr = std::mem::replace(&mut state.client_addr, client_addr);
+        METRICS.connection_migrated();
+        info!(
+            conn_id = %id,
+            %old_addr,
+            new_addr = %client_addr,
+            rtt_ms = state.connection.rtt().as_millis() as u64,
+            "Path migrated"
+        );
+        true
+    }
+
+    /// Count a new stream unless the connection already has `max` open
+    ///
+    /// Returns false, counting nothing, at the limit or for an unknown id.
+    pub fn try_open_stream(&self, id: ConnectionId, max: u32) -> bool {
+        let Some(handle) = self.id_to_handle.get(&id) else {
+            return false;
+        };
+        let Some(mut state) = self.connections.get_mut(*handle) else {
+            return false;
+        };
+        if state.active_streams >= max {
+            return false;
+        }
+        state.stream_opened();
+        true
+    }
+
+    /// Count a stream opened with [`try_open_stream`](Self::try_open_stream) as closed
+    pub fn stream_closed(&self, id: ConnectionId) {
+        if let Some(handle) = self.id_to_handle.get(&id) {
+            if let Some(mut state) = self.connections.get_mut(*handle) {
+                state.stream_closed();
+            }
+        }
+    }
+
     /// Get current connection count
     pub fn connection_count(&self) -> usize {
         self.connections.len()
@@ -132,14 +296,31 @@ impl ConnectionManager {
 
     /// List all active connections
     pub fn list_connections(&self) -> Vec<ConnectionInfo> {
-        self.id_to_handle
+        self.connections
             .iter()
-            .filter_map(|entry| {
-                self.connections.get(*entry.value()).map(|state| state.to_info())
-            })
+            .map(|(_, state)| state.to_info())
             .collect()
     }
 
+    /// Streams open on each connection right now
+    pub fn stream_distribution(&self) -> StreamDistribution {
+        StreamDistribution::from_states(self.connections.iter().map(|(_, state)| state))
+    }
+
+    /// List the connections matching `query`, sorted and paged
+    ///
+    /// Filters run against the live state, so connections they exclude
+    /// never pay for `ConnectionInfo` construction.
+    pub fn query_connections(&self, query: &ConnectionQuery) -> ConnectionPage {
+        let matching = self
+            .connections
+            .iter()
+            .filter(|(_, state)| query.matches(state))
+            .map(|(_, state)| state.to_info())
+            .collect();
+        query.page(matching)
+    }
+
     /// Check if at capacity
     pub fn is_full(&self) -> bool {
         self.connections.is_full()
@@ -150,6 +331,14 @@ impl ConnectionManager {
         self.shutdown_tx.subscribe()
     }
 
+    /// Receive lifecycle events from now on
+    ///
+    /// A subscriber that falls more than 1024 events behind skips the
+    /// oldest ones, seeing `RecvError::Lagged` once.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events_tx.subscribe()
+    }
+
     /// Signal shutdown to all connections
     pub fn signal_shutdown(&self) {
         info!("Signaling shutdown to all connections");
@@ -157,6 +346,10 @@ impl ConnectionManager {
     }
 
     /// Drain all connections (graceful shutdown)
+    ///
+    /// Handlers finish their in-flight streams after `signal_shutdown`;
+    /// this returns once every connection has unregistered, or closes the
+    /// stragglers after `timeout`.
     pub async fn drain(&self, timeout: Duration) {
         info!(
             connections = self.connection_count(),
@@ -164,43 +357,61 @@ impl ConnectionManager {
         );
 
         // Mark all connections as draining
-        for entry in self.id_to_handle.iter() {
-            if let Some(mut state) = self.connections.get_mut(*entry.value()) {
-                state.set_draining();
+        self.connections.for_each(|_, state| {
+            state.set_draining();
+            let _ = self.events_tx.send(ConnectionEvent::Draining { id: state.id.to_string() });
+        });
+
+        // Wait for connections to close or timeout, reporting progress
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut next_progress = tokio::time::Instant::now() + DRAIN_PROGRESS_INTERVAL;
+        loop {
+            // Register before checking so an unregister in between isn't missed
+            let unregistered = self.unregistered.notified();
+            let remaining = self.connection_count();
+            let now = tokio::time::Instant::now();
+            if remaining == 0 || now >= deadline {
+                break;
             }
-        }
-
-        // Wait for connections to close or timeout
-        let start = std::time::Instant::now();
-        while self.connection_count() > 0 && start.elapsed() < timeout {
-            tokio::time::sleep(Duration::from_millis(100)).await;
+            if now >= next_progress {
+                info!(
+                    remaining,
+                    secs_left = (deadline - now).as_secs(),
+                    "Draining connections"
+                );
+                next_progress = now + DRAIN_PROGRESS_INTERVAL;
+            }
+            let _ = tokio::time::timeout_at(deadline.min(next_progress), unregistered).await;
         }
 
         let remaining = self.connection_count();
         if remaining > 0 {
             warn!(remaining, "Force closing remaining connections after drain timeout");
+            self.close_all();
         } else {
             info!("All connections drained successfully");
         }
     }
 
+    /// Close every connection with `server shutdown` without waiting for
+    /// their streams
+    pub fn close_all(&self) {
+        self.connections.for_each(|_, state| {
+            CloseCode::ServerShutdown.close(&state.connection);
+        });
+    }
+
     /// Cleanup idle connections
     pub fn cleanup_idle(&self) -> usize {
         let mut cleaned = 0;
         let idle_timeout = self.config.idle_timeout;
 
-        // Collect IDs to remove (can't remove while iterating)
+        // Collect IDs to remove (can't remove while a slot is locked)
         let to_remove: Vec<ConnectionId> = self
-            .id_to_handle
+            .connections
             .iter()
-            .filter_map(|entry| {
-                if let Some(state) = self.connections.get(*entry.value()) {
-                    if state.idle_duration() > idle_timeout {
-                        return Some(*entry.key());
-                    }
-                }
-                None
-            })
+            .filter(|(_, state)| state.idle_duration() > idle_timeout)
+            .map(|(_, state)| state.id)
             .collect();
 
         for id in to_remove {
@@ -214,22 +425,45 @@ impl ConnectionManager {
 
         cleaned
     }
+
+    /// Close and unregister up to `max` connections without open streams,
+    /// longest idle first, to shed load
+    ///
+    /// Clients see close code 1 (`server at capacity`) and back off before
+    /// reconnecting.
+    pub fn shed_idle(&self, max: usize) -> usize {
+        let mut idle: Vec<(Duration, ConnectionId, Connection)> = self
+            .connections
+            .iter()
+            .filter(|(_, state)| state.active_streams == 0)
+            .map(|(_, state)| (state.idle_duration(), state.id, state.connection.clone()))
+            .collect();
+        idle.sort_unstable_by_key(|(idle_for, ..)| std::cmp::Reverse(*idle_for));
+        idle.truncate(max);
+
+        for (_, id, connection) in &idle {
+            CloseCode::Capacity.close(connection);
+            self.unregister(*id);
+        }
+        idle.len()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_connection_lifecycle() {
+    #[tokio::test]
+    async fn test_connection_lifecycle() {
         let config = ConnectionManagerConfig {
             max_connections: 100,
             idle_timeout: Duration::from_secs(30),
         };
         let manager = ConnectionManager::new(config);
+        let pair = crate::util::testing::quic_pair().await;
 
         let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
-        let id = manager.register(addr).unwrap();
+        let id = manager.register(addr, pair.server.clone()).unwrap();
 
         assert_eq!(manager.connection_count(), 1);
 
@@ -237,10 +471,241 @@ mod tests {
         {
             let state = manager.get(id).unwrap();
             assert!(state.is_active());
+            assert_eq!(state.connection.stable_id(), pair.server.stable_id());
         }
 
         manager.unregister(id);
         assert_eq!(manager.connection_count(), 0);
     }
+
+    #[tokio::test]
+    async fn test_user_connection_limit() {
+        let manager = ConnectionManager::new(ConnectionManagerConfig {
+            max_connections: 100,
+            idle_timeout: Duration::from_secs(30),
+        });
+        let pair = crate::util::testing::quic_pair().await;
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let free = Arc::new(UserPolicy {
+            user: "free".to_string(),
+            max_connections: Some(2),
+            ..Default::default()
+        });
+
+        let ids: Vec<_> = (0..3)
+            .map(|_| manager.register(addr, pair.server.clone()).unwrap())
+            .collect();
+        assert!(manager.set_policy(ids[0], free.clone()));
+        assert!(manager.set_policy(ids[1], free.clone()));
+        assert!(!manager.set_policy(ids[2], free.clone()));
+        assert!(manager.get(ids[2]).unwrap().policy.is_none());
+        assert_eq!(manager.user_connection_count("free"), 2);
+        assert_eq!(manager.get(ids[0]).unwrap().to_info().user.as_deref(), Some("free"));
+
+        // Closing one of the user's connections frees a slot
+        manager.unregister(ids[0]);
+        assert!(manager.set_policy(ids[2], free.clone()));
+
+        // Other users and unlimited users are counted separately
+        let paid = Arc::new(UserPolicy {
+            user: "paid".to_string(),
+            ..Default::default()
+        });
+        let id = manager.register(addr, pair.server.clone()).unwrap();
+        assert!(manager.set_policy(id, paid));
+        assert_eq!(manager.user_connection_count("paid"), 1);
+
+        manager.unregister(ids[1]);
+        manager.unregister(ids[2]);
+        assert_eq!(manager.user_connection_count("free"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let manager = ConnectionManager::new(ConnectionManagerConfig {
+            max_connections: 10,
+            idle_timeout: Duration::from_secs(30),
+        });
+        let pair = crate::util::testing::quic_pair().await;
+        let mut events = manager.subscribe_events();
+
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let id = manager.register(addr, pair.server.clone()).unwrap();
+        manager.activate(id);
+        manager.record_traffic(id, 10, 20);
+        manager.unregister(id);
+
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Opened { id: id.to_string(), client_addr: addr }
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Activated { id: id.to_string() }
+        );
+        match events.recv().await.unwrap() {
+            ConnectionEvent::Closed { id: closed, bytes_rx, bytes_tx, .. } => {
+                assert_eq!(closed, id.to_string());
+                assert_eq!((bytes_rx, bytes_tx), (10, 20));
+            }
+            event => panic!("unexpected event: {event:?}"),
+        }
+
+        // Draining is announced per connection
+        let id = manager.register(addr, pair.server.clone()).unwrap();
+        manager.drain(Duration::from_millis(10)).await;
+        assert!(matches!(events.recv().await.unwrap(), ConnectionEvent::Opened { .. }));
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ConnectionEvent::Draining { id: id.to_string() }
+        );
+
+        // Slow subscribers skip the oldest events
+        for _ in 0..EVENT_CAPACITY + 1 {
+            manager.activate(id);
+        }
+        assert!(matches!(
+            events.recv().await,
+            Err(broadcast::error::RecvError::Lagged(_))
+        ));
+        assert!(matches!(events.recv().await.unwrap(), ConnectionEvent::Activated { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_update_client_addr() {
+        let manager = ConnectionManager::new(ConnectionManagerConfig {
+            max_connections: 10,
+            idle_timeout: Duration::from_secs(30),
+        });
+        let pair = crate::util::testing::quic_pair().await;
+
+        let wifi: SocketAddr = "192.168.1.20:50000".parse().unwrap();
+        let cellular: SocketAddr = "10.20.30.40:40000".parse().unwrap();
+        let id = manager.register(wifi, pair.server.clone()).unwrap();
+        let before = METRICS.connection_migrations.load(Ordering::Relaxed);
+
+        assert!(!manager.update_client_addr(id, wifi));
+        assert!(manager.update_client_addr(id, cellular));
+        assert!(!manager.update_client_addr(id, cellular));
+        assert_eq!(manager.get(id).unwrap().client_addr, cellular);
+        assert!(METRICS.connection_migrations.load(Ordering::Relaxed) > before);
+
+        assert!(!manager.update_client_addr(ConnectionId::from_raw(id.as_u64() + 1), wifi));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let manager = ConnectionManager::new(ConnectionManagerConfig {
+            max_connections: 10,
+            idle_timeout: Duration::from_secs(30),
+        });
+        let pair = crate::util::testing::quic_pair().await;
+
+        let id = manager
+            .register(pair.server.remote_address(), pair.server.clone())
+            .unwrap();
+
+        assert!(!manager.disconnect(ConnectionId::from_raw(id.as_u64() + 1)));
+        assert!(manager.disconnect(id));
+
+        match pair.client.closed().await {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                assert_eq!(close.error_code, CloseCode::Disconnected.code());
+            }
+            e => panic!("unexpected close: {e}"),
+        }
+
+        manager.unregister(id);
+        assert!(!manager.disconnect(id));
+    }
+
+    #[tokio::test]
+    async fn test_shed_idle() {
+        let manager = ConnectionManager::new(ConnectionManagerConfig {
+            max_connections: 10,
+            idle_timeout: Duration::from_secs(30),
+        });
+        let pair = crate::util::testing::quic_pair().await;
+        let addr = pair.server.remote_address();
+
+        let oldest = manager.register(addr, pair.server.clone()).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        let newer = manager.register(addr, pair.server.clone()).unwrap();
+        let busy = manager.register(addr, pair.server.clone()).unwrap();
+        assert!(manager.try_open_stream(busy, 1));
+
+        // Longest idle goes first; connections with open streams are kept
+        assert_eq!(manager.shed_idle(1), 1);
+        assert!(manager.get(oldest).is_none());
+        assert!(manager.get(newer).is_some());
+        assert_eq!(manager.shed_idle(10), 1);
+        assert_eq!(manager.connection_count(), 1);
+        assert!(manager.get(busy).is_some());
+
+        match pair.client.closed().await {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                assert_eq!(close.error_code, CloseCode::Capacity.code());
+            }
+            e => panic!("unexpected close: {e}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let manager = ConnectionManager::new(ConnectionManagerConfig {
+            max_connections: 10,
+            idle_timeout: Duration::from_secs(30),
+        });
+        let pair = crate::util::testing::quic_pair().await;
+        let addr = pair.server.remote_address();
+
+        // Returns as soon as the last connection unregisters
+        let id = manager.register(addr, pair.server.clone()).unwrap();
+        let unregister = {
+            let manager = manager.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                manager.unregister(id);
+            }
+        };
+        let start = std::time::Instant::now();
+        tokio::join!(manager.drain(Duration::from_secs(10)), unregister);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // Stragglers are closed once the timeout passes
+        manager.register(addr, pair.server.clone()).unwrap();
+        manager.drain(Duration::from_millis(50)).await;
+        assert!(pair.server.close_reason().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_query_connections() {
+        let manager = ConnectionManager::new(ConnectionManagerConfig {
+            max_connections: 10,
+            idle_timeout: Duration::from_secs(30),
+        });
+        let pair = crate::util::testing::quic_pair().await;
+
+        for (addr, tx) in [("10.0.0.1:1", 300), ("10.0.0.2:1", 100), ("192.168.0.1:1", 200)] {
+            let id = manager.register(addr.parse().unwrap(), pair.server.clone()).unwrap();
+            manager.record_traffic(id, 0, tx);
+        }
+
+        let page = manager.query_connections(&ConnectionQuery {
+            client_ip_prefix: Some("10.".to_string()),
+            sort: crate::connection::ConnectionSort::BytesTx,
+            limit: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(page.total, 2);
+        assert_eq!(page.connections.len(), 1);
+        assert_eq!(page.connections[0].bytes_tx, 100);
+
+        let page = manager.query_connections(&ConnectionQuery {
+            min_idle: Some(Duration::from_secs(60)),
+            ..Default::default()
+        });
+        assert_eq!(page.total, 0);
+    }
 }
 
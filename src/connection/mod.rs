@@ -3,8 +3,12 @@
 //! Handles connection state, lifecycle, and tracking.
 
 mod manager;
+mod notify;
+mod query;
 mod state;
 
-pub use manager::{ConnectionManager, ConnectionManagerConfig};
-pub use state::{ConnectionId, ConnectionInfo, ConnectionState};
+pub use manager::{ConnectionEvent, ConnectionManager, ConnectionManagerConfig};
+pub use notify::WebhookNotifier;
+pub use query::{ConnectionPage, ConnectionQuery, ConnectionSort};
+pub use state::{ConnectionId, ConnectionInfo, ConnectionState, StreamDistribution, UserPolicy};
 
@@ -0,0 +1,313 @@
+//! Webhook notifications of connection events
+//!
+//! A background task batches client connects and disconnects from
+//! [`ConnectionManager::subscribe_events`](super::ConnectionManager::subscribe_events)
+//! and POSTs them as JSON. It only reads the event channel, so a slow or
+//! failing webhook never holds up tunnels; events it falls behind on are
+//! skipped.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use super::manager::ConnectionEvent;
+use crate::config::NotifyConfig;
+
+/// Wait before the first retry, doubled for each one after
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest response status line read from the webhook
+const MAX_STATUS_LINE: u64 = 1024;
+
+/// Body of each webhook request
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    events: &'a [ConnectionEvent],
+}
+
+/// Posts batches of connect and disconnect events to a webhook
+pub struct WebhookNotifier {
+    config: NotifyConfig,
+    /// `host:port` to connect to
+    addr: String,
+    /// `Host` header value
+    host: String,
+    path: String,
+}
+
+impl WebhookNotifier {
+    /// Create a notifier for `config.webhook_url`, which must be `http://`
+    pub fn new(config: NotifyConfig) -> Result<Self> {
+        let rest = config
+            .webhook_url
+            .strip_prefix("http://")
+            .context("Webhook URL must start with http://")?;
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            anyhow::bail!("Webhook URL has no host");
+        }
+
+        let has_port = host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        let addr = if has_port {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+
+        Ok(Self {
+            host: host.to_string(),
+            path: path.to_string(),
+            addr,
+            config,
+        })
+    }
+
+    /// Deliver `events` until the manager is dropped
+    pub fn spawn(self, events: broadcast::Receiver<ConnectionEvent>) -> JoinHandle<()> {
+        tokio::spawn(self.run(events))
+    }
+
+    async fn run(self, mut events: broadcast::Receiver<ConnectionEvent>) {
+        let interval = Duration::from_secs(self.config.batch_interval_secs);
+        let mut batch = Vec::with_capacity(self.config.max_batch);
+
+        loop {
+            // Wait for the event that opens a batch, then collect more
+            // until the interval passes or the batch fills up
+            match self.next_event(&mut events).await {
+                Some(event) => batch.push(event),
+                None => return,
+            }
+            let deadline = tokio::time::Instant::now() + interval;
+            let mut closed = false;
+            while batch.len() < self.config.max_batch {
+                match tokio::time::timeout_at(deadline, self.next_event(&mut events)).await {
+                    Ok(Some(event)) => batch.push(event),
+                    Ok(None) => {
+                        closed = true;
+                        break;
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            self.deliver(&batch).await;
+            batch.clear();
+            if closed {
+                return;
+            }
+        }
+    }
+
+    /// Next connect or disconnect of a reported client, or `None` once
+    /// the channel closes
+    async fn next_event(
+        &self,
+        events: &mut broadcast::Receiver<ConnectionEvent>,
+    ) -> Option<ConnectionEvent> {
+        loop {
+            match events.recv().await {
+                Ok(event) if self.wants(&event) => return Some(event),
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "Webhook notifier fell behind, events skipped");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Whether `event` is a connect or disconnect of a client in `client_cidrs`
+    fn wants(&self, event: &ConnectionEvent) -> bool {
+        let client_addr: &SocketAddr = match event {
+            ConnectionEvent::Opened { client_addr, .. }
+            | ConnectionEvent::Closed { client_addr, .. } => client_addr,
+            _ => return false,
+        };
+        let cidrs = &self.config.client_cidrs;
+        cidrs.is_empty() || cidrs.iter().any(|net| net.contains(&client_addr.ip()))
+    }
+
+    /// Post `batch`, retrying up to `retries` times; gives up with a warning
+    async fn deliver(&self, batch: &[ConnectionEvent]) {
+        let body = match serde_json::to_vec(&WebhookPayload { events: batch }) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!(error = %e, "Failed to encode webhook payload");
+                return;
+            }
+        };
+
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let mut backoff = RETRY_BACKOFF;
+        for attempt in 0..=self.config.retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            match tokio::time::timeout(timeout, self.post(&body)).await {
+                Ok(Ok(())) => {
+                    debug!(events = batch.len(), "Webhook delivered");
+                    return;
+                }
+                Ok(Err(e)) => debug!(attempt, error = %e, "Webhook delivery failed"),
+                Err(_) => debug!(attempt, "Webhook delivery timed out"),
+            }
+        }
+        warn!(
+            url = %self.config.webhook_url,
+            events = batch.len(),
+            "Webhook unreachable, events dropped"
+        );
+    }
+
+    /// Send one POST and check for a 2xx status
+    async fn post(&self, body: &[u8]) -> Result<()> {
+        let mut stream = TcpStream::connect(&self.addr).await?;
+        let head = format!(
+            "POST {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             User-Agent: mytunnel/{}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n",
+            self.path,
+            self.host,
+            crate::VERSION,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body).await?;
+        stream.flush().await?;
+
+        // Only the status line matters; the rest of the response is ignored
+        let mut status_line = Vec::new();
+        BufReader::new(stream.take(MAX_STATUS_LINE))
+            .read_until(b'\n', &mut status_line)
+            .await?;
+        let status_line = String::from_utf8_lossy(&status_line);
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .with_context(|| format!("Malformed webhook response {:?}", status_line))?;
+        if !(200..300).contains(&status) {
+            anyhow::bail!("Webhook returned status {}", status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Webhook answering each request with the next status in `statuses`,
+    /// forwarding every request body
+    async fn start_webhook(statuses: Vec<u16>) -> (SocketAddr, mpsc::Receiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            for status in statuses {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(len) = line.strip_prefix("Content-Length: ") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0u8; content_length];
+                reader.read_exact(&mut body).await.unwrap();
+                let response = format!("HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", status);
+                reader.get_mut().write_all(response.as_bytes()).await.unwrap();
+                tx.send(serde_json::from_slice(&body).unwrap()).await.unwrap();
+            }
+        });
+        (addr, rx)
+    }
+
+    fn notify_config(url: String) -> NotifyConfig {
+        NotifyConfig {
+            webhook_url: url,
+            client_cidrs: vec!["10.0.0.0/8".parse().unwrap()],
+            batch_interval_secs: 5,
+            max_batch: 2,
+            timeout_secs: 5,
+            retries: 1,
+        }
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let notifier = WebhookNotifier::new(notify_config("http://[::1]/hook".into())).unwrap();
+        assert_eq!((notifier.addr.as_str(), notifier.path.as_str()), ("[::1]:80", "/hook"));
+
+        let notifier = WebhookNotifier::new(notify_config("http://hooks:8080".into())).unwrap();
+        assert_eq!((notifier.addr.as_str(), notifier.path.as_str()), ("hooks:8080", "/"));
+
+        assert!(WebhookNotifier::new(notify_config("https://hooks/".into())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_webhook_delivery() {
+        // First attempt fails with a 500 and is retried
+        let (addr, mut bodies) = start_webhook(vec![500, 200]).await;
+        let url = format!("http://{}/hook", addr);
+        let notifier = WebhookNotifier::new(notify_config(url)).unwrap();
+        let (events_tx, events_rx) = broadcast::channel(16);
+        let task = notifier.spawn(events_rx);
+
+        let opened = |id: &str, addr: &str| ConnectionEvent::Opened {
+            id: id.to_string(),
+            client_addr: addr.parse().unwrap(),
+        };
+        // Outside client_cidrs, and not a connect or disconnect: both skipped
+        events_tx.send(opened("01", "192.168.1.1:1000")).unwrap();
+        events_tx.send(ConnectionEvent::Activated { id: "02".to_string() }).unwrap();
+        events_tx.send(opened("02", "10.1.1.1:1000")).unwrap();
+        events_tx
+            .send(ConnectionEvent::Closed {
+                id: "02".to_string(),
+                client_addr: "10.1.1.1:1000".parse().unwrap(),
+                bytes_rx: 5,
+                bytes_tx: 7,
+                duration_secs: 1.5,
+            })
+            .unwrap();
+
+        let failed = bodies.recv().await.unwrap();
+        let delivered = bodies.recv().await.unwrap();
+        assert_eq!(failed, delivered);
+        let events = delivered["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "opened");
+        assert_eq!(events[0]["id"], "02");
+        assert_eq!(events[1]["event"], "closed");
+        assert_eq!(events[1]["bytes_tx"], 7);
+
+        // The task ends with the channel
+        drop(events_tx);
+        task.await.unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+//! Filtering, sorting and paging for connection listings
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::state::{ConnectionInfo, ConnectionState};
+
+/// Field a connection listing is sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionSort {
+    /// Connection ID, i.e. registration order
+    #[default]
+    Id,
+    DurationSecs,
+    IdleSecs,
+    BytesRx,
+    BytesTx,
+    ActiveStreams,
+    RttMs,
+}
+
+impl ConnectionSort {
+    fn compare(self, a: &ConnectionInfo, b: &ConnectionInfo) -> Ordering {
+        match self {
+            // Fixed-width hex, so string order is numeric order
+            Self::Id => a.id.cmp(&b.id),
+            Self::DurationSecs => a.duration_secs.total_cmp(&b.duration_secs),
+            Self::IdleSecs => a.idle_secs.total_cmp(&b.idle_secs),
+            Self::BytesRx => a.bytes_rx.cmp(&b.bytes_rx),
+            Self::BytesTx => a.bytes_tx.cmp(&b.bytes_tx),
+            Self::ActiveStreams => a.active_streams.cmp(&b.active_streams),
+            Self::RttMs => a.rtt_ms.total_cmp(&b.rtt_ms),
+        }
+    }
+}
+
+impl FromStr for ConnectionSort {
+    type Err = String;
+
+    /// Parse a `ConnectionInfo` field name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(Self::Id),
+            "duration_secs" => Ok(Self::DurationSecs),
+            "idle_secs" => Ok(Self::IdleSecs),
+            "bytes_rx" => Ok(Self::BytesRx),
+            "bytes_tx" => Ok(Self::BytesTx),
+            "active_streams" => Ok(Self::ActiveStreams),
+            "rtt_ms" => Ok(Self::RttMs),
+            _ => Err(format!("unknown sort field: {}", s)),
+        }
+    }
+}
+
+/// Which connections to list, in what order
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionQuery {
+    /// Only connections idle at least this long
+    pub min_idle: Option<Duration>,
+    /// Only clients whose IP, as text, starts with this
+    pub client_ip_prefix: Option<String>,
+    pub sort: ConnectionSort,
+    pub descending: bool,
+    /// Matching connections to skip after sorting
+    pub offset: usize,
+    /// Maximum connections returned; `None` for all
+    pub limit: Option<usize>,
+}
+
+impl ConnectionQuery {
+    /// Whether a connection passes the filters
+    pub fn matches(&self, state: &ConnectionState) -> bool {
+        if let Some(min_idle) = self.min_idle {
+            if state.idle_duration() < min_idle {
+                return false;
+            }
+        }
+        if let Some(prefix) = &self.client_ip_prefix {
+            if !state.client_addr.ip().to_string().starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Sort matching connections and cut out the requested page
+    pub fn page(&self, mut connections: Vec<ConnectionInfo>) -> ConnectionPage {
+        let total = connections.len();
+        connections.sort_by(|a, b| {
+            let ordering = self.sort.compare(a, b);
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        let connections = connections
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+        ConnectionPage { total, connections }
+    }
+}
+
+/// One page of a connection listing
+#[derive(Debug)]
+pub struct ConnectionPage {
+    /// Connections matching the filters, across all pages
+    pub total: usize,
+    pub connections: Vec<ConnectionInfo>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(id: u64, bytes_tx: u64) -> ConnectionInfo {
+        ConnectionInfo {
+            id: format!("{:016x}", id),
+            client_addr: "127.0.0.1:1".to_string(),
+            phase: "Active".to_string(),
+            duration_secs: 0.0,
+            idle_secs: 0.0,
+            bytes_rx: 0,
+            bytes_tx,
+            active_streams: 0,
+            active_udp_flows: 0,
+            rtt_ms: 0.0,
+            cwnd: 0,
+            user: None,
+        }
+    }
+
+    fn ids(page: &ConnectionPage) -> Vec<String> {
+        page.connections.iter().map(|c| c.id[14..].to_string()).collect()
+    }
+
+    #[test]
+    fn test_sort_and_page() {
+        let connections = vec![info(3, 10), info(1, 30), info(2, 20), info(16, 0)];
+
+        let page = ConnectionQuery::default().page(connections.clone());
+        assert_eq!(page.total, 4);
+        assert_eq!(ids(&page), ["01", "02", "03", "10"]);
+
+        let query = ConnectionQuery {
+            sort: ConnectionSort::BytesTx,
+            descending: true,
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = query.page(connections);
+        assert_eq!(page.total, 4);
+        assert_eq!(ids(&page), ["02", "03"]);
+    }
+
+    #[test]
+    fn test_sort_parse() {
+        assert_eq!("bytes_tx".parse(), Ok(ConnectionSort::BytesTx));
+        assert_eq!("rtt_ms".parse(), Ok(ConnectionSort::RttMs));
+        assert!("cwnd".parse::<ConnectionSort>().is_err());
+    }
+}
@@ -1,7 +1,9 @@
 //! Connection state
 
+use quinn::Connection;
 use serde::Serialize;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Instant;
 
 /// Unique connection identifier
@@ -26,6 +28,15 @@ impl std::fmt::Display for ConnectionId {
     }
 }
 
+impl std::str::FromStr for ConnectionId {
+    type Err = std::num::ParseIntError;
+
+    /// Parse the hex form produced by `Display`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(Self)
+    }
+}
+
 /// Connection lifecycle state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnectionPhase {
@@ -39,6 +50,24 @@ pub enum ConnectionPhase {
     Closed,
 }
 
+/// Limits an authenticator set for one user, overriding the server's
+///
+/// `None` keeps the server-wide setting; for the numeric limits `Some(0)`
+/// lifts it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserPolicy {
+    /// Who the client authenticated as; connections are counted per user
+    pub user: String,
+    /// Most connections the user may have open at once
+    pub max_connections: Option<u32>,
+    /// Bandwidth budget for each of the user's connections (bytes/sec),
+    /// instead of `[limits] max_bandwidth_per_conn`
+    pub max_bandwidth: Option<u64>,
+    /// Target ports the user may reach, instead of `[routing] allowed_ports`
+    /// (empty = any port not blocked)
+    pub allowed_ports: Option<Vec<u16>>,
+}
+
 /// Per-connection state
 #[derive(Debug)]
 pub struct ConnectionState {
@@ -46,6 +75,8 @@ pub struct ConnectionState {
     pub id: ConnectionId,
     /// Client address
     pub client_addr: SocketAddr,
+    /// Underlying QUIC connection (not exposed through `to_info`)
+    pub connection: Connection,
     /// Connection phase
     pub phase: ConnectionPhase,
     /// Connection start time
@@ -60,15 +91,18 @@ pub struct ConnectionState {
     pub active_streams: u32,
     /// Active UDP flows count
     pub active_udp_flows: u32,
+    /// Limits of the user the client authenticated as, if any
+    pub policy: Option<Arc<UserPolicy>>,
 }
 
 impl ConnectionState {
     /// Create new connection state
-    pub fn new(id: ConnectionId, client_addr: SocketAddr) -> Self {
+    pub fn new(id: ConnectionId, client_addr: SocketAddr, connection: Connection) -> Self {
         let now = Instant::now();
         Self {
             id,
             client_addr,
+            connection,
             phase: ConnectionPhase::Connecting,
             connected_at: now,
             last_active: now,
@@ -76,6 +110,7 @@ impl ConnectionState {
             bytes_tx: 0,
             active_streams: 0,
             active_udp_flows: 0,
+            policy: None,
         }
     }
 
@@ -149,6 +184,7 @@ impl ConnectionState {
 
     /// Convert to serializable info
     pub fn to_info(&self) -> ConnectionInfo {
+        let path = self.connection.stats().path;
         ConnectionInfo {
             id: format!("{}", self.id),
             client_addr: self.client_addr.to_string(),
@@ -159,6 +195,9 @@ impl ConnectionState {
             bytes_tx: self.bytes_tx,
             active_streams: self.active_streams,
             active_udp_flows: self.active_udp_flows,
+            rtt_ms: path.rtt.as_secs_f64() * 1000.0,
+            cwnd: path.cwnd,
+            user: self.policy.as_ref().map(|policy| policy.user.clone()),
         }
     }
 }
@@ -184,5 +223,92 @@ pub struct ConnectionInfo {
     pub active_streams: u32,
     /// Active UDP flows
     pub active_udp_flows: u32,
+    /// Smoothed round-trip time in milliseconds
+    pub rtt_ms: f64,
+    /// Congestion window in bytes
+    pub cwnd: u64,
+    /// User the client authenticated as, when its authenticator named one
+    pub user: Option<String>,
+}
+
+/// How many streams each connection has open at one moment
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamDistribution {
+    /// `active_streams` of every connection, in no particular order
+    pub counts: Vec<u32>,
+}
+
+impl StreamDistribution {
+    /// Take the stream counts of `states`
+    pub fn from_states<S>(states: impl IntoIterator<Item = S>) -> Self
+    where
+        S: std::ops::Deref<Target = ConnectionState>,
+    {
+        Self {
+            counts: states.into_iter().map(|state| state.active_streams).collect(),
+        }
+    }
+
+    /// Most streams on any one connection (0 without connections)
+    pub fn max(&self) -> u32 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Mean streams per connection (0 without connections)
+    pub fn avg(&self) -> f64 {
+        if self.counts.is_empty() {
+            return 0.0;
+        }
+        let total: u64 = self.counts.iter().map(|&count| count as u64).sum();
+        total as f64 / self.counts.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stream_distribution() {
+        let pair = crate::util::testing::quic_pair().await;
+        let states: Vec<ConnectionState> = [0, 1, 1, 10]
+            .into_iter()
+            .enumerate()
+            .map(|(id, streams)| {
+                let mut state = ConnectionState::new(
+                    ConnectionId::from_raw(id as u64),
+                    pair.server.remote_address(),
+                    pair.server.clone(),
+                );
+                state.active_streams = streams;
+                state
+            })
+            .collect();
+
+        let distribution = StreamDistribution::from_states(&states);
+        assert_eq!(distribution.counts, [0, 1, 1, 10]);
+        assert_eq!(distribution.max(), 10);
+        assert_eq!(distribution.avg(), 3.0);
+
+        let empty = StreamDistribution::from_states(&[] as &[ConnectionState]);
+        assert_eq!(empty.max(), 0);
+        assert_eq!(empty.avg(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_info_serialization() {
+        let pair = crate::util::testing::quic_pair().await;
+        let state = ConnectionState::new(
+            ConnectionId::from_raw(0x2a),
+            pair.server.remote_address(),
+            pair.server.clone(),
+        );
+
+        let json = serde_json::to_value(state.to_info()).unwrap();
+        assert_eq!(json["id"], "000000000000002a");
+        assert!(json["rtt_ms"].as_f64().unwrap() > 0.0);
+        assert!(json["cwnd"].as_u64().unwrap() > 0);
+        assert!(json.get("connection").is_none());
+    }
 }
 
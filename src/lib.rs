@@ -3,6 +3,8 @@
 //! This library provides the core components for a high-performance
 //! tunnel server using QUIC transport with zero-copy forwarding.
 
+pub mod close_code;
+mod coalesce;
 pub mod config;
 pub mod connection;
 pub mod metrics;
@@ -12,6 +14,7 @@ pub mod router;
 pub mod server;
 pub mod util;
 
+pub use close_code::CloseCode;
 pub use config::Config;
 pub use server::Server;
 
@@ -3,10 +3,10 @@
 //! High-performance QUIC-based tunnel server with zero-copy forwarding.
 
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::signal;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use mytunnel_server::{Config, Server, VERSION};
 
@@ -14,9 +14,7 @@ use mytunnel_server::{Config, Server, VERSION};
 #[tokio::main]
 async fn main() -> Result<()> {
     // Install the ring crypto provider for rustls (required in rustls 0.23+)
-    rustls::crypto::ring::default_provider()
-        .install_default()
-        .expect("Failed to install rustls crypto provider");
+    mytunnel_server::util::init_crypto();
 
     // Parse command line arguments
     let config_path = std::env::args()
@@ -55,7 +53,12 @@ async fn main() -> Result<()> {
         mytunnel_server::metrics::start_api_server(
             config.metrics.api_bind_addr,
             server.connection_manager(),
+            server.buffer_pool(),
+            server.readiness(),
+            config.metrics.allow_reset,
         );
+        mytunnel_server::metrics::start_buffer_pool_metrics(server.buffer_pool());
+        mytunnel_server::metrics::start_stream_metrics(server.connection_manager());
         info!(
             bind_addr = %config.metrics.api_bind_addr,
             "Connections API server started"
@@ -63,7 +66,7 @@ async fn main() -> Result<()> {
     }
 
     info!(
-        bind_addr = %config.server.bind_addr,
+        bind_addrs = ?config.server.bind_addrs,
         workers = config.server.effective_workers(),
         "Server listening"
     );
@@ -78,14 +81,38 @@ async fn main() -> Result<()> {
         }
         _ = shutdown_signal() => {
             info!("Shutdown signal received, draining connections...");
-            server.shutdown().await;
+            tokio::select! {
+                _ = server.shutdown() => {}
+                _ = shutdown_signal() => server.shutdown_now(),
+            }
         }
+        _ = reload_on_hangup(&server, &config_path) => {}
     }
 
     info!("Server stopped");
     Ok(())
 }
 
+/// Reload the config file on SIGHUP; never returns
+#[cfg(unix)]
+async fn reload_on_hangup(server: &Server, config_path: &Path) {
+    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+        .expect("Failed to install SIGHUP handler");
+
+    while hangup.recv().await.is_some() {
+        info!(config_path = ?config_path, "SIGHUP received, reloading config");
+        if let Err(e) = Config::load(config_path).and_then(|config| server.reload(config)) {
+            warn!(error = %e, "Config reload failed, keeping current config");
+        }
+    }
+    std::future::pending::<()>().await
+}
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_server: &Server, _config_path: &Path) {
+    std::future::pending::<()>().await
+}
+
 /// Wait for shutdown signal (Ctrl+C or SIGTERM)
 async fn shutdown_signal() {
     let ctrl_c = async {
@@ -2,20 +2,27 @@
 //!
 //! Provides JSON endpoints for viewing connected users and server stats.
 
-use std::io::{Read, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
 use tracing::{debug, error, info, warn};
 
-use crate::connection::ConnectionManager;
-use super::counters::METRICS;
+use crate::connection::{ConnectionId, ConnectionManager, ConnectionQuery};
+use crate::pool::{BufferPool, BufferPoolStats};
+use super::counters::{MetricsSnapshot, METRICS};
 
 /// API response for /connections endpoint
 #[derive(Serialize)]
 struct ConnectionsResponse {
+    /// Connections matching the filters, across all pages
+    total: usize,
+    /// Connections in this page
     count: usize,
     connections: Vec<crate::connection::ConnectionInfo>,
 }
@@ -26,36 +33,88 @@ struct StatsResponse {
     connections_total: u64,
     connections_active: u64,
     connections_failed: u64,
+    /// Closed because `max_connections` was reached
+    connections_rejected_capacity: u64,
+    /// Dropped by the global or per-IP new-connection rate limit
+    connections_rejected_rate: u64,
+    /// Closed for a missing or invalid auth token
+    connections_rejected_auth: u64,
     bytes_received: u64,
     bytes_sent: u64,
     streams_opened: u64,
     streams_closed: u64,
     errors_total: u64,
+    buffer_pool_acquires: u64,
+    buffer_pool_releases: u64,
+    buffer_pool_misses: u64,
+    /// Most streams open on any one connection right now
+    max_streams_per_connection: u32,
+}
+
+/// API response for /metrics-json endpoint
+#[derive(Serialize)]
+struct MetricsJsonResponse {
+    metrics: MetricsSnapshot,
+    buffer_pool: BufferPoolStats,
+    connection_count: usize,
+}
+
+/// API response for DELETE /connections/{id}
+#[derive(Serialize)]
+struct DisconnectResponse {
+    closed: String,
+}
+
+/// API response for /health and /ready endpoints
+#[derive(Serialize)]
+struct ProbeResponse {
+    status: &'static str,
+    connections: usize,
 }
 
 /// Start the connections API server
 ///
-/// This runs a simple HTTP server that responds to:
+/// This runs a small HTTP/1.1 server, one thread per connection with
+/// keep-alive, that responds to:
 /// - GET /connections - List all active connections
+/// - DELETE /connections/{id} - Forcibly close a connection
 /// - GET /stats - Server statistics
-pub fn start_api_server(addr: SocketAddr, conn_manager: Arc<ConnectionManager>) {
+/// - POST /stats/reset - Zero the counters, if `allow_reset` is set
+/// - GET /metrics-json - Every counter plus buffer pool stats, for tools
+///   that can't scrape Prometheus
+/// - GET /events - Connection lifecycle events as Server-Sent Events
+/// - GET /health - Liveness probe, always 200
+/// - GET /ready - Readiness probe, 503 until `ready` is set or once it is cleared
+pub fn start_api_server(
+    addr: SocketAddr,
+    conn_manager: Arc<ConnectionManager>,
+    buffer_pool: BufferPool,
+    ready: Arc<AtomicBool>,
+    allow_reset: bool,
+) {
+    let state = ApiState {
+        conn_manager,
+        buffer_pool,
+        ready,
+        allow_reset,
+    };
     thread::spawn(move || {
-        if let Err(e) = run_api_server(addr, conn_manager) {
+        if let Err(e) = run_api_server(addr, state) {
             error!(error = %e, "API server error");
         }
     });
     info!(%addr, "Connections API server started");
 }
 
-fn run_api_server(addr: SocketAddr, conn_manager: Arc<ConnectionManager>) -> std::io::Result<()> {
+fn run_api_server(addr: SocketAddr, state: ApiState) -> std::io::Result<()> {
     let listener = TcpListener::bind(addr)?;
     
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                let conn_manager = conn_manager.clone();
+                let state = state.clone();
                 thread::spawn(move || {
-                    if let Err(e) = handle_request(stream, &conn_manager) {
+                    if let Err(e) = handle_connection(stream, &state) {
                         debug!(error = %e, "Request handling error");
                     }
                 });
@@ -69,70 +128,439 @@ fn run_api_server(addr: SocketAddr, conn_manager: Arc<ConnectionManager>) -> std
     Ok(())
 }
 
-fn handle_request(mut stream: TcpStream, conn_manager: &ConnectionManager) -> std::io::Result<()> {
-    let mut buffer = [0u8; 1024];
-    let n = stream.read(&mut buffer)?;
-    
+/// Server state shared by request handlers
+#[derive(Clone)]
+struct ApiState {
+    conn_manager: Arc<ConnectionManager>,
+    buffer_pool: BufferPool,
+    /// Set while the server accepts connections
+    ready: Arc<AtomicBool>,
+    /// Whether `POST /stats/reset` is served
+    allow_reset: bool,
+}
+
+/// Longest request line or header line accepted
+const MAX_LINE_LEN: u64 = 8 * 1024;
+/// How long a keep-alive connection may sit idle between requests
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Interval of comments on an idle event stream, which notice a departed client
+const EVENTS_KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// A parsed API request head
+#[derive(Debug)]
+struct ApiRequest {
+    method: String,
+    path: String,
+    /// Decoded query parameters, in request order
+    query: Vec<(String, String)>,
+    /// Whether the connection stays open after the response
+    keep_alive: bool,
+}
+
+impl ApiRequest {
+    /// Non-empty path segments, so `/connections/` matches `/connections`
+    fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|s| !s.is_empty()).collect()
+    }
+
+    /// First value of a query parameter
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Serve requests on one connection until it closes or asks to
+fn handle_connection(stream: TcpStream, state: &ApiState) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    loop {
+        let request = match read_request(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                debug!(error = %e, "Malformed API request");
+                let body = r#"{"error": "Bad request"}"#;
+                return write_response(&mut writer, "400 Bad Request", body, false);
+            }
+            Err(e) => return Err(e),
+        };
+
+        // The event stream holds the connection until the client leaves
+        if request.method == "GET" && request.segments() == ["events"] {
+            writer.set_write_timeout(Some(IDLE_TIMEOUT))?;
+            return stream_events(&mut writer, &state.conn_manager);
+        }
+
+        let (status, body) = route(&request, state);
+        write_response(&mut writer, status, &body, request.keep_alive)?;
+        if !request.keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Read one request head, skipping any body
+///
+/// Returns `None` if the peer closed the connection between requests.
+/// Malformed requests fail with `InvalidData`.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<ApiRequest>> {
+    // Stray empty lines before a request line are allowed
+    let request_line = loop {
+        match read_line(reader)? {
+            Some(line) if line.is_empty() => continue,
+            Some(line) => break line,
+            None => return Ok(None),
+        }
+    };
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid_data("malformed request line"));
+    };
+    let mut keep_alive = match version {
+        "HTTP/1.1" => true,
+        "HTTP/1.0" => false,
+        _ => return Err(invalid_data("unsupported HTTP version")),
+    };
+
+    let mut content_length = 0u64;
+    loop {
+        let line = read_line(reader)?.ok_or_else(|| invalid_data("truncated headers"))?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid_data("malformed header"))?;
+        let value = value.trim();
+
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .parse()
+                .map_err(|_| invalid_data("invalid Content-Length"))?;
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            // Chunked bodies aren't parsed, so the connection can't be reused
+            keep_alive = false;
+        } else if name.eq_ignore_ascii_case("connection") {
+            for option in value.split(',').map(str::trim) {
+                if option.eq_ignore_ascii_case("close") {
+                    keep_alive = false;
+                } else if option.eq_ignore_ascii_case("keep-alive") {
+                    keep_alive = true;
+                }
+            }
+        }
+    }
+
+    // No endpoint takes a body; drain it so the next request lines up
+    io::copy(&mut reader.take(content_length), &mut io::sink())?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    Ok(Some(ApiRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: parse_query(query),
+        keep_alive,
+    }))
+}
+
+/// Read a line without its `\r\n`, or `None` at end of stream
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    let n = reader.take(MAX_LINE_LEN + 1).read_until(b'\n', &mut line)?;
     if n == 0 {
-        return Ok(());
+        return Ok(None);
     }
-    
-    let request = String::from_utf8_lossy(&buffer[..n]);
-    let first_line = request.lines().next().unwrap_or("");
-    
-    // Parse request path
-    let path = first_line
-        .split_whitespace()
-        .nth(1)
-        .unwrap_or("/");
-    
-    let (status, body) = match path {
-        "/connections" => {
-            let connections = conn_manager.list_connections();
-            let response = ConnectionsResponse {
-                count: connections.len(),
-                connections,
-            };
-            ("200 OK", serde_json::to_string_pretty(&response).unwrap_or_default())
-        }
-        "/stats" => {
-            let snapshot = METRICS.snapshot();
-            let response = StatsResponse {
-                connections_total: snapshot.connections_total,
-                connections_active: snapshot.connections_active,
-                connections_failed: snapshot.connections_failed,
-                bytes_received: snapshot.bytes_received,
-                bytes_sent: snapshot.bytes_sent,
-                streams_opened: snapshot.streams_opened,
-                streams_closed: snapshot.streams_closed,
-                errors_total: snapshot.errors_total,
-            };
-            ("200 OK", serde_json::to_string_pretty(&response).unwrap_or_default())
+    if line.pop() != Some(b'\n') {
+        return Err(if n as u64 > MAX_LINE_LEN {
+            invalid_data("line too long")
+        } else {
+            io::ErrorKind::UnexpectedEof.into()
+        });
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| invalid_data("request is not UTF-8"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Split and percent-decode a query string
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Decode `%XX` escapes and `+` as space; invalid escapes are kept as-is
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = input.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok());
+                if let Some(byte) = hex {
+                    decoded.push(byte);
+                    i += 3;
+                    continue;
+                }
+                decoded.push(b'%');
+            }
+            byte => decoded.push(byte),
         }
-        "/" => {
-            let help = r#"{
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Dispatch a request to its endpoint
+fn route(request: &ApiRequest, state: &ApiState) -> (&'static str, String) {
+    let conn_manager = &state.conn_manager;
+    match (request.method.as_str(), request.segments().as_slice()) {
+        ("GET", []) => ("200 OK", HELP.to_string()),
+        ("GET", ["connections"]) => list_connections(request, conn_manager),
+        ("DELETE", ["connections", id]) => disconnect(id, conn_manager),
+        ("GET", ["stats"]) => stats(conn_manager),
+        ("POST", ["stats", "reset"]) => reset_stats(state),
+        ("GET", ["metrics-json"]) => metrics_json(state),
+        ("GET", ["health"]) => health(conn_manager),
+        ("GET", ["ready"]) => readiness(conn_manager, &state.ready),
+        (
+            _,
+            [] | ["connections"] | ["connections", _] | ["stats"] | ["stats", "reset"]
+            | ["metrics-json"] | ["events"] | ["health"] | ["ready"],
+        ) => (
+            "405 Method Not Allowed",
+            r#"{"error": "Method not allowed"}"#.to_string(),
+        ),
+        _ => ("404 Not Found", r#"{"error": "Not found"}"#.to_string()),
+    }
+}
+
+const HELP: &str = r#"{
   "endpoints": {
-    "/connections": "List all active connections",
-    "/stats": "Server statistics"
+    "/connections": "List active connections, filtered and paged by query parameters",
+    "DELETE /connections/{id}": "Disconnect a connection",
+    "/stats": "Server statistics",
+    "POST /stats/reset": "Zero the counters (needs metrics.allow_reset)",
+    "/metrics-json": "All metrics counters and buffer pool stats",
+    "/events": "Connection lifecycle events (Server-Sent Events)",
+    "/health": "Liveness probe",
+    "/ready": "Readiness probe"
   }
 }"#;
-            ("200 OK", help.to_string())
+
+/// Handle GET /connections
+fn list_connections(
+    request: &ApiRequest,
+    conn_manager: &ConnectionManager,
+) -> (&'static str, String) {
+    let query = match connection_query(request) {
+        Ok(query) => query,
+        Err(e) => {
+            let body = serde_json::json!({ "error": e }).to_string();
+            return ("400 Bad Request", body);
         }
-        _ => {
-            ("404 Not Found", r#"{"error": "Not found"}"#.to_string())
+    };
+
+    let page = conn_manager.query_connections(&query);
+    let response = ConnectionsResponse {
+        total: page.total,
+        count: page.connections.len(),
+        connections: page.connections,
+    };
+    ("200 OK", serde_json::to_string_pretty(&response).unwrap_or_default())
+}
+
+/// Build a connection query from `/connections` parameters
+///
+/// Takes `limit`, `offset`, `sort` (a connection field name), `order`
+/// (`asc` or `desc`), `min_idle_secs` and `client_ip_prefix`.
+fn connection_query(request: &ApiRequest) -> Result<ConnectionQuery, String> {
+    fn number<T: std::str::FromStr>(request: &ApiRequest, name: &str) -> Result<Option<T>, String> {
+        request
+            .query(name)
+            .map(|value| value.parse().map_err(|_| format!("invalid {}: {}", name, value)))
+            .transpose()
+    }
+
+    let descending = match request.query("order") {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => return Err(format!("invalid order: {}", order)),
+    };
+
+    Ok(ConnectionQuery {
+        min_idle: number::<u64>(request, "min_idle_secs")?.map(Duration::from_secs),
+        client_ip_prefix: request.query("client_ip_prefix").map(str::to_string),
+        sort: request.query("sort").map(str::parse).transpose()?.unwrap_or_default(),
+        descending,
+        offset: number(request, "offset")?.unwrap_or(0),
+        limit: number(request, "limit")?,
+    })
+}
+
+/// Handle GET /stats
+fn stats(conn_manager: &ConnectionManager) -> (&'static str, String) {
+    let snapshot = METRICS.snapshot();
+    let response = StatsResponse {
+        connections_total: snapshot.connections_total,
+        connections_active: snapshot.connections_active,
+        connections_failed: snapshot.connections_failed,
+        connections_rejected_capacity: snapshot.connections_rejected_capacity,
+        connections_rejected_rate: snapshot.connections_rejected_rate,
+        connections_rejected_auth: snapshot.connections_rejected_auth,
+        bytes_received: snapshot.bytes_received,
+        bytes_sent: snapshot.bytes_sent,
+        streams_opened: snapshot.streams_opened,
+        streams_closed: snapshot.streams_closed,
+        errors_total: snapshot.errors_total,
+        buffer_pool_acquires: snapshot.buffer_pool_acquires,
+        buffer_pool_releases: snapshot.buffer_pool_releases,
+        buffer_pool_misses: snapshot.buffer_pool_misses,
+        max_streams_per_connection: conn_manager.stream_distribution().max(),
+    };
+    ("200 OK", serde_json::to_string_pretty(&response).unwrap_or_default())
+}
+
+/// Handle POST /stats/reset, answering with the stats after the reset
+fn reset_stats(state: &ApiState) -> (&'static str, String) {
+    if !state.allow_reset {
+        return ("403 Forbidden", r#"{"error": "Stats reset is disabled"}"#.to_string());
+    }
+    METRICS.reset();
+    info!("Metrics counters reset");
+    stats(&state.conn_manager)
+}
+
+/// Handle GET /metrics-json
+fn metrics_json(state: &ApiState) -> (&'static str, String) {
+    let response = MetricsJsonResponse {
+        metrics: METRICS.snapshot(),
+        buffer_pool: state.buffer_pool.stats(),
+        connection_count: state.conn_manager.connection_count(),
+    };
+    ("200 OK", serde_json::to_string_pretty(&response).unwrap_or_default())
+}
+
+/// Handle GET /events
+///
+/// Writes each event as a `data: {json}` frame and flushes it. Returns
+/// once a write fails, which is how a departed client shows up, or when
+/// the manager goes away.
+fn stream_events<W: Write>(stream: &mut W, conn_manager: &ConnectionManager) -> io::Result<()> {
+    // Subscribe before the headers go out so the client sees every later event
+    let mut events = conn_manager.subscribe_events();
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\n\
+          Content-Type: text/event-stream\r\n\
+          Cache-Control: no-cache\r\n\
+          Access-Control-Allow-Origin: *\r\n\
+          Connection: close\r\n\
+          \r\n",
+    )?;
+    stream.flush()?;
+
+    // API threads are outside the tokio runtime; this one only needs timers
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()?;
+    let result: io::Result<()> = runtime.block_on(async {
+        loop {
+            match tokio::time::timeout(EVENTS_KEEPALIVE, events.recv()).await {
+                Ok(Ok(event)) => {
+                    let json = serde_json::to_string(&event).unwrap_or_default();
+                    write!(stream, "data: {}\n\n", json)?;
+                }
+                Ok(Err(RecvError::Lagged(missed))) => {
+                    write!(stream, ": {} events dropped\n\n", missed)?;
+                }
+                Ok(Err(RecvError::Closed)) => return Ok(()),
+                Err(_) => stream.write_all(b": keepalive\n\n")?,
+            }
+            stream.flush()?;
         }
+    });
+
+    if let Err(e) = result {
+        debug!(error = %e, "Event stream client disconnected");
+    }
+    Ok(())
+}
+
+/// Handle GET /health
+fn health(conn_manager: &ConnectionManager) -> (&'static str, String) {
+    let response = ProbeResponse {
+        status: "ok",
+        connections: conn_manager.connection_count(),
     };
-    
+    ("200 OK", serde_json::to_string_pretty(&response).unwrap_or_default())
+}
+
+/// Handle GET /ready
+fn readiness(conn_manager: &ConnectionManager, ready: &AtomicBool) -> (&'static str, String) {
+    let (status, response) = if ready.load(Ordering::Acquire) {
+        ("200 OK", "ready")
+    } else {
+        ("503 Service Unavailable", "not ready")
+    };
+    let response = ProbeResponse {
+        status: response,
+        connections: conn_manager.connection_count(),
+    };
+    (status, serde_json::to_string_pretty(&response).unwrap_or_default())
+}
+
+/// Handle DELETE /connections/{id}
+fn disconnect(id: &str, conn_manager: &ConnectionManager) -> (&'static str, String) {
+    let Ok(id) = id.parse::<ConnectionId>() else {
+        return ("400 Bad Request", r#"{"error": "Invalid connection id"}"#.to_string());
+    };
+    if !conn_manager.disconnect(id) {
+        return ("404 Not Found", r#"{"error": "Connection not found"}"#.to_string());
+    }
+
+    let response = DisconnectResponse { closed: id.to_string() };
+    ("200 OK", serde_json::to_string_pretty(&response).unwrap_or_default())
+}
+
+fn write_response<W: Write>(
+    stream: &mut W,
+    status: &str,
+    body: &str,
+    keep_alive: bool,
+) -> io::Result<()> {
     let response = format!(
         "HTTP/1.1 {}\r\n\
          Content-Type: application/json\r\n\
          Content-Length: {}\r\n\
          Access-Control-Allow-Origin: *\r\n\
-         Connection: close\r\n\
+         Connection: {}\r\n\
          \r\n\
          {}",
         status,
         body.len(),
+        if keep_alive { "keep-alive" } else { "close" },
         body
     );
     
@@ -142,3 +570,286 @@ fn handle_request(mut stream: TcpStream, conn_manager: &ConnectionManager) -> st
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionManagerConfig;
+    use std::io::Cursor;
+
+    fn state(ready: bool) -> ApiState {
+        ApiState {
+            conn_manager: ConnectionManager::new(ConnectionManagerConfig {
+                max_connections: 4,
+                idle_timeout: Duration::from_secs(30),
+            }),
+            buffer_pool: BufferPool::new(1, 1, 1),
+            ready: Arc::new(AtomicBool::new(ready)),
+            allow_reset: false,
+        }
+    }
+
+    #[test]
+    fn test_read_request_long_headers() {
+        let mut raw = String::from("GET /stats?verbose=1 HTTP/1.1\r\n");
+        for i in 0..64 {
+            raw.push_str(&format!("X-Filler-{i}: {}\r\n", "a".repeat(1000)));
+        }
+        raw.push_str("\r\n");
+
+        let request = read_request(&mut Cursor::new(raw)).unwrap().unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/stats");
+        assert_eq!(request.query("verbose"), Some("1"));
+        assert!(request.keep_alive);
+    }
+
+    #[test]
+    fn test_read_request_pipelined() {
+        let raw = "DELETE /connections/ff HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+                   GET /health HTTP/1.0\r\n\r\n";
+        let mut reader = Cursor::new(raw);
+
+        let first = read_request(&mut reader).unwrap().unwrap();
+        assert_eq!(first.method, "DELETE");
+        assert_eq!(first.segments(), ["connections", "ff"]);
+
+        let second = read_request(&mut reader).unwrap().unwrap();
+        assert_eq!(second.path, "/health");
+        assert!(!second.keep_alive);
+
+        assert!(read_request(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_request_malformed() {
+        let too_long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LEN as usize));
+        let malformed = [
+            "GET /\r\n\r\n",
+            "GET / HTTP/2\r\n\r\n",
+            "GET / HTTP/1.1\r\nbad\r\n\r\n",
+            &too_long,
+        ];
+        for raw in malformed {
+            let err = read_request(&mut Cursor::new(raw)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            parse_query("prefix=10.%2F8&name=a+b&flag&&bad=%zz"),
+            [
+                ("prefix".to_string(), "10./8".to_string()),
+                ("name".to_string(), "a b".to_string()),
+                ("flag".to_string(), String::new()),
+                ("bad".to_string(), "%zz".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_connection_query() {
+        let request = |query: &str| ApiRequest {
+            method: "GET".to_string(),
+            path: "/connections".to_string(),
+            query: parse_query(query),
+            keep_alive: true,
+        };
+
+        let query = connection_query(&request(
+            "limit=100&offset=200&sort=bytes_tx&order=desc&min_idle_secs=60&client_ip_prefix=10.",
+        ))
+        .unwrap();
+        assert_eq!(query.limit, Some(100));
+        assert_eq!(query.offset, 200);
+        assert_eq!(query.sort, crate::connection::ConnectionSort::BytesTx);
+        assert!(query.descending);
+        assert_eq!(query.min_idle, Some(Duration::from_secs(60)));
+        assert_eq!(query.client_ip_prefix.as_deref(), Some("10."));
+
+        for bad in ["limit=-1", "sort=nope", "order=up", "min_idle_secs=x"] {
+            assert!(connection_query(&request(bad)).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_route() {
+        let state = state(false);
+        let request = |method: &str, path: &str| ApiRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: Vec::new(),
+            keep_alive: true,
+        };
+
+        assert_eq!(route(&request("GET", "/health/"), &state).0, "200 OK");
+        assert_eq!(
+            route(&request("GET", "/ready"), &state).0,
+            "503 Service Unavailable"
+        );
+        assert_eq!(
+            route(&request("POST", "/stats"), &state).0,
+            "405 Method Not Allowed"
+        );
+        assert_eq!(
+            route(&request("DELETE", "/connections/zz"), &state).0,
+            "400 Bad Request"
+        );
+        assert_eq!(
+            route(&request("DELETE", "/connections/ff"), &state).0,
+            "404 Not Found"
+        );
+        assert_eq!(
+            route(&request("POST", "/events"), &state).0,
+            "405 Method Not Allowed"
+        );
+        assert_eq!(
+            route(&request("GET", "/stats/reset"), &state).0,
+            "405 Method Not Allowed"
+        );
+        // Resetting the shared counters would upset other tests, so only
+        // the disabled case is checked here
+        assert_eq!(route(&request("POST", "/stats/reset"), &state).0, "403 Forbidden");
+        assert_eq!(route(&request("GET", "/nope"), &state).0, "404 Not Found");
+
+        let (status, body) = route(&request("GET", "/stats"), &state);
+        assert_eq!(status, "200 OK");
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["max_streams_per_connection"], 0);
+    }
+
+    #[test]
+    fn test_metrics_json_fields() {
+        let (status, body) = metrics_json(&state(true));
+        assert_eq!(status, "200 OK");
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+        // Destructuring without `..` stops compiling when a counter is added
+        macro_rules! assert_counters {
+            ($($field:ident),* $(,)?) => {
+                let MetricsSnapshot { $($field: _),* } = METRICS.snapshot();
+                $(
+                    assert!(
+                        json["metrics"][stringify!($field)].is_u64(),
+                        "missing {}",
+                        stringify!($field)
+                    );
+                )*
+            };
+        }
+        assert_counters!(
+            connections_total,
+            connections_active,
+            connections_failed,
+            connections_rate_limited,
+            connections_rate_limited_per_ip,
+            connections_rejected_capacity,
+            connections_rejected_rate,
+            connections_rejected_auth,
+            auth_failed,
+            connection_migrations,
+            handshakes_in_progress,
+            handshakes_refused,
+            bytes_received,
+            bytes_sent,
+            packets_received,
+            packets_sent,
+            streams_opened,
+            streams_closed,
+            stream_finish_errors,
+            slow_headers,
+            streams_in_flight,
+            streams_rejected_capacity,
+            outbound_connections,
+            datagrams_received,
+            datagrams_sent,
+            datagrams_malformed,
+            errors_total,
+            timeouts_total,
+            buffer_pool_acquires,
+            buffer_pool_releases,
+            buffer_pool_misses,
+            memory_pressure,
+        );
+
+        assert_eq!(json["buffer_pool"]["tiers"].as_array().unwrap().len(), 3);
+        assert_eq!(json["buffer_pool"]["tiers"][0]["size"], 4096);
+        assert_eq!(json["connection_count"], 0);
+    }
+
+    #[test]
+    fn test_keep_alive_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &state(true)).unwrap();
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let filler = "b".repeat(4000);
+        let mut raw = String::from("GET /health HTTP/1.1\r\n");
+        for i in 0..16 {
+            raw.push_str(&format!("X-Filler-{i}: {filler}\r\n"));
+        }
+        raw.push_str("\r\nGET /ready HTTP/1.1\r\nConnection: close\r\n\r\n");
+        client.write_all(raw.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap();
+
+        let responses: Vec<_> = response.matches("HTTP/1.1 200 OK").collect();
+        assert_eq!(responses.len(), 2);
+        assert!(response.contains("Connection: keep-alive"));
+        assert!(response.contains("\"status\": \"ready\""));
+    }
+
+    #[tokio::test]
+    async fn test_events_stream() {
+        let state = state(true);
+        let manager = state.conn_manager.clone();
+        let pair = crate::util::testing::quic_pair().await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &state).unwrap();
+        });
+
+        let mut client = BufReader::new(TcpStream::connect(addr).unwrap());
+        client.get_mut().write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let mut head = String::new();
+        while head != "\r\n" {
+            head.clear();
+            client.read_line(&mut head).unwrap();
+            if head.starts_with("Content-Type") {
+                assert_eq!(head, "Content-Type: text/event-stream\r\n");
+            }
+        }
+
+        let client_addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let id = manager.register(client_addr, pair.server.clone()).unwrap();
+        manager.unregister(id);
+
+        let mut next_event = || {
+            let mut line = String::new();
+            client.read_line(&mut line).unwrap();
+            let mut blank = String::new();
+            client.read_line(&mut blank).unwrap();
+            assert_eq!(blank, "\n");
+            let json = line.strip_prefix("data: ").unwrap();
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        };
+        let open = next_event();
+        assert_eq!(open["event"], "opened");
+        assert_eq!(open["id"], id.to_string());
+        assert_eq!(open["client_addr"], "127.0.0.1:12345");
+        let close = next_event();
+        assert_eq!(close["event"], "closed");
+        assert_eq!(close["bytes_rx"], 0);
+        assert!(close["duration_secs"].is_f64());
+    }
+}
@@ -2,6 +2,7 @@
 //!
 //! Lock-free counters that can be safely updated from any thread.
 
+use serde::Serialize;
 use std::sync::atomic::{AtomicU64, Ordering};
 
 /// Global metrics instance
@@ -13,6 +14,15 @@ pub struct Metrics {
     pub connections_total: AtomicU64,
     pub connections_active: AtomicU64,
     pub connections_failed: AtomicU64,
+    pub connections_rate_limited: AtomicU64,
+    pub connections_rate_limited_per_ip: AtomicU64,
+    pub connections_rejected_capacity: AtomicU64,
+    pub connections_rejected_rate: AtomicU64,
+    pub connections_rejected_auth: AtomicU64,
+    pub auth_failed: AtomicU64,
+    pub connection_migrations: AtomicU64,
+    pub handshakes_in_progress: AtomicU64,
+    pub handshakes_refused: AtomicU64,
 
     // Traffic metrics
     pub bytes_received: AtomicU64,
@@ -23,10 +33,16 @@ pub struct Metrics {
     // Stream metrics
     pub streams_opened: AtomicU64,
     pub streams_closed: AtomicU64,
+    pub stream_finish_errors: AtomicU64,
+    pub slow_headers: AtomicU64,
+    pub streams_in_flight: AtomicU64,
+    pub streams_rejected_capacity: AtomicU64,
+    pub outbound_connections: AtomicU64,
 
     // UDP relay metrics
     pub datagrams_received: AtomicU64,
     pub datagrams_sent: AtomicU64,
+    pub datagrams_malformed: AtomicU64,
 
     // Error metrics
     pub errors_total: AtomicU64,
@@ -36,6 +52,9 @@ pub struct Metrics {
     pub buffer_pool_acquires: AtomicU64,
     pub buffer_pool_releases: AtomicU64,
     pub buffer_pool_misses: AtomicU64,
+
+    // Resource metrics
+    pub memory_pressure: AtomicU64,
 }
 
 impl Metrics {
@@ -44,19 +63,35 @@ impl Metrics {
             connections_total: AtomicU64::new(0),
             connections_active: AtomicU64::new(0),
             connections_failed: AtomicU64::new(0),
+            connections_rate_limited: AtomicU64::new(0),
+            connections_rate_limited_per_ip: AtomicU64::new(0),
+            connections_rejected_capacity: AtomicU64::new(0),
+            connections_rejected_rate: AtomicU64::new(0),
+            connections_rejected_auth: AtomicU64::new(0),
+            auth_failed: AtomicU64::new(0),
+            connection_migrations: AtomicU64::new(0),
+            handshakes_in_progress: AtomicU64::new(0),
+            handshakes_refused: AtomicU64::new(0),
             bytes_received: AtomicU64::new(0),
             bytes_sent: AtomicU64::new(0),
             packets_received: AtomicU64::new(0),
             packets_sent: AtomicU64::new(0),
             streams_opened: AtomicU64::new(0),
             streams_closed: AtomicU64::new(0),
+            stream_finish_errors: AtomicU64::new(0),
+            slow_headers: AtomicU64::new(0),
+            streams_in_flight: AtomicU64::new(0),
+            streams_rejected_capacity: AtomicU64::new(0),
+            outbound_connections: AtomicU64::new(0),
             datagrams_received: AtomicU64::new(0),
             datagrams_sent: AtomicU64::new(0),
+            datagrams_malformed: AtomicU64::new(0),
             errors_total: AtomicU64::new(0),
             timeouts_total: AtomicU64::new(0),
             buffer_pool_acquires: AtomicU64::new(0),
             buffer_pool_releases: AtomicU64::new(0),
             buffer_pool_misses: AtomicU64::new(0),
+            memory_pressure: AtomicU64::new(0),
         }
     }
 
@@ -77,6 +112,46 @@ impl Metrics {
         self.connections_failed.fetch_add(1, Ordering::Relaxed);
     }
 
+    #[inline]
+    pub fn connection_rate_limited(&self) {
+        self.connections_rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn connection_rate_limited_per_ip(&self) {
+        self.connections_rate_limited_per_ip.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn connection_rejected_capacity(&self) {
+        self.connections_rejected_capacity.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn connection_rejected_rate(&self) {
+        self.connections_rejected_rate.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn connection_rejected_auth(&self) {
+        self.connections_rejected_auth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn handshake_refused(&self) {
+        self.handshakes_refused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn auth_failure(&self) {
+        self.auth_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn connection_migrated(&self) {
+        self.connection_migrations.fetch_add(1, Ordering::Relaxed);
+    }
+
     // Traffic tracking
     #[inline]
     pub fn bytes_rx(&self, count: u64) {
@@ -101,6 +176,21 @@ impl Metrics {
         self.streams_closed.fetch_add(1, Ordering::Relaxed);
     }
 
+    #[inline]
+    pub fn stream_finish_error(&self) {
+        self.stream_finish_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn slow_header(&self) {
+        self.slow_headers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn stream_rejected_capacity(&self) {
+        self.streams_rejected_capacity.fetch_add(1, Ordering::Relaxed);
+    }
+
     // Datagram tracking
     #[inline]
     pub fn datagram_rx(&self) {
@@ -112,6 +202,11 @@ impl Metrics {
         self.datagrams_sent.fetch_add(1, Ordering::Relaxed);
     }
 
+    #[inline]
+    pub fn datagram_malformed(&self) {
+        self.datagrams_malformed.fetch_add(1, Ordering::Relaxed);
+    }
+
     // Error tracking
     #[inline]
     pub fn error(&self) {
@@ -139,41 +234,224 @@ impl Metrics {
         self.buffer_pool_misses.fetch_add(1, Ordering::Relaxed);
     }
 
+    /// Zero the monotonic counters, e.g. between load test runs
+    ///
+    /// Gauges (`connections_active`, `handshakes_in_progress`,
+    /// `streams_in_flight`, `outbound_connections`, `memory_pressure`) keep
+    /// tracking live state. The Prometheus exporter syncs counters by delta,
+    /// so its counters don't go backwards; increments in the last second
+    /// before a reset are not exported.
+    pub fn reset(&self) {
+        // Destructuring without `..` stops compiling when a field is added
+        let Metrics {
+            connections_total,
+            connections_active: _,
+            connections_failed,
+            connections_rate_limited,
+            connections_rate_limited_per_ip,
+            connections_rejected_capacity,
+            connections_rejected_rate,
+            connections_rejected_auth,
+            auth_failed,
+            connection_migrations,
+            handshakes_in_progress: _,
+            handshakes_refused,
+            bytes_received,
+            bytes_sent,
+            packets_received,
+            packets_sent,
+            streams_opened,
+            streams_closed,
+            stream_finish_errors,
+            slow_headers,
+            streams_in_flight: _,
+            streams_rejected_capacity,
+            outbound_connections: _,
+            datagrams_received,
+            datagrams_sent,
+            datagrams_malformed,
+            errors_total,
+            timeouts_total,
+            buffer_pool_acquires,
+            buffer_pool_releases,
+            buffer_pool_misses,
+            memory_pressure: _,
+        } = self;
+        for counter in [
+            connections_total,
+            connections_failed,
+            connections_rate_limited,
+            connections_rate_limited_per_ip,
+            connections_rejected_capacity,
+            connections_rejected_rate,
+            connections_rejected_auth,
+            auth_failed,
+            connection_migrations,
+            handshakes_refused,
+            bytes_received,
+            bytes_sent,
+            packets_received,
+            packets_sent,
+            streams_opened,
+            streams_closed,
+            stream_finish_errors,
+            slow_headers,
+            streams_rejected_capacity,
+            datagrams_received,
+            datagrams_sent,
+            datagrams_malformed,
+            errors_total,
+            timeouts_total,
+            buffer_pool_acquires,
+            buffer_pool_releases,
+            buffer_pool_misses,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
     /// Get snapshot of all metrics
     pub fn snapshot(&self) -> MetricsSnapshot {
         MetricsSnapshot {
             connections_total: self.connections_total.load(Ordering::Relaxed),
             connections_active: self.connections_active.load(Ordering::Relaxed),
             connections_failed: self.connections_failed.load(Ordering::Relaxed),
+            connections_rate_limited: self.connections_rate_limited.load(Ordering::Relaxed),
+            connections_rate_limited_per_ip: self
+                .connections_rate_limited_per_ip
+                .load(Ordering::Relaxed),
+            connections_rejected_capacity: self
+                .connections_rejected_capacity
+                .load(Ordering::Relaxed),
+            connections_rejected_rate: self.connections_rejected_rate.load(Ordering::Relaxed),
+            connections_rejected_auth: self.connections_rejected_auth.load(Ordering::Relaxed),
+            auth_failed: self.auth_failed.load(Ordering::Relaxed),
+            connection_migrations: self.connection_migrations.load(Ordering::Relaxed),
+            handshakes_in_progress: self.handshakes_in_progress.load(Ordering::Relaxed),
+            handshakes_refused: self.handshakes_refused.load(Ordering::Relaxed),
             bytes_received: self.bytes_received.load(Ordering::Relaxed),
             bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
             packets_received: self.packets_received.load(Ordering::Relaxed),
             packets_sent: self.packets_sent.load(Ordering::Relaxed),
             streams_opened: self.streams_opened.load(Ordering::Relaxed),
             streams_closed: self.streams_closed.load(Ordering::Relaxed),
+            stream_finish_errors: self.stream_finish_errors.load(Ordering::Relaxed),
+            slow_headers: self.slow_headers.load(Ordering::Relaxed),
+            streams_in_flight: self.streams_in_flight.load(Ordering::Relaxed),
+            streams_rejected_capacity: self.streams_rejected_capacity.load(Ordering::Relaxed),
+            outbound_connections: self.outbound_connections.load(Ordering::Relaxed),
             datagrams_received: self.datagrams_received.load(Ordering::Relaxed),
             datagrams_sent: self.datagrams_sent.load(Ordering::Relaxed),
+            datagrams_malformed: self.datagrams_malformed.load(Ordering::Relaxed),
             errors_total: self.errors_total.load(Ordering::Relaxed),
             timeouts_total: self.timeouts_total.load(Ordering::Relaxed),
+            buffer_pool_acquires: self.buffer_pool_acquires.load(Ordering::Relaxed),
+            buffer_pool_releases: self.buffer_pool_releases.load(Ordering::Relaxed),
+            buffer_pool_misses: self.buffer_pool_misses.load(Ordering::Relaxed),
+            memory_pressure: self.memory_pressure.load(Ordering::Relaxed),
         }
     }
 }
 
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Snapshot of metrics for reporting
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct MetricsSnapshot {
     pub connections_total: u64,
     pub connections_active: u64,
     pub connections_failed: u64,
+    pub connections_rate_limited: u64,
+    pub connections_rate_limited_per_ip: u64,
+    pub connections_rejected_capacity: u64,
+    pub connections_rejected_rate: u64,
+    pub connections_rejected_auth: u64,
+    pub auth_failed: u64,
+    pub connection_migrations: u64,
+    pub handshakes_in_progress: u64,
+    pub handshakes_refused: u64,
     pub bytes_received: u64,
     pub bytes_sent: u64,
     pub packets_received: u64,
     pub packets_sent: u64,
     pub streams_opened: u64,
     pub streams_closed: u64,
+    pub stream_finish_errors: u64,
+    pub slow_headers: u64,
+    pub streams_in_flight: u64,
+    pub streams_rejected_capacity: u64,
+    pub outbound_connections: u64,
     pub datagrams_received: u64,
     pub datagrams_sent: u64,
+    pub datagrams_malformed: u64,
     pub errors_total: u64,
     pub timeouts_total: u64,
+    pub buffer_pool_acquires: u64,
+    pub buffer_pool_releases: u64,
+    pub buffer_pool_misses: u64,
+    pub memory_pressure: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reset() {
+        // Not METRICS: other tests update it concurrently
+        let metrics = Metrics::new();
+        metrics.connection_opened();
+        metrics.connection_opened();
+        metrics.bytes_rx(1200);
+        metrics.stream_opened();
+
+        metrics.reset();
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.bytes_received, 0);
+        assert_eq!(snapshot.packets_received, 0);
+        assert_eq!(snapshot.connections_total, 0);
+        assert_eq!(snapshot.streams_opened, 0);
+        assert_eq!(snapshot.connections_active, 2);
+
+        // Connections open across the reset still close cleanly
+        metrics.connection_closed();
+        metrics.connection_closed();
+        assert_eq!(metrics.snapshot().connections_active, 0);
+    }
+
+    #[test]
+    fn test_rejection_counters() {
+        let metrics = Metrics::new();
+        let rejected = |metrics: &Metrics| {
+            let snapshot = metrics.snapshot();
+            (
+                snapshot.connections_rejected_capacity,
+                snapshot.connections_rejected_rate,
+                snapshot.connections_rejected_auth,
+            )
+        };
+
+        // Each cause moves only its own counter
+        metrics.connection_rejected_capacity();
+        assert_eq!(rejected(&metrics), (1, 0, 0));
+        metrics.connection_rejected_rate();
+        metrics.connection_rejected_rate();
+        assert_eq!(rejected(&metrics), (1, 2, 0));
+        metrics.connection_rejected_auth();
+        assert_eq!(rejected(&metrics), (1, 2, 1));
+
+        // The finer-grained counters are kept apart from them
+        metrics.connection_rate_limited();
+        metrics.connection_rate_limited_per_ip();
+        metrics.auth_failure();
+        assert_eq!(rejected(&metrics), (1, 2, 1));
+
+        metrics.reset();
+        assert_eq!(rejected(&metrics), (0, 0, 0));
+    }
 }
 
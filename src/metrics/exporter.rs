@@ -3,34 +3,79 @@
 //! HTTP endpoint for Prometheus scraping.
 
 use anyhow::Result;
-use metrics::{describe_counter, describe_gauge, gauge, counter};
-use metrics_exporter_prometheus::PrometheusBuilder;
-use std::net::SocketAddr;
+use metrics::{
+    describe_counter, describe_gauge, describe_histogram, gauge, counter, histogram, Unit,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::task::JoinHandle;
 
 use crate::config::MetricsConfig;
+use crate::connection::{ConnectionManager, StreamDistribution};
+use crate::pool::{BufferPool, BufferPoolStats};
 use super::counters::METRICS;
 
+/// Histogram buckets for latency metrics, in seconds
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Histogram buckets for streams per connection
+const STREAM_COUNT_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0];
+
+/// How often every connection's stream count is sampled
+const STREAM_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
 /// Initialize the Prometheus metrics exporter
 pub fn init_metrics(config: &MetricsConfig) -> Result<()> {
     // Register metric descriptions
     describe_counter!("mytunnel_connections_total", "Total connections received");
     describe_gauge!("mytunnel_connections_active", "Currently active connections");
     describe_counter!("mytunnel_connections_failed", "Failed connection attempts");
+    describe_counter!("mytunnel_connections_rate_limited", "Connections dropped by the new-connection rate limit, by limit (global or per_ip)");
+    describe_counter!("mytunnel_connections_rejected_capacity", "Connections closed because the server was at max_connections");
+    describe_counter!("mytunnel_connections_rejected_rate", "Connections dropped by either new-connection rate limit");
+    describe_counter!("mytunnel_connections_rejected_auth", "Connections closed because authentication was denied");
+    describe_counter!("mytunnel_auth_failed", "Connections closed for a missing or invalid auth token");
+    describe_counter!("mytunnel_connection_migrations", "Client address changes seen on established connections");
+    describe_gauge!("mytunnel_handshakes_in_progress", "QUIC handshakes currently holding a max_concurrent_handshakes slot");
+    describe_counter!("mytunnel_handshakes_refused", "Connections refused after waiting for a handshake slot");
     describe_counter!("mytunnel_bytes_received", "Total bytes received");
     describe_counter!("mytunnel_bytes_sent", "Total bytes sent");
+    describe_gauge!("mytunnel_throughput_rx_bps", "Bits per second received over the last sync interval");
+    describe_gauge!("mytunnel_throughput_tx_bps", "Bits per second sent over the last sync interval");
     describe_counter!("mytunnel_packets_received", "Total packets received");
     describe_counter!("mytunnel_packets_sent", "Total packets sent");
     describe_counter!("mytunnel_streams_opened", "Total streams opened");
     describe_counter!("mytunnel_streams_closed", "Total streams closed");
+    describe_counter!("mytunnel_stream_finish_errors", "Streams that failed to finish or were stopped by the peer");
+    describe_counter!("mytunnel_slow_headers", "Streams refused because their request header arrived too slowly");
+    describe_gauge!("mytunnel_streams_in_flight", "Stream handlers currently running across the server");
+    describe_counter!("mytunnel_streams_rejected_capacity", "Streams refused because max_concurrent_streams were already running");
+    describe_gauge!("mytunnel_outbound_connections", "Currently open origin connections");
     describe_counter!("mytunnel_datagrams_received", "Total datagrams received");
     describe_counter!("mytunnel_datagrams_sent", "Total datagrams sent");
+    describe_counter!("mytunnel_datagrams_malformed", "Datagrams dropped for a malformed relay header");
     describe_counter!("mytunnel_errors_total", "Total errors");
     describe_counter!("mytunnel_timeouts_total", "Total timeouts");
+    describe_gauge!("mytunnel_memory_pressure", "1 while resident memory is over max_memory_mb and connections are shed, else 0");
+    describe_gauge!("mytunnel_buffer_pool_in_use", "Pooled buffers currently checked out, by size tier");
+    describe_gauge!("mytunnel_buffer_pool_allocated", "Pooled buffers allocated, by size tier");
+    describe_histogram!("mytunnel_target_connect_seconds", Unit::Seconds, "Time to open a TCP connection to a tunnel target");
+    describe_histogram!("mytunnel_udp_relay_rtt_seconds", Unit::Seconds, "Round trip of a one-shot UDP relay request");
+    describe_histogram!("mytunnel_streams_per_connection", "Open streams of each connection, sampled every 10 seconds");
+    describe_gauge!("mytunnel_streams_per_connection_max", "Most open streams on any one connection");
+    describe_gauge!("mytunnel_streams_per_connection_avg", "Mean open streams per connection");
 
     // Build and install the Prometheus exporter
     PrometheusBuilder::new()
         .with_http_listener(config.bind_addr)
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), LATENCY_BUCKETS)?
+        .set_buckets_for_metric(
+            Matcher::Full("mytunnel_streams_per_connection".to_string()),
+            STREAM_COUNT_BUCKETS,
+        )?
         .install()?;
 
     // Start background task to sync atomic counters to metrics crate
@@ -40,15 +85,22 @@ pub fn init_metrics(config: &MetricsConfig) -> Result<()> {
 }
 
 /// Background task that periodically syncs our atomic counters to the metrics crate
+///
+/// Counters advance by the delta since the last sync; `saturating_sub` turns
+/// the drop after [`Metrics::reset`](super::counters::Metrics::reset) into
+/// a zero delta, so Prometheus never sees a counter go backwards.
 async fn sync_metrics_task() {
     let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
 
     let mut last_snapshot = METRICS.snapshot();
+    let mut last_sync = Instant::now();
 
     loop {
         interval.tick().await;
 
         let snapshot = METRICS.snapshot();
+        let elapsed = last_sync.elapsed();
+        last_sync = Instant::now();
 
         // Update counters with deltas
         let conn_delta = snapshot.connections_total.saturating_sub(last_snapshot.connections_total);
@@ -63,6 +115,60 @@ async fn sync_metrics_task() {
             counter!("mytunnel_connections_failed").increment(failed_delta);
         }
 
+        let rate_limited_delta = snapshot.connections_rate_limited.saturating_sub(last_snapshot.connections_rate_limited);
+        if rate_limited_delta > 0 {
+            counter!("mytunnel_connections_rate_limited", "limit" => "global").increment(rate_limited_delta);
+        }
+
+        let per_ip_delta = snapshot
+            .connections_rate_limited_per_ip
+            .saturating_sub(last_snapshot.connections_rate_limited_per_ip);
+        if per_ip_delta > 0 {
+            counter!("mytunnel_connections_rate_limited", "limit" => "per_ip").increment(per_ip_delta);
+        }
+
+        let capacity_delta = snapshot
+            .connections_rejected_capacity
+            .saturating_sub(last_snapshot.connections_rejected_capacity);
+        if capacity_delta > 0 {
+            counter!("mytunnel_connections_rejected_capacity").increment(capacity_delta);
+        }
+
+        let rejected_rate_delta = snapshot
+            .connections_rejected_rate
+            .saturating_sub(last_snapshot.connections_rejected_rate);
+        if rejected_rate_delta > 0 {
+            counter!("mytunnel_connections_rejected_rate").increment(rejected_rate_delta);
+        }
+
+        let rejected_auth_delta = snapshot
+            .connections_rejected_auth
+            .saturating_sub(last_snapshot.connections_rejected_auth);
+        if rejected_auth_delta > 0 {
+            counter!("mytunnel_connections_rejected_auth").increment(rejected_auth_delta);
+        }
+
+        let auth_failed_delta = snapshot.auth_failed.saturating_sub(last_snapshot.auth_failed);
+        if auth_failed_delta > 0 {
+            counter!("mytunnel_auth_failed").increment(auth_failed_delta);
+        }
+
+        let migrations_delta = snapshot
+            .connection_migrations
+            .saturating_sub(last_snapshot.connection_migrations);
+        if migrations_delta > 0 {
+            counter!("mytunnel_connection_migrations").increment(migrations_delta);
+        }
+
+        gauge!("mytunnel_handshakes_in_progress").set(snapshot.handshakes_in_progress as f64);
+
+        let handshakes_refused_delta = snapshot
+            .handshakes_refused
+            .saturating_sub(last_snapshot.handshakes_refused);
+        if handshakes_refused_delta > 0 {
+            counter!("mytunnel_handshakes_refused").increment(handshakes_refused_delta);
+        }
+
         let rx_delta = snapshot.bytes_received.saturating_sub(last_snapshot.bytes_received);
         if rx_delta > 0 {
             counter!("mytunnel_bytes_received").increment(rx_delta);
@@ -73,6 +179,9 @@ async fn sync_metrics_task() {
             counter!("mytunnel_bytes_sent").increment(tx_delta);
         }
 
+        gauge!("mytunnel_throughput_rx_bps").set(bits_per_sec(rx_delta, elapsed));
+        gauge!("mytunnel_throughput_tx_bps").set(bits_per_sec(tx_delta, elapsed));
+
         let pkt_rx_delta = snapshot.packets_received.saturating_sub(last_snapshot.packets_received);
         if pkt_rx_delta > 0 {
             counter!("mytunnel_packets_received").increment(pkt_rx_delta);
@@ -93,6 +202,27 @@ async fn sync_metrics_task() {
             counter!("mytunnel_streams_closed").increment(streams_closed_delta);
         }
 
+        let finish_errors_delta = snapshot.stream_finish_errors.saturating_sub(last_snapshot.stream_finish_errors);
+        if finish_errors_delta > 0 {
+            counter!("mytunnel_stream_finish_errors").increment(finish_errors_delta);
+        }
+
+        let slow_headers_delta = snapshot.slow_headers.saturating_sub(last_snapshot.slow_headers);
+        if slow_headers_delta > 0 {
+            counter!("mytunnel_slow_headers").increment(slow_headers_delta);
+        }
+
+        gauge!("mytunnel_streams_in_flight").set(snapshot.streams_in_flight as f64);
+
+        let streams_rejected_delta = snapshot
+            .streams_rejected_capacity
+            .saturating_sub(last_snapshot.streams_rejected_capacity);
+        if streams_rejected_delta > 0 {
+            counter!("mytunnel_streams_rejected_capacity").increment(streams_rejected_delta);
+        }
+
+        gauge!("mytunnel_outbound_connections").set(snapshot.outbound_connections as f64);
+
         let dg_rx_delta = snapshot.datagrams_received.saturating_sub(last_snapshot.datagrams_received);
         if dg_rx_delta > 0 {
             counter!("mytunnel_datagrams_received").increment(dg_rx_delta);
@@ -103,6 +233,11 @@ async fn sync_metrics_task() {
             counter!("mytunnel_datagrams_sent").increment(dg_tx_delta);
         }
 
+        let dg_malformed_delta = snapshot.datagrams_malformed.saturating_sub(last_snapshot.datagrams_malformed);
+        if dg_malformed_delta > 0 {
+            counter!("mytunnel_datagrams_malformed").increment(dg_malformed_delta);
+        }
+
         let errors_delta = snapshot.errors_total.saturating_sub(last_snapshot.errors_total);
         if errors_delta > 0 {
             counter!("mytunnel_errors_total").increment(errors_delta);
@@ -113,20 +248,88 @@ async fn sync_metrics_task() {
             counter!("mytunnel_timeouts_total").increment(timeouts_delta);
         }
 
+        gauge!("mytunnel_memory_pressure").set(snapshot.memory_pressure as f64);
+
         last_snapshot = snapshot;
     }
 }
 
-/// Start a simple HTTP server for health checks and metrics
-#[allow(dead_code)]
-pub fn start_health_server(addr: SocketAddr) -> JoinHandle<()> {
+/// Start a background task that publishes buffer pool usage as gauges
+pub fn start_buffer_pool_metrics(buffer_pool: BufferPool) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+            publish_buffer_pool_stats(&buffer_pool.stats());
+        }
+    })
+}
+
+/// Start a background task that samples how streams spread over connections
+pub fn start_stream_metrics(conn_manager: Arc<ConnectionManager>) -> JoinHandle<()> {
     tokio::spawn(async move {
-        // The Prometheus exporter already provides /metrics
-        // This could be extended to add /health and /ready endpoints
-        tracing::info!(%addr, "Health server running (metrics at /metrics)");
-        
-        // Keep task alive - actual serving is done by PrometheusBuilder
-        std::future::pending::<()>().await;
+        let mut interval = tokio::time::interval(STREAM_SAMPLE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            publish_stream_distribution(&conn_manager.stream_distribution());
+        }
     })
 }
 
+fn publish_stream_distribution(distribution: &StreamDistribution) {
+    let histogram = histogram!("mytunnel_streams_per_connection");
+    for &count in &distribution.counts {
+        histogram.record(count as f64);
+    }
+    gauge!("mytunnel_streams_per_connection_max").set(distribution.max() as f64);
+    gauge!("mytunnel_streams_per_connection_avg").set(distribution.avg());
+}
+
+fn publish_buffer_pool_stats(stats: &BufferPoolStats) {
+    for tier in &stats.tiers {
+        let label = tier_label(tier.size);
+        gauge!("mytunnel_buffer_pool_in_use", "tier" => label.clone()).set(tier.in_use as f64);
+        gauge!("mytunnel_buffer_pool_allocated", "tier" => label.clone())
+            .set(tier.allocated as f64);
+        gauge!("mytunnel_buffer_pool_overflow", "tier" => label).set(tier.overflow as f64);
+    }
+}
+
+/// Throughput in bits per second of `bytes` moved over `elapsed`
+///
+/// A zero interval reports no throughput rather than infinity.
+fn bits_per_sec(bytes: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs == 0.0 {
+        return 0.0;
+    }
+    bytes as f64 * 8.0 / secs
+}
+
+/// Metric label for a buffer tier
+///
+/// The default sizes keep their names; custom tiers are labelled in bytes.
+fn tier_label(size: usize) -> String {
+    match size {
+        4096 => "small".to_string(),
+        16384 => "medium".to_string(),
+        65536 => "large".to_string(),
+        size => size.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bits_per_sec() {
+        assert_eq!(bits_per_sec(125_000, Duration::from_secs(1)), 1_000_000.0);
+        // A late tick spreads the same bytes over a longer interval
+        assert_eq!(bits_per_sec(125_000, Duration::from_millis(1250)), 800_000.0);
+        assert_eq!(bits_per_sec(0, Duration::from_secs(1)), 0.0);
+        assert_eq!(bits_per_sec(1000, Duration::ZERO), 0.0);
+    }
+}
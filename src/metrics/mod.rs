@@ -8,5 +8,5 @@ mod exporter;
 
 pub use api::start_api_server;
 pub use counters::*;
-pub use exporter::init_metrics;
+pub use exporter::{init_metrics, start_buffer_pool_metrics, start_stream_metrics};
 
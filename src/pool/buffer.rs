@@ -1,14 +1,23 @@
 //! Fixed-size buffer pool
 //!
 //! Pre-allocated buffers with lock-free acquire/release for zero-allocation
-//! data forwarding in the hot path.
+//! data forwarding in the hot path. Buffers come in size tiers; a request
+//! is served from the smallest tier that fits it.
 
 use crossbeam::queue::ArrayQueue;
+use serde::Serialize;
 use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
 
-/// Buffer size tiers
+use crate::metrics::METRICS;
+
+/// Sizes of the default tier layout
+///
+/// Kept so callers can ask for the classic sizes by name; any byte length
+/// works with [`BufferPool::acquire`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BufferSize {
     /// 4KB - for small packets and headers
@@ -25,10 +34,21 @@ impl BufferSize {
     }
 }
 
+impl From<BufferSize> for usize {
+    fn from(size: BufferSize) -> usize {
+        size.as_usize()
+    }
+}
+
 /// A buffer from the pool
 pub struct Buffer {
     data: Box<[u8]>,
-    size: BufferSize,
+    /// Index of the tier it belongs to; None if larger than every tier
+    tier: Option<usize>,
+    /// Preallocated buffers go back on the queue; overflow ones are freed
+    pooled: bool,
+    /// Wake a waiter on release; off when undoing a take nobody else saw
+    wake_waiter: bool,
     pool: Arc<BufferPoolInner>,
 }
 
@@ -37,11 +57,6 @@ impl Buffer {
     pub fn capacity(&self) -> usize {
         self.data.len()
     }
-
-    /// Get the buffer size tier
-    pub fn size_tier(&self) -> BufferSize {
-        self.size
-    }
 }
 
 impl Deref for Buffer {
@@ -62,39 +77,131 @@ impl Drop for Buffer {
     fn drop(&mut self) {
         // Return buffer to pool
         let data = std::mem::replace(&mut self.data, Box::new([]));
-        self.pool.return_buffer(data, self.size);
+        self.pool.return_buffer(data, self.tier, self.pooled, self.wake_waiter);
+        METRICS.buffer_released();
+    }
+}
+
+/// Buffers and counters for one size tier
+struct Tier {
+    /// Size of every buffer in the tier
+    size: usize,
+    /// Idle preallocated buffers; its capacity is the tier's base size
+    buffers: ArrayQueue<Box<[u8]>>,
+    /// Overflow buffers `acquire` may allocate beyond the base size
+    max_overflow: AtomicUsize,
+
+    // Metrics
+    allocated: AtomicUsize,
+    in_use: AtomicUsize,
+    overflow: AtomicUsize,
+}
+
+impl Tier {
+    fn new(size: usize, count: usize) -> Self {
+        // A zero-capacity ArrayQueue panics; an empty tier lives on overflow
+        let buffers = ArrayQueue::new(count.max(1));
+        for _ in 0..count {
+            let _ = buffers.push(vec![0u8; size].into_boxed_slice());
+        }
+
+        Self {
+            size,
+            buffers,
+            max_overflow: AtomicUsize::new(0),
+            allocated: AtomicUsize::new(count),
+            in_use: AtomicUsize::new(0),
+            overflow: AtomicUsize::new(0),
+        }
+    }
+
+    /// Claim an overflow slot if the tier is under its cap
+    fn reserve_overflow(&self) -> bool {
+        let max = self.max_overflow.load(Ordering::Relaxed);
+        self.overflow
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| (n < max).then_some(n + 1))
+            .is_ok()
     }
 }
 
 /// Inner pool state (shared across clones)
 struct BufferPoolInner {
-    small_buffers: ArrayQueue<Box<[u8]>>,
-    medium_buffers: ArrayQueue<Box<[u8]>>,
-    large_buffers: ArrayQueue<Box<[u8]>>,
-    
-    // Metrics
-    small_allocated: AtomicUsize,
-    medium_allocated: AtomicUsize,
-    large_allocated: AtomicUsize,
-    small_in_use: AtomicUsize,
-    medium_in_use: AtomicUsize,
-    large_in_use: AtomicUsize,
+    /// Tiers sorted by ascending size
+    tiers: Vec<Tier>,
+    /// Clear buffers before they go back on the queue
+    zero_on_release: AtomicBool,
+    /// Signaled when a tier buffer is released while someone is waiting
+    released: Notify,
+    /// Callers blocked in `acquire_timeout`
+    waiters: AtomicUsize,
 }
 
 impl BufferPoolInner {
-    fn return_buffer(&self, data: Box<[u8]>, size: BufferSize) {
-        match size {
-            BufferSize::Small => {
-                self.small_in_use.fetch_sub(1, Ordering::Relaxed);
-                let _ = self.small_buffers.push(data);
-            }
-            BufferSize::Medium => {
-                self.medium_in_use.fetch_sub(1, Ordering::Relaxed);
-                let _ = self.medium_buffers.push(data);
+    /// Index of the smallest tier holding `len` bytes
+    fn tier_for(&self, len: usize) -> Option<usize> {
+        self.tiers.iter().position(|tier| tier.size >= len)
+    }
+
+    fn return_buffer(&self, mut data: Box<[u8]>, tier: Option<usize>, pooled: bool, wake: bool) {
+        if self.zero_on_release.load(Ordering::Relaxed) {
+            data.fill(0);
+        }
+
+        // Oversized buffers were never tracked by a tier
+        let Some(tier) = tier.map(|index| &self.tiers[index]) else {
+            return;
+        };
+
+        tier.in_use.fetch_sub(1, Ordering::Relaxed);
+        if pooled {
+            let _ = tier.buffers.push(data);
+        } else {
+            // Overflow shrinks back to the base size as soon as it's released
+            tier.overflow.fetch_sub(1, Ordering::Relaxed);
+            tier.allocated.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        // Skip the Notify lock on the hot path unless someone is waiting;
+        // one buffer can satisfy one waiter, so only one is woken
+        if wake && self.waiters.load(Ordering::SeqCst) > 0 {
+            self.released.notify_one();
+        }
+    }
+
+    /// Take a buffer from tier `index`, falling back to overflow up to the cap
+    fn take(self: &Arc<Self>, index: usize) -> Option<Buffer> {
+        let tier = &self.tiers[index];
+        let (data, pooled) = match tier.buffers.pop() {
+            Some(data) => (data, true),
+            None if tier.reserve_overflow() => {
+                tier.allocated.fetch_add(1, Ordering::Relaxed);
+                (vec![0u8; tier.size].into_boxed_slice(), false)
             }
-            BufferSize::Large => {
-                self.large_in_use.fetch_sub(1, Ordering::Relaxed);
-                let _ = self.large_buffers.push(data);
+            None => return None,
+        };
+
+        tier.in_use.fetch_add(1, Ordering::Relaxed);
+        METRICS.buffer_acquired();
+        Some(Buffer {
+            data,
+            tier: Some(index),
+            pooled,
+            wake_waiter: true,
+            pool: self.clone(),
+        })
+    }
+
+    /// Take two buffers from tier `index`, or none
+    ///
+    /// A first buffer taken without a second goes back without waking
+    /// anyone; a waiting caller would otherwise wake itself in a loop.
+    fn take_pair(self: &Arc<Self>, index: usize) -> Option<(Buffer, Buffer)> {
+        let mut first = self.take(index)?;
+        match self.take(index) {
+            Some(second) => Some((first, second)),
+            None => {
+                first.wake_waiter = false;
+                None
             }
         }
     }
@@ -107,134 +214,416 @@ pub struct BufferPool {
 }
 
 impl BufferPool {
-    /// Create a new buffer pool with pre-allocated buffers
+    /// Create a pool with the default 4KB/16KB/64KB tiers
     pub fn new(small_count: usize, medium_count: usize, large_count: usize) -> Self {
+        Self::with_tiers(&[
+            (BufferSize::Small.as_usize(), small_count),
+            (BufferSize::Medium.as_usize(), medium_count),
+            (BufferSize::Large.as_usize(), large_count),
+        ])
+    }
+
+    /// Create a pool from `(size, count)` pairs, preallocating `count`
+    /// buffers of `size` bytes each
+    ///
+    /// Pairs may come in any order; for a repeated size only the first
+    /// pair is used.
+    pub fn with_tiers(tiers: &[(usize, usize)]) -> Self {
+        let mut layout = tiers.to_vec();
+        layout.sort_by_key(|&(size, _)| size);
+        layout.dedup_by_key(|&mut (size, _)| size);
+
         let inner = BufferPoolInner {
-            small_buffers: ArrayQueue::new(small_count),
-            medium_buffers: ArrayQueue::new(medium_count),
-            large_buffers: ArrayQueue::new(large_count),
-            small_allocated: AtomicUsize::new(0),
-            medium_allocated: AtomicUsize::new(0),
-            large_allocated: AtomicUsize::new(0),
-            small_in_use: AtomicUsize::new(0),
-            medium_in_use: AtomicUsize::new(0),
-            large_in_use: AtomicUsize::new(0),
+            tiers: layout
+                .into_iter()
+                .map(|(size, count)| Tier::new(size, count))
+                .collect(),
+            zero_on_release: AtomicBool::new(false),
+            released: Notify::new(),
+            waiters: AtomicUsize::new(0),
         };
 
-        // Pre-allocate buffers
-        for _ in 0..small_count {
-            let buf = vec![0u8; BufferSize::Small.as_usize()].into_boxed_slice();
-            let _ = inner.small_buffers.push(buf);
-            inner.small_allocated.fetch_add(1, Ordering::Relaxed);
+        Self {
+            inner: Arc::new(inner),
         }
+    }
 
-        for _ in 0..medium_count {
-            let buf = vec![0u8; BufferSize::Medium.as_usize()].into_boxed_slice();
-            let _ = inner.medium_buffers.push(buf);
-            inner.medium_allocated.fetch_add(1, Ordering::Relaxed);
-        }
+    /// Zero every buffer as it is released
+    ///
+    /// Keeps one connection's data from showing up in another's buffer, at
+    /// the cost of a memset per release.
+    pub fn with_zero_on_release(self, zero: bool) -> Self {
+        self.inner.zero_on_release.store(zero, Ordering::Relaxed);
+        self
+    }
 
-        for _ in 0..large_count {
-            let buf = vec![0u8; BufferSize::Large.as_usize()].into_boxed_slice();
-            let _ = inner.large_buffers.push(buf);
-            inner.large_allocated.fetch_add(1, Ordering::Relaxed);
+    /// Let the tier of exactly `size` bytes allocate up to `max` buffers
+    /// past its base size
+    ///
+    /// Overflow buffers are counted as allocated while in use and freed,
+    /// not queued, when released. Unknown sizes are ignored.
+    pub fn with_tier_overflow(self, size: usize, max: usize) -> Self {
+        if let Some(tier) = self.inner.tiers.iter().find(|tier| tier.size == size) {
+            tier.max_overflow.store(max, Ordering::Relaxed);
         }
+        self
+    }
 
-        Self {
-            inner: Arc::new(inner),
+    /// Set the overflow caps of the default 4KB/16KB/64KB tiers
+    pub fn with_max_overflow(self, small: usize, medium: usize, large: usize) -> Self {
+        self.with_tier_overflow(BufferSize::Small.as_usize(), small)
+            .with_tier_overflow(BufferSize::Medium.as_usize(), medium)
+            .with_tier_overflow(BufferSize::Large.as_usize(), large)
+    }
+
+    /// Acquire a buffer of at least `len` bytes from the smallest tier that fits
+    ///
+    /// Falls back to an overflow buffer when the tier is empty. Returns None
+    /// if the overflow cap is reached too or no tier is big enough (caller
+    /// should retry or allocate).
+    pub fn acquire(&self, len: impl Into<usize>) -> Option<Buffer> {
+        let buffer = self
+            .inner
+            .tier_for(len.into())
+            .and_then(|index| self.inner.take(index));
+        if buffer.is_none() {
+            METRICS.buffer_miss();
         }
+        buffer
     }
 
-    /// Acquire a buffer of the specified size
-    /// Returns None if pool is exhausted (caller should retry or allocate)
-    pub fn acquire(&self, size: BufferSize) -> Option<Buffer> {
-        let (queue, in_use) = match size {
-            BufferSize::Small => (&self.inner.small_buffers, &self.inner.small_in_use),
-            BufferSize::Medium => (&self.inner.medium_buffers, &self.inner.medium_in_use),
-            BufferSize::Large => (&self.inner.large_buffers, &self.inner.large_in_use),
+    /// Acquire a buffer of at least `len` bytes, waiting up to `timeout`
+    /// for one to be released if the tier and its overflow are exhausted
+    ///
+    /// Returns None once the timeout expires, so callers can push back
+    /// instead of allocating without bound. A `len` larger than every tier
+    /// has nothing to wait for and gets an untracked buffer right away.
+    pub async fn acquire_timeout(
+        &self,
+        len: impl Into<usize>,
+        timeout: Duration,
+    ) -> Option<Buffer> {
+        let len = len.into();
+        let Some(index) = self.inner.tier_for(len) else {
+            return Some(self.alloc_untracked(len));
         };
+        self.wait_for(timeout, || self.inner.take(index)).await
+    }
 
-        queue.pop().map(|data| {
-            in_use.fetch_add(1, Ordering::Relaxed);
-            Buffer {
-                data,
-                size,
-                pool: self.inner.clone(),
-            }
-        })
+    /// Acquire two buffers of at least `len` bytes at once, waiting up to
+    /// `timeout` like [`acquire_timeout`](Self::acquire_timeout)
+    ///
+    /// Either both are taken or neither: a first buffer is put back while
+    /// waiting for the second, so callers needing a pair can't each hold
+    /// one and wait on the other.
+    pub async fn acquire_pair_timeout(
+        &self,
+        len: impl Into<usize>,
+        timeout: Duration,
+    ) -> Option<(Buffer, Buffer)> {
+        let len = len.into();
+        let Some(index) = self.inner.tier_for(len) else {
+            return Some((self.alloc_untracked(len), self.alloc_untracked(len)));
+        };
+        self.wait_for(timeout, || self.inner.take_pair(index)).await
     }
 
-    /// Acquire a buffer, allocating a new one if pool is exhausted
-    pub fn acquire_or_alloc(&self, size: BufferSize) -> Buffer {
-        self.acquire(size).unwrap_or_else(|| {
-            // Pool exhausted, allocate new buffer (not ideal but prevents failure)
-            let data = vec![0u8; size.as_usize()].into_boxed_slice();
-            Buffer {
-                data,
-                size,
-                pool: self.inner.clone(),
+    /// Retry `take` each time a buffer is released, until it succeeds or
+    /// `timeout` expires
+    async fn wait_for<T>(
+        &self,
+        timeout: Duration,
+        mut take: impl FnMut() -> Option<T>,
+    ) -> Option<T> {
+        if let Some(taken) = take() {
+            return Some(taken);
+        }
+
+        self.inner.waiters.fetch_add(1, Ordering::SeqCst);
+        let deadline = tokio::time::Instant::now() + timeout;
+        let taken = loop {
+            // Register before retrying so a release in between isn't missed
+            let released = self.inner.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            if let Some(taken) = take() {
+                break Some(taken);
             }
-        })
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                break None;
+            }
+        };
+        self.inner.waiters.fetch_sub(1, Ordering::SeqCst);
+
+        if taken.is_none() {
+            METRICS.buffer_miss();
+        }
+        taken
+    }
+
+    /// Acquire a buffer of at least `len` bytes, allocating one if `len` is
+    /// larger than every tier
+    ///
+    /// Like [`acquire`](Self::acquire), this returns None once the tier
+    /// and its overflow are exhausted; the pool never grows past the cap.
+    /// An oversized `len` gets an untracked buffer of exactly that size.
+    pub fn acquire_or_alloc(&self, len: impl Into<usize>) -> Option<Buffer> {
+        let len = len.into();
+        match self.inner.tier_for(len) {
+            Some(_) => self.acquire(len),
+            None => Some(self.alloc_untracked(len)),
+        }
+    }
+
+    /// A `len`-byte buffer outside every tier, freed on release
+    fn alloc_untracked(&self, len: usize) -> Buffer {
+        Buffer {
+            data: vec![0u8; len].into_boxed_slice(),
+            tier: None,
+            pooled: false,
+            wake_waiter: true,
+            pool: self.inner.clone(),
+        }
     }
 
     /// Get pool statistics
     pub fn stats(&self) -> BufferPoolStats {
         BufferPoolStats {
-            small_allocated: self.inner.small_allocated.load(Ordering::Relaxed),
-            small_in_use: self.inner.small_in_use.load(Ordering::Relaxed),
-            medium_allocated: self.inner.medium_allocated.load(Ordering::Relaxed),
-            medium_in_use: self.inner.medium_in_use.load(Ordering::Relaxed),
-            large_allocated: self.inner.large_allocated.load(Ordering::Relaxed),
-            large_in_use: self.inner.large_in_use.load(Ordering::Relaxed),
+            tiers: self
+                .inner
+                .tiers
+                .iter()
+                .map(|tier| TierStats {
+                    size: tier.size,
+                    allocated: tier.allocated.load(Ordering::Relaxed),
+                    in_use: tier.in_use.load(Ordering::Relaxed),
+                    overflow: tier.overflow.load(Ordering::Relaxed),
+                })
+                .collect(),
         }
     }
 }
 
 /// Buffer pool statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct BufferPoolStats {
-    pub small_allocated: usize,
-    pub small_in_use: usize,
-    pub medium_allocated: usize,
-    pub medium_in_use: usize,
-    pub large_allocated: usize,
-    pub large_in_use: usize,
+    /// One entry per tier, by ascending size
+    pub tiers: Vec<TierStats>,
+}
+
+impl BufferPoolStats {
+    /// Statistics for the tier of exactly `size` bytes
+    pub fn tier(&self, size: impl Into<usize>) -> Option<&TierStats> {
+        let size = size.into();
+        self.tiers.iter().find(|tier| tier.size == size)
+    }
+}
+
+/// Statistics for one buffer tier
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TierStats {
+    pub size: usize,
+    pub allocated: usize,
+    pub in_use: usize,
+    /// Buffers allocated past the preallocated count
+    pub overflow: usize,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn small(pool: &BufferPool) -> TierStats {
+        pool.stats().tier(BufferSize::Small).unwrap().clone()
+    }
+
     #[test]
     fn test_buffer_pool_acquire_release() {
         let pool = BufferPool::new(10, 5, 2);
-        
+
         // Acquire a buffer
         let buf = pool.acquire(BufferSize::Small).unwrap();
         assert_eq!(buf.capacity(), 4096);
-        
-        let stats = pool.stats();
-        assert_eq!(stats.small_in_use, 1);
-        
+
+        assert_eq!(small(&pool).in_use, 1);
+
         // Drop returns to pool
         drop(buf);
-        
-        let stats = pool.stats();
-        assert_eq!(stats.small_in_use, 0);
+
+        assert_eq!(small(&pool).in_use, 0);
+    }
+
+    #[test]
+    fn test_buffer_pool_zero_on_release() {
+        let pool = BufferPool::new(1, 1, 1).with_zero_on_release(true);
+
+        let mut buf = pool.acquire(BufferSize::Small).unwrap();
+        buf[..6].copy_from_slice(b"secret");
+        drop(buf);
+
+        // The single small buffer comes back cleared
+        let buf = pool.acquire(BufferSize::Small).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+
+        // Without the flag the old contents survive
+        let pool = BufferPool::new(1, 1, 1);
+        let mut buf = pool.acquire(BufferSize::Small).unwrap();
+        buf[..6].copy_from_slice(b"secret");
+        drop(buf);
+        assert_eq!(&pool.acquire(BufferSize::Small).unwrap()[..6], b"secret");
     }
 
     #[test]
     fn test_buffer_pool_exhaustion() {
         let pool = BufferPool::new(2, 1, 1);
-        
+
         let _b1 = pool.acquire(BufferSize::Small).unwrap();
         let _b2 = pool.acquire(BufferSize::Small).unwrap();
-        
+
         // Pool exhausted for small buffers
         assert!(pool.acquire(BufferSize::Small).is_none());
-        
-        // But acquire_or_alloc still works
-        let _b3 = pool.acquire_or_alloc(BufferSize::Small);
+
+        // Without overflow acquire_or_alloc doesn't allocate either
+        assert!(pool.acquire_or_alloc(BufferSize::Small).is_none());
+        assert_eq!(small(&pool).in_use, 2);
+        assert_eq!(small(&pool).allocated, 2);
     }
-}
 
+    #[test]
+    fn test_buffer_pool_overflow() {
+        let pool = BufferPool::new(1, 1, 1).with_max_overflow(2, 0, 0);
+
+        let base = pool.acquire(BufferSize::Small).unwrap();
+        let o1 = pool.acquire(BufferSize::Small).unwrap();
+        let o2 = pool.acquire(BufferSize::Small).unwrap();
+        assert!(pool.acquire(BufferSize::Small).is_none());
+
+        let stats = small(&pool);
+        assert_eq!(stats.allocated, 3);
+        assert_eq!(stats.in_use, 3);
+        assert_eq!(stats.overflow, 2);
+
+        // Past the cap acquire_or_alloc refuses too
+        assert!(pool.acquire_or_alloc(BufferSize::Small).is_none());
+        assert_eq!(small(&pool).overflow, 2);
+        assert_eq!(small(&pool).allocated, 3);
+
+        // Released overflow is freed and the tier shrinks back
+        drop((o1, o2));
+        let stats = small(&pool);
+        assert_eq!(stats.allocated, 1);
+        assert_eq!(stats.in_use, 1);
+        assert_eq!(stats.overflow, 0);
+
+        drop(base);
+        assert_eq!(pool.inner.tiers[0].buffers.len(), 1);
+
+        // Other tiers keep their own cap
+        let _medium = pool.acquire(BufferSize::Medium).unwrap();
+        assert!(pool.acquire(BufferSize::Medium).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_pair_all_or_nothing() {
+        let pool = BufferPool::new(3, 1, 1);
+        let wait = Duration::from_millis(20);
+        let first = pool.acquire_pair_timeout(BufferSize::Small, wait).await.unwrap();
+
+        // One buffer left: the pair fails without keeping it
+        assert!(pool.acquire_pair_timeout(BufferSize::Small, wait).await.is_none());
+        assert_eq!(small(&pool).in_use, 2);
+
+        // A waiting pair completes once enough buffers come back
+        let pool2 = pool.clone();
+        let waiter = tokio::spawn(async move {
+            pool2.acquire_pair_timeout(BufferSize::Small, Duration::from_secs(5)).await
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(first);
+        assert!(waiter.await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_timeout_waits_for_release() {
+        let pool = BufferPool::new(2, 1, 1);
+        let held = vec![
+            pool.acquire(BufferSize::Small).unwrap(),
+            pool.acquire(BufferSize::Small).unwrap(),
+        ];
+
+        // Nothing is released, so the wait runs out
+        let wait = Duration::from_millis(20);
+        assert!(pool.acquire_timeout(BufferSize::Small, wait).await.is_none());
+
+        // Four acquirers beyond capacity block until buffers come back
+        let (done_tx, mut done_rx) = tokio::sync::mpsc::unbounded_channel();
+        for _ in 0..4 {
+            let pool = pool.clone();
+            let done_tx = done_tx.clone();
+            tokio::spawn(async move {
+                let buf = pool.acquire_timeout(BufferSize::Small, Duration::from_secs(5)).await;
+                done_tx.send(buf.is_some()).unwrap();
+                // Hold briefly, then hand the buffer to the next waiter
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            });
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(done_rx.try_recv().is_err());
+
+        drop(held);
+        for _ in 0..4 {
+            assert!(done_rx.recv().await.unwrap());
+        }
+        assert!(small(&pool).in_use <= 2);
+        assert_eq!(small(&pool).overflow, 0);
+
+        // Larger than every tier: allocated without waiting
+        let huge = pool.acquire_timeout(100_000usize, Duration::ZERO).await.unwrap();
+        assert_eq!(huge.capacity(), 100_000);
+    }
+
+    #[test]
+    fn test_tier_selection() {
+        let pool = BufferPool::with_tiers(&[(65536, 4), (9000, 4), (1500, 4)]);
+        let sizes: Vec<usize> = pool.stats().tiers.iter().map(|tier| tier.size).collect();
+        assert_eq!(sizes, [1500, 9000, 65536]);
+
+        let capacity = |len: usize| pool.acquire(len).map(|buf| buf.capacity());
+        assert_eq!(capacity(0), Some(1500));
+        assert_eq!(capacity(1500), Some(1500));
+        assert_eq!(capacity(1501), Some(9000));
+        assert_eq!(capacity(9000), Some(9000));
+        assert_eq!(capacity(9001), Some(65536));
+        assert_eq!(capacity(65536), Some(65536));
+        assert_eq!(capacity(65537), None);
+
+        // Too big for any tier: an exact, untracked allocation
+        let huge = pool.acquire_or_alloc(100_000usize).unwrap();
+        assert_eq!(huge.capacity(), 100_000);
+        assert!(pool.stats().tiers.iter().all(|tier| tier.in_use == 0));
+        drop(huge);
+
+        // The classic names still map onto matching tiers
+        let pool = BufferPool::with_tiers(&[(4096, 1), (4096, 9)]);
+        assert_eq!(pool.stats().tiers.len(), 1);
+        assert_eq!(pool.acquire(BufferSize::Small).unwrap().capacity(), 4096);
+        assert!(pool.acquire(BufferSize::Medium).is_none());
+    }
+
+    #[test]
+    fn test_buffer_pool_metrics() {
+        let pool = BufferPool::new(1, 1, 1);
+        let before = METRICS.snapshot();
+
+        let b1 = pool.acquire(BufferSize::Medium).unwrap();
+        assert!(pool.acquire_or_alloc(BufferSize::Medium).is_none());
+        drop(b1);
+
+        // Other tests share the global counters, so only check lower bounds
+        let after = METRICS.snapshot();
+        assert!(after.buffer_pool_acquires > before.buffer_pool_acquires);
+        assert!(after.buffer_pool_misses > before.buffer_pool_misses);
+        assert!(after.buffer_pool_releases > before.buffer_pool_releases);
+    }
+}
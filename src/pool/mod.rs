@@ -5,6 +5,6 @@
 mod buffer;
 mod slab;
 
-pub use buffer::{Buffer, BufferPool, BufferSize};
+pub use buffer::{Buffer, BufferPool, BufferPoolStats, BufferSize, TierStats};
 pub use slab::{ConnectionSlab, SlabHandle};
 
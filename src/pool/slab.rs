@@ -33,7 +33,7 @@ impl<T> ConnectionSlab<T> {
     /// Create a new slab with the given capacity
     pub fn new(capacity: usize) -> Self {
         // Round up to multiple of 64 for bitset
-        let num_words = (capacity + 63) / 64;
+        let num_words = capacity.div_ceil(64);
         let actual_capacity = num_words * 64;
 
         // Initialize slots
@@ -159,6 +159,36 @@ impl<T> ConnectionSlab<T> {
         }
     }
 
+    /// Visit every occupied slot, locking one slot at a time
+    ///
+    /// Each yielded guard holds its slot's lock until dropped, so don't
+    /// call `remove` on the same handle while holding it. Slots filled or
+    /// freed during iteration may or may not be seen.
+    pub fn iter(&self) -> impl Iterator<Item = (SlabHandle, parking_lot::MappedMutexGuard<'_, T>)> {
+        self.free_bitset
+            .iter()
+            .enumerate()
+            .flat_map(|(word_idx, word)| {
+                // Only occupied bits are clear; skip fully free words quickly
+                let occupied = !word.load(Ordering::Acquire);
+                (0..64)
+                    .filter(move |bit| occupied & (1u64 << bit) != 0)
+                    .map(move |bit| word_idx * 64 + bit)
+            })
+            .take_while(|&idx| idx < self.capacity)
+            .filter_map(|idx| {
+                let handle = SlabHandle(idx);
+                self.get_mut(handle).map(|guard| (handle, guard))
+            })
+    }
+
+    /// Call `f` on every occupied slot, holding only that slot's lock
+    pub fn for_each(&self, mut f: impl FnMut(SlabHandle, &mut T)) {
+        for (handle, mut value) in self.iter() {
+            f(handle, &mut value);
+        }
+    }
+
     /// Get current allocation count
     pub fn len(&self) -> usize {
         self.allocated.load(Ordering::Relaxed)
@@ -212,5 +242,34 @@ mod tests {
         let h3 = slab.insert(3).unwrap();
         assert_eq!(h3.index(), h1.index()); // Reused slot
     }
+
+    #[test]
+    fn test_slab_iter() {
+        let slab: ConnectionSlab<u64> = ConnectionSlab::new(130);
+        let handles: Vec<_> = (0..130).map(|i| slab.insert(i).unwrap()).collect();
+
+        // Leave gaps, including a whole bitset word's worth
+        for handle in &handles[1..70] {
+            slab.remove(*handle);
+        }
+        slab.remove(handles[128]);
+
+        let seen: Vec<(usize, u64)> = slab.iter().map(|(h, v)| (h.index(), *v)).collect();
+        let expected: Vec<(usize, u64)> = [0]
+            .into_iter()
+            .chain(70..128)
+            .chain([129])
+            .map(|i| (i, i as u64))
+            .collect();
+        assert_eq!(seen, expected);
+
+        slab.for_each(|_, value| *value *= 2);
+        assert_eq!(*slab.get(handles[129]).unwrap(), 258);
+
+        // Removing between visits is fine once the guard is dropped
+        let first = slab.iter().next().map(|(h, _)| h).unwrap();
+        slab.remove(first);
+        assert_eq!(slab.iter().count(), 59);
+    }
 }
 
@@ -0,0 +1,192 @@
+//! DNS cache shared by the proxies
+//!
+//! The system resolver doesn't report record TTLs, so answers are kept for
+//! a fixed `cache_ttl_secs`. Failed lookups are cached briefly too, so a
+//! client hammering a dead name doesn't hammer the resolver.
+
+use dashmap::DashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Longest a failed lookup is remembered
+const NEGATIVE_TTL: Duration = Duration::from_secs(5);
+
+/// Most host names cached at once; new names past this are resolved but
+/// not cached until entries expire
+const MAX_ENTRIES: usize = 10_000;
+
+/// Future returned by [`Resolver::lookup`]
+pub type LookupFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Vec<IpAddr>>> + Send + 'a>>;
+
+/// Resolves host names to addresses
+pub trait Resolver: Send + Sync {
+    fn lookup<'a>(&'a self, host: &'a str) -> LookupFuture<'a>;
+}
+
+/// Resolver backed by the operating system, via [`tokio::net::lookup_host`]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn lookup<'a>(&'a self, host: &'a str) -> LookupFuture<'a> {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host, 0)).await?;
+            Ok(addrs.map(|addr| addr.ip()).collect())
+        })
+    }
+}
+
+/// A cached answer
+#[derive(Clone)]
+struct Entry {
+    /// Addresses, or the kind and message of the lookup failure
+    answer: Result<Arc<[IpAddr]>, (io::ErrorKind, String)>,
+    expires: Instant,
+}
+
+/// TTL-bounded cache of host name lookups
+pub struct DnsCache {
+    resolver: Arc<dyn Resolver>,
+    ttl: Duration,
+    /// Lowercased host name -> answer
+    entries: DashMap<String, Entry>,
+}
+
+impl DnsCache {
+    /// Cache answers from the system resolver for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_resolver(ttl, Arc::new(SystemResolver))
+    }
+
+    /// Cache answers from `resolver` for `ttl`
+    pub fn with_resolver(ttl: Duration, resolver: Arc<dyn Resolver>) -> Self {
+        Self {
+            resolver,
+            ttl,
+            entries: DashMap::new(),
+        }
+    }
+
+    /// Resolve a `host:port` target
+    ///
+    /// Address literals are returned as-is without touching the cache.
+    pub async fn lookup(&self, target: &str) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(addr) = target.parse::<SocketAddr>() {
+            return Ok(vec![addr]);
+        }
+        let (host, port) = target
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("invalid target {}", target))
+            })?;
+
+        let ips = self.lookup_host(host).await?;
+        Ok(ips.iter().map(|&ip| SocketAddr::new(ip, port)).collect())
+    }
+
+    async fn lookup_host(&self, host: &str) -> io::Result<Arc<[IpAddr]>> {
+        let key = host.to_ascii_lowercase();
+        let now = Instant::now();
+        if let Some(entry) = self.entries.get(&key).map(|e| e.clone()) {
+            if entry.expires > now {
+                return entry.answer.map_err(|(kind, message)| io::Error::new(kind, message));
+            }
+        }
+
+        let answer = match self.resolver.lookup(host).await {
+            Ok(ips) if ips.is_empty() => Err((
+                io::ErrorKind::NotFound,
+                format!("no addresses for {}", host),
+            )),
+            Ok(ips) => Ok(Arc::from(ips)),
+            Err(e) => Err((e.kind(), e.to_string())),
+        };
+
+        let ttl = match answer {
+            Ok(_) => self.ttl,
+            Err(_) => self.ttl.min(NEGATIVE_TTL),
+        };
+        if !ttl.is_zero() {
+            self.insert(key, Entry { answer: answer.clone(), expires: now + ttl });
+        }
+        answer.map_err(|(kind, message)| io::Error::new(kind, message))
+    }
+
+    fn insert(&self, key: String, entry: Entry) {
+        if self.entries.len() >= MAX_ENTRIES {
+            let now = Instant::now();
+            self.entries.retain(|_, e| e.expires > now);
+            if self.entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        self.entries.insert(key, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Resolver answering `example.com` and failing anything else, counting calls
+    #[derive(Default)]
+    struct CountingResolver {
+        calls: AtomicUsize,
+    }
+
+    impl Resolver for CountingResolver {
+        fn lookup<'a>(&'a self, host: &'a str) -> LookupFuture<'a> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                match host {
+                    "example.com" => Ok(vec!["93.184.216.34".parse().unwrap()]),
+                    _ => Err(io::Error::other("no such host")),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_within_ttl() {
+        let resolver = Arc::new(CountingResolver::default());
+        let cache = DnsCache::with_resolver(Duration::from_secs(60), resolver.clone());
+
+        let first = cache.lookup("example.com:443").await.unwrap();
+        assert_eq!(first, vec!["93.184.216.34:443".parse().unwrap()]);
+        // Same host on another port, differently cased: still one lookup
+        let second = cache.lookup("Example.COM:80").await.unwrap();
+        assert_eq!(second, vec!["93.184.216.34:80".parse().unwrap()]);
+        assert_eq!(resolver.calls.load(Ordering::SeqCst), 1);
+
+        // Failures are cached too
+        assert!(cache.lookup("missing.test:53").await.is_err());
+        assert!(cache.lookup("missing.test:53").await.is_err());
+        assert_eq!(resolver.calls.load(Ordering::SeqCst), 2);
+
+        // Literals skip the resolver
+        let literal = cache.lookup("[2001:db8::1]:443").await.unwrap();
+        assert_eq!(literal, vec!["[2001:db8::1]:443".parse().unwrap()]);
+        assert_eq!(resolver.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_disabled() {
+        let resolver = Arc::new(CountingResolver::default());
+        let cache = DnsCache::with_resolver(Duration::from_millis(20), resolver.clone());
+        cache.lookup("example.com:443").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        cache.lookup("example.com:443").await.unwrap();
+        assert_eq!(resolver.calls.load(Ordering::SeqCst), 2);
+
+        let resolver = Arc::new(CountingResolver::default());
+        let cache = DnsCache::with_resolver(Duration::ZERO, resolver.clone());
+        cache.lookup("example.com:443").await.unwrap();
+        cache.lookup("example.com:443").await.unwrap();
+        assert_eq!(resolver.calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,22 @@
+//! Per-stream byte transforms
+//!
+//! Lets embedders inspect or rewrite tunneled bytes without touching
+//! the proxy copy loops.
+
+/// Hook invoked on every chunk forwarded by the TCP proxy
+///
+/// Implementations may modify the chunk in place, including growing or
+/// shrinking it. An empty chunk after the hook is simply not forwarded.
+pub trait StreamMiddleware: Send + Sync {
+    /// Called on bytes read from the tunnel client before they are sent to the origin
+    fn on_client_to_origin(&self, _buf: &mut Vec<u8>) {}
+
+    /// Called on bytes read from the origin before they are sent to the tunnel client
+    fn on_origin_to_client(&self, _buf: &mut Vec<u8>) {}
+}
+
+/// Middleware that forwards bytes unchanged
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMiddleware;
+
+impl StreamMiddleware for NoopMiddleware {}
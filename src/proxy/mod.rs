@@ -2,9 +2,23 @@
 //!
 //! High-performance TCP and UDP forwarding.
 
+mod dns;
+mod middleware;
+mod proxy_protocol;
+mod secure_dns;
 mod tcp;
+mod throttle;
 mod udp;
 
-pub use tcp::TcpProxy;
-pub use udp::UdpRelay;
+pub use dns::{DnsCache, LookupFuture, Resolver, SystemResolver};
+pub use middleware::{NoopMiddleware, StreamMiddleware};
+pub use secure_dns::UpstreamResolver;
+pub use tcp::{
+    BuffersExhausted, ConnectTimeout, OriginConnection, OutboundLimitExceeded, ProxyStats,
+    TcpProxy,
+};
+pub use throttle::BandwidthLimiter;
+pub use udp::{FlowResponseSink, OversizedResponse, UdpRelay};
+#[cfg(target_os = "linux")]
+pub use udp::{BatchedUdpReceiver, BatchedUdpSender, RecvSlot};
 
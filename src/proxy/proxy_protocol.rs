@@ -0,0 +1,101 @@
+//! PROXY protocol v2 headers
+//!
+//! Tells an upstream that sits behind the tunnel which client address a
+//! TCP connection really came from.
+
+use std::net::{IpAddr, SocketAddr};
+
+/// Fixed 12-byte signature opening every v2 header
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Protocol version 2, PROXY command
+const VERSION_PROXY: u8 = 0x21;
+/// TCP over IPv4
+const FAMILY_TCP4: u8 = 0x11;
+/// TCP over IPv6
+const FAMILY_TCP6: u8 = 0x21;
+
+/// Encode a v2 header for a TCP connection from `source` to `destination`
+///
+/// Both addresses must share a family, so if either is IPv6 the other is
+/// written as an IPv4-mapped IPv6 address.
+pub fn encode_v2(source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    let mut header = Vec::with_capacity(16 + 36);
+    header.extend_from_slice(&SIGNATURE);
+    header.push(VERSION_PROXY);
+
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            header.push(FAMILY_TCP4);
+            header.extend_from_slice(&12u16.to_be_bytes());
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+        }
+        (src, dst) => {
+            header.push(FAMILY_TCP6);
+            header.extend_from_slice(&36u16.to_be_bytes());
+            header.extend_from_slice(&to_ipv6_octets(src));
+            header.extend_from_slice(&to_ipv6_octets(dst));
+        }
+    }
+
+    header.extend_from_slice(&source.port().to_be_bytes());
+    header.extend_from_slice(&destination.port().to_be_bytes());
+    header
+}
+
+fn to_ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped().octets(),
+        IpAddr::V6(v6) => v6.octets(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_v2_ipv4() {
+        let header = encode_v2(
+            "192.0.2.1:51000".parse().unwrap(),
+            "198.51.100.7:443".parse().unwrap(),
+        );
+
+        assert_eq!(header.len(), 28);
+        assert_eq!(&header[..12], &SIGNATURE);
+        assert_eq!(&header[12..16], &[0x21, 0x11, 0x00, 0x0c]);
+        assert_eq!(&header[16..20], &[192, 0, 2, 1]);
+        assert_eq!(&header[20..24], &[198, 51, 100, 7]);
+        assert_eq!(&header[24..26], &51000u16.to_be_bytes());
+        assert_eq!(&header[26..28], &443u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_encode_v2_ipv6() {
+        let header = encode_v2(
+            "[2001:db8::1]:51000".parse().unwrap(),
+            "[2001:db8::2]:443".parse().unwrap(),
+        );
+
+        assert_eq!(header.len(), 52);
+        assert_eq!(&header[12..16], &[0x21, 0x21, 0x00, 0x24]);
+        assert_eq!(header[16..32], "2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        assert_eq!(header[32..48], "2001:db8::2".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        assert_eq!(&header[48..50], &51000u16.to_be_bytes());
+        assert_eq!(&header[50..52], &443u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_encode_v2_mixed_family() {
+        let header = encode_v2(
+            "192.0.2.1:51000".parse().unwrap(),
+            "[2001:db8::2]:443".parse().unwrap(),
+        );
+
+        assert_eq!(header[13], FAMILY_TCP6);
+        assert_eq!(
+            header[16..32],
+            "::ffff:192.0.2.1".parse::<std::net::Ipv6Addr>().unwrap().octets()
+        );
+    }
+}
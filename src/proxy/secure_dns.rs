@@ -0,0 +1,679 @@
+//! DNS-over-HTTPS and DNS-over-TLS resolution
+//!
+//! Sends A and AAAA queries for each target to a configured upstream over
+//! TLS, so target names never reach the host's resolver. Only the
+//! upstream's own name, if it isn't an address, is looked up by the system.
+//! Connections are kept open between lookups and reused.
+
+use parking_lot::Mutex;
+use rustls::pki_types::ServerName;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+use super::dns::{LookupFuture, Resolver};
+
+/// Give up on a lookup, both queries included, after this long
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest DNS message accepted from the upstream
+const MAX_MESSAGE: usize = 65535;
+
+/// Longest HTTP response header line read from a DoH upstream
+const MAX_HEADER_LINE: usize = 8192;
+
+/// Most connections kept open for reuse
+const MAX_IDLE: usize = 4;
+
+/// Don't reuse a connection idle for longer; upstreams close quiet
+/// connections, and a dead one would stall the lookup until it times out
+const IDLE_REUSE: Duration = Duration::from_secs(10);
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+
+/// Query IDs; TLS authenticates the upstream, so they only need to tell
+/// the two answers apart
+const QUERIES: [(u16, u16); 2] = [(1, TYPE_A), (2, TYPE_AAAA)];
+
+/// How queries are carried to the upstream
+enum Transport {
+    /// RFC 8484 POSTs to `path`, over HTTP/1.1
+    Https { host: String, path: String },
+    /// RFC 7858 length-prefixed messages
+    Tls,
+}
+
+/// An upstream connection, buffered for reading responses
+type Connection = BufReader<TlsStream<TcpStream>>;
+
+/// Resolver querying a DoH or DoT upstream
+pub struct UpstreamResolver {
+    transport: Transport,
+    /// `host:port` to connect to
+    addr: String,
+    server_name: ServerName<'static>,
+    connector: TlsConnector,
+    /// Connections left open by earlier lookups, with when they went idle
+    idle: Mutex<Vec<(Instant, Connection)>>,
+}
+
+impl UpstreamResolver {
+    /// Resolve through the DoH endpoint `url`, e.g. `https://1.1.1.1/dns-query`
+    pub fn doh(url: &str) -> anyhow::Result<Self> {
+        let rest = url
+            .strip_prefix("https://")
+            .ok_or_else(|| anyhow::anyhow!("DoH upstream must start with https://"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/dns-query"),
+        };
+        let transport = Transport::Https {
+            host: authority.to_string(),
+            path: path.to_string(),
+        };
+        Self::new(transport, authority, 443)
+    }
+
+    /// Resolve through the DoT server `upstream`, `host[:port]` (853 by default)
+    pub fn dot(upstream: &str) -> anyhow::Result<Self> {
+        let authority = upstream.strip_prefix("tls://").unwrap_or(upstream);
+        Self::new(Transport::Tls, authority, 853)
+    }
+
+    fn new(transport: Transport, authority: &str, default_port: u16) -> anyhow::Result<Self> {
+        let (host, port) = split_authority(authority)
+            .ok_or_else(|| anyhow::anyhow!("Invalid DNS upstream {:?}", authority))?;
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|_| anyhow::anyhow!("Invalid DNS upstream name {:?}", host))?;
+        let addr = match host.parse::<Ipv6Addr>() {
+            Ok(_) => format!("[{}]:{}", host, port.unwrap_or(default_port)),
+            Err(_) => format!("{}:{}", host, port.unwrap_or(default_port)),
+        };
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut tls = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        if matches!(transport, Transport::Https { .. }) {
+            tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+        }
+
+        Ok(Self {
+            transport,
+            addr,
+            server_name,
+            connector: TlsConnector::from(Arc::new(tls)),
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        // The upstream may have closed an idle connection since, so an I/O
+        // failure on one is retried once on a fresh connection
+        if let Some(mut connection) = self.take_idle() {
+            match self.resolve_on(&mut connection, host).await {
+                Ok((ips, reusable)) => {
+                    if reusable {
+                        self.put_idle(connection);
+                    }
+                    return Ok(ips);
+                }
+                Err(e) if is_answer_error(&e) => return Err(e),
+                Err(_) => {}
+            }
+        }
+
+        let mut connection = self.connect().await?;
+        let (ips, reusable) = self.resolve_on(&mut connection, host).await?;
+        if reusable {
+            self.put_idle(connection);
+        }
+        Ok(ips)
+    }
+
+    async fn connect(&self) -> io::Result<Connection> {
+        let tcp = TcpStream::connect(&self.addr).await?;
+        tcp.set_nodelay(true)?;
+        let stream = self.connector.connect(self.server_name.clone(), tcp).await?;
+        Ok(BufReader::new(stream))
+    }
+
+    /// Most recently used idle connection that is still fresh enough
+    fn take_idle(&self) -> Option<Connection> {
+        let mut idle = self.idle.lock();
+        idle.retain(|(since, _)| since.elapsed() < IDLE_REUSE);
+        idle.pop().map(|(_, connection)| connection)
+    }
+
+    fn put_idle(&self, connection: Connection) {
+        let mut idle = self.idle.lock();
+        if idle.len() < MAX_IDLE {
+            idle.push((Instant::now(), connection));
+        }
+    }
+
+    /// Send both queries for `host` over an established `stream`
+    ///
+    /// Also returns whether the upstream left the connection open for
+    /// another lookup.
+    async fn resolve_on<S>(&self, stream: &mut S, host: &str) -> io::Result<(Vec<IpAddr>, bool)>
+    where
+        S: AsyncBufRead + AsyncWrite + Unpin,
+    {
+        let queries = QUERIES
+            .iter()
+            .map(|&(id, qtype)| encode_query(id, host, qtype))
+            .collect::<io::Result<Vec<_>>>()?;
+        let (responses, reusable) = match &self.transport {
+            Transport::Https { host, path } => doh_exchange(stream, host, path, &queries).await?,
+            Transport::Tls => (dot_exchange(stream, &queries).await?, true),
+        };
+
+        let mut ips = Vec::new();
+        let mut answered = Vec::with_capacity(QUERIES.len());
+        for response in &responses {
+            let (id, addrs) = parse_response(response)?;
+            if answered.contains(&id) || !QUERIES.iter().any(|&(query, _)| query == id) {
+                return Err(invalid_data("unexpected DNS response ID"));
+            }
+            answered.push(id);
+            ips.extend(addrs);
+        }
+        Ok((ips, reusable))
+    }
+}
+
+/// Whether `e` came from a bad name or a bad answer rather than from the
+/// connection, so retrying elsewhere wouldn't help
+fn is_answer_error(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput)
+}
+
+impl Resolver for UpstreamResolver {
+    fn lookup<'a>(&'a self, host: &'a str) -> LookupFuture<'a> {
+        Box::pin(async move {
+            tokio::time::timeout(LOOKUP_TIMEOUT, self.resolve(host))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "DNS upstream timed out"))?
+        })
+    }
+}
+
+/// Split `host[:port]`, with IPv6 hosts in brackets
+fn split_authority(authority: &str) -> Option<(&str, Option<u16>)> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']')?;
+            match rest {
+                "" => (host, None),
+                _ => (host, Some(rest.strip_prefix(':')?)),
+            }
+        }
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    if host.is_empty() {
+        return None;
+    }
+    match port {
+        Some(port) => Some((host, Some(port.parse().ok()?))),
+        None => Some((host, None)),
+    }
+}
+
+/// POST each query on one keep-alive connection and return the bodies
+///
+/// Also returns whether the upstream will keep the connection open.
+async fn doh_exchange<S>(
+    stream: &mut S,
+    host: &str,
+    path: &str,
+    queries: &[Vec<u8>],
+) -> io::Result<(Vec<Vec<u8>>, bool)>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    let mut responses = Vec::with_capacity(queries.len());
+    let mut keep_alive = true;
+    for query in queries {
+        let head = format!(
+            "POST {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Content-Type: application/dns-message\r\n\
+             Accept: application/dns-message\r\n\
+             Content-Length: {}\r\n\
+             \r\n",
+            path,
+            host,
+            query.len(),
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(query).await?;
+        stream.flush().await?;
+
+        let status_line = read_header_line(stream).await?;
+        let status = status_line.split_whitespace().nth(1);
+        if status != Some("200") {
+            return Err(invalid_data(&format!("DoH upstream answered {:?}", status_line)));
+        }
+        let mut content_length = None;
+        let mut chunked = false;
+        loop {
+            let line = read_header_line(stream).await?;
+            if line.is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (name, value) = (name.trim(), value.trim());
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("connection") {
+                keep_alive = !value.eq_ignore_ascii_case("close");
+            }
+        }
+
+        let body = if chunked {
+            read_chunked(stream).await?
+        } else {
+            let len = content_length
+                .filter(|&len| len <= MAX_MESSAGE)
+                .ok_or_else(|| invalid_data("DoH response without a usable Content-Length"))?;
+            let mut body = vec![0u8; len];
+            stream.read_exact(&mut body).await?;
+            body
+        };
+        responses.push(body);
+        if !keep_alive && responses.len() < queries.len() {
+            return Err(invalid_data("DoH upstream closed the connection early"));
+        }
+    }
+    Ok((responses, keep_alive))
+}
+
+/// Read a `Transfer-Encoding: chunked` body, trailers included
+async fn read_chunked<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_header_line(reader).await?;
+        // Chunk extensions follow a semicolon and are ignored
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| invalid_data("bad chunk size in DoH response"))?;
+        if size == 0 {
+            break;
+        }
+        if body.len() + size > MAX_MESSAGE {
+            return Err(invalid_data("DoH response too large"));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+        if !read_header_line(reader).await?.is_empty() {
+            return Err(invalid_data("chunk longer than its size in DoH response"));
+        }
+    }
+    while !read_header_line(reader).await?.is_empty() {}
+    Ok(body)
+}
+
+/// Read one CRLF-terminated header line, without the terminator
+async fn read_header_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    let read = (&mut *reader)
+        .take(MAX_HEADER_LINE as u64)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.last() != Some(&b'\n') {
+        // Short of the limit, the upstream closed the connection
+        if read < MAX_HEADER_LINE {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated DoH response"));
+        }
+        return Err(invalid_data("DoH response header line too long"));
+    }
+    let line = String::from_utf8_lossy(&line);
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Pipeline length-prefixed queries and return as many responses, in
+/// whatever order the upstream sends them
+async fn dot_exchange<S>(stream: &mut S, queries: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>>
+where
+    S: AsyncBufRead + AsyncWrite + Unpin,
+{
+    let mut out = Vec::new();
+    for query in queries {
+        out.extend_from_slice(&(query.len() as u16).to_be_bytes());
+        out.extend_from_slice(query);
+    }
+    stream.write_all(&out).await?;
+    stream.flush().await?;
+
+    let mut responses = Vec::with_capacity(queries.len());
+    for _ in queries {
+        let len = stream.read_u16().await? as usize;
+        let mut body = vec![0u8; len];
+        stream.read_exact(&mut body).await?;
+        responses.push(body);
+    }
+    Ok(responses)
+}
+
+/// Build a recursive query for `host`
+fn encode_query(id: u16, host: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let name = host.strip_suffix('.').unwrap_or(host);
+    if name.is_empty() || name.len() > 253 {
+        return Err(invalid_input(host));
+    }
+
+    let mut msg = Vec::with_capacity(18 + name.len());
+    msg.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired; one question
+    msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid_input(host));
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+/// ID and A/AAAA addresses of a response; a name that doesn't exist has none
+fn parse_response(msg: &[u8]) -> io::Result<(u16, Vec<IpAddr>)> {
+    let truncated = || invalid_data("truncated DNS response");
+    let u16_at = |pos: usize| -> io::Result<u16> {
+        let bytes = msg.get(pos..pos + 2).ok_or_else(truncated)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+
+    let id = u16_at(0)?;
+    let flags = u16_at(2)?;
+    if flags & 0x8000 == 0 {
+        return Err(invalid_data("DNS message is not a response"));
+    }
+    match flags & 0x000f {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok((id, Vec::new())),
+        rcode => return Err(invalid_data(&format!("DNS upstream failed with rcode {}", rcode))),
+    }
+
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(msg, pos).ok_or_else(truncated)? + 4;
+    }
+
+    let mut ips = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(msg, pos).ok_or_else(truncated)?;
+        let rtype = u16_at(pos)?;
+        let class = u16_at(pos + 2)?;
+        let rdlen = u16_at(pos + 8)? as usize;
+        let rdata = msg.get(pos + 10..pos + 10 + rdlen).ok_or_else(truncated)?;
+        pos += 10 + rdlen;
+
+        if class != CLASS_IN {
+            continue;
+        }
+        // CNAMEs and anything else in the chain are skipped; the addresses
+        // they lead to follow in the same answer section
+        match (rtype, rdata.len()) {
+            (TYPE_A, 4) => {
+                let octets: [u8; 4] = rdata.try_into().unwrap();
+                ips.push(IpAddr::V4(Ipv4Addr::from(octets)));
+            }
+            (TYPE_AAAA, 16) => {
+                let octets: [u8; 16] = rdata.try_into().unwrap();
+                ips.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => {}
+        }
+    }
+    Ok((id, ips))
+}
+
+/// Position just past the (possibly compressed) name at `pos`
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            // Compression pointer ends the name
+            _ if len & 0xc0 == 0xc0 => return msg.get(pos + 1).map(|_| pos + 2),
+            _ => pos += 1 + len,
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn invalid_input(host: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid host name {:?}", host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answer `query` with `ips` of the queried type, names compressed
+    fn answer(query: &[u8], ips: &[IpAddr]) -> Vec<u8> {
+        let qtype = u16::from_be_bytes([query[query.len() - 4], query[query.len() - 3]]);
+        let ips: Vec<&IpAddr> = ips
+            .iter()
+            .filter(|ip| (qtype == TYPE_A) == ip.is_ipv4())
+            .collect();
+
+        let mut msg = query[..2].to_vec();
+        msg.extend_from_slice(&[0x81, 0x80, 0, 1, 0, ips.len() as u8, 0, 0, 0, 0]);
+        msg.extend_from_slice(&query[12..]);
+        for ip in ips {
+            msg.extend_from_slice(&[0xc0, 12]);
+            msg.extend_from_slice(&qtype.to_be_bytes());
+            msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+            msg.extend_from_slice(&300u32.to_be_bytes());
+            match ip {
+                IpAddr::V4(ip) => {
+                    msg.extend_from_slice(&4u16.to_be_bytes());
+                    msg.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    msg.extend_from_slice(&16u16.to_be_bytes());
+                    msg.extend_from_slice(&ip.octets());
+                }
+            }
+        }
+        msg
+    }
+
+    #[test]
+    fn test_upstream_urls() {
+        let doh = UpstreamResolver::doh("https://dns.example/dns-query").unwrap();
+        assert_eq!(doh.addr, "dns.example:443");
+        let doh = UpstreamResolver::doh("https://[2606:4700::1111]:8443").unwrap();
+        assert_eq!(doh.addr, "[2606:4700::1111]:8443");
+        assert!(matches!(doh.transport, Transport::Https { ref path, .. } if path == "/dns-query"));
+
+        assert_eq!(UpstreamResolver::dot("1.1.1.1").unwrap().addr, "1.1.1.1:853");
+        let dot = UpstreamResolver::dot("tls://dns.example:8853").unwrap();
+        assert_eq!(dot.addr, "dns.example:8853");
+
+        assert!(UpstreamResolver::doh("http://dns.example/dns-query").is_err());
+        assert!(UpstreamResolver::dot("dns.example:port").is_err());
+        assert!(UpstreamResolver::dot("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_doh_lookup() {
+        let resolver = UpstreamResolver::doh("https://dns.example/dns-query").unwrap();
+        let (client, server) = tokio::io::duplex(4096);
+        let ips: Vec<IpAddr> = vec!["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+
+        let upstream_ips = ips.clone();
+        let upstream = tokio::spawn(async move {
+            let mut server = BufReader::new(server);
+            let mut requests = Vec::new();
+            // Two lookups on one connection; the second is answered in chunks
+            for i in 0..2 * QUERIES.len() {
+                let request_line = read_header_line(&mut server).await.unwrap();
+                let mut headers = Vec::new();
+                loop {
+                    let line = read_header_line(&mut server).await.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    headers.push(line);
+                }
+                let len: usize = headers
+                    .iter()
+                    .find_map(|h| h.strip_prefix("Content-Length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let mut query = vec![0u8; len];
+                server.read_exact(&mut query).await.unwrap();
+
+                let body = answer(&query, &upstream_ips);
+                let response = if i < QUERIES.len() {
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\n\
+                         content-length: {}\r\n\r\n",
+                        body.len()
+                    );
+                    [head.as_bytes(), &body].concat()
+                } else {
+                    let (first, rest) = body.split_at(5);
+                    let mut response =
+                        b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n".to_vec();
+                    for chunk in [first, rest] {
+                        let size = format!("{:x};ext=1\r\n", chunk.len());
+                        response.extend_from_slice(size.as_bytes());
+                        response.extend_from_slice(chunk);
+                        response.extend_from_slice(b"\r\n");
+                    }
+                    response.extend_from_slice(b"0\r\nx-trailer: 1\r\n\r\n");
+                    response
+                };
+                server.get_mut().write_all(&response).await.unwrap();
+                requests.push((request_line, headers));
+            }
+            requests
+        });
+
+        let mut client = BufReader::new(client);
+        for _ in 0..2 {
+            let resolved = resolver.resolve_on(&mut client, "www.example.com").await.unwrap();
+            assert_eq!(resolved, (ips.clone(), true));
+        }
+
+        let requests = upstream.await.unwrap();
+        assert_eq!(requests[0].0, "POST /dns-query HTTP/1.1");
+        assert!(requests[0].1.contains(&"Host: dns.example".to_string()));
+        let headers = requests.iter().flat_map(|(_, headers)| headers);
+        assert!(!headers.into_iter().any(|h| h.starts_with("Connection")));
+    }
+
+    #[tokio::test]
+    async fn test_doh_connection_close() {
+        let resolver = UpstreamResolver::doh("https://dns.example/dns-query").unwrap();
+        let (client, server) = tokio::io::duplex(4096);
+
+        tokio::spawn(async move {
+            let mut server = BufReader::new(server);
+            for i in 0..QUERIES.len() {
+                let mut len = 0;
+                loop {
+                    let line = read_header_line(&mut server).await.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        len = value.parse().unwrap();
+                    }
+                }
+                let mut query = vec![0u8; len];
+                server.read_exact(&mut query).await.unwrap();
+
+                // The upstream is done with the connection after this lookup
+                let connection = if i + 1 == QUERIES.len() { "close" } else { "keep-alive" };
+                let body = answer(&query, &[]);
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nConnection: {}\r\ncontent-length: {}\r\n\r\n",
+                    connection,
+                    body.len()
+                );
+                server.get_mut().write_all(head.as_bytes()).await.unwrap();
+                server.get_mut().write_all(&body).await.unwrap();
+            }
+        });
+
+        let mut client = BufReader::new(client);
+        let resolved = resolver.resolve_on(&mut client, "missing.example").await.unwrap();
+        assert_eq!(resolved, (vec![], false));
+    }
+
+    #[tokio::test]
+    async fn test_dot_lookup() {
+        let resolver = UpstreamResolver::dot("1.1.1.1").unwrap();
+        let (client, mut server) = tokio::io::duplex(4096);
+
+        tokio::spawn(async move {
+            let mut queries = Vec::new();
+            for _ in QUERIES {
+                let len = server.read_u16().await.unwrap() as usize;
+                let mut query = vec![0u8; len];
+                server.read_exact(&mut query).await.unwrap();
+                queries.push(query);
+            }
+            // Answered out of order, and the name doesn't exist
+            for query in queries.iter().rev() {
+                let mut body = answer(query, &[]);
+                body[3] |= RCODE_NXDOMAIN as u8;
+                server.write_u16(body.len() as u16).await.unwrap();
+                server.write_all(&body).await.unwrap();
+            }
+        });
+
+        let mut client = BufReader::new(client);
+        let resolved = resolver.resolve_on(&mut client, "missing.example").await.unwrap();
+        assert_eq!(resolved, (vec![], true));
+    }
+
+    #[test]
+    fn test_bad_messages() {
+        assert!(encode_query(1, "a..example", TYPE_A).is_err());
+        assert!(encode_query(1, &"a".repeat(64), TYPE_A).is_err());
+
+        let query = encode_query(1, "example.com", TYPE_A).unwrap();
+        // A query isn't a response
+        assert!(parse_response(&query).is_err());
+        let response = answer(&query, &["192.0.2.1".parse().unwrap()]);
+        assert!(parse_response(&response[..response.len() - 2]).is_err());
+        let mut servfail = response.clone();
+        servfail[3] |= 2;
+        assert!(parse_response(&servfail).is_err());
+    }
+}
@@ -1,27 +1,342 @@
-//! TCP proxy with zero-copy forwarding
+//! TCP proxy between QUIC streams and origin connections
 //!
-//! Uses splice() on Linux for kernel-level data transfer without
-//! copying data to userspace.
+//! Each stream is copied in userspace through two pooled buffers, one per
+//! direction. With splice enabled on Linux, origin reads go through an
+//! io_uring splice into a pipe instead; QUIC streams live in userspace, so
+//! the data is still read out of the pipe and no copy is saved.
 
 use anyhow::{Context, Result};
-use quinn::{RecvStream, SendStream};
+use quinn::{RecvStream, SendStream, VarInt};
+use std::fmt::Display;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::task::JoinSet;
 use tracing::{debug, instrument};
 
+use crate::coalesce::Coalesce;
 use crate::metrics::METRICS;
-use crate::pool::BufferPool;
+use crate::pool::{Buffer, BufferPool, BufferSize};
+use crate::router::{AddressGuard, BlockedAddress};
+use crate::util::{connect_tcp, connect_tcp_from, connect_tcp_in_port_range, set_dscp};
+
+use super::dns::DnsCache;
+use super::middleware::StreamMiddleware;
+use super::proxy_protocol;
+use super::throttle::BandwidthLimiter;
+
+#[cfg(target_os = "linux")]
+use crate::util::io_uring::SpliceReader;
+
+/// Stand-in where io_uring doesn't exist; never constructed
+#[cfg(not(target_os = "linux"))]
+enum SpliceReader {}
+
+#[cfg(not(target_os = "linux"))]
+impl SpliceReader {
+    async fn read(&mut self, _socket: &TcpStream, _buf: &mut [u8]) -> std::io::Result<usize> {
+        match *self {}
+    }
+}
+
+/// Copy loop chunk size; each direction holds one pool buffer of this size
+const CHUNK_SIZE: usize = BufferSize::Medium as usize;
+
+/// Head start each origin connect attempt gets before the next address is
+/// tried (RFC 8305 section 5)
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Returned when the server-wide outbound connection cap is reached
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("outbound connection limit reached")]
+pub struct OutboundLimitExceeded;
+
+/// Returned when no pair of copy buffers frees up within the buffer wait
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("buffer pool exhausted")]
+pub struct BuffersExhausted;
+
+/// Returned when an origin connect outlasts the configured timeout
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Timed out after {0:?}")]
+pub struct ConnectTimeout(pub Duration);
+
+/// Bytes moved by one proxied stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProxyStats {
+    /// Client to origin
+    pub rx_bytes: u64,
+    /// Origin to client
+    pub tx_bytes: u64,
+}
+
+/// Stream error code sent when a stream is closed for inactivity
+const STREAM_IDLE_CODE: u32 = 1;
+
+/// How long a stream waits for the buffer pool by default
+pub const DEFAULT_BUFFER_WAIT: Duration = Duration::from_millis(100);
+
+/// Tracks when either copy direction last moved bytes
+struct IdleTracker {
+    /// `None` disables the timeout
+    timeout: Option<Duration>,
+    started: Instant,
+    /// Milliseconds after `started` of the last activity
+    last_active_ms: AtomicU64,
+    expired: AtomicBool,
+}
+
+impl IdleTracker {
+    fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            started: Instant::now(),
+            last_active_ms: AtomicU64::new(0),
+            expired: AtomicBool::new(false),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        self.last_active_ms.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    fn expired(&self) -> bool {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// Await `read`, or `None` once neither direction has moved bytes for
+    /// the timeout
+    ///
+    /// Activity in the other direction pushes the deadline back.
+    async fn read<F: Future>(&self, read: F) -> Option<F::Output> {
+        let Some(timeout) = self.timeout else {
+            return Some(read.await);
+        };
+        tokio::pin!(read);
+
+        loop {
+            let last_active = Duration::from_millis(self.last_active_ms.load(Ordering::Relaxed));
+            let idle_for = self.started.elapsed().saturating_sub(last_active);
+            if self.expired() || idle_for >= timeout {
+                self.expired.store(true, Ordering::Relaxed);
+                return None;
+            }
+            if let Ok(output) = tokio::time::timeout(timeout - idle_for, &mut read).await {
+                return Some(output);
+            }
+        }
+    }
+}
+
+/// A reserved slot in an outbound connection gauge, released on drop
+struct OutboundSlot {
+    gauge: &'static AtomicU64,
+}
+
+impl OutboundSlot {
+    /// Reserve a slot unless `max` slots are already taken (0 = unlimited)
+    fn acquire(gauge: &'static AtomicU64, max: u64) -> Option<Self> {
+        gauge
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (max == 0 || current < max).then_some(current + 1)
+            })
+            .ok()?;
+        Some(Self { gauge })
+    }
+}
+
+impl Drop for OutboundSlot {
+    fn drop(&mut self) {
+        self.gauge.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// An established origin connection, with the buffers to copy it through
+pub struct OriginConnection {
+    stream: TcpStream,
+    buffers: (Buffer, Buffer),
+    /// Held by connections this proxy opened, not by adopted ones
+    _slot: Option<OutboundSlot>,
+}
 
 /// TCP proxy for stream forwarding
 pub struct TcpProxy {
-    #[allow(dead_code)]
+    /// Source of the copy loop buffers
     buffer_pool: BufferPool,
+    /// How long to wait for a free buffer before aborting the stream
+    buffer_wait: Duration,
+    /// Per-chunk byte transform hook
+    middleware: Option<Arc<dyn StreamMiddleware>>,
+    /// Local port range for origin connections
+    egress_ports: Option<RangeInclusive<u16>>,
+    /// Local source IP for origin connections
+    source_ip: Option<IpAddr>,
+    /// Server-wide cap on open origin connections (0 = unlimited)
+    max_outbound: u64,
+    /// Give up on origin connects that take longer than this
+    connect_timeout: Option<Duration>,
+    /// Wait for the peer to acknowledge all data after finishing
+    confirm_delivery: bool,
+    /// Connection-wide bandwidth budget shared by both directions
+    bandwidth: Option<Arc<BandwidthLimiter>>,
+    /// Client address announced to the origin in a PROXY v2 header
+    proxy_protocol_source: Option<SocketAddr>,
+    /// Abort a stream once neither direction has moved bytes for this long
+    idle_timeout: Option<Duration>,
+    /// DSCP value marking packets to the origin
+    dscp: Option<u8>,
+    /// Send the first bytes to the origin in the SYN
+    fast_open: bool,
+    /// Read the origin through io_uring splice where available
+    splice: bool,
+    /// Shared cache for target lookups
+    dns_cache: Option<Arc<DnsCache>>,
+    /// Refuses resolved addresses in private ranges
+    address_guard: Option<AddressGuard>,
+    /// Batch small origin reads before writing them to the client
+    coalesce: Option<Coalesce>,
 }
 
 impl TcpProxy {
     /// Create a new TCP proxy
     pub fn new(buffer_pool: BufferPool) -> Self {
-        Self { buffer_pool }
+        Self {
+            buffer_pool,
+            buffer_wait: DEFAULT_BUFFER_WAIT,
+            middleware: None,
+            egress_ports: None,
+            source_ip: None,
+            max_outbound: 0,
+            connect_timeout: None,
+            confirm_delivery: false,
+            bandwidth: None,
+            proxy_protocol_source: None,
+            idle_timeout: None,
+            dscp: None,
+            fast_open: false,
+            splice: false,
+            dns_cache: None,
+            address_guard: None,
+            coalesce: None,
+        }
+    }
+
+    /// Install a middleware that sees every forwarded chunk
+    pub fn with_middleware(mut self, middleware: Arc<dyn StreamMiddleware>) -> Self {
+        self.middleware = Some(middleware);
+        self
+    }
+
+    /// Wait up to `wait` for a free pool buffer before aborting a stream
+    pub fn with_buffer_wait(mut self, wait: Duration) -> Self {
+        self.buffer_wait = wait;
+        self
+    }
+
+    /// Batch origin data for up to `delay` until `bytes` are buffered
+    /// before writing it to the client (`bytes` 0 = write every read)
+    ///
+    /// Fewer, larger stream writes cost interactive traffic up to `delay`
+    /// of latency. Origin reads then stay in userspace, without splice.
+    pub fn with_coalesce(mut self, bytes: usize, delay: Duration) -> Self {
+        self.coalesce = (bytes > 0).then_some(Coalesce { bytes, delay });
+        self
+    }
+
+    /// Bind origin connections to a local port within `ports`
+    pub fn with_egress_ports(mut self, ports: Option<RangeInclusive<u16>>) -> Self {
+        self.egress_ports = ports;
+        self
+    }
+
+    /// Bind origin connections to the local address `ip`
+    ///
+    /// Only target addresses of the same family are tried.
+    pub fn with_source_ip(mut self, ip: Option<IpAddr>) -> Self {
+        self.source_ip = ip;
+        self
+    }
+
+    /// Wait for the peer to acknowledge all stream data before completing
+    pub fn with_confirm_delivery(mut self, confirm: bool) -> Self {
+        self.confirm_delivery = confirm;
+        self
+    }
+
+    /// Throttle both directions against a shared bandwidth budget
+    pub fn with_bandwidth_limiter(mut self, limiter: Option<Arc<BandwidthLimiter>>) -> Self {
+        self.bandwidth = limiter;
+        self
+    }
+
+    /// Send a PROXY protocol v2 header naming `client_addr` before any data
+    pub fn with_proxy_protocol(mut self, client_addr: Option<SocketAddr>) -> Self {
+        self.proxy_protocol_source = client_addr;
+        self
+    }
+
+    /// Cap the number of origin connections open across the server
+    pub fn with_max_outbound(mut self, max: u64) -> Self {
+        self.max_outbound = max;
+        self
+    }
+
+    /// Fail origin connects that don't complete within `timeout`
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Abort streams that move no bytes in either direction for `timeout`
+    ///
+    /// Both sides are closed and the timeout is counted in
+    /// `METRICS.timeouts_total`.
+    pub fn with_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Mark packets to the origin with the DSCP value `dscp`
+    pub fn with_dscp(mut self, dscp: Option<u8>) -> Self {
+        self.dscp = dscp;
+        self
+    }
+
+    /// Connect to origins with TCP Fast Open where the kernel supports it
+    ///
+    /// Connects then complete before the origin has answered, so connect
+    /// failures show up on the first write instead, and only the first
+    /// resolved address is tried.
+    pub fn with_fast_open(mut self, fast_open: bool) -> Self {
+        self.fast_open = fast_open;
+        self
+    }
+
+    /// Read origin data through an io_uring splice (Linux only)
+    ///
+    /// Each stream then holds its own ring and pipe, three extra fds.
+    /// Ignored for coalesced streams and where io_uring is unavailable.
+    pub fn with_splice(mut self, splice: bool) -> Self {
+        self.splice = splice;
+        self
+    }
+
+    /// Resolve targets through `cache` instead of asking the system each time
+    pub fn with_dns_cache(mut self, cache: Option<Arc<DnsCache>>) -> Self {
+        self.dns_cache = cache;
+        self
+    }
+
+    /// Only connect to resolved addresses `guard` permits
+    pub fn with_address_guard(mut self, guard: Option<AddressGuard>) -> Self {
+        self.address_guard = guard;
+        self
     }
 
     /// Proxy data between QUIC stream and TCP socket
@@ -31,68 +346,252 @@ impl TcpProxy {
         quic_send: SendStream,
         quic_recv: RecvStream,
         target: &str,
-    ) -> Result<()> {
-        // Connect to target
-        let tcp_stream = TcpStream::connect(target)
+    ) -> Result<ProxyStats> {
+        let origin = self.connect(target).await?;
+        self.proxy_connected(quic_send, quic_recv, origin).await
+    }
+
+    /// Connect to the target, counting it against the outbound connection cap
+    ///
+    /// `target` is a `host:port` string or an already resolved address.
+    /// Both copy buffers are taken first, so a stream is never
+    /// acknowledged without them. Fails with [`BuffersExhausted`] if the
+    /// pool stays empty past the buffer wait, [`OutboundLimitExceeded`] if
+    /// the cap is reached, [`ConnectTimeout`] if the connect timeout
+    /// expires and [`BlockedAddress`] if the address guard refuses every
+    /// address.
+    pub async fn connect<T>(&self, target: T) -> Result<OriginConnection>
+    where
+        T: ToSocketAddrs + Display + Copy,
+    {
+        let buffers = self
+            .buffer_pool
+            .acquire_pair_timeout(CHUNK_SIZE, self.buffer_wait)
             .await
-            .with_context(|| format!("Failed to connect to {}", target))?;
+            .ok_or(BuffersExhausted)?;
+        let slot = OutboundSlot::acquire(&METRICS.outbound_connections, self.max_outbound)
+            .ok_or(OutboundLimitExceeded)?;
+
+        let started = Instant::now();
+        let connecting = self.connect_target(target);
+        let connected = match self.connect_timeout {
+            Some(limit) => match tokio::time::timeout(limit, connecting).await {
+                Ok(connected) => connected,
+                Err(_) => {
+                    METRICS.timeout();
+                    Err(ConnectTimeout(limit).into())
+                }
+            },
+            None => connecting.await,
+        };
+        let stream = connected.with_context(|| format!("Failed to connect to {}", target))?;
 
+        metrics::histogram!("mytunnel_target_connect_seconds")
+            .record(started.elapsed().as_secs_f64());
         debug!(target = %target, "Connected to target");
 
-        // Try splice-based forwarding on Linux, fall back to userspace copy
-        #[cfg(target_os = "linux")]
-        {
-            if let Ok(()) = self
-                .proxy_with_splice(quic_send, quic_recv, tcp_stream)
+        Ok(OriginConnection {
+            stream,
+            buffers,
+            _slot: Some(slot),
+        })
+    }
+
+    /// Proxy a connection made to the server, as a reverse tunnel accepts
+    ///
+    /// It is copied like an origin connection, through the same limits and
+    /// counters, but takes no outbound slot. Fails with
+    /// [`BuffersExhausted`] if the pool stays empty past the buffer wait.
+    pub async fn adopt(&self, stream: TcpStream) -> Result<OriginConnection> {
+        let buffers = self
+            .buffer_pool
+            .acquire_pair_timeout(CHUNK_SIZE, self.buffer_wait)
+            .await
+            .ok_or(BuffersExhausted)?;
+        Ok(OriginConnection {
+            stream,
+            buffers,
+            _slot: None,
+        })
+    }
+
+    /// Proxy data between QUIC stream and an established origin connection
+    pub async fn proxy_connected(
+        &self,
+        quic_send: SendStream,
+        quic_recv: RecvStream,
+        origin: OriginConnection,
+    ) -> Result<ProxyStats> {
+        let OriginConnection { stream: mut tcp_stream, buffers, _slot } = origin;
+
+        if let Some(source) = self.proxy_protocol_source {
+            let header = proxy_protocol::encode_v2(source, tcp_stream.peer_addr()?);
+            tcp_stream
+                .write_all(&header)
                 .await
-            {
-                return Ok(());
-            }
-            // Fallback to userspace if splice fails
+                .context("Failed to send PROXY protocol header")?;
         }
 
+        // Splice-based forwarding on Linux if enabled; otherwise userspace copy
+        #[cfg(target_os = "linux")]
+        let stats = self.proxy_with_splice(quic_send, quic_recv, tcp_stream, buffers)
+            .await?;
+
         // Userspace proxy (cross-platform)
         #[cfg(not(target_os = "linux"))]
-        self.proxy_userspace(quic_send, quic_recv, tcp_stream)
+        let stats = self.proxy_userspace(quic_send, quic_recv, tcp_stream, buffers)
             .await?;
 
-        Ok(())
+        Ok(stats)
     }
 
-    /// Zero-copy proxy using splice() (Linux only)
+    /// Open the origin connection, honoring the source IP and egress port range
+    ///
+    /// Every resolved address is raced with [`race_connects`], so a slow
+    /// address family doesn't hold up a dual-stacked target. With Fast Open
+    /// only the first address is tried: its connect returns before the SYN
+    /// is answered, so it would win every race.
+    async fn connect_target<T>(&self, target: T) -> Result<TcpStream>
+    where
+        T: ToSocketAddrs + Display + Copy,
+    {
+        let source_ip = self.source_ip;
+        let mut resolved: Vec<SocketAddr> = match &self.dns_cache {
+            Some(cache) => cache.lookup(&target.to_string()).await?,
+            None => tokio::net::lookup_host(target).await?.collect(),
+        };
+        // Check what we connect to, not the name, so rebinding can't get around it
+        if let Some(guard) = &self.address_guard {
+            let first = resolved.first().map(|addr| addr.ip());
+            resolved.retain(|addr| guard.permits(addr.ip()));
+            if let (true, Some(ip)) = (resolved.is_empty(), first) {
+                return Err(BlockedAddress(ip).into());
+            }
+        }
+        let addrs: Vec<SocketAddr> = resolved
+            .into_iter()
+            .filter(|addr| source_ip.map_or(true, |ip| ip.is_ipv4() == addr.is_ipv4()))
+            .collect();
+        if addrs.is_empty() {
+            return Err(match source_ip {
+                Some(ip) => anyhow::anyhow!("No address for {} reachable from {}", target, ip),
+                None => anyhow::anyhow!("Failed to resolve {}", target),
+            });
+        }
+
+        let mut addrs = interleave_families(addrs);
+        if self.fast_open {
+            addrs.truncate(1);
+        }
+
+        let ports = self.egress_ports.clone();
+        let dscp = self.dscp;
+        let fast_open = self.fast_open;
+        race_connects(addrs, CONNECTION_ATTEMPT_DELAY, move |addr| {
+            let ports = ports.clone();
+            async move {
+                let stream = match (ports, source_ip) {
+                    (Some(ports), source_ip) => {
+                        connect_tcp_in_port_range(addr, source_ip, ports, fast_open).await?
+                    }
+                    (None, Some(source_ip)) => connect_tcp_from(addr, source_ip, fast_open).await?,
+                    (None, None) => connect_tcp(addr, fast_open).await?,
+                };
+                if let Some(dscp) = dscp {
+                    set_dscp(&stream, dscp)?;
+                }
+                Ok(stream)
+            }
+        })
+        .await
+    }
+
+    /// Proxy using io_uring splice for the target -> client direction (Linux only)
+    ///
+    /// QUIC streams live in userspace, so only the origin socket has a
+    /// real fd: its data is spliced into a pipe in the kernel, then read
+    /// out for the QUIC stream. Uses the plain userspace copy when splice is
+    /// off or io_uring is unavailable.
     #[cfg(target_os = "linux")]
     async fn proxy_with_splice(
         &self,
         quic_send: SendStream,
         quic_recv: RecvStream,
         tcp_stream: TcpStream,
-    ) -> Result<()> {
-        // For now, fall back to userspace copy since QUIC streams aren't raw FDs
-        // splice() works between socket FDs, but QUIC streams are userspace constructs
-        // In a real implementation, we'd use io_uring for async splice
-        
-        // Fall through to userspace proxy
-        self.proxy_userspace(quic_send, quic_recv, tcp_stream).await
+        buffers: (Buffer, Buffer),
+    ) -> Result<ProxyStats> {
+        // Coalescing reads past the first chunk, which splice can't do
+        let splice = if self.splice
+            && self.coalesce.is_none()
+            && crate::util::io_uring::is_available()
+        {
+            SpliceReader::new()
+                .map_err(|e| debug!(error = %e, "io_uring setup failed, using userspace copy"))
+                .ok()
+        } else {
+            None
+        };
+
+        self.proxy_copy(quic_send, quic_recv, tcp_stream, buffers, splice).await
     }
 
     /// Userspace proxy (works on all platforms)
+    #[cfg(not(target_os = "linux"))]
     async fn proxy_userspace(
+        &self,
+        quic_send: SendStream,
+        quic_recv: RecvStream,
+        tcp_stream: TcpStream,
+        buffers: (Buffer, Buffer),
+    ) -> Result<ProxyStats> {
+        self.proxy_copy(quic_send, quic_recv, tcp_stream, buffers, None).await
+    }
+
+    /// Copy both directions, reading the origin through `splice` if given
+    ///
+    /// Each direction half-closes its destination when its source ends and
+    /// leaves the other running until its own EOF.
+    ///
+    /// A splice error switches the origin reads back to userspace for the
+    /// rest of the stream. Each direction copies through one of `buffers`.
+    async fn proxy_copy(
         &self,
         mut quic_send: SendStream,
         mut quic_recv: RecvStream,
         tcp_stream: TcpStream,
-    ) -> Result<()> {
+        (mut up_buf, mut down_buf): (Buffer, Buffer),
+        mut splice: Option<SpliceReader>,
+    ) -> Result<ProxyStats> {
         let (mut tcp_read, mut tcp_write) = tcp_stream.into_split();
+        let idle = IdleTracker::new(self.idle_timeout);
 
         // Spawn bidirectional copy tasks
         let client_to_target = async {
-            let mut buf = vec![0u8; 16384]; // 16KB buffer
+            let buf = &mut up_buf[..CHUNK_SIZE];
+            let mut chunk = Vec::new();
             let mut total: u64 = 0;
 
             loop {
-                match quic_recv.read(&mut buf).await {
+                let Some(read) = idle.read(quic_recv.read(buf)).await else {
+                    let _ = quic_recv.stop(VarInt::from_u32(STREAM_IDLE_CODE));
+                    break;
+                };
+                match read {
                     Ok(Some(n)) if n > 0 => {
-                        if tcp_write.write_all(&buf[..n]).await.is_err() {
+                        idle.touch();
+                        if let Some(limiter) = &self.bandwidth {
+                            limiter.consume(n).await;
+                        }
+                        let data = match &self.middleware {
+                            Some(middleware) => {
+                                chunk.clear();
+                                chunk.extend_from_slice(&buf[..n]);
+                                middleware.on_client_to_origin(&mut chunk);
+                                &chunk[..]
+                            }
+                            None => &buf[..n],
+                        };
+                        if tcp_write.write_all(data).await.is_err() {
                             break;
                         }
                         total += n as u64;
@@ -102,37 +601,159 @@ impl TcpProxy {
                     Err(_) => break,
                 }
             }
+            // Pass the client's half-close on; the origin may still be sending
+            let _ = tcp_write.shutdown().await;
             total
         };
 
         let target_to_client = async {
-            let mut buf = vec![0u8; 16384];
+            let buf = &mut down_buf[..CHUNK_SIZE];
+            let mut chunk = Vec::new();
             let mut total: u64 = 0;
 
             loop {
-                match tcp_read.read(&mut buf).await {
+                let read = match splice.as_mut() {
+                    Some(reader) => match idle
+                        .read(reader.read(tcp_read.as_ref(), buf))
+                        .await
+                    {
+                        Some(Err(e)) => {
+                            debug!(error = %e, "io_uring splice failed, using userspace copy");
+                            splice = None;
+                            continue;
+                        }
+                        read => read,
+                    },
+                    None => idle.read(tcp_read.read(buf)).await,
+                };
+                let Some(read) = read else {
+                    break;
+                };
+
+                match read {
                     Ok(n) if n > 0 => {
-                        if quic_send.write_all(&buf[..n]).await.is_err() {
+                        idle.touch();
+                        let (n, ended) = match &self.coalesce {
+                            Some(coalesce) if splice.is_none() => {
+                                coalesce.fill(&mut tcp_read, buf, n).await
+                            }
+                            _ => (n, false),
+                        };
+                        if let Some(limiter) = &self.bandwidth {
+                            limiter.consume(n).await;
+                        }
+                        let data = match &self.middleware {
+                            Some(middleware) => {
+                                chunk.clear();
+                                chunk.extend_from_slice(&buf[..n]);
+                                middleware.on_origin_to_client(&mut chunk);
+                                &chunk[..]
+                            }
+                            None => &buf[..n],
+                        };
+                        if quic_send.write_all(data).await.is_err() {
                             break;
                         }
                         total += n as u64;
                         METRICS.bytes_tx(n as u64);
+                        if ended {
+                            break;
+                        }
                     }
                     Ok(_) => break, // EOF
                     Err(_) => break,
                 }
             }
-            let _ = quic_send.finish();
+            if idle.expired() {
+                let _ = quic_send.reset(VarInt::from_u32(STREAM_IDLE_CODE));
+            } else if let Err(e) = finish_stream(&mut quic_send, self.confirm_delivery).await {
+                debug!(error = %e, "Failed to finish QUIC stream");
+                METRICS.stream_finish_error();
+            }
             total
         };
 
         // Run both directions concurrently
         let (rx_bytes, tx_bytes) = tokio::join!(client_to_target, target_to_client);
 
-        debug!(rx_bytes, tx_bytes, "TCP proxy completed");
+        if idle.expired() {
+            METRICS.timeout();
+            debug!(rx_bytes, tx_bytes, "TCP proxy closed after idle timeout");
+        } else {
+            debug!(rx_bytes, tx_bytes, "TCP proxy completed");
+        }
+
+        Ok(ProxyStats { rx_bytes, tx_bytes })
+    }
+}
+
+/// Order addresses for racing, alternating families and starting with the
+/// family of the first resolved address (RFC 8305 section 4)
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let (mut first, mut second) = if first_v6 {
+        (v6.into_iter(), v4.into_iter())
+    } else {
+        (v4.into_iter(), v6.into_iter())
+    };
+
+    let mut ordered = Vec::new();
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Race connection attempts to `addrs` in order (Happy Eyeballs, RFC 8305)
+///
+/// Each attempt starts `attempt_delay` after the previous one, or as soon
+/// as it fails. The first to connect wins and the others are cancelled.
+/// Fails with the last error if every attempt fails.
+async fn race_connects<F, Fut>(
+    addrs: Vec<SocketAddr>,
+    attempt_delay: Duration,
+    connect: F,
+) -> Result<TcpStream>
+where
+    F: Fn(SocketAddr) -> Fut,
+    Fut: Future<Output = Result<TcpStream>> + Send + 'static,
+{
+    let mut pending = addrs.into_iter().peekable();
+    // Dropping the set aborts the attempts still in flight
+    let mut attempts = JoinSet::new();
+    let mut last_err = None;
+
+    while pending.peek().is_some() || !attempts.is_empty() {
+        if let Some(addr) = pending.next() {
+            attempts.spawn(connect(addr));
+        }
 
-        Ok(())
+        tokio::select! {
+            Some(joined) = attempts.join_next() => {
+                match joined.map_err(anyhow::Error::from).and_then(|attempt| attempt) {
+                    Ok(stream) => return Ok(stream),
+                    Err(e) => last_err = Some(e),
+                }
+            }
+            _ = tokio::time::sleep(attempt_delay), if pending.peek().is_some() => {}
+        }
     }
+
+    Err(last_err.unwrap_or_else(|| anyhow::anyhow!("No addresses to connect to")))
+}
+
+/// Finish the send side, optionally waiting until the peer has received all data
+async fn finish_stream(send: &mut SendStream, confirm_delivery: bool) -> Result<()> {
+    send.finish()?;
+    if confirm_delivery {
+        if let Some(code) = send.stopped().await? {
+            anyhow::bail!("Peer stopped stream with code {}", code);
+        }
+    }
+    Ok(())
 }
 
 /// Zero-copy splice helper for raw file descriptors
@@ -159,7 +780,7 @@ impl SpliceProxy {
 
         // Create pipe for splice buffer
         let (pipe_read, pipe_write) = pipe()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            .map_err(std::io::Error::other)?;
 
         let flags = SpliceFFlags::SPLICE_F_MOVE | SpliceFFlags::SPLICE_F_NONBLOCK;
         let mut total: u64 = 0;
@@ -174,7 +795,7 @@ impl SpliceProxy {
                 buffer_size,
                 flags,
             )
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            .map_err(std::io::Error::other)?;
 
             if n == 0 {
                 break; // EOF
@@ -191,7 +812,7 @@ impl SpliceProxy {
                     remaining,
                     flags,
                 )
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                .map_err(std::io::Error::other)?;
 
                 remaining -= written;
             }
@@ -207,10 +828,391 @@ impl SpliceProxy {
 mod tests {
     use super::*;
 
+    /// A loopback address that never answers a connect
+    ///
+    /// The listener's accept queue is full, so it drops further SYNs and
+    /// connecting hangs like a black-holed address. Keep the returned
+    /// sockets alive for as long as the address is used.
+    async fn black_hole() -> (SocketAddr, socket2::Socket, Vec<TcpStream>) {
+        let socket =
+            socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+        socket.bind(&"127.0.0.1:0".parse::<SocketAddr>().unwrap().into()).unwrap();
+        socket.listen(0).unwrap();
+        let target = socket.local_addr().unwrap().as_socket().unwrap();
+
+        let mut queued = Vec::new();
+        for _ in 0..8 {
+            let connect = TcpStream::connect(target);
+            match tokio::time::timeout(Duration::from_millis(100), connect).await {
+                Ok(stream) => queued.push(stream.unwrap()),
+                Err(_) => break,
+            }
+        }
+        (target, socket, queued)
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        let (target, _listener, _queued) = black_hole().await;
+
+        let timeouts = METRICS.timeouts_total.load(Ordering::Relaxed);
+        let proxy = TcpProxy::new(BufferPool::new(1, 2, 1))
+            .with_connect_timeout(Duration::from_millis(200));
+        let started = Instant::now();
+        let err = proxy.connect(target).await.err().unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(format!("{:#}", err).contains("Timed out"));
+        assert!(METRICS.timeouts_total.load(Ordering::Relaxed) > timeouts);
+    }
+
+    #[tokio::test]
+    async fn test_address_guard() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let target = format!("localhost:{port}");
+
+        // The name is fine; what it resolves to isn't
+        let guarded = TcpProxy::new(BufferPool::new(1, 2, 1))
+            .with_address_guard(Some(AddressGuard::new(vec![])));
+        let err = guarded.connect(target.as_str()).await.err().unwrap();
+        assert!(err.is::<BlockedAddress>(), "{err:#}");
+
+        let allowlisted = TcpProxy::new(BufferPool::new(1, 2, 1)).with_address_guard(Some(
+            AddressGuard::new(vec!["127.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()]),
+        ));
+        assert!(allowlisted.connect(target.as_str()).await.is_ok());
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let addrs: Vec<SocketAddr> = ["[::1]:1", "[::2]:1", "[::3]:1", "10.0.0.1:1", "10.0.0.2:1"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+
+        let ordered: Vec<String> = interleave_families(addrs)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(ordered, ["[::1]:1", "10.0.0.1:1", "[::2]:1", "10.0.0.2:1", "[::3]:1"]);
+    }
+
+    #[tokio::test]
+    async fn test_race_connects() {
+        let (slow, _listener, _queued) = black_hole().await;
+        let fast_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let fast = fast_listener.local_addr().unwrap();
+        let refused = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let connect = |addr| async move { Ok(TcpStream::connect(addr).await?) };
+
+        // A hanging first address only costs the attempt delay
+        let started = Instant::now();
+        let stream = race_connects(vec![slow, fast], Duration::from_millis(100), connect)
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), fast);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // A failing first address starts the next attempt right away
+        let started = Instant::now();
+        let stream = race_connects(vec![refused, fast], Duration::from_secs(5), connect)
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), fast);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        assert!(race_connects(vec![refused], Duration::from_secs(5), connect).await.is_err());
+    }
+
+    #[test]
+    fn test_outbound_slot_cap() {
+        static GAUGE: AtomicU64 = AtomicU64::new(0);
+
+        let first = OutboundSlot::acquire(&GAUGE, 2).unwrap();
+        let second = OutboundSlot::acquire(&GAUGE, 2).unwrap();
+        assert_eq!(GAUGE.load(Ordering::SeqCst), 2);
+
+        // Past the cap
+        assert!(OutboundSlot::acquire(&GAUGE, 2).is_none());
+        assert_eq!(GAUGE.load(Ordering::SeqCst), 2);
+
+        drop(first);
+        assert_eq!(GAUGE.load(Ordering::SeqCst), 1);
+        let _third = OutboundSlot::acquire(&GAUGE, 2).unwrap();
+
+        drop(second);
+        assert_eq!(GAUGE.load(Ordering::SeqCst), 1);
+
+        // 0 means unlimited
+        let unlimited: Vec<_> = (0..10)
+            .map(|_| OutboundSlot::acquire(&GAUGE, 0).unwrap())
+            .collect();
+        assert_eq!(GAUGE.load(Ordering::SeqCst), 11);
+        drop(unlimited);
+        assert_eq!(GAUGE.load(Ordering::SeqCst), 1);
+    }
+
     #[tokio::test]
     async fn test_tcp_proxy_creation() {
         let pool = BufferPool::new(10, 5, 2);
         let _proxy = TcpProxy::new(pool);
     }
+
+    #[tokio::test]
+    async fn test_copy_uses_pool_buffers() {
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap().to_string();
+        let (close_tx, close_rx) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let (mut sock, _) = origin.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            sock.read_exact(&mut buf).await.unwrap();
+            sock.write_all(&buf).await.unwrap();
+            // Keep the transfer open until the test has looked at the pool
+            let _ = close_rx.await;
+        });
+
+        let pool = BufferPool::new(10, 5, 2);
+        let in_use = || pool.stats().tier(BufferSize::Medium).unwrap().in_use;
+        let pair = crate::util::testing::quic_pair().await;
+        let (mut client_send, mut client_recv) = pair.client.open_bi().await.unwrap();
+        client_send.write_all(b"ping").await.unwrap();
+
+        let (server_send, server_recv) = pair.server.accept_bi().await.unwrap();
+        let proxy = TcpProxy::new(pool.clone());
+        let proxy_task = tokio::spawn(async move {
+            proxy.proxy_stream(server_send, server_recv, &origin_addr).await
+        });
+
+        // Mid-transfer both directions hold a pooled buffer
+        let mut reply = [0u8; 4];
+        client_recv.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"ping");
+        assert_eq!(in_use(), 2);
+
+        client_send.finish().unwrap();
+        drop(close_tx);
+        client_recv.read_to_end(64).await.unwrap();
+        proxy_task.await.unwrap().unwrap();
+        assert_eq!(in_use(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pool_exhausted_fails_connect() {
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap();
+
+        // A single 16KB buffer can't cover both directions
+        let pool = BufferPool::new(1, 1, 1);
+        let proxy = TcpProxy::new(pool.clone()).with_buffer_wait(Duration::from_millis(20));
+        let err = proxy.connect(origin_addr).await.err().unwrap();
+        assert!(err.is::<BuffersExhausted>());
+
+        // Nothing is held and the origin never saw a connection
+        assert_eq!(pool.stats().tier(BufferSize::Medium).unwrap().in_use, 0);
+        let accept = tokio::time::timeout(Duration::from_millis(50), origin.accept()).await;
+        assert!(accept.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_half_close_reaches_origin() {
+        // An origin that answers only once the request is complete, like a
+        // request body without a length
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut sock, _) = origin.accept().await.unwrap();
+            let mut request = Vec::new();
+            sock.read_to_end(&mut request).await.unwrap();
+            sock.write_all(format!("got {} bytes", request.len()).as_bytes()).await.unwrap();
+        });
+
+        let pair = crate::util::testing::quic_pair().await;
+        let (mut client_send, mut client_recv) = pair.client.open_bi().await.unwrap();
+        client_send.write_all(b"hello").await.unwrap();
+        client_send.finish().unwrap();
+
+        let (server_send, server_recv) = pair.server.accept_bi().await.unwrap();
+        // Through splice where io_uring is available
+        let proxy = TcpProxy::new(BufferPool::new(10, 5, 2)).with_splice(true);
+        let proxy_task = tokio::spawn(async move {
+            proxy.proxy_stream(server_send, server_recv, &origin_addr).await
+        });
+
+        // The response still flows after the client stopped sending
+        let read = client_recv.read_to_end(64);
+        let reply = tokio::time::timeout(Duration::from_secs(5), read).await.unwrap().unwrap();
+        assert_eq!(reply, b"got 5 bytes");
+        let stats = proxy_task.await.unwrap().unwrap();
+        assert_eq!(stats, ProxyStats { rx_bytes: 5, tx_bytes: 11 });
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_copy() {
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut sock, _) = origin.accept().await.unwrap();
+            sock.set_nodelay(true).unwrap();
+            for i in 0..100u8 {
+                sock.write_all(&[i; 10]).await.unwrap();
+            }
+        });
+
+        let pair = crate::util::testing::quic_pair().await;
+        let (mut client_send, mut client_recv) = pair.client.open_bi().await.unwrap();
+        client_send.finish().unwrap();
+
+        let (server_send, server_recv) = pair.server.accept_bi().await.unwrap();
+        let proxy = TcpProxy::new(BufferPool::new(10, 5, 2))
+            .with_coalesce(256, Duration::from_millis(1));
+        let proxy_task = tokio::spawn(async move {
+            proxy.proxy_stream(server_send, server_recv, &origin_addr).await
+        });
+
+        let reply = client_recv.read_to_end(4096).await.unwrap();
+        let expected: Vec<u8> = (0..100u8).flat_map(|i| [i; 10]).collect();
+        assert_eq!(reply, expected);
+        assert_eq!(proxy_task.await.unwrap().unwrap().tx_bytes, 1000);
+    }
+
+    struct Uppercase;
+
+    impl StreamMiddleware for Uppercase {
+        fn on_client_to_origin(&self, buf: &mut Vec<u8>) {
+            buf.make_ascii_uppercase();
+        }
+
+        fn on_origin_to_client(&self, buf: &mut Vec<u8>) {
+            buf.make_ascii_uppercase();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_transforms_both_directions() {
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap().to_string();
+        let origin_task = tokio::spawn(async move {
+            let (mut sock, _) = origin.accept().await.unwrap();
+            let mut buf = [0u8; 5];
+            sock.read_exact(&mut buf).await.unwrap();
+            sock.write_all(b"world").await.unwrap();
+            buf
+        });
+
+        let pair = crate::util::testing::quic_pair().await;
+        let (mut client_send, mut client_recv) = pair.client.open_bi().await.unwrap();
+        client_send.write_all(b"hello").await.unwrap();
+        client_send.finish().unwrap();
+
+        let (server_send, server_recv) = pair.server.accept_bi().await.unwrap();
+        let proxy = TcpProxy::new(BufferPool::new(10, 5, 2)).with_middleware(Arc::new(Uppercase));
+        let proxy_task = tokio::spawn(async move {
+            proxy.proxy_stream(server_send, server_recv, &origin_addr).await
+        });
+
+        assert_eq!(&origin_task.await.unwrap(), b"HELLO");
+        let reply = client_recv.read_to_end(64).await.unwrap();
+        assert_eq!(reply, b"WORLD");
+        let stats = proxy_task.await.unwrap().unwrap();
+        assert_eq!(stats, ProxyStats { rx_bytes: 5, tx_bytes: 5 });
+    }
+
+    #[tokio::test]
+    async fn test_finish_error_counted() {
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut sock, _) = origin.accept().await.unwrap();
+            sock.write_all(b"world").await.unwrap();
+        });
+
+        // Client sends a request, then refuses the response
+        let pair = crate::util::testing::quic_pair().await;
+        let (mut client_send, mut client_recv) = pair.client.open_bi().await.unwrap();
+        client_send.write_all(b"hello").await.unwrap();
+        client_send.finish().unwrap();
+        client_recv.stop(quinn::VarInt::from_u32(7)).unwrap();
+
+        let before = METRICS.stream_finish_errors.load(Ordering::Relaxed);
+        let (server_send, server_recv) = pair.server.accept_bi().await.unwrap();
+        TcpProxy::new(BufferPool::new(10, 5, 2))
+            .with_confirm_delivery(true)
+            .proxy_stream(server_send, server_recv, &origin_addr)
+            .await
+            .unwrap();
+
+        assert!(METRICS.stream_finish_errors.load(Ordering::Relaxed) > before);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_header() {
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap();
+        let client_addr: SocketAddr = "203.0.113.9:40000".parse().unwrap();
+        let header = proxy_protocol::encode_v2(client_addr, origin_addr);
+        let expected_len = header.len() + 5;
+        let received = tokio::spawn(async move {
+            let (mut socket, _) = origin.accept().await.unwrap();
+            let mut data = vec![0u8; expected_len];
+            socket.read_exact(&mut data).await.unwrap();
+            data
+        });
+
+        let pair = crate::util::testing::quic_pair().await;
+        let (mut client_send, _client_recv) = pair.client.open_bi().await.unwrap();
+        client_send.write_all(b"hello").await.unwrap();
+        client_send.finish().unwrap();
+
+        let (server_send, server_recv) = pair.server.accept_bi().await.unwrap();
+        let proxy = TcpProxy::new(BufferPool::new(10, 5, 2)).with_proxy_protocol(Some(client_addr));
+        let proxy_task = tokio::spawn(async move {
+            proxy.proxy_stream(server_send, server_recv, &origin_addr.to_string()).await
+        });
+
+        // The origin closes after reading, which ends the proxy
+        let data = received.await.unwrap();
+        proxy_task.await.unwrap().unwrap();
+        assert_eq!(&data[..header.len()], &header[..]);
+        assert_eq!(&data[header.len()..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        // The origin accepts, then never sends or closes
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap().to_string();
+        let held = tokio::spawn(async move { origin.accept().await.unwrap() });
+
+        let pair = crate::util::testing::quic_pair().await;
+        let (mut client_send, mut client_recv) = pair.client.open_bi().await.unwrap();
+        client_send.write_all(b"hi").await.unwrap();
+
+        let before = METRICS.timeouts_total.load(Ordering::Relaxed);
+        let (server_send, server_recv) = pair.server.accept_bi().await.unwrap();
+        let proxy = TcpProxy::new(BufferPool::new(10, 5, 2))
+            .with_idle_timeout(Some(Duration::from_millis(200)));
+        let stats = tokio::time::timeout(
+            Duration::from_secs(5),
+            proxy.proxy_stream(server_send, server_recv, &origin_addr),
+        )
+        .await
+        .expect("idle stream was not closed")
+        .unwrap();
+
+        assert_eq!(stats.rx_bytes, 2);
+        assert!(METRICS.timeouts_total.load(Ordering::Relaxed) > before);
+        assert!(matches!(
+            client_recv.read_to_end(64).await,
+            Err(quinn::ReadToEndError::Read(quinn::ReadError::Reset(code)))
+                if code == VarInt::from_u32(STREAM_IDLE_CODE)
+        ));
+        drop(held);
+    }
 }
 
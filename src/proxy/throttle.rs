@@ -0,0 +1,146 @@
+//! Bandwidth throttling
+//!
+//! Token bucket shared by every stream of a connection so that all
+//! proxied traffic counts against a single bytes/sec budget.
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Limits throughput to a fixed number of bytes per second
+///
+/// The bucket starts empty and holds at most one second's worth of
+/// bytes, so idle periods allow short bursts but the long-run rate
+/// never exceeds the budget.
+pub struct BandwidthLimiter {
+    bytes_per_sec: f64,
+    bucket: Mutex<Bucket>,
+    /// Wider budget the same traffic also counts against
+    parent: Option<Arc<BandwidthLimiter>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl BandwidthLimiter {
+    /// Create a limiter allowing `bytes_per_sec` bytes per second
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                refilled_at: Instant::now(),
+            }),
+            parent: None,
+        }
+    }
+
+    /// Also count traffic against `parent`, e.g. a budget shared by every
+    /// connection from one client IP
+    pub fn with_parent(mut self, parent: Option<Arc<BandwidthLimiter>>) -> Self {
+        self.parent = parent;
+        self
+    }
+
+    /// Account for `bytes` of traffic, sleeping if the budget is exhausted
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.bytes_per_sec).min(self.bytes_per_sec);
+            bucket.refilled_at = now;
+
+            // Go into debt and wait it off, so concurrent callers queue up fairly
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        // Wait off both debts at once, so the tighter budget sets the pace
+        let own = async {
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+        };
+        match &self.parent {
+            Some(parent) => {
+                tokio::join!(own, Box::pin(parent.consume(bytes)));
+            }
+            None => own.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttles_to_rate() {
+        let limiter = BandwidthLimiter::new(256 * 1024);
+        let start = Instant::now();
+
+        for _ in 0..64 {
+            limiter.consume(16 * 1024).await;
+        }
+
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(3900), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(4500), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_directions_share_budget() {
+        let limiter = Arc::new(BandwidthLimiter::new(256 * 1024));
+        let start = Instant::now();
+
+        // 512KB each way against one 256KB/s budget
+        let tasks: Vec<_> = (0..2)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    for _ in 0..32 {
+                        limiter.consume(16 * 1024).await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(3900));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_parent_budget_shared() {
+        let parent = Arc::new(BandwidthLimiter::new(256 * 1024));
+        let start = Instant::now();
+
+        // Two roomy connection budgets under one 256KB/s parent
+        let tasks: Vec<_> = (0..2)
+            .map(|_| {
+                let limiter = BandwidthLimiter::new(1024 * 1024).with_parent(Some(parent.clone()));
+                tokio::spawn(async move {
+                    for _ in 0..32 {
+                        limiter.consume(16 * 1024).await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(3900), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(4500), "{:?}", elapsed);
+    }
+}
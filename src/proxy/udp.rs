@@ -4,13 +4,22 @@
 
 use anyhow::{Context, Result};
 use dashmap::DashMap;
-use std::net::SocketAddr;
+use parking_lot::Mutex;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(target_os = "linux")]
+use std::ops::DerefMut;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
 
 use crate::metrics::METRICS;
 use crate::pool::BufferPool;
+use crate::router::{AddressGuard, BlockedAddress};
+use crate::util::{local_bind_addr, set_dscp};
+
+use super::dns::DnsCache;
 
 /// Maximum number of packets to batch
 #[cfg(target_os = "linux")]
@@ -19,12 +28,70 @@ const MAX_BATCH_SIZE: usize = 64;
 /// UDP socket pool entry TTL
 const SOCKET_TTL: Duration = Duration::from_secs(60);
 
+/// Largest possible UDP datagram
+const MAX_UDP_DATAGRAM: usize = 65535;
+
+/// Datagrams a flow reads per recvmmsg() call
+#[cfg(target_os = "linux")]
+const FLOW_RECV_BATCH: usize = 8;
+
+/// Returned when a relayed response is larger than the allowed payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("UDP response of {len} bytes exceeds max payload of {max}")]
+pub struct OversizedResponse {
+    pub len: usize,
+    pub max: usize,
+}
+
+/// Receives `(flow_id, host, port, payload)` for every response on a UDP flow
+pub type FlowResponseSink = Arc<dyn Fn(u32, &str, u16, &[u8]) + Send + Sync>;
+
+/// Open UDP flows
+type FlowTable = DashMap<FlowKey, Arc<UdpFlow>>;
+
+/// A client-chosen flow id and the target it talks to
+///
+/// Two clients behind the same tunnel can query one target on separate
+/// flows, each with its own socket.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FlowKey {
+    flow_id: u32,
+    host: String,
+    port: u16,
+}
+
+/// Destination and payload of a packet to send
+#[cfg(target_os = "linux")]
+type OutgoingPacket<'a> = (SocketAddr, &'a [u8]);
+
 /// UDP relay for datagram forwarding
 pub struct UdpRelay {
-    #[allow(dead_code)]
+    /// Receive buffers for flows
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
     buffer_pool: BufferPool,
     /// Socket pool for reusing connections
     socket_pool: Arc<UdpSocketPool>,
+    /// Largest response payload passed back to the client
+    max_payload: usize,
+    /// Open flows, when flow mode is enabled
+    flows: Arc<FlowTable>,
+    /// Idle timeout and response sink for flow mode
+    flow_mode: Option<(Duration, FlowResponseSink)>,
+    /// DSCP value marking packets to targets
+    dscp: Option<u8>,
+    /// Shared cache for target lookups
+    dns_cache: Option<Arc<DnsCache>>,
+    /// Refuses resolved addresses in private ranges
+    address_guard: Option<AddressGuard>,
+}
+
+/// A socket kept open to a target so every response can be forwarded
+struct UdpFlow {
+    socket: Arc<UdpSocket>,
+    /// Last time a packet went either way on the flow
+    last_active: Arc<Mutex<Instant>>,
+    /// Task forwarding responses until the flow goes idle
+    task: JoinHandle<()>,
 }
 
 impl UdpRelay {
@@ -33,31 +100,184 @@ impl UdpRelay {
         Self {
             buffer_pool,
             socket_pool: Arc::new(UdpSocketPool::new()),
+            max_payload: MAX_UDP_DATAGRAM,
+            flows: Arc::new(DashMap::new()),
+            flow_mode: None,
+            dscp: None,
+            dns_cache: None,
+            address_guard: None,
+        }
+    }
+
+    /// Reject responses larger than `max` bytes
+    pub fn with_max_payload(mut self, max: usize) -> Self {
+        self.max_payload = max;
+        self
+    }
+
+    /// Mark packets to targets with the DSCP value `dscp`
+    pub fn with_dscp(mut self, dscp: Option<u8>) -> Self {
+        self.dscp = dscp;
+        self
+    }
+
+    /// Resolve targets through `cache` instead of asking the system each time
+    pub fn with_dns_cache(mut self, cache: Option<Arc<DnsCache>>) -> Self {
+        self.dns_cache = cache;
+        self
+    }
+
+    /// Only relay to resolved addresses `guard` permits
+    pub fn with_address_guard(mut self, guard: Option<AddressGuard>) -> Self {
+        self.address_guard = guard;
+        self
+    }
+
+    /// Keep flows open and pass every response to `sink` until the flow
+    /// has seen no traffic for `idle_timeout`
+    ///
+    /// Use [`relay_flow`](Self::relay_flow) instead of
+    /// [`relay_packet`](Self::relay_packet) once enabled.
+    pub fn with_flows(mut self, idle_timeout: Duration, sink: FlowResponseSink) -> Self {
+        self.flow_mode = Some((idle_timeout, sink));
+        self
+    }
+
+    /// Whether flow mode is enabled
+    pub fn flows_enabled(&self) -> bool {
+        self.flow_mode.is_some()
+    }
+
+    /// Send a packet on flow `flow_id` to `host:port`, opening the flow if needed
+    ///
+    /// Responses are delivered to the flow mode sink, not returned. A new
+    /// flow is bound to `source_ip` if given; open flows keep their address.
+    pub async fn relay_flow(
+        &self,
+        flow_id: u32,
+        host: &str,
+        port: u16,
+        data: &[u8],
+        source_ip: Option<IpAddr>,
+    ) -> Result<()> {
+        let Some((idle_timeout, sink)) = &self.flow_mode else {
+            anyhow::bail!("UDP flow mode is not enabled");
+        };
+
+        let key = FlowKey {
+            flow_id,
+            host: host.to_string(),
+            port,
+        };
+        let flow = match self.flows.get(&key) {
+            Some(flow) => flow.clone(),
+            None => {
+                let target = format!("{}:{}", host, port);
+                let dns_cache = self.dns_cache.as_deref();
+                let guard = self.address_guard.as_ref();
+                let target_addr = resolve(&target, source_ip, dns_cache, guard).await?;
+
+                self.flows
+                    .entry(key.clone())
+                    .or_try_insert_with(|| {
+                        self.open_flow(key, target_addr, source_ip, *idle_timeout, sink.clone())
+                            .map(Arc::new)
+                    })?
+                    .clone()
+            }
+        };
+
+        *flow.last_active.lock() = Instant::now();
+        flow.socket
+            .send(data)
+            .await
+            .context("Failed to send UDP packet")?;
+
+        Ok(())
+    }
+
+    /// Open a socket connected to `target` and spawn its response task
+    fn open_flow(
+        &self,
+        key: FlowKey,
+        target: SocketAddr,
+        source_ip: Option<IpAddr>,
+        idle_timeout: Duration,
+        sink: FlowResponseSink,
+    ) -> Result<UdpFlow> {
+        let bind_addr = local_bind_addr(target, source_ip, 0);
+
+        // Set up synchronously so the flow can be created under the map entry lock
+        let socket = std::net::UdpSocket::bind(bind_addr).context("Failed to bind UDP socket")?;
+        socket.connect(target).context("Failed to connect UDP socket")?;
+        socket.set_nonblocking(true)?;
+        if let Some(dscp) = self.dscp {
+            set_dscp(&socket, dscp)?;
         }
+        let socket = Arc::new(UdpSocket::from_std(socket)?);
+
+        let last_active = Arc::new(Mutex::new(Instant::now()));
+        let task = tokio::spawn(
+            FlowReceiver {
+                socket: socket.clone(),
+                last_active: last_active.clone(),
+                flows: self.flows.clone(),
+                key,
+                idle_timeout,
+                max_payload: self.max_payload,
+                sink,
+            }
+            .run(self.buffer_pool.clone()),
+        );
+
+        Ok(UdpFlow {
+            socket,
+            last_active,
+            task,
+        })
     }
 
     /// Relay a single UDP packet and wait for response
-    pub async fn relay_packet(&self, target: &str, data: &[u8]) -> Result<Vec<u8>> {
+    ///
+    /// The packet leaves from `source_ip` if given. Fails with
+    /// [`OversizedResponse`] if the response exceeds the max payload.
+    pub async fn relay_packet(
+        &self,
+        target: &str,
+        data: &[u8],
+        source_ip: Option<IpAddr>,
+    ) -> Result<Vec<u8>> {
         // Resolve target address
-        let target_addr: SocketAddr = tokio::net::lookup_host(target)
-            .await?
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("Failed to resolve {}", target))?;
+        let dns_cache = self.dns_cache.as_deref();
+        let guard = self.address_guard.as_ref();
+        let target_addr = resolve(target, source_ip, dns_cache, guard).await?;
 
         // Get or create socket
-        let socket = self.socket_pool.get_or_create(target_addr).await?;
+        let socket = self.socket_pool.get_or_create(target_addr, source_ip, self.dscp).await?;
 
         // Send packet
+        let started = Instant::now();
         socket
             .send_to(data, target_addr)
             .await
             .context("Failed to send UDP packet")?;
 
         // Wait for response with timeout
-        let mut response_buf = vec![0u8; 65536];
+        let mut response_buf = vec![0u8; MAX_UDP_DATAGRAM];
         let timeout = Duration::from_secs(5);
 
-        match tokio::time::timeout(timeout, socket.recv_from(&mut response_buf)).await {
+        let received = tokio::time::timeout(timeout, socket.recv_from(&mut response_buf)).await;
+        if let Ok(Ok(_)) = received {
+            metrics::histogram!("mytunnel_udp_relay_rtt_seconds")
+                .record(started.elapsed().as_secs_f64());
+        }
+
+        match received {
+            Ok(Ok((n, _))) if n > self.max_payload => Err(OversizedResponse {
+                len: n,
+                max: self.max_payload,
+            }
+            .into()),
             Ok(Ok((n, _))) => {
                 response_buf.truncate(n);
                 Ok(response_buf)
@@ -68,33 +288,272 @@ impl UdpRelay {
     }
 
     /// Relay multiple packets in a batch (for efficiency)
+    ///
+    /// Packets are grouped by destination socket and sent with one
+    /// sendmmsg() call per [`MAX_BATCH_SIZE`] packets. Returns how many
+    /// packets were sent; a send error skips the rest of that destination.
+    /// Packets to addresses the address guard refuses are dropped. Packets
+    /// leave from `source_ip` if given; those to a destination of the other
+    /// address family are dropped too.
     #[cfg(target_os = "linux")]
-    pub async fn relay_batch(&self, packets: &[(SocketAddr, &[u8])]) -> Result<usize> {
+    pub async fn relay_batch(
+        &self,
+        packets: &[(SocketAddr, &[u8])],
+        source_ip: Option<IpAddr>,
+    ) -> Result<usize> {
+        use std::os::unix::io::AsRawFd;
+
         if packets.is_empty() {
             return Ok(0);
         }
 
-        // For batch sending, we use sendmmsg
-        // This requires a single destination, so group by target
-        // For simplicity in MVP, we send individually but could optimize later
+        // Each target has its own pooled socket, so group by target
+        let mut groups: Vec<(SocketAddr, Vec<OutgoingPacket<'_>>)> = Vec::new();
+        for &(target, data) in packets {
+            match groups.iter_mut().find(|(t, _)| *t == target) {
+                Some((_, group)) => group.push((target, data)),
+                None => groups.push((target, vec![(target, data)])),
+            }
+        }
 
         let mut sent = 0;
-        for (target, data) in packets.iter().take(MAX_BATCH_SIZE) {
-            let socket = self.socket_pool.get_or_create(*target).await?;
-            if socket.send_to(data, target).await.is_ok() {
-                sent += 1;
-                METRICS.datagram_tx();
+        for (target, group) in groups {
+            if self.address_guard.as_ref().is_some_and(|guard| !guard.permits(target.ip())) {
+                debug!(target = %target, packets = group.len(), "Batched UDP target blocked");
+                continue;
+            }
+            if source_ip.is_some_and(|ip| ip.is_ipv4() != target.is_ipv4()) {
+                debug!(
+                    target = %target,
+                    packets = group.len(),
+                    "Batched UDP target unreachable from the egress address"
+                );
+                continue;
+            }
+            let socket = self.socket_pool.get_or_create(target, source_ip, self.dscp).await?;
+            let sender = BatchedUdpSender::from_raw_fd(socket.as_raw_fd());
+
+            for chunk in group.chunks(MAX_BATCH_SIZE) {
+                match send_all(&socket, &sender, chunk).await {
+                    Ok(n) => sent += n,
+                    Err((n, e)) => {
+                        sent += n;
+                        debug!(target = %target, error = %e, "Batched UDP send failed");
+                        break;
+                    }
+                }
             }
         }
 
+        for _ in 0..sent {
+            METRICS.datagram_tx();
+        }
+
         Ok(sent)
     }
 }
 
+/// Send every packet in `chunk`, retrying the remainder after partial sends
+///
+/// On error, returns how many packets went out before it.
+#[cfg(target_os = "linux")]
+async fn send_all(
+    socket: &UdpSocket,
+    sender: &BatchedUdpSender,
+    chunk: &[OutgoingPacket<'_>],
+) -> std::result::Result<usize, (usize, std::io::Error)> {
+    let mut sent = 0;
+    while sent < chunk.len() {
+        let result = socket
+            .async_io(tokio::io::Interest::WRITABLE, || sender.send_batch(&chunk[sent..]))
+            .await;
+        match result {
+            Ok(0) => {
+                let e = std::io::Error::new(std::io::ErrorKind::WriteZero, "sendmmsg sent nothing");
+                return Err((sent, e));
+            }
+            Ok(n) => sent += n,
+            Err(e) => return Err((sent, e)),
+        }
+    }
+    Ok(sent)
+}
+
+impl Drop for UdpRelay {
+    fn drop(&mut self) {
+        for flow in self.flows.iter() {
+            flow.task.abort();
+        }
+        self.flows.clear();
+    }
+}
+
+/// Forwards every datagram received on a flow to its sink
+struct FlowReceiver {
+    socket: Arc<UdpSocket>,
+    last_active: Arc<Mutex<Instant>>,
+    flows: Arc<FlowTable>,
+    key: FlowKey,
+    idle_timeout: Duration,
+    max_payload: usize,
+    sink: FlowResponseSink,
+}
+
+impl FlowReceiver {
+    /// Forward responses until the flow has been idle for `idle_timeout`,
+    /// then remove it from the flow table
+    ///
+    /// On Linux, queued responses are drained with recvmmsg() into
+    /// buffers from `buffer_pool`.
+    #[cfg(target_os = "linux")]
+    async fn run(self, buffer_pool: BufferPool) {
+        use std::os::unix::io::AsRawFd;
+
+        let receiver = BatchedUdpReceiver::from_raw_fd(self.socket.as_raw_fd());
+        // One slot is enough to make progress; the rest are taken only if
+        // the pool has them to spare
+        let Some(first) = buffer_pool.acquire_timeout(self.max_payload, self.idle_timeout).await
+        else {
+            warn!(
+                flow_id = self.key.flow_id,
+                host = %self.key.host,
+                port = self.key.port,
+                "No receive buffer for UDP flow, closing"
+            );
+            self.close();
+            return;
+        };
+        let spare = (1..FLOW_RECV_BATCH)
+            .map_while(|_| buffer_pool.acquire_or_alloc(self.max_payload));
+        let mut slots: Vec<_> = std::iter::once(first).chain(spare).map(RecvSlot::new).collect();
+
+        while let Some(wait) = self.idle_remaining() {
+            let recv = self.socket.async_io(tokio::io::Interest::READABLE, || {
+                receiver.recv_batch(&mut slots)
+            });
+            match tokio::time::timeout(wait, recv).await {
+                Ok(Ok(n)) => {
+                    for slot in &slots[..n] {
+                        self.deliver(slot.len, slot.data());
+                    }
+                }
+                Ok(Err(e)) => {
+                    self.receive_failed(e);
+                    break;
+                }
+                // Re-check: a packet may have been sent while we waited
+                Err(_) => continue,
+            }
+        }
+
+        self.close();
+    }
+
+    /// Forward responses until the flow has been idle for `idle_timeout`,
+    /// then remove it from the flow table
+    #[cfg(not(target_os = "linux"))]
+    async fn run(self, _buffer_pool: BufferPool) {
+        let mut buf = vec![0u8; MAX_UDP_DATAGRAM];
+
+        while let Some(wait) = self.idle_remaining() {
+            match tokio::time::timeout(wait, self.socket.recv(&mut buf)).await {
+                Ok(Ok(n)) => self.deliver(n, &buf[..n]),
+                Ok(Err(e)) => {
+                    self.receive_failed(e);
+                    break;
+                }
+                // Re-check: a packet may have been sent while we waited
+                Err(_) => continue,
+            }
+        }
+
+        self.close();
+    }
+
+    /// Time left before the flow counts as idle, if any
+    fn idle_remaining(&self) -> Option<Duration> {
+        let idle_for = self.last_active.lock().elapsed();
+        self.idle_timeout
+            .checked_sub(idle_for)
+            .filter(|wait| !wait.is_zero())
+    }
+
+    /// Pass a `len`-byte response to the sink, dropping it if oversized
+    fn deliver(&self, len: usize, data: &[u8]) {
+        let FlowKey { flow_id, ref host, port } = self.key;
+        if len > self.max_payload {
+            warn!(
+                flow_id,
+                host = %host,
+                port,
+                error = %OversizedResponse { len, max: self.max_payload },
+                "Dropping oversized UDP response"
+            );
+            return;
+        }
+
+        *self.last_active.lock() = Instant::now();
+        (self.sink)(flow_id, host, port, data);
+    }
+
+    fn receive_failed(&self, e: std::io::Error) {
+        debug!(
+            flow_id = self.key.flow_id,
+            host = %self.key.host,
+            port = self.key.port,
+            error = %e,
+            "UDP flow receive error"
+        );
+    }
+
+    fn close(self) {
+        debug!(
+            flow_id = self.key.flow_id,
+            host = %self.key.host,
+            port = self.key.port,
+            "UDP flow idle, closing"
+        );
+        self.flows.remove(&self.key);
+    }
+}
+
+/// Resolve `target`, preferring an address of the same family as `source_ip`
+///
+/// Goes through `dns_cache` if given, otherwise asks the system. Addresses
+/// `guard` refuses are skipped, failing with [`BlockedAddress`] if none
+/// are left.
+async fn resolve(
+    target: &str,
+    source_ip: Option<IpAddr>,
+    dns_cache: Option<&DnsCache>,
+    guard: Option<&AddressGuard>,
+) -> Result<SocketAddr> {
+    let mut addrs: Vec<SocketAddr> = match dns_cache {
+        Some(cache) => cache.lookup(target).await?,
+        None => tokio::net::lookup_host(target).await?.collect(),
+    };
+    if let Some(guard) = guard {
+        let first = addrs.first().map(|addr| addr.ip());
+        addrs.retain(|addr| guard.permits(addr.ip()));
+        if let (true, Some(ip)) = (addrs.is_empty(), first) {
+            return Err(BlockedAddress(ip).into());
+        }
+    }
+    let mut addrs = addrs.into_iter();
+    match source_ip {
+        Some(ip) => addrs
+            .find(|addr| addr.is_ipv4() == ip.is_ipv4())
+            .ok_or_else(|| anyhow::anyhow!("No address for {} reachable from {}", target, ip)),
+        None => addrs
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Failed to resolve {}", target)),
+    }
+}
+
 /// Socket pool for UDP connections
 struct UdpSocketPool {
-    /// Map of target -> (socket, last_used)
-    sockets: DashMap<SocketAddr, (Arc<UdpSocket>, Instant)>,
+    /// Map of (target, source IP) -> (socket, last_used)
+    sockets: DashMap<(SocketAddr, Option<IpAddr>), (Arc<UdpSocket>, Instant)>,
 }
 
 impl UdpSocketPool {
@@ -104,10 +563,16 @@ impl UdpSocketPool {
         }
     }
 
-    /// Get or create a socket for the target
-    async fn get_or_create(&self, target: SocketAddr) -> Result<Arc<UdpSocket>> {
+    /// Get or create a socket for the target, bound to `source_ip` if given
+    /// and marked with `dscp` when created
+    async fn get_or_create(
+        &self,
+        target: SocketAddr,
+        source_ip: Option<IpAddr>,
+        dscp: Option<u8>,
+    ) -> Result<Arc<UdpSocket>> {
         // Check existing socket
-        if let Some(entry) = self.sockets.get(&target) {
+        if let Some(entry) = self.sockets.get(&(target, source_ip)) {
             let (socket, last_used) = entry.value();
             if last_used.elapsed() < SOCKET_TTL {
                 return Ok(socket.clone());
@@ -115,18 +580,15 @@ impl UdpSocketPool {
         }
 
         // Create new socket
-        let bind_addr: SocketAddr = if target.is_ipv4() {
-            "0.0.0.0:0".parse().unwrap()
-        } else {
-            "[::]:0".parse().unwrap()
-        };
-
-        let socket = UdpSocket::bind(bind_addr)
+        let socket = UdpSocket::bind(local_bind_addr(target, source_ip, 0))
             .await
             .context("Failed to bind UDP socket")?;
+        if let Some(dscp) = dscp {
+            set_dscp(&socket, dscp)?;
+        }
 
         let socket = Arc::new(socket);
-        self.sockets.insert(target, (socket.clone(), Instant::now()));
+        self.sockets.insert((target, source_ip), (socket.clone(), Instant::now()));
 
         // Cleanup old sockets periodically
         self.cleanup_stale();
@@ -144,20 +606,23 @@ impl UdpSocketPool {
 
 /// Batched UDP sender using sendmmsg (Linux only)
 #[cfg(target_os = "linux")]
-#[allow(dead_code)]
 pub struct BatchedUdpSender {
     socket: std::os::unix::io::RawFd,
 }
 
 #[cfg(target_os = "linux")]
-#[allow(dead_code)]
 impl BatchedUdpSender {
     /// Create from raw file descriptor
+    ///
+    /// The sender borrows `fd`; the caller keeps the socket open.
     pub fn from_raw_fd(fd: std::os::unix::io::RawFd) -> Self {
         Self { socket: fd }
     }
 
     /// Send multiple packets in a single syscall
+    ///
+    /// Sends at most [`MAX_BATCH_SIZE`] packets and may send fewer than
+    /// requested; returns the number sent.
     pub fn send_batch(&self, packets: &[(SocketAddr, &[u8])]) -> std::io::Result<usize> {
         use libc::{mmsghdr, msghdr, iovec, sendmmsg, sockaddr_storage, socklen_t};
         use std::mem::MaybeUninit;
@@ -257,6 +722,99 @@ impl BatchedUdpSender {
     }
 }
 
+/// A receive buffer and the datagram last read into it
+#[cfg(target_os = "linux")]
+pub struct RecvSlot<B> {
+    pub buf: B,
+    /// Full length of the datagram; larger than `buf` if it was truncated
+    pub len: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl<B: DerefMut<Target = [u8]>> RecvSlot<B> {
+    pub fn new(buf: B) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// The received bytes that fit in the buffer
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len.min(self.buf.len())]
+    }
+}
+
+/// Batched UDP receiver using recvmmsg (Linux only)
+#[cfg(target_os = "linux")]
+pub struct BatchedUdpReceiver {
+    socket: std::os::unix::io::RawFd,
+}
+
+#[cfg(target_os = "linux")]
+impl BatchedUdpReceiver {
+    /// Create from raw file descriptor
+    ///
+    /// The receiver borrows `fd`; the caller keeps the socket open.
+    pub fn from_raw_fd(fd: std::os::unix::io::RawFd) -> Self {
+        Self { socket: fd }
+    }
+
+    /// Receive queued datagrams into `bufs` in a single syscall
+    ///
+    /// Never blocks: fails with `WouldBlock` if nothing is queued. Reads at
+    /// most [`MAX_BATCH_SIZE`] datagrams and returns how many arrived; each
+    /// filled slot's `len` is set to its datagram's length.
+    pub fn recv_batch<B: DerefMut<Target = [u8]>>(
+        &self,
+        bufs: &mut [RecvSlot<B>],
+    ) -> std::io::Result<usize> {
+        use libc::{iovec, mmsghdr, recvmmsg, MSG_DONTWAIT, MSG_TRUNC};
+        use std::ptr;
+
+        let batch_size = bufs.len().min(MAX_BATCH_SIZE);
+        if batch_size == 0 {
+            return Ok(0);
+        }
+
+        let mut iovecs: Vec<iovec> = bufs[..batch_size]
+            .iter_mut()
+            .map(|slot| iovec {
+                iov_base: slot.buf.as_mut_ptr() as *mut _,
+                iov_len: slot.buf.len(),
+            })
+            .collect();
+
+        let mut msgs: Vec<mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iov| {
+                let mut msg: mmsghdr = unsafe { std::mem::zeroed() };
+                msg.msg_hdr.msg_iov = iov;
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+
+        // MSG_TRUNC reports the full length of datagrams that didn't fit
+        let result = unsafe {
+            recvmmsg(
+                self.socket,
+                msgs.as_mut_ptr(),
+                batch_size as _,
+                (MSG_DONTWAIT | MSG_TRUNC) as _,
+                ptr::null_mut(),
+            )
+        };
+
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let received = result as usize;
+        for (slot, msg) in bufs.iter_mut().zip(&msgs).take(received) {
+            slot.len = msg.msg_len as usize;
+        }
+        Ok(received)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -267,13 +825,187 @@ mod tests {
         let _relay = UdpRelay::new(pool);
     }
 
+    /// Start a UDP origin that answers every packet with `size` bytes
+    async fn origin_replying(size: usize) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((_, peer)) = socket.recv_from(&mut buf).await {
+                let _ = socket.send_to(&vec![0xAB; size], peer).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_oversized_response_rejected() {
+        let relay = UdpRelay::new(BufferPool::new(10, 5, 2)).with_max_payload(1350);
+
+        let origin = origin_replying(2000).await;
+        let err = relay.relay_packet(&origin.to_string(), b"ping", None).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<OversizedResponse>(),
+            Some(&OversizedResponse { len: 2000, max: 1350 })
+        );
+
+        let origin = origin_replying(1350).await;
+        let response = relay.relay_packet(&origin.to_string(), b"ping", None).await.unwrap();
+        assert_eq!(response.len(), 1350);
+    }
+
+    #[tokio::test]
+    async fn test_flow_forwards_every_response() {
+        // Origin that answers each packet with three datagrams
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let origin = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((_, peer)) = socket.recv_from(&mut buf).await {
+                for reply in [&b"one"[..], b"two", b"three"] {
+                    let _ = socket.send_to(reply, peer).await;
+                }
+            }
+        });
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let sink: FlowResponseSink =
+            Arc::new(move |flow_id: u32, host: &str, port: u16, payload: &[u8]| {
+                let _ = tx.send((flow_id, host.to_string(), port, payload.to_vec()));
+            });
+        let relay = UdpRelay::new(BufferPool::new(10, 5, 2))
+            .with_flows(Duration::from_millis(200), sink);
+
+        relay.relay_flow(7, "127.0.0.1", origin.port(), b"ping", None).await.unwrap();
+
+        let mut replies = Vec::new();
+        for _ in 0..3 {
+            let (flow_id, host, port, payload) = rx.recv().await.unwrap();
+            assert_eq!((flow_id, host.as_str(), port), (7, "127.0.0.1", origin.port()));
+            replies.push(payload);
+        }
+        assert_eq!(replies, [&b"one"[..], b"two", b"three"]);
+        assert_eq!(relay.flows.len(), 1);
+
+        // The flow closes once idle
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(relay.flows.is_empty());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_relay_batch() {
+        let a = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+
+        // More than one sendmmsg() worth for `a`, interleaved with `b`
+        let payloads: Vec<Vec<u8>> = (0..100u8).map(|i| vec![i]).collect();
+        let packets: Vec<(SocketAddr, &[u8])> = payloads
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (if i % 4 == 0 { b_addr } else { a_addr }, p.as_slice()))
+            .collect();
+
+        let relay = UdpRelay::new(BufferPool::new(10, 5, 2));
+        assert_eq!(relay.relay_batch(&packets, None).await.unwrap(), 100);
+
+        let mut buf = [0u8; 8];
+        for expected in (0..100u8).filter(|i| i % 4 != 0) {
+            let n = a.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], [expected]);
+        }
+        for expected in (0..100u8).step_by(4) {
+            let n = b.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], [expected]);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_relay_batch_address_guard() {
+        let allowed = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let blocked = UdpSocket::bind("127.0.0.2:0").await.unwrap();
+        let packets: Vec<(SocketAddr, &[u8])> = vec![
+            (blocked.local_addr().unwrap(), b"no"),
+            (allowed.local_addr().unwrap(), b"yes"),
+        ];
+
+        let guard = AddressGuard::new(vec!["127.0.0.1/32".parse().unwrap()]);
+        let relay = UdpRelay::new(BufferPool::new(10, 5, 2)).with_address_guard(Some(guard));
+        assert_eq!(relay.relay_batch(&packets, None).await.unwrap(), 1);
+
+        let mut buf = [0u8; 8];
+        let n = allowed.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"yes");
+        assert!(blocked.try_recv(&mut buf).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_relay_batch_source_ip() {
+        let target = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let v6 = "[::1]:9".parse().unwrap();
+        let packets: Vec<(SocketAddr, &[u8])> =
+            vec![(target.local_addr().unwrap(), b"egress"), (v6, b"other family")];
+
+        let relay = UdpRelay::new(BufferPool::new(10, 5, 2));
+        let source = IpAddr::from([127, 0, 0, 2]);
+        assert_eq!(relay.relay_batch(&packets, Some(source)).await.unwrap(), 1);
+
+        let mut buf = [0u8; 8];
+        let (n, from) = target.recv_from(&mut buf).await.unwrap();
+        assert_eq!((&buf[..n], from.ip()), (&b"egress"[..], source));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_recv_batch() {
+        use crate::pool::BufferSize;
+        use std::os::unix::io::AsRawFd;
+
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.connect(receiver.local_addr().unwrap()).unwrap();
+        for i in 0..10u8 {
+            sender.send(&[i; 3]).unwrap();
+        }
+
+        let batch = BatchedUdpReceiver::from_raw_fd(receiver.as_raw_fd());
+        let pool = BufferPool::new(16, 1, 1);
+        let mut slots: Vec<_> =
+            (0..16).map(|_| RecvSlot::new(pool.acquire(BufferSize::Small).unwrap())).collect();
+
+        // Naive recv would take 10 syscalls
+        let mut received = Vec::new();
+        let mut syscalls = 0;
+        while received.len() < 10 {
+            let n = batch.recv_batch(&mut slots).unwrap();
+            syscalls += 1;
+            received.extend(slots[..n].iter().map(|slot| slot.data().to_vec()));
+        }
+        assert!(syscalls < 10);
+        assert_eq!(received, (0..10u8).map(|i| vec![i; 3]).collect::<Vec<_>>());
+
+        // Nothing left: fails instead of blocking
+        let err = batch.recv_batch(&mut slots).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+
+        // Truncated datagrams report their full length
+        sender.send(&[7u8; 6000]).unwrap();
+        assert_eq!(batch.recv_batch(&mut slots).unwrap(), 1);
+        assert_eq!(slots[0].len, 6000);
+        assert_eq!(slots[0].data().len(), BufferSize::Small.as_usize());
+    }
+
     #[tokio::test]
     async fn test_socket_pool() {
         let pool = UdpSocketPool::new();
         let addr: SocketAddr = "8.8.8.8:53".parse().unwrap();
         
-        let socket1 = pool.get_or_create(addr).await.unwrap();
-        let socket2 = pool.get_or_create(addr).await.unwrap();
+        let socket1 = pool.get_or_create(addr, None, None).await.unwrap();
+        let socket2 = pool.get_or_create(addr, None, None).await.unwrap();
         
         // Should return same socket
         assert!(Arc::ptr_eq(&socket1, &socket2));
@@ -2,8 +2,10 @@
 //!
 //! Routes incoming requests to appropriate handlers.
 
+use parking_lot::RwLock;
 use std::net::SocketAddr;
 
+use super::guard::AddressGuard;
 use super::policy::{RouteDecision, RoutingPolicy};
 
 /// Request types
@@ -28,29 +30,45 @@ pub struct Request {
     pub target_port: u16,
     /// Source address
     pub source_addr: SocketAddr,
+    /// Ports the client's user may reach, replacing the policy's allowed
+    /// ports (see [`UserPolicy`](crate::connection::UserPolicy))
+    pub allowed_ports: Option<Vec<u16>>,
 }
 
 /// Routes requests based on policy
+///
+/// The policy can be replaced at runtime; requests already routed are
+/// unaffected.
 pub struct RequestRouter {
-    policy: RoutingPolicy,
+    policy: RwLock<RoutingPolicy>,
 }
 
 impl RequestRouter {
     /// Create a new router with default policy
     pub fn new() -> Self {
-        Self {
-            policy: RoutingPolicy::default(),
-        }
+        Self::with_policy(RoutingPolicy::default())
     }
 
     /// Create router with custom policy
     pub fn with_policy(policy: RoutingPolicy) -> Self {
-        Self { policy }
+        Self {
+            policy: RwLock::new(policy),
+        }
+    }
+
+    /// Replace the routing policy
+    pub fn set_policy(&self, policy: RoutingPolicy) {
+        *self.policy.write() = policy;
     }
 
     /// Route a request
     pub fn route(&self, request: &Request) -> RouteDecision {
-        self.policy.decide(request)
+        self.policy.read().decide(request)
+    }
+
+    /// Guard for the addresses targets resolve to, if private ranges are blocked
+    pub fn address_guard(&self) -> Option<AddressGuard> {
+        self.policy.read().address_guard.clone()
     }
 
     /// Check if target is allowed
@@ -78,9 +96,28 @@ mod tests {
             target_host: "example.com".to_string(),
             target_port: 443,
             source_addr: "127.0.0.1:12345".parse().unwrap(),
+            allowed_ports: None,
         };
 
         assert!(router.is_allowed(&request));
     }
+
+    #[test]
+    fn test_set_policy() {
+        let router = RequestRouter::new();
+        let request = Request {
+            request_type: RequestType::TcpConnect,
+            target_host: "example.com".to_string(),
+            target_port: 25,
+            source_addr: "127.0.0.1:12345".parse().unwrap(),
+            allowed_ports: None,
+        };
+
+        router.set_policy(RoutingPolicy {
+            blocked_ports: vec![25],
+            ..Default::default()
+        });
+        assert!(!router.is_allowed(&request));
+    }
 }
 
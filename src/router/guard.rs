@@ -0,0 +1,91 @@
+//! Private address guard
+//!
+//! Keeps clients from reaching the server's own network (loopback, RFC 1918,
+//! link-local such as cloud metadata at 169.254.169.254, unique-local). The
+//! proxies check the addresses a target resolved to, so a host name that
+//! resolves to a private address is refused just like the address itself.
+
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// Returned when every address a target resolved to is private
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Target address {0} is in a private range")]
+pub struct BlockedAddress(pub IpAddr);
+
+/// Refuses private addresses, except those in an allowlist
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AddressGuard {
+    /// Private ranges clients may still reach
+    allowed: Vec<IpNet>,
+}
+
+impl AddressGuard {
+    /// Refuse private addresses outside `allowed`
+    pub fn new(allowed: Vec<IpNet>) -> Self {
+        Self { allowed }
+    }
+
+    /// Whether a client may connect to `ip`
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        !is_private(ip) || self.allowed.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Whether `ip` belongs to the local host or a private network
+///
+/// Covers unspecified, loopback, RFC 1918, link-local and unique-local
+/// addresses. IPv4-mapped IPv6 addresses count as their IPv4 address.
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => {
+            // 0.0.0.0/8 reaches the local host on Linux
+            v4.octets()[0] == 0 || v4.is_loopback() || v4.is_private() || v4.is_link_local()
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            v6.is_unspecified()
+                || v6.is_loopback()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_private() {
+        for ip in [
+            "127.0.0.1",
+            "0.0.0.0",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(is_private(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["93.184.216.34", "172.32.0.1", "8.8.8.8", "2001:db8::1", "::ffff:1.1.1.1"] {
+            assert!(!is_private(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn test_guard_allowlist() {
+        let guard = AddressGuard::new(vec!["10.0.5.0/24".parse().unwrap()]);
+        assert!(guard.permits("10.0.5.7".parse().unwrap()));
+        assert!(guard.permits("::ffff:10.0.5.7".parse().unwrap()));
+        assert!(!guard.permits("10.0.6.7".parse().unwrap()));
+        assert!(!guard.permits("169.254.169.254".parse().unwrap()));
+        assert!(guard.permits("1.1.1.1".parse().unwrap()));
+    }
+}
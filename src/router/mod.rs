@@ -3,8 +3,10 @@
 //! Routes requests based on target and policy.
 
 mod dispatcher;
+mod guard;
 mod policy;
 
-pub use dispatcher::RequestRouter;
-pub use policy::RoutingPolicy;
+pub use dispatcher::{Request, RequestRouter, RequestType};
+pub use guard::{is_private, AddressGuard, BlockedAddress};
+pub use policy::{RouteDecision, RoutingPolicy};
 
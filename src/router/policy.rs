@@ -2,7 +2,12 @@
 //!
 //! Defines rules for routing decisions.
 
+use ipnet::IpNet;
+use std::net::IpAddr;
+
 use super::dispatcher::Request;
+use super::guard::AddressGuard;
+use crate::config::{RoutingConfig, SourceRule};
 
 /// Route decision
 #[derive(Debug, Clone)]
@@ -28,10 +33,20 @@ pub struct RoutingPolicy {
     pub default_allow: bool,
     /// Blocked hosts (exact match)
     pub blocked_hosts: Vec<String>,
+    /// Blocked domain patterns (suffix match, see [`domain_matches`])
+    pub blocked_domains: Vec<String>,
+    /// Blocked IP ranges (only IP-literal targets are checked)
+    pub blocked_cidrs: Vec<IpNet>,
+    /// Refuses private addresses, when `block_private_ranges` is set
+    pub address_guard: Option<AddressGuard>,
     /// Blocked ports
     pub blocked_ports: Vec<u16>,
     /// Allowed ports only (if not empty)
     pub allowed_ports: Vec<u16>,
+    /// Per-client overrides of the host and port lists, first match wins
+    pub source_rules: Vec<SourceRule>,
+    /// Egress hint attached to allowed requests
+    pub egress: Option<String>,
 }
 
 impl Default for RoutingPolicy {
@@ -39,39 +54,102 @@ impl Default for RoutingPolicy {
         Self {
             default_allow: true,
             blocked_hosts: vec![],
+            blocked_domains: vec![],
+            blocked_cidrs: vec![],
+            address_guard: None,
             blocked_ports: vec![],
             allowed_ports: vec![], // Empty = all allowed
+            source_rules: vec![],
+            egress: None,
         }
     }
 }
 
 impl RoutingPolicy {
+    /// Build a policy from the `[routing]` config section
+    pub fn from_config(config: &RoutingConfig) -> Self {
+        Self {
+            default_allow: config.default_allow,
+            blocked_hosts: config.blocked_hosts.clone(),
+            blocked_domains: config.blocked_domains.clone(),
+            blocked_cidrs: config.blocked_cidrs.clone(),
+            address_guard: config
+                .block_private_ranges
+                .then(|| AddressGuard::new(config.allowed_private_cidrs.clone())),
+            blocked_ports: config.blocked_ports.clone(),
+            allowed_ports: config.allowed_ports.clone(),
+            source_rules: config.source_rules.clone(),
+            egress: config.egress.clone(),
+        }
+    }
+
     /// Make a routing decision for a request
     pub fn decide(&self, request: &Request) -> RouteDecision {
+        // A rule for the client's range replaces the lists it sets
+        let source_ip = request.source_addr.ip().to_canonical();
+        let rule = self.source_rules.iter().find(|r| r.source.contains(&source_ip));
+        let blocked_hosts = rule
+            .and_then(|r| r.blocked_hosts.as_ref())
+            .unwrap_or(&self.blocked_hosts);
+        let blocked_ports = rule
+            .and_then(|r| r.blocked_ports.as_ref())
+            .unwrap_or(&self.blocked_ports);
+        let allowed_ports = request
+            .allowed_ports
+            .as_ref()
+            .or_else(|| rule.and_then(|r| r.allowed_ports.as_ref()))
+            .unwrap_or(&self.allowed_ports);
+        let default_allow = rule
+            .and_then(|r| r.default_allow)
+            .unwrap_or(self.default_allow);
+
         // Check blocked hosts
-        if self.blocked_hosts.iter().any(|h| h == &request.target_host) {
+        if blocked_hosts.iter().any(|h| h == &request.target_host) {
             return RouteDecision::Deny {
                 reason: "Host is blocked".to_string(),
             };
         }
 
+        // Check blocked domains
+        if self.blocked_domains.iter().any(|d| domain_matches(d, &request.target_host)) {
+            return RouteDecision::Deny {
+                reason: "Domain is blocked".to_string(),
+            };
+        }
+
+        // Check blocked ranges; domain names never match
+        if let Ok(ip) = request.target_host.parse::<IpAddr>() {
+            if self.blocked_cidrs.iter().any(|net| net.contains(&ip)) {
+                return RouteDecision::Deny {
+                    reason: "Address range is blocked".to_string(),
+                };
+            }
+            // Host names are checked by the proxies once resolved
+            if self.address_guard.as_ref().is_some_and(|guard| !guard.permits(ip)) {
+                return RouteDecision::Deny {
+                    reason: "Address is private".to_string(),
+                };
+            }
+        }
+
         // Check blocked ports
-        if self.blocked_ports.contains(&request.target_port) {
+        if blocked_ports.contains(&request.target_port) {
             return RouteDecision::Deny {
                 reason: "Port is blocked".to_string(),
             };
         }
 
         // Check allowed ports (if specified)
-        if !self.allowed_ports.is_empty() && !self.allowed_ports.contains(&request.target_port) {
+        if !allowed_ports.is_empty() && !allowed_ports.contains(&request.target_port) {
             return RouteDecision::Deny {
                 reason: "Port not in allowed list".to_string(),
             };
         }
 
         // Default decision
-        if self.default_allow {
-            RouteDecision::Allow { egress_hint: None }
+        if default_allow {
+            let egress_hint = rule.and_then(|r| r.egress.clone()).or_else(|| self.egress.clone());
+            RouteDecision::Allow { egress_hint }
         } else {
             RouteDecision::Deny {
                 reason: "Default deny policy".to_string(),
@@ -80,17 +158,59 @@ impl RoutingPolicy {
     }
 }
 
+/// Check `host` against a domain pattern, ignoring case and trailing dots
+///
+/// `*.example.com` matches any subdomain of `example.com`; a bare
+/// `example.com` matches itself as well. Matching is on whole labels, so
+/// `badexample.com` and `example.com.evil.com` never match.
+fn domain_matches(pattern: &str, host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+
+    let (suffix, include_self) = match pattern.strip_prefix("*.") {
+        Some(suffix) => (suffix, false),
+        None => (pattern.as_str(), true),
+    };
+    if suffix.is_empty() {
+        return false;
+    }
+
+    if host == suffix {
+        return include_self;
+    }
+    matches!(
+        host.strip_suffix(suffix),
+        Some(rest) if rest.len() > 1 && rest.ends_with('.')
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::router::dispatcher::RequestType;
 
     fn make_request(host: &str, port: u16) -> Request {
+        make_request_from("127.0.0.1:12345", host, port)
+    }
+
+    fn make_request_from(source: &str, host: &str, port: u16) -> Request {
         Request {
             request_type: RequestType::TcpConnect,
             target_host: host.to_string(),
             target_port: port,
-            source_addr: "127.0.0.1:12345".parse().unwrap(),
+            source_addr: source.parse().unwrap(),
+            allowed_ports: None,
+        }
+    }
+
+    fn source_rule(source: &str) -> SourceRule {
+        SourceRule {
+            source: source.parse().unwrap(),
+            default_allow: None,
+            blocked_hosts: None,
+            blocked_ports: None,
+            allowed_ports: None,
+            egress: None,
         }
     }
 
@@ -116,6 +236,80 @@ mod tests {
         assert!(matches!(policy.decide(&request), RouteDecision::Allow { .. }));
     }
 
+    #[test]
+    fn test_domain_matches() {
+        assert!(domain_matches("*.example.com", "ads.example.com"));
+        assert!(domain_matches("*.example.com", "a.b.example.com"));
+        assert!(!domain_matches("*.example.com", "example.com"));
+        assert!(!domain_matches("*.example.com", "example.com.evil.com"));
+        assert!(!domain_matches("*.example.com", "badexample.com"));
+
+        assert!(domain_matches("example.com", "example.com"));
+        assert!(domain_matches("example.com", "www.example.com"));
+        assert!(!domain_matches("example.com", "notexample.com"));
+
+        // Case and trailing dots are normalized
+        assert!(domain_matches("*.Example.COM.", "ADS.example.com."));
+        assert!(!domain_matches("*.", "example.com"));
+        assert!(!domain_matches("example.com", ".example.com"));
+    }
+
+    #[test]
+    fn test_blocked_domain() {
+        let policy = RoutingPolicy {
+            blocked_domains: vec!["*.doubleclick.net".to_string()],
+            ..Default::default()
+        };
+
+        let request = make_request("ad.doubleclick.net", 443);
+        assert!(matches!(policy.decide(&request), RouteDecision::Deny { .. }));
+
+        let request = make_request("doubleclick.net", 443);
+        assert!(matches!(policy.decide(&request), RouteDecision::Allow { .. }));
+    }
+
+    #[test]
+    fn test_blocked_cidr() {
+        let policy = RoutingPolicy {
+            blocked_cidrs: vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()],
+            ..Default::default()
+        };
+
+        let request = make_request("10.1.2.3", 443);
+        assert!(matches!(policy.decide(&request), RouteDecision::Deny { .. }));
+
+        let request = make_request("fd12::1", 443);
+        assert!(matches!(policy.decide(&request), RouteDecision::Deny { .. }));
+
+        let request = make_request("11.0.0.1", 443);
+        assert!(matches!(policy.decide(&request), RouteDecision::Allow { .. }));
+
+        // Domains are left to the exact-match list
+        let request = make_request("10.0.0.0.example.com", 443);
+        assert!(matches!(policy.decide(&request), RouteDecision::Allow { .. }));
+    }
+
+    #[test]
+    fn test_block_private_ranges() {
+        let open = RoutingPolicy::default();
+        let guarded = RoutingPolicy {
+            address_guard: Some(AddressGuard::new(vec!["10.9.0.0/16".parse().unwrap()])),
+            ..Default::default()
+        };
+
+        for host in ["10.1.2.3", "169.254.169.254", "127.0.0.1", "::1"] {
+            let request = make_request(host, 80);
+            assert!(matches!(open.decide(&request), RouteDecision::Allow { .. }));
+            assert!(matches!(guarded.decide(&request), RouteDecision::Deny { .. }), "{host}");
+        }
+        let request = make_request("10.9.8.7", 80);
+        assert!(matches!(guarded.decide(&request), RouteDecision::Allow { .. }));
+
+        // Names pass here and are checked once resolved
+        let request = make_request("localhost", 80);
+        assert!(matches!(guarded.decide(&request), RouteDecision::Allow { .. }));
+    }
+
     #[test]
     fn test_blocked_port() {
         let policy = RoutingPolicy {
@@ -129,5 +323,83 @@ mod tests {
         let request = make_request("example.com", 443);
         assert!(matches!(policy.decide(&request), RouteDecision::Allow { .. }));
     }
-}
 
+    #[test]
+    fn test_user_allowed_ports() {
+        let policy = RoutingPolicy {
+            blocked_ports: vec![25],
+            allowed_ports: vec![443],
+            ..Default::default()
+        };
+        let user_request = |port, allowed: Vec<u16>| Request {
+            allowed_ports: Some(allowed),
+            ..make_request("example.com", port)
+        };
+
+        // The user's list replaces the server's
+        assert!(matches!(policy.decide(&user_request(22, vec![22])), RouteDecision::Allow { .. }));
+        assert!(matches!(policy.decide(&user_request(443, vec![22])), RouteDecision::Deny { .. }));
+        assert!(matches!(policy.decide(&user_request(8080, vec![])), RouteDecision::Allow { .. }));
+        // Blocked ports stay blocked
+        assert!(matches!(policy.decide(&user_request(25, vec![25])), RouteDecision::Deny { .. }));
+    }
+
+    #[test]
+    fn test_source_rules() {
+        let policy = RoutingPolicy {
+            blocked_ports: vec![22],
+            source_rules: vec![
+                // Internal hosts may use SSH
+                SourceRule {
+                    blocked_ports: Some(vec![]),
+                    ..source_rule("10.0.0.0/8")
+                },
+                // Guests may only browse
+                SourceRule {
+                    allowed_ports: Some(vec![80, 443]),
+                    blocked_hosts: Some(vec!["intranet.local".to_string()]),
+                    ..source_rule("192.168.100.0/24")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let internal = make_request_from("10.1.1.1:40000", "git.local", 22);
+        assert!(matches!(policy.decide(&internal), RouteDecision::Allow { .. }));
+        // IPv4-mapped client addresses from a dual-stack socket still match
+        let internal = make_request_from("[::ffff:10.1.1.1]:40000", "git.local", 22);
+        assert!(matches!(policy.decide(&internal), RouteDecision::Allow { .. }));
+
+        let guest = make_request_from("192.168.100.7:40000", "git.local", 22);
+        assert!(matches!(policy.decide(&guest), RouteDecision::Deny { .. }));
+        let guest = make_request_from("192.168.100.7:40000", "intranet.local", 443);
+        assert!(matches!(policy.decide(&guest), RouteDecision::Deny { .. }));
+        let guest = make_request_from("192.168.100.7:40000", "example.com", 443);
+        assert!(matches!(policy.decide(&guest), RouteDecision::Allow { .. }));
+
+        // Everyone else gets the global lists
+        let other = make_request_from("203.0.113.5:40000", "git.local", 22);
+        assert!(matches!(policy.decide(&other), RouteDecision::Deny { .. }));
+    }
+
+    #[test]
+    fn test_egress_hint() {
+        let policy = RoutingPolicy {
+            egress: Some("wan1".to_string()),
+            source_rules: vec![SourceRule {
+                egress: Some("wan2".to_string()),
+                ..source_rule("10.0.0.0/8")
+            }],
+            ..Default::default()
+        };
+
+        let egress_of = |source: &str| {
+            match policy.decide(&make_request_from(source, "example.com", 443)) {
+                RouteDecision::Allow { egress_hint } => egress_hint,
+                decision => panic!("unexpected decision {:?}", decision),
+            }
+        };
+        assert_eq!(egress_of("10.0.0.1:40000").as_deref(), Some("wan2"));
+        assert_eq!(egress_of("192.0.2.1:40000").as_deref(), Some("wan1"));
+    }
+}
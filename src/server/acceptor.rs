@@ -5,21 +5,94 @@
 use anyhow::Result;
 use bytes::Bytes;
 use quinn::{Connection, Incoming, RecvStream, SendStream};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::task::JoinSet;
 use tracing::{debug, info, instrument, warn, Span};
 
-use crate::config::Config;
-use crate::connection::{ConnectionId, ConnectionManager};
+use crate::close_code::CloseCode;
+use crate::config::{Config, EgressConfig};
+use crate::connection::{ConnectionId, ConnectionManager, UserPolicy};
 use crate::metrics::METRICS;
 use crate::pool::BufferPool;
-use crate::proxy::{TcpProxy, UdpRelay};
+use crate::proxy::{
+    BandwidthLimiter, BuffersExhausted, ConnectTimeout, DnsCache, FlowResponseSink,
+    OutboundLimitExceeded, OversizedResponse, ProxyStats, TcpProxy, UdpRelay,
+};
+use crate::router::{BlockedAddress, Request, RequestRouter, RequestType, RouteDecision};
+
+use super::auth::{AuthResult, Authenticator};
+use super::limits::{HandshakePermit, PerIpBandwidth, StreamLimiter};
+use super::reverse::{
+    bind_in_range, ReverseClient, ReverseListener, BIND_PEER_ANY, REQUEST_BIND_ONCE,
+    REQUEST_BIND_REMOTE,
+};
+
+/// Control frame carrying the client's auth token: [0x02][len][token]
+const FRAME_AUTH: u8 = 0x02;
+/// How long a client has to authenticate after the handshake
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often a connection's client address is checked for migration
+const MIGRATION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Stream request type: TCP connect to a host name
+const REQUEST_TCP_DOMAIN: u8 = 0x01;
+/// Stream request type: TCP connect to an IPv4 address
+pub(super) const REQUEST_TCP_IPV4: u8 = 0x02;
+/// Stream request type: TCP connect to an IPv6 address
+pub(super) const REQUEST_TCP_IPV6: u8 = 0x03;
+
+/// Stream response status: request accepted
+pub(super) const STATUS_OK: u8 = 0x00;
+/// Stream response status: server cannot open more origin connections
+const STATUS_OUTBOUND_LIMIT: u8 = 0xFD;
+/// Stream response status: rate limited by routing policy
+const STATUS_RATE_LIMITED: u8 = 0xFE;
+/// Stream response status: request failed or denied, followed by a reason byte
+pub(super) const STATUS_ERROR: u8 = 0xFF;
+
+/// Failure reason: none of the below
+pub(super) const REASON_UNSPECIFIED: u8 = 0x00;
+/// Failure reason: target could not be resolved or reached
+const REASON_HOST_UNREACHABLE: u8 = 0x01;
+/// Failure reason: target refused the connection
+const REASON_CONNECTION_REFUSED: u8 = 0x02;
+/// Failure reason: denied by routing policy
+const REASON_POLICY_DENIED: u8 = 0x03;
+/// Failure reason: connect timed out
+pub(super) const REASON_TIMEOUT: u8 = 0x04;
+/// Failure reason: the connection already has `max_streams_per_conn` open
+const REASON_STREAM_LIMIT: u8 = 0x05;
+/// Failure reason: the server already runs `max_concurrent_streams` streams
+pub(super) const REASON_SERVER_BUSY: u8 = 0x06;
+
+/// Complete the handshake only to close the connection as at capacity
+///
+/// Dropping the `Incoming` would leave the client waiting for a handshake
+/// timeout; a close frame tells it why it was turned away.
+pub async fn reject_at_capacity(
+    incoming: Incoming,
+    handshake_permit: HandshakePermit,
+) -> Result<()> {
+    let connection = incoming.accept()?.await?;
+    drop(handshake_permit);
+
+    CloseCode::Capacity.close(&connection);
+    Ok(())
+}
 
 /// Handles a single QUIC connection
 pub struct ConnectionHandler {
     conn_manager: Arc<ConnectionManager>,
     buffer_pool: BufferPool,
-    #[allow(dead_code)]
+    router: Arc<RequestRouter>,
     config: Arc<Config>,
+    dns_cache: Option<Arc<DnsCache>>,
+    stream_limiter: StreamLimiter,
+    ip_bandwidth: Option<Arc<PerIpBandwidth>>,
+    authenticator: Option<Arc<dyn Authenticator>>,
 }
 
 impl ConnectionHandler {
@@ -27,39 +100,139 @@ impl ConnectionHandler {
     pub fn new(
         conn_manager: Arc<ConnectionManager>,
         buffer_pool: BufferPool,
+        router: Arc<RequestRouter>,
         config: Arc<Config>,
     ) -> Self {
         Self {
             conn_manager,
             buffer_pool,
+            router,
             config,
+            dns_cache: None,
+            stream_limiter: StreamLimiter::new(0),
+            ip_bandwidth: None,
+            authenticator: None,
         }
     }
 
+    /// Resolve proxy and relay targets through a shared DNS cache
+    ///
+    /// Without one, targets are looked up by the system resolver.
+    pub fn with_dns_cache(mut self, cache: Arc<DnsCache>) -> Self {
+        self.dns_cache = Some(cache);
+        self
+    }
+
+    /// Take a slot from the server-wide `limiter` for every stream
+    pub fn with_stream_limiter(mut self, limiter: StreamLimiter) -> Self {
+        self.stream_limiter = limiter;
+        self
+    }
+
+    /// Count each connection's traffic against its client IP's budget too
+    pub fn with_ip_bandwidth(mut self, budgets: Option<Arc<PerIpBandwidth>>) -> Self {
+        self.ip_bandwidth = budgets;
+        self
+    }
+
+    /// Require every connection to pass `authenticator` (None = open server)
+    pub fn with_authenticator(mut self, authenticator: Option<Arc<dyn Authenticator>>) -> Self {
+        self.authenticator = authenticator;
+        self
+    }
+
     /// Handle an incoming connection
-    #[instrument(skip(self, incoming), fields(client_addr))]
-    pub async fn handle(self, incoming: Incoming) -> Result<()> {
+    ///
+    /// `handshake_permit` holds a handshake slot and is released as soon
+    /// as the handshake completes or fails, before authentication.
+    ///
+    /// With 0-RTT enabled the client's early data (its auth frame) is
+    /// buffered during the handshake, but nothing is read until the
+    /// handshake completes: a replayed first flight never completes it, so
+    /// replayed early data can't open origin connections.
+    #[instrument(skip(self, incoming, handshake_permit), fields(client_addr))]
+    pub async fn handle(
+        self,
+        incoming: Incoming,
+        handshake_permit: HandshakePermit,
+    ) -> Result<()> {
         let client_addr = incoming.remote_address();
         Span::current().record("client_addr", client_addr.to_string());
 
         // Accept the connection
-        let connection = match incoming.await {
-            Ok(conn) => conn,
+        let connecting = match incoming.accept() {
+            Ok(connecting) => connecting,
             Err(e) => {
                 METRICS.connection_failed();
                 return Err(e.into());
             }
         };
+        let (connection, early_handshake) = if self.config.quic.enable_0rtt {
+            match connecting.into_0rtt() {
+                Ok((conn, handshake)) => (conn, Some(handshake)),
+                Err(connecting) => match connecting.await {
+                    Ok(conn) => (conn, None),
+                    Err(e) => {
+                        METRICS.connection_failed();
+                        return Err(e.into());
+                    }
+                },
+            }
+        } else {
+            match connecting.await {
+                Ok(conn) => (conn, None),
+                Err(e) => {
+                    METRICS.connection_failed();
+                    return Err(e.into());
+                }
+            }
+        };
+
+        // Only the handshake holds a slot, not a client slow to authenticate
+        if let Some(handshake) = early_handshake {
+            handshake.await;
+            if let Some(reason) = connection.close_reason() {
+                METRICS.connection_failed();
+                return Err(reason.into());
+            }
+        }
+        drop(handshake_permit);
+
+        // Check the client's token before the connection takes a slot
+        let mut policy = None;
+        if let Some(authenticator) = &self.authenticator {
+            match authenticate(&connection, authenticator.as_ref(), AUTH_TIMEOUT).await {
+                AuthResult::Allow => {}
+                AuthResult::AllowWith(user) => policy = Some(Arc::new(user)),
+                AuthResult::Deny => {
+                    warn!("Authentication failed");
+                    METRICS.auth_failure();
+                    METRICS.connection_rejected_auth();
+                    CloseCode::AuthFailed.close(&connection);
+                    return Ok(());
+                }
+            }
+        }
 
         // Register connection
-        let conn_id = match self.conn_manager.register(client_addr) {
+        let conn_id = match self.conn_manager.register(client_addr, connection.clone()) {
             Some(id) => id,
             None => {
                 warn!("Failed to register connection: pool full");
-                connection.close(quinn::VarInt::from_u32(1), b"server at capacity");
+                METRICS.connection_rejected_capacity();
+                CloseCode::Capacity.close(&connection);
                 return Ok(());
             }
         };
+        if let Some(policy) = policy {
+            if !self.conn_manager.set_policy(conn_id, policy.clone()) {
+                warn!(conn_id = %conn_id, user = %policy.user, "User connection limit reached");
+                METRICS.connection_rejected_capacity();
+                CloseCode::Capacity.close(&connection);
+                self.conn_manager.unregister(conn_id);
+                return Ok(());
+            }
+        }
 
         info!(conn_id = %conn_id, "Connection established");
         self.conn_manager.activate(conn_id);
@@ -85,29 +258,99 @@ impl ConnectionHandler {
     }
 
     /// Main connection handling loop
+    ///
+    /// On shutdown the connection stops taking new streams, lets the
+    /// in-flight ones finish, then closes.
     async fn handle_connection(
         &self,
         conn_id: ConnectionId,
         connection: Connection,
         shutdown_rx: &mut tokio::sync::broadcast::Receiver<()>,
     ) -> Result<()> {
+        // Limits of the user the client authenticated as replace the server's
+        let policy = self.conn_manager.get(conn_id).and_then(|state| state.policy.clone());
+
+        // One bandwidth budget for all streams on this connection, inside
+        // the one shared by every connection from the same IP
+        let max_bandwidth = policy
+            .as_ref()
+            .and_then(|policy| policy.max_bandwidth)
+            .unwrap_or(self.config.limits.max_bandwidth_per_conn);
+        let ip_bandwidth = self
+            .ip_bandwidth
+            .as_ref()
+            .and_then(|budgets| budgets.limiter(connection.remote_address().ip()));
+        let bandwidth = match (max_bandwidth, ip_bandwidth) {
+            (0, ip_bandwidth) => ip_bandwidth,
+            (max_bandwidth, ip_bandwidth) => {
+                Some(Arc::new(BandwidthLimiter::new(max_bandwidth).with_parent(ip_bandwidth)))
+            }
+        };
+        // UDP flows live as long as the connection
+        let udp_relay = Arc::new(self.udp_relay(conn_id, &connection));
+        let mut streams = JoinSet::new();
+        let mut draining = false;
+        let mut migration_check = tokio::time::interval_at(
+            tokio::time::Instant::now() + MIGRATION_CHECK_INTERVAL,
+            MIGRATION_CHECK_INTERVAL,
+        );
+
         loop {
+            if draining && streams.is_empty() {
+                debug!(conn_id = %conn_id, "Connection drained");
+                CloseCode::ServerShutdown.close(&connection);
+                break;
+            }
+
             tokio::select! {
                 // Handle bidirectional streams (TCP proxy requests)
-                stream = connection.accept_bi() => {
+                stream = connection.accept_bi(), if !draining => {
                     match stream {
-                        Ok((send, recv)) => {
+                        Ok((mut send, _recv)) if !self.conn_manager.try_open_stream(
+                            conn_id,
+                            self.config.quic.max_streams_per_conn,
+                        ) => {
+                            debug!(conn_id = %conn_id, "Stream rejected: stream limit reached");
+                            streams.spawn(async move {
+                                let _ = send.write_all(&[STATUS_ERROR, REASON_STREAM_LIMIT]).await;
+                                let _ = send.finish();
+                            });
+                        }
+                        Ok((mut send, recv)) => {
+                            let Some(permit) = self.stream_limiter.try_acquire() else {
+                                debug!(
+                                    conn_id = %conn_id,
+                                    "Stream rejected: server stream limit reached"
+                                );
+                                self.conn_manager.stream_closed(conn_id);
+                                streams.spawn(async move {
+                                    let status = [STATUS_ERROR, REASON_SERVER_BUSY];
+                                    let _ = send.write_all(&status).await;
+                                    let _ = send.finish();
+                                });
+                                continue;
+                            };
                             METRICS.stream_opened();
+                            let conn_manager = self.conn_manager.clone();
                             let handler = StreamHandler {
                                 conn_id,
+                                client_addr: connection.remote_address(),
+                                connection: connection.clone(),
                                 conn_manager: self.conn_manager.clone(),
                                 buffer_pool: self.buffer_pool.clone(),
+                                router: self.router.clone(),
+                                config: self.config.clone(),
+                                bandwidth: bandwidth.clone(),
+                                dns_cache: self.dns_cache.clone(),
+                                policy: policy.clone(),
                             };
-                            tokio::spawn(async move {
+                            streams.spawn(async move {
                                 if let Err(e) = handler.handle_stream(send, recv).await {
                                     debug!(error = %e, "Stream error");
                                 }
+                                conn_manager.stream_closed(conn_id);
                                 METRICS.stream_closed();
+                                drop(permit);
                             });
                         }
                         Err(quinn::ConnectionError::ApplicationClosed(_)) => {
@@ -129,7 +372,11 @@ impl ConnectionHandler {
                             let handler = DatagramHandler {
                                 conn_id,
                                 connection: connection.clone(),
-                                buffer_pool: self.buffer_pool.clone(),
+                                conn_manager: self.conn_manager.clone(),
+                                router: self.router.clone(),
+                                config: self.config.clone(),
+                                relay: udp_relay.clone(),
+                                policy: policy.clone(),
                             };
                             tokio::spawn(async move {
                                 if let Err(e) = handler.handle_datagram(data).await {
@@ -142,80 +389,464 @@ impl ConnectionHandler {
                         }
                         Err(e) => {
                             debug!(conn_id = %conn_id, error = %e, "Datagram receive error");
-                            // Continue - datagrams are unreliable
+                            // Continue - datagrams are unreliable - unless the
+                            // connection itself is gone
+                            if connection.close_reason().is_some() {
+                                break;
+                            }
                         }
                     }
                 }
 
+                // Reap finished streams
+                Some(_) = streams.join_next(), if !streams.is_empty() => {}
+
+                // Notice path changes, e.g. a phone moving from Wi-Fi to cellular
+                _ = migration_check.tick() => {
+                    self.conn_manager.update_client_addr(conn_id, connection.remote_address());
+                }
+
                 // Shutdown signal
-                _ = shutdown_rx.recv() => {
-                    info!(conn_id = %conn_id, "Shutdown signal received, closing connection");
-                    connection.close(quinn::VarInt::from_u32(0), b"server shutdown");
-                    break;
+                _ = shutdown_rx.recv(), if !draining => {
+                    info!(
+                        conn_id = %conn_id,
+                        streams = streams.len(),
+                        "Shutdown signal received, draining connection"
+                    );
+                    connection.set_max_concurrent_bi_streams(quinn::VarInt::from_u32(0));
+                    draining = true;
                 }
             }
         }
 
+        // Streams still running after the connection closed end on their own
+        streams.detach_all();
         Ok(())
     }
+
+    /// Build the UDP relay for a connection
+    ///
+    /// With a flow idle timeout configured, every response on a flow is
+    /// sent back as its own datagram.
+    fn udp_relay(&self, conn_id: ConnectionId, connection: &Connection) -> UdpRelay {
+        let relay = UdpRelay::new(self.buffer_pool.clone())
+            .with_max_payload(self.config.quic.max_udp_payload as usize)
+            .with_dscp(self.config.server.dscp)
+            .with_dns_cache(self.dns_cache.clone())
+            .with_address_guard(self.router.address_guard());
+
+        let idle_secs = self.config.quic.udp_flow_idle_timeout_secs;
+        if idle_secs == 0 {
+            return relay;
+        }
+
+        let connection = connection.clone();
+        let conn_manager = self.conn_manager.clone();
+        let sink: FlowResponseSink =
+            Arc::new(move |flow_id: u32, host: &str, port: u16, payload: &[u8]| {
+                let datagram = encode_datagram(flow_id, host, port, payload);
+                if connection.send_datagram(Bytes::from(datagram)).is_ok() {
+                    METRICS.datagram_tx();
+                    conn_manager.record_traffic(conn_id, 0, payload.len() as u64);
+                }
+            });
+        relay.with_flows(Duration::from_secs(idle_secs), sink)
+    }
 }
 
 /// Handles a single bidirectional stream (TCP tunnel request)
 struct StreamHandler {
     conn_id: ConnectionId,
-    #[allow(dead_code)]
+    client_addr: SocketAddr,
+    connection: Connection,
     conn_manager: Arc<ConnectionManager>,
     buffer_pool: BufferPool,
+    router: Arc<RequestRouter>,
+    config: Arc<Config>,
+    bandwidth: Option<Arc<BandwidthLimiter>>,
+    dns_cache: Option<Arc<DnsCache>>,
+    policy: Option<Arc<UserPolicy>>,
 }
 
 impl StreamHandler {
     /// Handle a bidirectional stream
     async fn handle_stream(self, mut send: SendStream, mut recv: RecvStream) -> Result<()> {
-        // Read request header (target address)
-        // Format: [1 byte type][2 bytes port][N bytes host]
-        let mut header = [0u8; 3];
-        recv.read_exact(&mut header).await?;
+        // A client that stalls mid-header would otherwise hold its slot
+        // until the stream idles out
+        let header_timeout = Duration::from_millis(self.config.proxy.header_timeout_ms);
+        let Ok(request) = tokio::time::timeout(header_timeout, read_request(&mut recv)).await
+        else {
+            debug!(conn_id = %self.conn_id, "Stream rejected: request header too slow");
+            METRICS.slow_header();
+            send.write_all(&[STATUS_ERROR, REASON_TIMEOUT]).await?;
+            let _ = send.finish();
+            return Ok(());
+        };
+        let target = match request {
+            Ok(StreamRequest::Connect(target)) => target,
+            Ok(StreamRequest::BindRemote(port)) => return self.bind_remote(send, recv, port).await,
+            Ok(StreamRequest::BindOnce(port, peer)) => {
+                return self.bind_once(send, recv, port, peer).await
+            }
+            Err(e) => {
+                if let Some(UnknownRequestType(request_type)) = e.downcast_ref() {
+                    warn!(request_type, "Unknown request type");
+                    send.write_all(&[STATUS_ERROR, REASON_UNSPECIFIED]).await?;
+                    return Ok(());
+                }
+                if e.is::<MalformedRequest>() {
+                    debug!(conn_id = %self.conn_id, error = %e, "Malformed stream request");
+                    send.write_all(&[STATUS_ERROR, REASON_UNSPECIFIED]).await?;
+                    let _ = send.finish();
+                    return Ok(());
+                }
+                return Err(e);
+            }
+        };
+
+        debug!(conn_id = %self.conn_id, target = %target, "Stream request");
+        let started = Instant::now();
+
+        let request = Request {
+            request_type: RequestType::TcpConnect,
+            target_host: target.host(),
+            target_port: target.port(),
+            source_addr: self.client_addr,
+            allowed_ports: self.policy.as_ref().and_then(|policy| policy.allowed_ports.clone()),
+        };
+        let decision = self.router.route(&request);
+        if let Some(status) = rejection_status(&decision) {
+            debug!(
+                conn_id = %self.conn_id,
+                target = %target,
+                ?decision,
+                "Stream request rejected by routing policy"
+            );
+            self.log_access(&target, started, "denied", status.get(1).copied(), None);
+            send.write_all(status).await?;
+            let _ = send.finish();
+            return Ok(());
+        }
+
+        let proxy_protocol = self.config.proxy.send_proxy_protocol.then_some(self.client_addr);
+        let proxy = self
+            .copy_proxy()
+            .with_egress_ports(self.config.proxy.egress_port_range())
+            .with_source_ip(egress_source(&self.config.egress, &decision))
+            .with_proxy_protocol(proxy_protocol)
+            .with_max_outbound(self.config.limits.max_outbound_connections)
+            .with_connect_timeout(Duration::from_secs(self.config.proxy.connect_timeout_secs))
+            .with_dscp(self.config.server.dscp)
+            .with_fast_open(self.config.proxy.tcp_fast_open)
+            .with_dns_cache(self.dns_cache.clone())
+            .with_address_guard(self.router.address_guard());
+
+        // Connect before acknowledging so failures reach the client
+        let connected = match &target {
+            TcpTarget::Domain(host, port) => {
+                proxy.connect(format!("{}:{}", host, port).as_str()).await
+            }
+            TcpTarget::Addr(addr) => proxy.connect(*addr).await,
+        };
+        let origin = match connected {
+            Ok(origin) => origin,
+            Err(e) => {
+                if e.is::<OutboundLimitExceeded>() {
+                    warn!(conn_id = %self.conn_id, "Outbound connection limit reached");
+                    self.log_access(&target, started, "outbound_limit", None, None);
+                    send.write_all(&[STATUS_OUTBOUND_LIMIT]).await?;
+                } else {
+                    let reason = connect_failure_reason(&e);
+                    let outcome = match reason {
+                        REASON_POLICY_DENIED => "denied",
+                        _ => "connect_failed",
+                    };
+                    self.log_access(&target, started, outcome, Some(reason), None);
+                    send.write_all(&[STATUS_ERROR, reason]).await?;
+                }
+                let _ = send.finish();
+                return Err(e);
+            }
+        };
+
+        send.write_all(&[STATUS_OK]).await?;
+        let stats = match proxy.proxy_connected(send, recv, origin).await {
+            Ok(stats) => stats,
+            Err(e) => {
+                self.log_access(&target, started, "error", None, None);
+                return Err(e);
+            }
+        };
+        self.conn_manager
+            .record_traffic(self.conn_id, stats.rx_bytes, stats.tx_bytes);
+        self.log_access(&target, started, "closed", None, Some(&stats));
+
+        Ok(())
+    }
+
+    /// Log how a stream to `target` ended under the "access" target
+    ///
+    /// `reason` is the code sent to the client with a rejection; `stats`
+    /// is only known for streams that were proxied to the end.
+    fn log_access(
+        &self,
+        target: &TcpTarget,
+        started: Instant,
+        outcome: &'static str,
+        reason: Option<u8>,
+        stats: Option<&ProxyStats>,
+    ) {
+        if !self.config.logging.access_log {
+            return;
+        }
+        info!(
+            target: "access",
+            conn_id = %self.conn_id,
+            client_addr = %self.client_addr,
+            target = %target,
+            outcome,
+            reason,
+            bytes_in = stats.map_or(0, |stats| stats.rx_bytes),
+            bytes_out = stats.map_or(0, |stats| stats.tx_bytes),
+            duration_ms = started.elapsed().as_millis() as u64,
+            "Stream closed"
+        );
+    }
 
-        let request_type = header[0];
-        let port = u16::from_be_bytes([header[1], header[2]]);
+    /// Proxy with the settings and limits every copy on this connection
+    /// shares, tunneled or reverse
+    fn copy_proxy(&self) -> TcpProxy {
+        TcpProxy::new(self.buffer_pool.clone())
+            .with_confirm_delivery(self.config.proxy.confirm_delivery)
+            .with_bandwidth_limiter(self.bandwidth.clone())
+            .with_idle_timeout(self.config.stream_idle_timeout())
+            .with_splice(self.config.proxy.splice)
+            .with_buffer_wait(Duration::from_millis(self.config.pool.acquire_timeout_ms))
+            .with_coalesce(
+                self.config.proxy.coalesce_bytes,
+                Duration::from_micros(self.config.proxy.coalesce_delay_us),
+            )
+    }
 
-        // Read host length and host
-        let mut host_len_buf = [0u8; 1];
-        recv.read_exact(&mut host_len_buf).await?;
-        let host_len = host_len_buf[0] as usize;
+    /// Listen on `port` for the client until it ends the request stream
+    async fn bind_remote(self, mut send: SendStream, recv: RecvStream, port: u16) -> Result<()> {
+        let Some(reverse) = self.listen(&mut send, port).await? else {
+            return Ok(());
+        };
+        let bound = reverse.listener.local_addr()?;
+        info!(conn_id = %self.conn_id, bind = %bound, "Reverse tunnel opened");
 
-        let mut host_buf = vec![0u8; host_len];
-        recv.read_exact(&mut host_buf).await?;
-        let host = String::from_utf8(host_buf)?;
+        let result = reverse.serve(recv).await;
+        info!(conn_id = %self.conn_id, bind = %bound, "Reverse tunnel closed");
+        let _ = send.finish();
+        result
+    }
 
+    /// Listen on `port` for one connection from `peer` (None = anyone) and
+    /// carry it on this stream
+    async fn bind_once(
+        self,
+        mut send: SendStream,
+        recv: RecvStream,
+        port: u16,
+        peer: Option<IpAddr>,
+    ) -> Result<()> {
+        let Some(reverse) = self.listen(&mut send, port).await? else {
+            return Ok(());
+        };
         debug!(
             conn_id = %self.conn_id,
-            request_type,
-            host = %host,
-            port,
-            "Stream request"
+            bind = %reverse.listener.local_addr()?,
+            "Waiting for bind connection"
         );
+        reverse.serve_once(send, recv, peer).await
+    }
 
-        match request_type {
-            // TCP connect request
-            0x01 => {
-                let target = format!("{}:{}", host, port);
-                
-                // Send acknowledgment
-                send.write_all(&[0x00]).await?; // Success
-                
-                // Start TCP proxy
-                let proxy = TcpProxy::new(self.buffer_pool.clone());
-                proxy.proxy_stream(send, recv, &target).await?;
-            }
-            // Unknown request type
-            _ => {
-                warn!(request_type, "Unknown request type");
-                send.write_all(&[0xFF]).await?; // Error
+    /// Bind a reverse tunnel listener on `port` and send the bound port
+    ///
+    /// Port 0 binds any free port in the reverse port range. Returns `None`
+    /// once the refusal is sent, when reverse tunnels are disabled, the
+    /// port is outside the range or it can't be bound.
+    async fn listen(&self, send: &mut SendStream, port: u16) -> Result<Option<ReverseListener>> {
+        let features = &self.config.features;
+        let ports = features.reverse_port_range();
+        let refusal = if !features.allow_reverse {
+            Some("disabled")
+        } else if port != 0 && !ports.contains(&port) {
+            Some("port outside reverse port range")
+        } else {
+            None
+        };
+        if let Some(refusal) = refusal {
+            debug!(conn_id = %self.conn_id, port, reason = refusal, "Reverse tunnel rejected");
+            send.write_all(&[STATUS_ERROR, REASON_POLICY_DENIED]).await?;
+            let _ = send.finish();
+            return Ok(None);
+        }
+
+        let listener = match bind_in_range(features.reverse_bind_ip, port, ports).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                send.write_all(&[STATUS_ERROR, REASON_UNSPECIFIED]).await?;
+                let _ = send.finish();
+                return Err(e.into());
             }
+        };
+        let [hi, lo] = listener.local_addr()?.port().to_be_bytes();
+        send.write_all(&[STATUS_OK, hi, lo]).await?;
+
+        let client = ReverseClient {
+            conn_id: self.conn_id,
+            client_addr: self.client_addr,
+            connection: self.connection.clone(),
+            conn_manager: self.conn_manager.clone(),
+            proxy: self.copy_proxy(),
+            access_log: self.config.logging.access_log,
+        };
+        Ok(Some(ReverseListener {
+            client: Arc::new(client),
+            listener,
+        }))
+    }
+}
+
+/// What a client asks for on a new stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StreamRequest {
+    /// Open a TCP connection and proxy the stream to it
+    Connect(TcpTarget),
+    /// Listen on a server port and hand connections back to the client
+    BindRemote(u16),
+    /// Listen on a server port for one connection, from the given IP if
+    /// any, and proxy it on the stream
+    BindOnce(u16, Option<IpAddr>),
+}
+
+/// Target of a TCP connect request
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TcpTarget {
+    /// Host name the server resolves
+    Domain(String, u16),
+    /// Address the client sent in raw form; no resolution needed
+    Addr(SocketAddr),
+}
+
+impl TcpTarget {
+    /// Host as seen by routing policy
+    fn host(&self) -> String {
+        match self {
+            TcpTarget::Domain(host, _) => host.clone(),
+            TcpTarget::Addr(addr) => addr.ip().to_string(),
         }
+    }
 
-        Ok(())
+    fn port(&self) -> u16 {
+        match self {
+            TcpTarget::Domain(_, port) => *port,
+            TcpTarget::Addr(addr) => addr.port(),
+        }
+    }
+}
+
+impl std::fmt::Display for TcpTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TcpTarget::Domain(host, port) => write!(f, "{}:{}", host, port),
+            TcpTarget::Addr(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+/// Stream request with a type byte this server doesn't understand
+#[derive(Debug, thiserror::Error)]
+#[error("unknown request type {0:#04x}")]
+struct UnknownRequestType(u8);
+
+/// Longest host name a domain request may carry, as in DNS
+const MAX_HOST_LEN: usize = 253;
+
+/// Why a domain request's host or a bind request's peer was refused
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+enum MalformedRequest {
+    /// Host length of zero
+    #[error("empty host")]
+    EmptyHost,
+    /// Host longer than [`MAX_HOST_LEN`]
+    #[error("host of {0} bytes is too long")]
+    HostTooLong(usize),
+    /// Host bytes are not valid UTF-8
+    #[error("host is not valid UTF-8")]
+    InvalidUtf8,
+    /// One-shot bind peer of a type other than any, IPv4 or IPv6
+    #[error("unknown bind peer type {0:#04x}")]
+    UnknownPeerType(u8),
+}
+
+/// Read a stream request header
+///
+/// Format: [1 byte type][2 bytes port] followed by, depending on type:
+/// - 0x01: [1 byte host len][N bytes host]
+/// - 0x02: [4 bytes IPv4 address]
+/// - 0x03: [16 bytes IPv6 address]
+/// - 0x10: nothing (bind remote)
+/// - 0x12: [1 byte peer type] and, for 0x02 or 0x03, the peer's address
+///   as above (bind once; peer type 0x00 takes any peer)
+///
+/// Fails with [`UnknownRequestType`] for any other type byte, and with
+/// [`MalformedRequest`] for a host that can't be a host name or an unknown
+/// peer type.
+async fn read_request<R: AsyncRead + Unpin>(recv: &mut R) -> Result<StreamRequest> {
+    let mut header = [0u8; 3];
+    recv.read_exact(&mut header).await?;
+
+    let request_type = header[0];
+    let port = u16::from_be_bytes([header[1], header[2]]);
+
+    match request_type {
+        REQUEST_TCP_DOMAIN => {
+            let host_len = recv.read_u8().await? as usize;
+            if host_len == 0 {
+                return Err(MalformedRequest::EmptyHost.into());
+            }
+            if host_len > MAX_HOST_LEN {
+                return Err(MalformedRequest::HostTooLong(host_len).into());
+            }
+            let mut host_buf = vec![0u8; host_len];
+            recv.read_exact(&mut host_buf).await?;
+            let host = String::from_utf8(host_buf).map_err(|_| MalformedRequest::InvalidUtf8)?;
+            Ok(StreamRequest::Connect(TcpTarget::Domain(host, port)))
+        }
+        REQUEST_TCP_IPV4 => {
+            let mut octets = [0u8; 4];
+            recv.read_exact(&mut octets).await?;
+            let addr = SocketAddr::new(Ipv4Addr::from(octets).into(), port);
+            Ok(StreamRequest::Connect(TcpTarget::Addr(addr)))
+        }
+        REQUEST_TCP_IPV6 => {
+            let mut octets = [0u8; 16];
+            recv.read_exact(&mut octets).await?;
+            let addr = SocketAddr::new(Ipv6Addr::from(octets).into(), port);
+            Ok(StreamRequest::Connect(TcpTarget::Addr(addr)))
+        }
+        REQUEST_BIND_REMOTE => Ok(StreamRequest::BindRemote(port)),
+        REQUEST_BIND_ONCE => {
+            let peer = match recv.read_u8().await? {
+                BIND_PEER_ANY => None,
+                REQUEST_TCP_IPV4 => {
+                    let mut octets = [0u8; 4];
+                    recv.read_exact(&mut octets).await?;
+                    Some(Ipv4Addr::from(octets).into())
+                }
+                REQUEST_TCP_IPV6 => {
+                    let mut octets = [0u8; 16];
+                    recv.read_exact(&mut octets).await?;
+                    Some(Ipv6Addr::from(octets).into())
+                }
+                other => return Err(MalformedRequest::UnknownPeerType(other).into()),
+            };
+            Ok(StreamRequest::BindOnce(port, peer))
+        }
+        _ => Err(UnknownRequestType(request_type).into()),
     }
 }
 
@@ -223,53 +854,1042 @@ impl StreamHandler {
 struct DatagramHandler {
     conn_id: ConnectionId,
     connection: Connection,
-    buffer_pool: BufferPool,
+    conn_manager: Arc<ConnectionManager>,
+    router: Arc<RequestRouter>,
+    config: Arc<Config>,
+    relay: Arc<UdpRelay>,
+    policy: Option<Arc<UserPolicy>>,
 }
 
 impl DatagramHandler {
     /// Handle a datagram
     async fn handle_datagram(self, data: Bytes) -> Result<()> {
-        // Parse datagram header
-        // Format: [2 bytes port][N bytes host][payload]
-        if data.len() < 4 {
-            return Ok(());
-        }
-
-        let port = u16::from_be_bytes([data[0], data[1]]);
-        let host_len = data[2] as usize;
-
-        if data.len() < 3 + host_len {
+        let Some(DatagramHeader {
+            flow_id,
+            port,
+            host,
+            payload,
+        }) = decode_datagram(self.conn_id, &data)
+        else {
             return Ok(());
-        }
-
-        let host = std::str::from_utf8(&data[3..3 + host_len])?;
-        let payload = &data[3 + host_len..];
-
+        };
         debug!(
             conn_id = %self.conn_id,
+            flow_id,
             host = %host,
             port,
             payload_len = payload.len(),
             "Datagram relay"
         );
 
+        let request = Request {
+            request_type: RequestType::UdpRelay,
+            target_host: host.to_string(),
+            target_port: port,
+            source_addr: self.connection.remote_address(),
+            allowed_ports: self.policy.as_ref().and_then(|policy| policy.allowed_ports.clone()),
+        };
+        let decision = self.router.route(&request);
+        if rejection_status(&decision).is_some() {
+            debug!(
+                conn_id = %self.conn_id,
+                host = %host,
+                port,
+                ?decision,
+                "Datagram rejected by routing policy"
+            );
+            return Ok(());
+        }
+
+        let source_ip = egress_source(&self.config.egress, &decision);
+
+        // Flow responses go back through the relay's sink
+        if self.relay.flows_enabled() {
+            self.relay.relay_flow(flow_id, host, port, payload, source_ip).await?;
+            self.conn_manager
+                .record_traffic(self.conn_id, payload.len() as u64, 0);
+            return Ok(());
+        }
+
         // Relay UDP packet
-        let relay = UdpRelay::new(self.buffer_pool.clone());
         let target = format!("{}:{}", host, port);
-        
-        if let Ok(response) = relay.relay_packet(&target, payload).await {
-            // Send response back through QUIC datagram
-            let mut response_buf = Vec::with_capacity(3 + host_len + response.len());
-            response_buf.extend_from_slice(&port.to_be_bytes());
-            response_buf.push(host_len as u8);
-            response_buf.extend_from_slice(host.as_bytes());
-            response_buf.extend_from_slice(&response);
-            
-            let _ = self.connection.send_datagram(Bytes::from(response_buf));
-            METRICS.datagram_tx();
+
+        match self.relay.relay_packet(&target, payload, source_ip).await {
+            Ok(response) => {
+                // Send response back through QUIC datagram
+                let response_buf = encode_datagram(flow_id, host, port, &response);
+                let _ = self.connection.send_datagram(Bytes::from(response_buf));
+                METRICS.datagram_tx();
+                self.conn_manager.record_traffic(
+                    self.conn_id,
+                    payload.len() as u64,
+                    response.len() as u64,
+                );
+            }
+            Err(e) if e.is::<OversizedResponse>() => {
+                warn!(
+                    conn_id = %self.conn_id,
+                    target = %target,
+                    error = %e,
+                    "Dropping oversized UDP response"
+                );
+            }
+            Err(_) => {}
         }
 
         Ok(())
     }
 }
 
+/// Read the auth frame from the client's first unidirectional stream and
+/// check its token with `authenticator`
+///
+/// Denies the client unless the frame arrives and is checked within
+/// `timeout`.
+async fn authenticate(
+    connection: &Connection,
+    authenticator: &dyn Authenticator,
+    timeout: Duration,
+) -> AuthResult {
+    let check = async {
+        let mut recv = connection.accept_uni().await?;
+        let mut header = [0u8; 2];
+        recv.read_exact(&mut header).await?;
+        if header[0] != FRAME_AUTH {
+            anyhow::bail!("Expected auth frame, got type {:#04x}", header[0]);
+        }
+        let mut presented = vec![0u8; header[1] as usize];
+        recv.read_exact(&mut presented).await?;
+        Ok(authenticator
+            .authenticate(&presented, connection.remote_address())
+            .await)
+    };
+
+    match tokio::time::timeout(timeout, check).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => {
+            debug!(error = %e, "Failed to read auth frame");
+            AuthResult::Deny
+        }
+        Err(_) => AuthResult::Deny,
+    }
+}
+
+/// Map a routing decision to the response sent back on rejection
+///
+/// Returns None when the request may proceed.
+fn rejection_status(decision: &RouteDecision) -> Option<&'static [u8]> {
+    match decision {
+        RouteDecision::Allow { .. } => None,
+        RouteDecision::Deny { .. } => Some(&[STATUS_ERROR, REASON_POLICY_DENIED]),
+        RouteDecision::RateLimited => Some(&[STATUS_RATE_LIMITED]),
+    }
+}
+
+/// Classify a failed origin connect into the reason byte sent after `STATUS_ERROR`
+///
+/// Anything that isn't a refusal or a timeout, including failed lookups,
+/// counts as unreachable.
+fn connect_failure_reason(err: &anyhow::Error) -> u8 {
+    for cause in err.chain() {
+        if cause.is::<ConnectTimeout>() {
+            return REASON_TIMEOUT;
+        }
+        if cause.is::<BlockedAddress>() {
+            return REASON_POLICY_DENIED;
+        }
+        if cause.is::<BuffersExhausted>() {
+            return REASON_SERVER_BUSY;
+        }
+        if let Some(io_err) = cause.downcast_ref::<std::io::Error>() {
+            match io_err.kind() {
+                std::io::ErrorKind::ConnectionRefused => return REASON_CONNECTION_REFUSED,
+                std::io::ErrorKind::TimedOut => return REASON_TIMEOUT,
+                _ => {}
+            }
+        }
+    }
+    REASON_HOST_UNREACHABLE
+}
+
+/// Local source IP for an allowed request's egress hint
+///
+/// Unknown interface names fall back to default routing with a warning.
+fn egress_source(egress: &EgressConfig, decision: &RouteDecision) -> Option<IpAddr> {
+    let RouteDecision::Allow {
+        egress_hint: Some(hint),
+    } = decision
+    else {
+        return None;
+    };
+
+    let source_ip = egress.interfaces.get(hint).copied();
+    if source_ip.is_none() {
+        warn!(egress = %hint, "Unknown egress interface, using default routing");
+    }
+    source_ip
+}
+
+/// Parsed relay datagram header
+#[derive(Debug, PartialEq, Eq)]
+struct DatagramHeader<'a> {
+    /// Client-chosen id echoed in responses so the client can route them
+    flow_id: u32,
+    port: u16,
+    host: &'a str,
+    payload: &'a [u8],
+}
+
+/// Reason a relay datagram was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+enum MalformedDatagram {
+    /// Shorter than the fixed header
+    #[error("datagram too short")]
+    TooShort,
+    /// Host length points past the end of the datagram
+    #[error("host length exceeds datagram")]
+    BadHostLength,
+    /// Host bytes are not valid UTF-8
+    #[error("host is not valid UTF-8")]
+    InvalidUtf8,
+}
+
+impl<'a> DatagramHeader<'a> {
+    /// Parse a relay datagram
+    ///
+    /// Format: [4 bytes flow id][2 bytes port][1 byte host len][N bytes host][payload]
+    fn parse(data: &'a [u8]) -> std::result::Result<Self, MalformedDatagram> {
+        if data.len() < 8 {
+            return Err(MalformedDatagram::TooShort);
+        }
+
+        let flow_id = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let port = u16::from_be_bytes([data[4], data[5]]);
+        let host_len = data[6] as usize;
+
+        if data.len() < 7 + host_len {
+            return Err(MalformedDatagram::BadHostLength);
+        }
+
+        let host = std::str::from_utf8(&data[7..7 + host_len])
+            .map_err(|_| MalformedDatagram::InvalidUtf8)?;
+
+        Ok(Self {
+            flow_id,
+            port,
+            host,
+            payload: &data[7 + host_len..],
+        })
+    }
+}
+
+/// Encode a relay response datagram in the same format as requests
+fn encode_datagram(flow_id: u32, host: &str, port: u16, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(7 + host.len() + payload.len());
+    buf.extend_from_slice(&flow_id.to_be_bytes());
+    buf.extend_from_slice(&port.to_be_bytes());
+    buf.push(host.len() as u8);
+    buf.extend_from_slice(host.as_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// Parse a relay datagram, counting and logging it if malformed
+fn decode_datagram(conn_id: ConnectionId, data: &[u8]) -> Option<DatagramHeader<'_>> {
+    match DatagramHeader::parse(data) {
+        Ok(header) => Some(header),
+        Err(reason) => {
+            METRICS.datagram_malformed();
+            debug!(conn_id = %conn_id, len = data.len(), %reason, "Malformed datagram dropped");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::RoutingPolicy;
+    use crate::server::auth::{AuthFuture, StaticToken};
+    use std::sync::atomic::Ordering;
+
+    fn assert_malformed(data: &[u8], expected: MalformedDatagram) {
+        assert_eq!(DatagramHeader::parse(data), Err(expected));
+
+        let before = METRICS.datagrams_malformed.load(Ordering::Relaxed);
+        assert!(decode_datagram(ConnectionId::from_raw(1), data).is_none());
+        assert!(METRICS.datagrams_malformed.load(Ordering::Relaxed) > before);
+    }
+
+    #[test]
+    fn test_datagram_too_short() {
+        assert_malformed(&[], MalformedDatagram::TooShort);
+        assert_malformed(&[0, 0, 0, 1, 0x00, 0x35, 0x01], MalformedDatagram::TooShort);
+    }
+
+    #[test]
+    fn test_datagram_bad_host_length() {
+        assert_malformed(&[0, 0, 0, 1, 0x00, 0x35, 0x10, b'a'], MalformedDatagram::BadHostLength);
+    }
+
+    #[test]
+    fn test_datagram_invalid_utf8() {
+        assert_malformed(
+            &[0, 0, 0, 1, 0x00, 0x35, 0x02, 0xff, 0xfe],
+            MalformedDatagram::InvalidUtf8,
+        );
+    }
+
+    #[test]
+    fn test_rejection_status() {
+        let allow = RouteDecision::Allow { egress_hint: None };
+        let deny = RouteDecision::Deny {
+            reason: "Host is blocked".to_string(),
+        };
+
+        assert_eq!(rejection_status(&allow), None);
+        assert_eq!(
+            rejection_status(&deny),
+            Some(&[STATUS_ERROR, REASON_POLICY_DENIED][..])
+        );
+        assert_eq!(
+            rejection_status(&RouteDecision::RateLimited),
+            Some(&[STATUS_RATE_LIMITED][..])
+        );
+    }
+
+    #[test]
+    fn test_connect_failure_reason() {
+        use std::io::{Error, ErrorKind};
+
+        let refused = anyhow::Error::from(Error::from(ErrorKind::ConnectionRefused))
+            .context("Failed to connect to 127.0.0.1:1");
+        assert_eq!(connect_failure_reason(&refused), REASON_CONNECTION_REFUSED);
+
+        let timeout = anyhow::Error::from(ConnectTimeout(Duration::from_secs(10)))
+            .context("Failed to connect to 192.0.2.1:80");
+        assert_eq!(connect_failure_reason(&timeout), REASON_TIMEOUT);
+        let timeout = anyhow::Error::from(Error::from(ErrorKind::TimedOut));
+        assert_eq!(connect_failure_reason(&timeout), REASON_TIMEOUT);
+
+        let unreachable = anyhow::Error::from(Error::from(ErrorKind::Other));
+        assert_eq!(connect_failure_reason(&unreachable), REASON_HOST_UNREACHABLE);
+        let lookup = anyhow::anyhow!("No addresses to connect to");
+        assert_eq!(connect_failure_reason(&lookup), REASON_HOST_UNREACHABLE);
+    }
+
+    #[test]
+    fn test_egress_source() {
+        let egress = EgressConfig {
+            interfaces: [("wan1".to_string(), "192.0.2.10".parse().unwrap())].into(),
+        };
+        let allow = |hint: Option<&str>| RouteDecision::Allow {
+            egress_hint: hint.map(str::to_string),
+        };
+
+        assert_eq!(
+            egress_source(&egress, &allow(Some("wan1"))),
+            Some("192.0.2.10".parse().unwrap())
+        );
+        assert_eq!(egress_source(&egress, &allow(Some("wan9"))), None);
+        assert_eq!(egress_source(&egress, &allow(None)), None);
+    }
+
+    #[test]
+    fn test_datagram_valid() {
+        let header = DatagramHeader::parse(&[0, 0, 1, 2, 0x00, 0x35, 0x01, b'a', b'x']).unwrap();
+        assert_eq!(header.flow_id, 0x0102);
+        assert_eq!(header.port, 53);
+        assert_eq!(header.host, "a");
+        assert_eq!(header.payload, b"x");
+
+        let encoded = encode_datagram(0x0102, "a", 53, b"x");
+        assert_eq!(DatagramHeader::parse(&encoded).unwrap(), header);
+    }
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let connect = |target| StreamRequest::Connect(target);
+
+        // Domain form, as sent by older clients
+        let request = read_request(&mut &b"\x01\x01\xbb\x0bexample.com"[..]).await.unwrap();
+        let target = TcpTarget::Domain("example.com".to_string(), 443);
+        assert_eq!(target.to_string(), "example.com:443");
+        assert_eq!(request, connect(target));
+
+        let request = read_request(&mut &[0x02, 0x1f, 0x90, 192, 168, 1, 1][..]).await.unwrap();
+        let target = TcpTarget::Addr("192.168.1.1:8080".parse().unwrap());
+        assert_eq!(target.host(), "192.168.1.1");
+        assert_eq!(request, connect(target));
+
+        let mut v6 = vec![0x03, 0x01, 0xbb];
+        v6.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        let request = read_request(&mut v6.as_slice()).await.unwrap();
+        let target = TcpTarget::Addr("[2001:db8::1]:443".parse().unwrap());
+        assert_eq!(target.to_string(), "[2001:db8::1]:443");
+        assert_eq!(request, connect(target));
+
+        let request = read_request(&mut &[0x10, 0x1f, 0x90][..]).await.unwrap();
+        assert_eq!(request, StreamRequest::BindRemote(8080));
+        let request = read_request(&mut &[0x12, 0x00, 0x00, 0x00][..]).await.unwrap();
+        assert_eq!(request, StreamRequest::BindOnce(0, None));
+        let request = read_request(&mut &[0x12, 0x00, 0x00, 0x02, 10, 0, 0, 5][..]).await.unwrap();
+        assert_eq!(request, StreamRequest::BindOnce(0, Some("10.0.0.5".parse().unwrap())));
+        let err = read_request(&mut &[0x12, 0x00, 0x00, 0x01][..]).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(MalformedRequest::UnknownPeerType(0x01))));
+
+        let err = read_request(&mut &[0x7f, 0x00, 0x50][..]).await.unwrap_err();
+        assert!(err.is::<UnknownRequestType>());
+        assert!(read_request(&mut &[0x02, 0x00, 0x50, 10, 0][..]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_request_malformed() {
+        let malformed = |data: Vec<u8>| async move {
+            let err = read_request(&mut data.as_slice()).await.unwrap_err();
+            err.downcast::<MalformedRequest>().ok()
+        };
+
+        assert_eq!(malformed(vec![0x01, 0x00, 0x50, 0]).await, Some(MalformedRequest::EmptyHost));
+        // Refused before waiting for the host bytes
+        assert_eq!(
+            malformed(vec![0x01, 0x00, 0x50, 254]).await,
+            Some(MalformedRequest::HostTooLong(254))
+        );
+        assert_eq!(
+            malformed(vec![0x01, 0x00, 0x50, 2, 0xff, 0xfe]).await,
+            Some(MalformedRequest::InvalidUtf8)
+        );
+
+        // Truncated headers are read errors, not malformed requests
+        assert_eq!(malformed(vec![0x01]).await, None);
+        assert_eq!(malformed(vec![0x01, 0x00, 0x50, 11, b'e']).await, None);
+
+        let mut longest = vec![0x01, 0x00, 0x50, MAX_HOST_LEN as u8];
+        longest.resize(4 + MAX_HOST_LEN, b'a');
+        assert!(read_request(&mut longest.as_slice()).await.is_ok());
+    }
+
+    /// Authenticate a fresh connection whose client sends `frame` on a uni stream
+    async fn authenticate_with(authenticator: &dyn Authenticator, frame: Option<&[u8]>) -> bool {
+        let pair = crate::util::testing::quic_pair().await;
+        if let Some(frame) = frame {
+            let mut send = pair.client.open_uni().await.unwrap();
+            send.write_all(frame).await.unwrap();
+            send.finish().unwrap();
+        }
+        let timeout = Duration::from_millis(200);
+        authenticate(&pair.server, authenticator, timeout).await != AuthResult::Deny
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let auth = StaticToken::new("s3cret");
+        assert!(authenticate_with(&auth, Some(b"\x02\x06s3cret")).await);
+        assert!(!authenticate_with(&auth, Some(b"\x02\x06s3creT")).await);
+        assert!(!authenticate_with(&auth, Some(b"\x02\x05s3cre")).await);
+        assert!(!authenticate_with(&auth, Some(b"\x01\x06s3cret")).await);
+        assert!(!authenticate_with(&auth, Some(b"\x02\x06s3")).await);
+        assert!(!authenticate_with(&auth, None).await);
+    }
+
+    /// Allows tokens on its list, remembering who asked
+    struct MockAuthenticator {
+        allowed: Vec<&'static [u8]>,
+        seen: parking_lot::Mutex<Vec<SocketAddr>>,
+    }
+
+    impl Authenticator for MockAuthenticator {
+        fn authenticate<'a>(&'a self, token: &'a [u8], client_addr: SocketAddr) -> AuthFuture<'a> {
+            Box::pin(async move {
+                self.seen.lock().push(client_addr);
+                // Stand in for a round trip to an auth service
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                if self.allowed.contains(&token) {
+                    AuthResult::Allow
+                } else {
+                    AuthResult::Deny
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_authenticator() {
+        let auth = MockAuthenticator {
+            allowed: vec![b"alice", b"bob"],
+            seen: parking_lot::Mutex::new(Vec::new()),
+        };
+        assert!(authenticate_with(&auth, Some(b"\x02\x05alice")).await);
+        assert!(authenticate_with(&auth, Some(b"\x02\x03bob")).await);
+        assert!(!authenticate_with(&auth, Some(b"\x02\x07mallory")).await);
+        // No frame means the backend is never asked
+        assert!(!authenticate_with(&auth, None).await);
+
+        let seen = auth.seen.lock();
+        assert_eq!(seen.len(), 3);
+        assert!(seen.iter().all(|addr| addr.ip().is_loopback()));
+    }
+
+    /// A handler allowing 2 streams per connection, and its connection manager
+    fn test_handler() -> (ConnectionHandler, Arc<ConnectionManager>) {
+        test_handler_with("")
+    }
+
+    /// Like [`test_handler`], with `extra` appended to the config
+    fn test_handler_with(extra: &str) -> (ConnectionHandler, Arc<ConnectionManager>) {
+        // Room for one TCP stream's two copy buffers
+        test_handler_with_pool(extra, BufferPool::new(1, 2, 1))
+    }
+
+    /// Like [`test_handler_with`], copying through `buffer_pool`
+    fn test_handler_with_pool(
+        extra: &str,
+        buffer_pool: BufferPool,
+    ) -> (ConnectionHandler, Arc<ConnectionManager>) {
+        let base = r#"
+            [server]
+            bind_addr = "127.0.0.1:0"
+            [quic]
+            max_streams_per_conn = 2
+            [tls]
+            cert_path = "cert.pem"
+            key_path = "key.pem"
+            [pool]
+            [metrics]
+            [logging]
+        "#;
+        let config: Config = toml::from_str(&format!("{base}\n{extra}")).unwrap();
+        let manager = ConnectionManager::new(crate::connection::ConnectionManagerConfig {
+            max_connections: 4,
+            idle_timeout: Duration::from_secs(30),
+        });
+        let router = RequestRouter::with_policy(RoutingPolicy::from_config(&config.routing));
+        let handler = ConnectionHandler::new(
+            manager.clone(),
+            buffer_pool,
+            Arc::new(router),
+            Arc::new(config),
+        );
+        (handler, manager)
+    }
+
+    #[tokio::test]
+    async fn test_drain_finishes_streams() {
+        let (handler, manager) = test_handler();
+        let pair = crate::util::testing::quic_pair().await;
+        let conn_id = manager
+            .register(pair.server.remote_address(), pair.server.clone())
+            .unwrap();
+        let mut shutdown_rx = manager.subscribe_shutdown();
+
+        // A stream whose request hasn't fully arrived yet
+        let (mut send, _recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_TCP_DOMAIN]).await.unwrap();
+
+        let server = pair.server.clone();
+        let handling = tokio::spawn(async move {
+            handler.handle_connection(conn_id, server, &mut shutdown_rx).await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        manager.signal_shutdown();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(pair.server.close_reason().is_none());
+
+        // The connection closes once its last stream ends
+        send.finish().unwrap();
+        handling.await.unwrap().unwrap();
+        assert!(pair.server.close_reason().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_stream_traffic_recorded() {
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            let (mut socket, _) = origin.accept().await.unwrap();
+            let mut request = [0u8; 4];
+            socket.read_exact(&mut request).await.unwrap();
+            socket.write_all(b"response").await.unwrap();
+        });
+
+        let (handler, manager) = test_handler();
+        let pair = crate::util::testing::quic_pair().await;
+        let conn_id = manager
+            .register(pair.server.remote_address(), pair.server.clone())
+            .unwrap();
+        let mut shutdown_rx = manager.subscribe_shutdown();
+        let server = pair.server.clone();
+        tokio::spawn(async move {
+            handler.handle_connection(conn_id, server, &mut shutdown_rx).await
+        });
+
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        let mut request = vec![REQUEST_TCP_IPV4];
+        request.extend_from_slice(&origin_addr.port().to_be_bytes());
+        request.extend_from_slice(&[127, 0, 0, 1]);
+        request.extend_from_slice(b"ping");
+        send.write_all(&request).await.unwrap();
+        send.finish().unwrap();
+
+        let reply = recv.read_to_end(64).await.unwrap();
+        assert_eq!(reply, b"\x00response");
+
+        // Traffic is recorded once the proxy returns, just after the stream ends
+        let recorded = async {
+            loop {
+                let info = manager.query_connections(&Default::default()).connections.remove(0);
+                if info.bytes_tx > 0 {
+                    break info;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let info = tokio::time::timeout(Duration::from_secs(5), recorded).await.unwrap();
+        assert_eq!(info.bytes_rx, 4);
+        assert_eq!(info.bytes_tx, 8);
+    }
+
+    #[tokio::test]
+    async fn test_stream_limit() {
+        let (handler, manager) = test_handler();
+        let pair = crate::util::testing::quic_pair().await;
+        let conn_id = manager
+            .register(pair.server.remote_address(), pair.server.clone())
+            .unwrap();
+        let mut shutdown_rx = manager.subscribe_shutdown();
+        let server = pair.server.clone();
+        tokio::spawn(async move {
+            handler.handle_connection(conn_id, server, &mut shutdown_rx).await
+        });
+
+        // Requests that never complete hold their streams open
+        let mut held = Vec::new();
+        for _ in 0..2 {
+            let (mut send, recv) = pair.client.open_bi().await.unwrap();
+            send.write_all(&[REQUEST_TCP_DOMAIN]).await.unwrap();
+            held.push((send, recv));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(manager.get(conn_id).unwrap().to_info().active_streams, 2);
+
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_TCP_DOMAIN]).await.unwrap();
+        let reply = recv.read_to_end(8).await.unwrap();
+        assert_eq!(reply, [STATUS_ERROR, REASON_STREAM_LIMIT]);
+
+        // Closing a held stream frees its slot
+        held.pop().unwrap().0.finish().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(manager.get(conn_id).unwrap().to_info().active_streams, 1);
+    }
+
+    /// Run `handler` on a fresh connection pair, returning the pair
+    async fn serve_pair(
+        handler: ConnectionHandler,
+        manager: &Arc<ConnectionManager>,
+    ) -> crate::util::testing::QuicPair {
+        serve_pair_as(handler, manager, None).await
+    }
+
+    /// Like [`serve_pair`], for a client authenticated with `policy`
+    async fn serve_pair_as(
+        handler: ConnectionHandler,
+        manager: &Arc<ConnectionManager>,
+        policy: Option<UserPolicy>,
+    ) -> crate::util::testing::QuicPair {
+        let pair = crate::util::testing::quic_pair().await;
+        let conn_id = manager
+            .register(pair.server.remote_address(), pair.server.clone())
+            .unwrap();
+        if let Some(policy) = policy {
+            assert!(manager.set_policy(conn_id, Arc::new(policy)));
+        }
+        let mut shutdown_rx = manager.subscribe_shutdown();
+        let server = pair.server.clone();
+        tokio::spawn(async move {
+            handler.handle_connection(conn_id, server, &mut shutdown_rx).await
+        });
+        pair
+    }
+
+    /// Time downloading 64 KiB from a local origin through a connection
+    /// authenticated with `policy`
+    async fn timed_download(policy: UserPolicy) -> Duration {
+        let (handler, manager) = test_handler();
+        let pair = serve_pair_as(handler, &manager, Some(policy)).await;
+        let started = Instant::now();
+        download(&pair, 64 * 1024).await;
+        started.elapsed()
+    }
+
+    /// Download `size` bytes from a fresh local origin through `pair`
+    async fn download(pair: &crate::util::testing::QuicPair, size: usize) {
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_addr = origin.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            let (mut socket, _) = origin.accept().await.unwrap();
+            socket.write_all(&vec![7u8; size]).await.unwrap();
+        });
+
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        let mut request = vec![REQUEST_TCP_IPV4];
+        request.extend_from_slice(&origin_addr.port().to_be_bytes());
+        request.extend_from_slice(&[127, 0, 0, 1]);
+        send.write_all(&request).await.unwrap();
+        send.finish().unwrap();
+
+        let reply = recv.read_to_end(2 * size).await.unwrap();
+        assert_eq!(reply.len(), 1 + size);
+    }
+
+    #[tokio::test]
+    async fn test_user_bandwidth_quota() {
+        // 1 Mbps: 64 KiB takes about half a second from an empty bucket
+        let throttled = timed_download(UserPolicy {
+            user: "free".to_string(),
+            max_bandwidth: Some(125_000),
+            ..Default::default()
+        })
+        .await;
+        let unlimited = timed_download(UserPolicy {
+            user: "paid".to_string(),
+            ..Default::default()
+        })
+        .await;
+
+        assert!(throttled >= Duration::from_millis(400), "{throttled:?}");
+        assert!(unlimited < Duration::from_millis(400), "{unlimited:?}");
+    }
+
+    #[tokio::test]
+    async fn test_ip_bandwidth_shared() {
+        // Two connections from 127.0.0.1 under one 1 Mbps budget: 32 KiB
+        // each takes about half a second, not a quarter
+        let budgets = Arc::new(PerIpBandwidth::new(125_000));
+        let mut pairs = Vec::new();
+        for _ in 0..2 {
+            let (handler, manager) = test_handler();
+            let handler = handler.with_ip_bandwidth(Some(budgets.clone()));
+            pairs.push(serve_pair(handler, &manager).await);
+        }
+
+        let started = Instant::now();
+        tokio::join!(download(&pairs[0], 32 * 1024), download(&pairs[1], 32 * 1024));
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(400), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn test_user_allowed_ports() {
+        let (handler, manager) = test_handler();
+        let policy = UserPolicy {
+            user: "web".to_string(),
+            allowed_ports: Some(vec![443]),
+            ..Default::default()
+        };
+        let pair = serve_pair_as(handler, &manager, Some(policy)).await;
+
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_TCP_IPV4, 0, 22, 127, 0, 0, 1]).await.unwrap();
+        let reply = recv.read_to_end(8).await.unwrap();
+        assert_eq!(reply, [STATUS_ERROR, REASON_POLICY_DENIED]);
+    }
+
+    #[tokio::test]
+    async fn test_outbound_limit() {
+        let extra = "[limits]\nmax_outbound_connections = 1";
+        let (handler, manager) = test_handler_with_pool(extra, BufferPool::new(1, 4, 1));
+        let pair = serve_pair(handler, &manager).await;
+        // An origin that accepts and stays silent, so open streams keep their slot
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let [hi, lo] = origin.local_addr().unwrap().port().to_be_bytes();
+
+        let mut streams = Vec::new();
+        let mut replies = Vec::new();
+        for _ in 0..2 {
+            let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+            send.write_all(&[REQUEST_TCP_IPV4, hi, lo, 127, 0, 0, 1]).await.unwrap();
+            let mut status = [0u8; 1];
+            recv.read_exact(&mut status).await.unwrap();
+            replies.push(status[0]);
+            streams.push((send, recv));
+        }
+
+        // Other tests share the outbound gauge, so the cap may be hit
+        // sooner, but two streams can never both get through
+        assert!(replies.iter().all(|&r| r == STATUS_OK || r == STATUS_OUTBOUND_LIMIT));
+        assert!(replies.contains(&STATUS_OUTBOUND_LIMIT), "{replies:?}");
+    }
+
+    #[tokio::test]
+    async fn test_bad_request_headers() {
+        let (handler, manager) = test_handler_with("[proxy]\nheader_timeout_ms = 100");
+        let pair = serve_pair(handler, &manager).await;
+
+        // One byte, then nothing: refused once the header timeout passes
+        let slow_before = METRICS.slow_headers.load(Ordering::Relaxed);
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_TCP_DOMAIN]).await.unwrap();
+        let reply = tokio::time::timeout(Duration::from_secs(5), recv.read_to_end(8))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply, [STATUS_ERROR, REASON_TIMEOUT]);
+        assert!(METRICS.slow_headers.load(Ordering::Relaxed) > slow_before);
+
+        // Half a host, then the client gives up: the stream just ends
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_TCP_DOMAIN, 0, 80, 11, b'e']).await.unwrap();
+        send.finish().unwrap();
+        assert!(!matches!(recv.read_to_end(8).await.as_deref(), Ok([STATUS_OK, ..])));
+
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_TCP_DOMAIN, 0, 80, 0]).await.unwrap();
+        let reply = recv.read_to_end(8).await.unwrap();
+        assert_eq!(reply, [STATUS_ERROR, REASON_UNSPECIFIED]);
+
+        // The connection's stream slots were all given back
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(manager.query_connections(&Default::default()).connections[0].active_streams, 0);
+    }
+
+    #[tokio::test]
+    async fn test_private_targets_refused() {
+        let (handler, manager) = test_handler_with("[routing]\nblock_private_ranges = true");
+        let pair = serve_pair(handler, &manager).await;
+
+        let domain = |host: &str| {
+            let mut request = vec![REQUEST_TCP_DOMAIN, 0, 22, host.len() as u8];
+            request.extend_from_slice(host.as_bytes());
+            request
+        };
+        let requests = [
+            domain("localhost"),
+            vec![REQUEST_TCP_IPV4, 0, 22, 127, 0, 0, 1],
+            vec![REQUEST_TCP_IPV4, 0, 80, 10, 0, 0, 1],
+            vec![REQUEST_TCP_IPV4, 0, 80, 169, 254, 169, 254],
+        ];
+        for request in requests {
+            let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+            send.write_all(&request).await.unwrap();
+            send.finish().unwrap();
+            let reply = recv.read_to_end(8).await.unwrap();
+            assert_eq!(reply, [STATUS_ERROR, REASON_POLICY_DENIED], "{request:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_remote_disabled() {
+        let (handler, manager) = test_handler();
+        let pair = serve_pair(handler, &manager).await;
+
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_BIND_REMOTE, 0, 0]).await.unwrap();
+        let reply = recv.read_to_end(8).await.unwrap();
+        assert_eq!(reply, [STATUS_ERROR, REASON_POLICY_DENIED]);
+    }
+
+    #[tokio::test]
+    async fn test_bind_remote_port_range() {
+        let (handler, manager) = test_handler_with(
+            "[features]\nallow_reverse = true\nreverse_bind_ip = \"127.0.0.1\"\n\
+             reverse_port_min = 47000\nreverse_port_max = 47099",
+        );
+        let pair = serve_pair(handler, &manager).await;
+
+        // Privileged and out-of-range ports are refused
+        for port in [22u16, 46999, 47100] {
+            let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+            let [hi, lo] = port.to_be_bytes();
+            send.write_all(&[REQUEST_BIND_REMOTE, hi, lo]).await.unwrap();
+            let reply = recv.read_to_end(8).await.unwrap();
+            assert_eq!(reply, [STATUS_ERROR, REASON_POLICY_DENIED], "port {port}");
+        }
+
+        // Any port binds within the range
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_BIND_REMOTE, 0, 0]).await.unwrap();
+        let mut reply = [0u8; 3];
+        recv.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[0], STATUS_OK);
+        let port = u16::from_be_bytes([reply[1], reply[2]]);
+        assert!((47000..=47099).contains(&port), "bound {port}");
+    }
+
+    #[tokio::test]
+    async fn test_bind_remote() {
+        use crate::server::reverse::REQUEST_REVERSE_CONNECT;
+        use tokio::io::AsyncWriteExt;
+
+        let (handler, manager) = test_handler_with(
+            "[features]\nallow_reverse = true\nreverse_bind_ip = \"127.0.0.1\"",
+        );
+        let pair = serve_pair(handler, &manager).await;
+
+        let (mut control, mut control_recv) = pair.client.open_bi().await.unwrap();
+        control.write_all(&[REQUEST_BIND_REMOTE, 0, 0]).await.unwrap();
+        let mut reply = [0u8; 3];
+        control_recv.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[0], STATUS_OK);
+        let port = u16::from_be_bytes([reply[1], reply[2]]);
+        assert_ne!(port, 0);
+
+        let mut peer = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        peer.write_all(b"ping").await.unwrap();
+
+        // The server hands the connection to the client on a stream it opens
+        let (mut send, mut recv) = pair.client.accept_bi().await.unwrap();
+        let mut header = [0u8; 3];
+        recv.read_exact(&mut header).await.unwrap();
+        assert_eq!(header, [REQUEST_REVERSE_CONNECT, reply[1], reply[2]]);
+        send.write_all(&[STATUS_OK]).await.unwrap();
+        let mut request = [0u8; 4];
+        recv.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request, b"ping");
+        send.write_all(b"pong").await.unwrap();
+        send.finish().unwrap();
+
+        let mut response = [0u8; 4];
+        peer.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"pong");
+
+        // Finishing the request stream closes the listener
+        control.finish().unwrap();
+        let closed = async {
+            while tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), closed).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reverse_traffic_limited() {
+        use crate::server::reverse::REQUEST_REVERSE_CONNECT;
+        use tokio::io::AsyncWriteExt;
+        const SIZE: usize = 64 * 1024;
+
+        // 1 Mbps: 64 KiB takes about half a second from an empty bucket
+        let (handler, manager) = test_handler_with(
+            "[features]\nallow_reverse = true\nreverse_bind_ip = \"127.0.0.1\"\n\
+             [limits]\nmax_bandwidth_per_conn = 125000",
+        );
+        let pair = serve_pair(handler, &manager).await;
+        let (mut control, mut control_recv) = pair.client.open_bi().await.unwrap();
+        control.write_all(&[REQUEST_BIND_REMOTE, 0, 0]).await.unwrap();
+        let mut reply = [0u8; 3];
+        control_recv.read_exact(&mut reply).await.unwrap();
+        let port = u16::from_be_bytes([reply[1], reply[2]]);
+
+        let tx_before = METRICS.bytes_sent.load(Ordering::Relaxed);
+        let mut peer = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        peer.write_all(&[7u8; SIZE]).await.unwrap();
+        peer.shutdown().await.unwrap();
+
+        let (mut send, mut recv) = pair.client.accept_bi().await.unwrap();
+        let mut header = [0u8; 3];
+        recv.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0], REQUEST_REVERSE_CONNECT);
+        let started = Instant::now();
+        send.write_all(&[STATUS_OK]).await.unwrap();
+        send.finish().unwrap();
+        let data = recv.read_to_end(2 * SIZE).await.unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(data.len(), SIZE);
+        assert!(elapsed >= Duration::from_millis(400), "{elapsed:?}");
+        assert!(METRICS.bytes_sent.load(Ordering::Relaxed) >= tx_before + SIZE as u64);
+    }
+
+    #[tokio::test]
+    async fn test_bind_once_port_range() {
+        let (handler, manager) = test_handler_with(
+            "[features]\nallow_reverse = true\nreverse_bind_ip = \"127.0.0.1\"\n\
+             reverse_port_min = 47100\nreverse_port_max = 47199",
+        );
+        let pair = serve_pair(handler, &manager).await;
+
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_BIND_ONCE, 0, 22, BIND_PEER_ANY]).await.unwrap();
+        let reply = recv.read_to_end(8).await.unwrap();
+        assert_eq!(reply, [STATUS_ERROR, REASON_POLICY_DENIED]);
+
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_BIND_ONCE, 0, 0, BIND_PEER_ANY]).await.unwrap();
+        let mut reply = [0u8; 3];
+        recv.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[0], STATUS_OK);
+        let port = u16::from_be_bytes([reply[1], reply[2]]);
+        assert!((47100..=47199).contains(&port), "bound {port}");
+    }
+
+    #[tokio::test]
+    async fn test_bind_once() {
+        use tokio::io::AsyncWriteExt;
+
+        let (handler, manager) = test_handler_with(
+            "[features]\nallow_reverse = true\nreverse_bind_ip = \"127.0.0.1\"",
+        );
+        let pair = serve_pair(handler, &manager).await;
+
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_BIND_ONCE, 0, 0, BIND_PEER_ANY]).await.unwrap();
+        let mut reply = [0u8; 3];
+        recv.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[0], STATUS_OK);
+        let port = u16::from_be_bytes([reply[1], reply[2]]);
+
+        // The second reply names the peer, then the stream carries its data
+        let mut peer = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let mut accepted = [0u8; 8];
+        recv.read_exact(&mut accepted).await.unwrap();
+        assert_eq!(accepted[..2], [STATUS_OK, REQUEST_TCP_IPV4]);
+        assert_eq!(accepted[2..6], [127, 0, 0, 1]);
+        assert_eq!(u16::from_be_bytes([accepted[6], accepted[7]]), peer_addr.port());
+
+        peer.write_all(b"ping").await.unwrap();
+        let mut request = [0u8; 4];
+        recv.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request, b"ping");
+        send.write_all(b"pong").await.unwrap();
+        send.finish().unwrap();
+        let mut response = [0u8; 4];
+        peer.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"pong");
+
+        // Only the first connection is taken
+        assert!(tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_once_expected_peer() {
+        let (handler, manager) = test_handler_with(
+            "[features]\nallow_reverse = true\nreverse_bind_ip = \"127.0.0.1\"",
+        );
+        let pair = serve_pair(handler, &manager).await;
+
+        let (mut send, mut recv) = pair.client.open_bi().await.unwrap();
+        send.write_all(&[REQUEST_BIND_ONCE, 0, 0, REQUEST_TCP_IPV4, 127, 0, 0, 2]).await.unwrap();
+        let mut reply = [0u8; 3];
+        recv.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[0], STATUS_OK);
+        let port = u16::from_be_bytes([reply[1], reply[2]]);
+
+        // Anyone else is turned away and the bind keeps waiting
+        let mut stranger = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut buf = [0u8; 1];
+        assert!(!matches!(stranger.read(&mut buf).await, Ok(1)));
+
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.2:0".parse().unwrap()).unwrap();
+        let _peer = socket.connect(([127, 0, 0, 1], port).into()).await.unwrap();
+        let mut accepted = [0u8; 8];
+        recv.read_exact(&mut accepted).await.unwrap();
+        assert_eq!(accepted[..6], [STATUS_OK, REQUEST_TCP_IPV4, 127, 0, 0, 2]);
+    }
+}
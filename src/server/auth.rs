@@ -0,0 +1,82 @@
+//! Client authentication backends
+//!
+//! The token a client presents in its auth frame is checked by an
+//! [`Authenticator`]. The server builds a [`StaticToken`] from `[auth]`;
+//! embedders can supply their own to validate against another service,
+//! and give each user its own limits with [`AuthResult::AllowWith`].
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use crate::connection::UserPolicy;
+
+/// Outcome of checking a client's token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthResult {
+    /// The client may open tunnels
+    Allow,
+    /// The client may open tunnels within its user's limits
+    AllowWith(UserPolicy),
+    /// The connection is closed with `authentication failed`
+    Deny,
+}
+
+/// Future returned by [`Authenticator::authenticate`]
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = AuthResult> + Send + 'a>>;
+
+/// Decides whether a client may use the tunnel
+///
+/// Called once per connection with the token from its auth frame. The
+/// call shares the auth timeout with reading the frame, so a backend that
+/// doesn't answer in time denies the client.
+pub trait Authenticator: Send + Sync {
+    fn authenticate<'a>(&'a self, token: &'a [u8], client_addr: SocketAddr) -> AuthFuture<'a>;
+}
+
+/// Allows clients presenting one shared secret
+pub struct StaticToken {
+    token: Vec<u8>,
+}
+
+impl StaticToken {
+    /// Allow clients presenting `token`
+    pub fn new(token: impl Into<Vec<u8>>) -> Self {
+        Self { token: token.into() }
+    }
+}
+
+impl Authenticator for StaticToken {
+    fn authenticate<'a>(&'a self, token: &'a [u8], _client_addr: SocketAddr) -> AuthFuture<'a> {
+        let result = if constant_time_eq(token, &self.token) {
+            AuthResult::Allow
+        } else {
+            AuthResult::Deny
+        };
+        Box::pin(std::future::ready(result))
+    }
+}
+
+/// Compare two byte strings without short-circuiting on the first mismatch
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_static_token() {
+        let auth = StaticToken::new("s3cret");
+        let addr = "127.0.0.1:5000".parse().unwrap();
+
+        assert_eq!(auth.authenticate(b"s3cret", addr).await, AuthResult::Allow);
+        assert_eq!(auth.authenticate(b"s3creT", addr).await, AuthResult::Deny);
+        assert_eq!(auth.authenticate(b"s3cre", addr).await, AuthResult::Deny);
+        assert_eq!(auth.authenticate(b"", addr).await, AuthResult::Deny);
+    }
+}
@@ -0,0 +1,297 @@
+//! TLS certificate selection and hot reload
+//!
+//! New handshakes are served a certificate from [`CertResolver`], chosen
+//! by the SNI server name; [`CertReloader`] swaps certificates when their
+//! files change, so rotations take effect without dropping established
+//! connections.
+
+use anyhow::{Context, Result};
+use parking_lot::{Mutex, RwLock};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Certificates presented to new handshakes
+///
+/// Clients get the certificate for their SNI server name, matched exactly
+/// or by a `*.domain` wildcard, and the default one otherwise.
+/// Connections keep the certificate they were established with.
+#[derive(Debug)]
+pub struct CertResolver {
+    default: RwLock<Arc<CertifiedKey>>,
+    /// Lowercased server name -> certificate
+    by_sni: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl CertResolver {
+    /// Present `default` to every handshake
+    pub fn new(default: CertifiedKey) -> Self {
+        Self {
+            default: RwLock::new(Arc::new(default)),
+            by_sni: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Present `key` from now on to handshakes for `sni`, or to those
+    /// matching no server name if `None`
+    pub fn set(&self, sni: Option<&str>, key: CertifiedKey) {
+        match sni {
+            Some(sni) => {
+                self.by_sni.write().insert(sni.to_ascii_lowercase(), Arc::new(key));
+            }
+            None => *self.default.write() = Arc::new(key),
+        }
+    }
+
+    /// Certificate for a handshake asking for `server_name`
+    fn select(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        if let Some(name) = server_name {
+            let name = name.to_ascii_lowercase();
+            let by_sni = self.by_sni.read();
+            let wildcard = name.split_once('.').map(|(_, domain)| format!("*.{}", domain));
+            let found = by_sni
+                .get(&name)
+                .or_else(|| wildcard.and_then(|w| by_sni.get(&w)));
+            if let Some(key) = found {
+                return key.clone();
+            }
+        }
+        self.default.read().clone()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.select(client_hello.server_name()))
+    }
+}
+
+/// Build a certificate and signing key from PEM, checking that they match
+pub fn certified_key(cert_pem: &[u8], key_pem: &[u8]) -> Result<CertifiedKey> {
+    let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut &cert_pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to parse certificates")?;
+
+    let key = rustls_pemfile::private_key(&mut &key_pem[..])
+        .context("Failed to parse private key")?
+        .ok_or_else(|| anyhow::anyhow!("No private key found in file"))?;
+
+    certified_key_der(certs, key)
+}
+
+/// Build a certificate and signing key from DER, checking that they match
+pub fn certified_key_der(
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<CertifiedKey> {
+    CertifiedKey::from_der(certs, key, &rustls::crypto::ring::default_provider())
+        .context("Invalid certificate or key")
+}
+
+/// Certificate and key files, served for `sni` or as the default
+#[derive(Debug, Clone)]
+pub struct CertFiles {
+    pub sni: Option<String>,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// A watched pair of files and the contents last loaded from them
+struct WatchedCert {
+    files: CertFiles,
+    /// Compared instead of mtimes, which copies and some deploy tools keep
+    loaded: Mutex<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Reloads certificate files into a [`CertResolver`] when they change
+pub struct CertReloader {
+    certs: Vec<WatchedCert>,
+    resolver: Arc<CertResolver>,
+}
+
+impl CertReloader {
+    /// Load every pair of `files`
+    ///
+    /// A pair without an SNI becomes the default certificate; `default` is
+    /// used when there is none.
+    pub async fn load(default: Option<CertifiedKey>, files: Vec<CertFiles>) -> Result<Self> {
+        let mut default = default;
+        let mut by_sni = Vec::new();
+        let mut certs = Vec::with_capacity(files.len());
+        for files in files {
+            let (cert_pem, key_pem) = read_pair(&files.cert_path, &files.key_path).await?;
+            let key = certified_key(&cert_pem, &key_pem)
+                .with_context(|| format!("Invalid certificate {}", files.cert_path.display()))?;
+            match &files.sni {
+                Some(sni) => by_sni.push((sni.clone(), key)),
+                None => default = Some(key),
+            }
+            certs.push(WatchedCert {
+                files,
+                loaded: Mutex::new((cert_pem, key_pem)),
+            });
+        }
+
+        let default = default.ok_or_else(|| anyhow::anyhow!("No default TLS certificate"))?;
+        let resolver = Arc::new(CertResolver::new(default));
+        for (sni, key) in by_sni {
+            resolver.set(Some(&sni), key);
+        }
+
+        Ok(Self { certs, resolver })
+    }
+
+    /// Resolver serving the loaded certificates
+    pub fn resolver(&self) -> Arc<CertResolver> {
+        self.resolver.clone()
+    }
+
+    /// Reload every pair whose files changed, returning whether any
+    /// certificate was replaced
+    ///
+    /// A pair that fails to parse or doesn't match leaves its current
+    /// certificate in place, so a half-finished rotation is retried on the
+    /// next check. The first such failure is returned after the other
+    /// pairs are checked.
+    pub async fn reload_if_changed(&self) -> Result<bool> {
+        let mut changed = false;
+        let mut failure = None;
+        for cert in &self.certs {
+            match self.reload_cert(cert).await {
+                Ok(replaced) => changed |= replaced,
+                Err(e) => {
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(changed),
+        }
+    }
+
+    async fn reload_cert(&self, cert: &WatchedCert) -> Result<bool> {
+        let files = &cert.files;
+        let (cert_pem, key_pem) = read_pair(&files.cert_path, &files.key_path).await?;
+        {
+            let loaded = cert.loaded.lock();
+            if loaded.0 == cert_pem && loaded.1 == key_pem {
+                return Ok(false);
+            }
+        }
+
+        let key = certified_key(&cert_pem, &key_pem)
+            .with_context(|| format!("Invalid certificate {}", files.cert_path.display()))?;
+        self.resolver.set(files.sni.as_deref(), key);
+        *cert.loaded.lock() = (cert_pem, key_pem);
+
+        info!(
+            cert = %files.cert_path.display(),
+            sni = files.sni.as_deref().unwrap_or("default"),
+            "TLS certificate reloaded"
+        );
+        Ok(true)
+    }
+
+    /// Check the files every `interval` until the task is aborted
+    pub fn spawn(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.reload_if_changed().await {
+                    warn!(error = %e, "TLS certificate reload failed, keeping the current one");
+                }
+            }
+        })
+    }
+}
+
+async fn read_pair(cert_path: &Path, key_path: &Path) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cert_pem = tokio::fs::read(cert_path)
+        .await
+        .with_context(|| format!("Failed to read certificate file {}", cert_path.display()))?;
+    let key_pem = tokio::fs::read(key_path)
+        .await
+        .with_context(|| format!("Failed to read key file {}", key_path.display()))?;
+    Ok((cert_pem, key_pem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scratch directory for certificate files
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("mytunnel-certs-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write a fresh self-signed certificate for `name`, returning its files and DER
+    fn write_cert(
+        dir: &Path,
+        name: &str,
+        sni: Option<&str>,
+    ) -> (CertFiles, CertificateDer<'static>) {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let files = CertFiles {
+            sni: sni.map(str::to_string),
+            cert_path: dir.join(format!("{}.pem", name)),
+            key_path: dir.join(format!("{}.key", name)),
+        };
+        std::fs::write(&files.cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&files.key_path, cert.key_pair.serialize_pem()).unwrap();
+        (files, cert.cert.der().clone())
+    }
+
+    #[tokio::test]
+    async fn test_select_by_sni() {
+        let dir = scratch_dir("sni");
+        let (default, default_der) = write_cert(&dir, "default", None);
+        let (a, a_der) = write_cert(&dir, "a.example.com", Some("a.example.com"));
+        let (b, b_der) = write_cert(&dir, "b.example.org", Some("*.example.org"));
+
+        let reloader = CertReloader::load(None, vec![default, a, b]).await.unwrap();
+        let presented = |name| reloader.resolver.select(name).cert[0].clone();
+        assert_eq!(presented(Some("a.example.com")), a_der);
+        assert_eq!(presented(Some("A.Example.com")), a_der);
+        assert_eq!(presented(Some("b.example.org")), b_der);
+        assert_eq!(presented(Some("x.b.example.org")), default_der);
+        assert_eq!(presented(Some("other.example.com")), default_der);
+        assert_eq!(presented(None), default_der);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_cert_on_mismatch() {
+        let dir = scratch_dir("reload");
+        let (files, first_der) = write_cert(&dir, "server", None);
+        let second = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+        let reloader = CertReloader::load(None, vec![files.clone()]).await.unwrap();
+        let presented = |r: &CertReloader| r.resolver.select(None).cert[0].clone();
+        assert!(!reloader.reload_if_changed().await.unwrap());
+
+        // New certificate but the old key: rejected, old pair still served
+        std::fs::write(&files.cert_path, second.cert.pem()).unwrap();
+        assert!(reloader.reload_if_changed().await.is_err());
+        assert_eq!(presented(&reloader), first_der);
+
+        std::fs::write(&files.key_path, second.key_pair.serialize_pem()).unwrap();
+        assert!(reloader.reload_if_changed().await.unwrap());
+        assert_eq!(presented(&reloader), *second.cert.der());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,464 @@
+//! Admission limits for the accept loop
+//!
+//! Bounds the amount of work the server takes on before a connection
+//! is fully established.
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::metrics::METRICS;
+use crate::proxy::BandwidthLimiter;
+
+/// How long an incoming connection may wait for a handshake slot
+pub const HANDSHAKE_QUEUE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How often idle per-IP rate buckets and bandwidth budgets are dropped
+pub const PER_IP_EVICT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Limits the number of QUIC handshakes in progress at once
+#[derive(Clone)]
+pub struct HandshakeLimiter {
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    queue_timeout: Duration,
+}
+
+impl HandshakeLimiter {
+    /// Create a limiter allowing `max_concurrent` in-progress handshakes
+    pub fn new(max_concurrent: usize, queue_timeout: Duration) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            queue_timeout,
+        }
+    }
+
+    /// Wait briefly for a handshake slot
+    ///
+    /// Returns None, counting the refusal, if no slot frees up within the
+    /// queue timeout; the caller should refuse the connection. The
+    /// handshake counts in `METRICS.handshakes_in_progress` until the
+    /// permit is dropped.
+    pub async fn acquire(&self) -> Option<HandshakePermit> {
+        let acquire = self.semaphore.clone().acquire_owned();
+        let Ok(Ok(permit)) = tokio::time::timeout(self.queue_timeout, acquire).await else {
+            METRICS.handshake_refused();
+            return None;
+        };
+        METRICS.handshakes_in_progress.fetch_add(1, Ordering::Relaxed);
+        Some(HandshakePermit { _permit: permit })
+    }
+
+    /// Number of handshakes currently in progress
+    pub fn in_progress(&self) -> usize {
+        self.max_concurrent - self.semaphore.available_permits()
+    }
+}
+
+/// An in-progress handshake's slot in the [`HandshakeLimiter`], released on drop
+pub struct HandshakePermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        METRICS.handshakes_in_progress.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Limits the number of stream handlers running across the whole server
+///
+/// Unlike handshakes, streams don't queue: one that finds every slot
+/// taken is refused at once.
+#[derive(Clone)]
+pub struct StreamLimiter {
+    /// `None` when unlimited
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl StreamLimiter {
+    /// Create a limiter allowing `max_concurrent` streams at once (0 = unlimited)
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            semaphore: (max_concurrent > 0).then(|| Arc::new(Semaphore::new(max_concurrent))),
+        }
+    }
+
+    /// Take a slot for a new stream without waiting
+    ///
+    /// Returns None, counting the rejection, if every slot is taken. The
+    /// stream counts in `METRICS.streams_in_flight` until the permit is
+    /// dropped.
+    pub fn try_acquire(&self) -> Option<StreamPermit> {
+        let permit = match &self.semaphore {
+            Some(semaphore) => match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    METRICS.stream_rejected_capacity();
+                    return None;
+                }
+            },
+            None => None,
+        };
+        METRICS.streams_in_flight.fetch_add(1, Ordering::Relaxed);
+        Some(StreamPermit { _permit: permit })
+    }
+}
+
+/// A running stream's slot in the [`StreamLimiter`], released on drop
+pub struct StreamPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        METRICS.streams_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Token bucket limiting how many new connections are accepted per second
+///
+/// The bucket holds up to one second's worth of tokens, so bursts of
+/// `per_sec` connections are admitted at once.
+pub struct ConnectionRateLimiter {
+    per_sec: AtomicU32,
+    bucket: Mutex<TokenBucket>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(per_sec: f64, now: Instant) -> Self {
+        Self {
+            tokens: per_sec,
+            refilled_at: now,
+        }
+    }
+
+    /// Refill for the time since the last call, then take one token
+    fn take(&mut self, per_sec: f64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_sec).min(per_sec);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl ConnectionRateLimiter {
+    /// Create a limiter admitting `per_sec` connections per second (0 = unlimited)
+    pub fn new(per_sec: u32) -> Self {
+        Self {
+            per_sec: AtomicU32::new(per_sec),
+            bucket: Mutex::new(TokenBucket::full(per_sec as f64, Instant::now())),
+        }
+    }
+
+    /// Change the rate; takes effect for the next connection (0 = unlimited)
+    pub fn set_rate(&self, per_sec: u32) {
+        let mut bucket = self.bucket.lock();
+        bucket.tokens = bucket.tokens.min(per_sec as f64);
+        self.per_sec.store(per_sec, Ordering::Relaxed);
+    }
+
+    /// Take a token for a new connection
+    ///
+    /// Returns false if the bucket is empty.
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> bool {
+        let per_sec = self.per_sec.load(Ordering::Relaxed) as f64;
+        if per_sec == 0.0 {
+            return true;
+        }
+
+        self.bucket.lock().take(per_sec, now)
+    }
+}
+
+/// Token buckets limiting new connections per second from each client IP
+///
+/// Buckets are created on a client's first connection and dropped by
+/// [`evict_idle`](Self::evict_idle) once they have refilled, since a
+/// fresh bucket would behave the same.
+pub struct PerIpRateLimiter {
+    per_sec: AtomicU32,
+    buckets: DashMap<IpAddr, TokenBucket>,
+}
+
+impl PerIpRateLimiter {
+    /// Create a limiter admitting `per_sec` connections per second per IP (0 = unlimited)
+    pub fn new(per_sec: u32) -> Self {
+        Self {
+            per_sec: AtomicU32::new(per_sec),
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Change the rate; existing buckets start over at the new rate
+    pub fn set_rate(&self, per_sec: u32) {
+        self.per_sec.store(per_sec, Ordering::Relaxed);
+        self.buckets.clear();
+    }
+
+    /// Take a token for a new connection from `ip`
+    ///
+    /// Returns false if that IP's bucket is empty.
+    pub fn try_acquire(&self, ip: IpAddr) -> bool {
+        self.try_acquire_at(ip, Instant::now())
+    }
+
+    fn try_acquire_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let per_sec = self.per_sec.load(Ordering::Relaxed) as f64;
+        if per_sec == 0.0 {
+            return true;
+        }
+
+        // IPv4 clients on a dual-stack socket share a bucket with plain IPv4
+        self.buckets
+            .entry(ip.to_canonical())
+            .or_insert_with(|| TokenBucket::full(per_sec, now))
+            .take(per_sec, now)
+    }
+
+    /// Drop buckets that have been idle long enough to refill
+    pub fn evict_idle(&self) {
+        self.evict_idle_at(Instant::now());
+    }
+
+    fn evict_idle_at(&self, now: Instant) {
+        let per_sec = self.per_sec.load(Ordering::Relaxed) as f64;
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.tokens + elapsed * per_sec < per_sec
+        });
+    }
+}
+
+/// Bandwidth budgets shared by every connection from each client IP
+///
+/// A connection holds its IP's limiter for as long as it is open; once
+/// none does, [`evict_idle`](Self::evict_idle) drops it.
+pub struct PerIpBandwidth {
+    bytes_per_sec: AtomicU64,
+    limiters: DashMap<IpAddr, Arc<BandwidthLimiter>>,
+}
+
+impl PerIpBandwidth {
+    /// Create budgets of `bytes_per_sec` per IP (0 = unlimited)
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: AtomicU64::new(bytes_per_sec),
+            limiters: DashMap::new(),
+        }
+    }
+
+    /// Change the rate for connections accepted from now on
+    ///
+    /// Open connections keep counting against their old budget.
+    pub fn set_rate(&self, bytes_per_sec: u64) {
+        self.bytes_per_sec.store(bytes_per_sec, Ordering::Relaxed);
+        self.limiters.clear();
+    }
+
+    /// The budget a new connection from `ip` shares, or None if unlimited
+    pub fn limiter(&self, ip: IpAddr) -> Option<Arc<BandwidthLimiter>> {
+        let bytes_per_sec = self.bytes_per_sec.load(Ordering::Relaxed);
+        if bytes_per_sec == 0 {
+            return None;
+        }
+
+        let limiter = self
+            .limiters
+            .entry(ip.to_canonical())
+            .or_insert_with(|| Arc::new(BandwidthLimiter::new(bytes_per_sec)));
+        Some(limiter.clone())
+    }
+
+    /// Drop budgets no open connection holds
+    pub fn evict_idle(&self) {
+        self.limiters.retain(|_, limiter| Arc::strong_count(limiter) > 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[tokio::test]
+    async fn test_handshake_budget_under_burst() {
+        let limiter = HandshakeLimiter::new(4, Duration::from_secs(5));
+        let current = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..64)
+            .map(|_| {
+                let limiter = limiter.clone();
+                let current = current.clone();
+                let peak = peak.clone();
+                tokio::spawn(async move {
+                    let _permit = limiter.acquire().await.unwrap();
+                    let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    current.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert!(peak.load(Ordering::SeqCst) <= 4);
+        assert_eq!(limiter.in_progress(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_budget_refuses_when_exhausted() {
+        let limiter = HandshakeLimiter::new(1, Duration::from_millis(10));
+
+        let refused_before = METRICS.handshakes_refused.load(Ordering::Relaxed);
+
+        let held = limiter.acquire().await.unwrap();
+        assert_eq!(limiter.in_progress(), 1);
+        assert!(limiter.acquire().await.is_none());
+        assert!(METRICS.handshakes_refused.load(Ordering::Relaxed) > refused_before);
+
+        drop(held);
+        assert!(limiter.acquire().await.is_some());
+    }
+
+    #[test]
+    fn test_stream_limit_saturated() {
+        let limiter = StreamLimiter::new(3);
+        let rejected_before = METRICS.streams_rejected_capacity.load(Ordering::Relaxed);
+
+        let held: Vec<_> = (0..3).map(|_| limiter.try_acquire().unwrap()).collect();
+        assert!(limiter.try_acquire().is_none());
+        assert!(limiter.try_acquire().is_none());
+        assert!(METRICS.streams_rejected_capacity.load(Ordering::Relaxed) >= rejected_before + 2);
+
+        // Finishing any stream frees its slot for the next one
+        drop(held);
+        let _permits: Vec<_> = (0..3).map(|_| limiter.try_acquire().unwrap()).collect();
+        assert!(limiter.try_acquire().is_none());
+
+        let unlimited = StreamLimiter::new(0);
+        let _permits: Vec<_> = (0..1000).map(|_| unlimited.try_acquire().unwrap()).collect();
+    }
+
+    #[test]
+    fn test_connection_rate_limit_tight_loop() {
+        let limiter = ConnectionRateLimiter::new(10);
+        let now = Instant::now();
+
+        let admitted = (0..100).filter(|_| limiter.try_acquire_at(now)).count();
+        assert_eq!(admitted, 10);
+
+        // Half a second refills half the bucket
+        let later = now + Duration::from_millis(500);
+        let admitted = (0..100).filter(|_| limiter.try_acquire_at(later)).count();
+        assert_eq!(admitted, 5);
+
+        // Refill never exceeds one second's burst
+        let much_later = later + Duration::from_secs(60);
+        let admitted = (0..100).filter(|_| limiter.try_acquire_at(much_later)).count();
+        assert_eq!(admitted, 10);
+    }
+
+    #[test]
+    fn test_connection_rate_limit_set_rate() {
+        let limiter = ConnectionRateLimiter::new(10);
+        let now = Instant::now();
+
+        // Lowering the rate also shrinks the current burst
+        limiter.set_rate(2);
+        let admitted = (0..100).filter(|_| limiter.try_acquire_at(now)).count();
+        assert_eq!(admitted, 2);
+
+        limiter.set_rate(0);
+        assert!((0..100).all(|_| limiter.try_acquire_at(now)));
+    }
+
+    #[test]
+    fn test_per_ip_rate_limit() {
+        let limiter = PerIpRateLimiter::new(3);
+        let now = Instant::now();
+        let abusive: IpAddr = "192.0.2.1".parse().unwrap();
+        let quiet: IpAddr = "192.0.2.2".parse().unwrap();
+
+        let admitted = (0..100).filter(|_| limiter.try_acquire_at(abusive, now)).count();
+        assert_eq!(admitted, 3);
+
+        // Other clients keep their own budget
+        assert!(limiter.try_acquire_at(quiet, now));
+
+        // The mapped form of an IPv4 address shares its bucket
+        let mapped: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+        assert!(!limiter.try_acquire_at(mapped, now));
+        assert_eq!(limiter.buckets.len(), 2);
+    }
+
+    #[test]
+    fn test_per_ip_rate_limit_eviction() {
+        let limiter = PerIpRateLimiter::new(10);
+        let now = Instant::now();
+
+        for i in 0..100u8 {
+            assert!(limiter.try_acquire_at(IpAddr::from([10, 0, 0, i]), now));
+        }
+        assert_eq!(limiter.buckets.len(), 100);
+
+        // Buckets still refilling are kept
+        limiter.evict_idle_at(now + Duration::from_millis(50));
+        assert_eq!(limiter.buckets.len(), 100);
+
+        limiter.evict_idle_at(now + Duration::from_millis(100));
+        assert_eq!(limiter.buckets.len(), 0);
+    }
+
+    #[test]
+    fn test_per_ip_bandwidth() {
+        let budgets = PerIpBandwidth::new(1024);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+
+        // Connections from one IP share a budget, mapped IPv4 included
+        let first = budgets.limiter(client).unwrap();
+        let second = budgets.limiter("::ffff:192.0.2.1".parse().unwrap()).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&first, &budgets.limiter("192.0.2.2".parse().unwrap()).unwrap()));
+
+        // Kept while a connection holds it
+        budgets.evict_idle();
+        assert_eq!(budgets.limiters.len(), 1);
+        drop((first, second));
+        budgets.evict_idle();
+        assert_eq!(budgets.limiters.len(), 0);
+
+        budgets.set_rate(0);
+        assert!(budgets.limiter(client).is_none());
+    }
+
+    #[test]
+    fn test_connection_rate_limit_unlimited() {
+        let limiter = ConnectionRateLimiter::new(0);
+        assert!((0..1000).all(|_| limiter.try_acquire()));
+    }
+}
@@ -3,29 +3,74 @@
 //! High-performance QUIC listener with SO_REUSEPORT for multi-core scaling.
 
 use anyhow::{Context, Result};
+use parking_lot::RwLock;
+use quinn::congestion::{BbrConfig, ControllerFactory, CubicConfig, NewRenoConfig};
 use quinn::{Endpoint, ServerConfig, TransportConfig, VarInt};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::WebPkiClientVerifier;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch};
 use tracing::{debug, info, warn};
 
-use crate::config::Config;
-use crate::connection::{ConnectionManager, ConnectionManagerConfig};
+use crate::close_code::CloseCode;
+use crate::config::{Config, DnsConfig, ReloadDiff};
+use crate::connection::{ConnectionManager, ConnectionManagerConfig, WebhookNotifier};
+use crate::metrics::METRICS;
 use crate::pool::BufferPool;
+use crate::proxy::{DnsCache, Resolver, SystemResolver, UpstreamResolver};
+use crate::router::{RequestRouter, RoutingPolicy};
 
-use super::acceptor::ConnectionHandler;
+use super::acceptor::{reject_at_capacity, ConnectionHandler};
+use super::auth::{Authenticator, StaticToken};
+use super::certs::{certified_key_der, CertFiles, CertReloader, CertResolver};
+use super::tickets::FileTicketer;
+use super::memory::MemoryWatchdog;
+use super::limits::{
+    ConnectionRateLimiter, HandshakeLimiter, PerIpBandwidth, PerIpRateLimiter, StreamLimiter,
+    HANDSHAKE_QUEUE_TIMEOUT, PER_IP_EVICT_INTERVAL,
+};
+
+/// Incoming connections queued between the endpoints and the accept loop
+const ACCEPT_QUEUE_DEPTH: usize = 64;
+
+/// Number of max-size datagrams buffered per direction
+const DATAGRAM_BUFFER_DEPTH: usize = 48;
 
 /// QUIC tunnel server
 pub struct Server {
-    /// QUIC endpoint
-    endpoint: Endpoint,
-    /// Server configuration
-    config: Arc<Config>,
+    /// QUIC endpoints, one per bind address
+    endpoints: Vec<Endpoint>,
+    /// Server configuration, replaced on reload
+    config: RwLock<Arc<Config>>,
     /// Connection manager
     conn_manager: Arc<ConnectionManager>,
     /// Buffer pool
     buffer_pool: BufferPool,
+    /// Routing policy applied to every tunnel request
+    router: Arc<RequestRouter>,
+    /// Bound on concurrent in-progress handshakes
+    handshake_limiter: HandshakeLimiter,
+    /// Bound on stream handlers running across all connections
+    stream_limiter: StreamLimiter,
+    /// Bound on new connections per second
+    rate_limiter: ConnectionRateLimiter,
+    /// Bound on new connections per second from each client IP
+    per_ip_limiter: Arc<PerIpRateLimiter>,
+    /// Bandwidth budget shared by the connections from each client IP
+    per_ip_bandwidth: Arc<PerIpBandwidth>,
+    /// Set while the endpoint is accepting and not draining
+    ready: Arc<AtomicBool>,
+    /// Refuses and sheds connections while memory is over `max_memory_mb`
+    memory_watchdog: Arc<MemoryWatchdog>,
+    /// Watches the certificate files, unless the certificate was generated
+    cert_reloader: Option<Arc<CertReloader>>,
+    /// Resolves proxy and relay targets for every connection
+    dns_cache: Arc<DnsCache>,
+    /// Checks every client's token; None when the server is open
+    authenticator: Option<Arc<dyn Authenticator>>,
     /// Shutdown signal
     shutdown_rx: watch::Receiver<bool>,
     shutdown_tx: watch::Sender<bool>,
@@ -35,15 +80,17 @@ impl Server {
     /// Create a new server instance
     pub async fn new(config: Arc<Config>) -> Result<Self> {
         // Initialize buffer pool
-        let buffer_pool = BufferPool::new(
-            config.pool.buffer_count_4k,
-            config.pool.buffer_count_16k,
-            config.pool.buffer_count_64k,
-        );
+        let tiers = config.pool.buffer_tiers();
+        let layout: Vec<(usize, usize)> = tiers.iter().map(|t| (t.size, t.count)).collect();
+        let buffer_pool = tiers
+            .iter()
+            .fold(BufferPool::with_tiers(&layout), |pool, tier| {
+                pool.with_tier_overflow(tier.size, tier.max_overflow)
+            })
+            .with_zero_on_release(config.pool.zero_on_release);
         info!(
-            small = config.pool.buffer_count_4k,
-            medium = config.pool.buffer_count_16k,
-            large = config.pool.buffer_count_64k,
+            tiers = ?layout,
+            zero_on_release = config.pool.zero_on_release,
             "Buffer pool initialized"
         );
 
@@ -54,44 +101,120 @@ impl Server {
         });
 
         // Load or generate TLS configuration
-        let server_config = build_server_config(&config).await?;
-
-        // Create UDP socket with optimizations
-        let socket = crate::util::create_udp_socket(config.server.bind_addr, true)?;
-
-        // Create QUIC endpoint
-        let runtime = quinn::default_runtime()
-            .ok_or_else(|| anyhow::anyhow!("No async runtime found"))?;
-        
-        let endpoint = Endpoint::new(
-            quinn::EndpointConfig::default(),
-            Some(server_config),
-            socket,
-            runtime,
-        )?;
+        let (certs, cert_reloader) = load_or_generate_certs(&config).await?;
+        let server_config = build_server_config(&config, certs).await?;
+
+        // Create QUIC endpoints for every bind address, several per address
+        // sharing it through SO_REUSEPORT. IPv6 endpoints only take IPv6
+        // traffic when an IPv4 address is bound separately.
+        let bind_addrs = &config.server.bind_addrs;
+        let only_v6 = bind_addrs.iter().any(|addr| addr.is_ipv4());
+        let sockets_per_addr = config.server.effective_reuseport_sockets();
+        let mut endpoints = Vec::with_capacity(bind_addrs.len() * sockets_per_addr);
+        for &addr in bind_addrs {
+            // Later sockets join the first one's port, even if it was picked by the OS
+            let mut bound_addr = addr;
+            for _ in 0..sockets_per_addr {
+                let socket = crate::util::create_udp_socket(bound_addr, config.server.reuse_port, only_v6)
+                    .with_context(|| format!("Failed to bind {}", bound_addr))?;
+                bound_addr = socket.local_addr()?;
+                let runtime = quinn::default_runtime()
+                    .ok_or_else(|| anyhow::anyhow!("No async runtime found"))?;
+
+                endpoints.push(Endpoint::new(
+                    quinn::EndpointConfig::default(),
+                    Some(server_config.clone()),
+                    socket,
+                    runtime,
+                )?);
+            }
+        }
+        info!(
+            addrs = bind_addrs.len(),
+            sockets_per_addr,
+            "QUIC endpoints bound"
+        );
+
+        if config.features.allow_reverse {
+            info!(bind_ip = %config.features.reverse_bind_ip, "Reverse tunnels enabled");
+        }
+
+        let router = Arc::new(RequestRouter::with_policy(RoutingPolicy::from_config(
+            &config.routing,
+        )));
+
+        let handshake_limiter = HandshakeLimiter::new(
+            config.server.max_concurrent_handshakes,
+            HANDSHAKE_QUEUE_TIMEOUT,
+        );
+
+        let stream_limiter = StreamLimiter::new(config.limits.max_concurrent_streams);
+        let rate_limiter = ConnectionRateLimiter::new(config.limits.max_new_conn_per_sec);
+        let per_ip_limiter = Arc::new(PerIpRateLimiter::new(
+            config.limits.max_new_conn_per_sec_per_ip,
+        ));
+        let per_ip_bandwidth = Arc::new(PerIpBandwidth::new(config.limits.max_bandwidth_per_ip));
+
+        let dns_cache = Arc::new(DnsCache::with_resolver(
+            Duration::from_secs(config.dns.cache_ttl_secs),
+            dns_resolver(&config.dns)?,
+        ));
+
+        let ready = Arc::new(AtomicBool::new(false));
+        let memory_watchdog = Arc::new(MemoryWatchdog::new(
+            config.limits.max_memory_mb,
+            conn_manager.clone(),
+            ready.clone(),
+        ));
+
+        let authenticator = config.auth.as_ref().map(|auth| {
+            Arc::new(StaticToken::new(auth.token.as_bytes())) as Arc<dyn Authenticator>
+        });
 
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
         Ok(Self {
-            endpoint,
-            config,
+            endpoints,
+            config: RwLock::new(config),
             conn_manager,
             buffer_pool,
+            router,
+            handshake_limiter,
+            stream_limiter,
+            rate_limiter,
+            per_ip_limiter,
+            per_ip_bandwidth,
             shutdown_rx,
+            memory_watchdog,
+            ready,
+            cert_reloader,
+            dns_cache,
+            authenticator,
             shutdown_tx,
         })
     }
 
+    /// Check client tokens with `authenticator` instead of `[auth] token`
+    ///
+    /// Clients must then authenticate even without an `[auth]` section.
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
     /// Run the server (main accept loop)
+    ///
+    /// Each endpoint feeds one shared accept loop, which ends once every
+    /// endpoint is closed or shutdown is signaled.
     pub async fn run(&self) -> Result<()> {
         info!(
-            bind_addr = %self.config.server.bind_addr,
+            bind_addrs = ?self.local_addrs(),
             "Server accepting connections"
         );
 
         // Start idle connection cleanup task
         let conn_manager = self.conn_manager.clone();
-        let cleanup_interval = Duration::from_secs(self.config.quic.idle_timeout_secs / 2);
+        let cleanup_interval = Duration::from_secs(self.config().quic.idle_timeout_secs / 2);
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(cleanup_interval);
             loop {
@@ -100,18 +223,108 @@ impl Server {
             }
         });
 
+        // Start per-IP rate bucket and bandwidth budget eviction task
+        let per_ip_limiter = self.per_ip_limiter.clone();
+        let per_ip_bandwidth = self.per_ip_bandwidth.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PER_IP_EVICT_INTERVAL);
+            loop {
+                interval.tick().await;
+                per_ip_limiter.evict_idle();
+                per_ip_bandwidth.evict_idle();
+            }
+        });
+
+        // Start webhook notifications
+        if let Some(notify) = &self.config().notify {
+            WebhookNotifier::new(notify.clone())?.spawn(self.conn_manager.subscribe_events());
+        }
+
+        // Start certificate reload task, stopped again once the loop ends
+        let reload_interval = self.config().tls.reload_interval_secs;
+        let cert_reload = match (&self.cert_reloader, reload_interval > 0) {
+            (Some(reloader), true) => {
+                Some(reloader.clone().spawn(Duration::from_secs(reload_interval)))
+            }
+            _ => None,
+        };
+
+        let (incoming_tx, mut incoming_rx) = mpsc::channel(ACCEPT_QUEUE_DEPTH);
+        for endpoint in &self.endpoints {
+            let endpoint = endpoint.clone();
+            let incoming_tx = incoming_tx.clone();
+            tokio::spawn(async move {
+                while let Some(incoming) = endpoint.accept().await {
+                    if incoming_tx.send(incoming).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(incoming_tx);
+
         let mut shutdown_rx = self.shutdown_rx.clone();
+        self.ready.store(true, Ordering::Release);
+
+        // Started once ready, so a limit already exceeded clears readiness
+        self.memory_watchdog.clone().spawn(self.shutdown_rx.clone());
 
         loop {
             tokio::select! {
                 // Accept new connections
-                incoming = self.endpoint.accept() => {
+                incoming = incoming_rx.recv() => {
                     match incoming {
                         Some(incoming) => {
+                            if self.memory_watchdog.under_pressure() {
+                                debug!(
+                                    client_addr = %incoming.remote_address(),
+                                    "Connection refused: memory limit exceeded"
+                                );
+                                incoming.refuse();
+                                continue;
+                            }
+
+                            // Check the client's own rate before it can use up the global one
+                            if !self.per_ip_limiter.try_acquire(incoming.remote_address().ip()) {
+                                debug!(
+                                    client_addr = %incoming.remote_address(),
+                                    "Connection dropped: client IP rate limited"
+                                );
+                                METRICS.connection_rate_limited_per_ip();
+                                METRICS.connection_rejected_rate();
+                                continue;
+                            }
+
+                            // Check new connection rate
+                            if !self.rate_limiter.try_acquire() {
+                                debug!(
+                                    client_addr = %incoming.remote_address(),
+                                    "Connection dropped: rate limited"
+                                );
+                                METRICS.connection_rate_limited();
+                                METRICS.connection_rejected_rate();
+                                continue;
+                            }
+
                             // Check capacity
                             if self.conn_manager.is_full() {
-                                warn!("Connection rejected: at capacity");
-                                // Connection will be dropped
+                                warn!(
+                                    client_addr = %incoming.remote_address(),
+                                    "Connection rejected: at capacity"
+                                );
+                                METRICS.connection_rejected_capacity();
+
+                                // Finish the handshake so the client gets a close reason
+                                let limiter = self.handshake_limiter.clone();
+                                tokio::spawn(async move {
+                                    let Some(permit) = limiter.acquire().await else {
+                                        incoming.refuse();
+                                        return;
+                                    };
+                                    if let Err(e) = reject_at_capacity(incoming, permit).await {
+                                        debug!(error = %e, "Capacity rejection failed");
+                                    }
+                                });
                                 continue;
                             }
 
@@ -119,17 +332,33 @@ impl Server {
                             let handler = ConnectionHandler::new(
                                 self.conn_manager.clone(),
                                 self.buffer_pool.clone(),
-                                self.config.clone(),
-                            );
+                                self.router.clone(),
+                                self.config(),
+                            )
+                            .with_dns_cache(self.dns_cache.clone())
+                            .with_stream_limiter(self.stream_limiter.clone())
+                            .with_ip_bandwidth(Some(self.per_ip_bandwidth.clone()))
+                            .with_authenticator(self.authenticator.clone());
+                            let limiter = self.handshake_limiter.clone();
 
                             tokio::spawn(async move {
-                                if let Err(e) = handler.handle(incoming).await {
+                                // Bound concurrent handshakes before doing any crypto work
+                                let Some(permit) = limiter.acquire().await else {
+                                    debug!(
+                                        client_addr = %incoming.remote_address(),
+                                        "Connection refused: handshake budget exhausted"
+                                    );
+                                    incoming.refuse();
+                                    return;
+                                };
+
+                                if let Err(e) = handler.handle(incoming, permit).await {
                                     debug!(error = %e, "Connection error");
                                 }
                             });
                         }
                         None => {
-                            // Endpoint closed
+                            // All endpoints closed
                             break;
                         }
                     }
@@ -144,48 +373,148 @@ impl Server {
             }
         }
 
+        self.ready.store(false, Ordering::Release);
+        if let Some(task) = cert_reload {
+            task.abort();
+        }
         Ok(())
     }
 
+    /// Local addresses of all endpoints (repeated for SO_REUSEPORT sockets)
+    pub fn local_addrs(&self) -> Vec<std::net::SocketAddr> {
+        self.endpoints
+            .iter()
+            .filter_map(|endpoint| endpoint.local_addr().ok())
+            .collect()
+    }
+
+    /// Get the current configuration
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    /// Apply a reloaded configuration without dropping connections
+    ///
+    /// Swaps in the new `[routing]` and `[limits]` sections. Routing applies
+    /// to every request from now on; per-connection limits apply to
+    /// connections accepted after the reload.
+    pub fn reload(&self, new: Config) -> Result<ReloadDiff> {
+        let current = self.config();
+        let diff = current.reload_diff(&new)?;
+
+        let mut next = (*current).clone();
+        next.routing = new.routing;
+        next.limits = new.limits;
+
+        self.router.set_policy(RoutingPolicy::from_config(&next.routing));
+        self.rate_limiter.set_rate(next.limits.max_new_conn_per_sec);
+        self.per_ip_limiter.set_rate(next.limits.max_new_conn_per_sec_per_ip);
+        self.per_ip_bandwidth.set_rate(next.limits.max_bandwidth_per_ip);
+        self.memory_watchdog.set_limit(next.limits.max_memory_mb);
+        *self.config.write() = Arc::new(next);
+
+        for change in &diff.changes {
+            info!(%change, "Config reloaded");
+        }
+        for section in &diff.ignored {
+            warn!(section, "Config section changed; requires restart to take effect");
+        }
+        if diff.changes.is_empty() {
+            info!("Config reloaded with no runtime changes");
+        }
+
+        Ok(diff)
+    }
+
     /// Get the connection manager
     pub fn connection_manager(&self) -> Arc<ConnectionManager> {
         self.conn_manager.clone()
     }
 
+    /// Get the buffer pool
+    pub fn buffer_pool(&self) -> BufferPool {
+        self.buffer_pool.clone()
+    }
+
+    /// Get the readiness flag, cleared once shutdown begins
+    pub fn readiness(&self) -> Arc<AtomicBool> {
+        self.ready.clone()
+    }
+
     /// Gracefully shutdown the server
     pub async fn shutdown(&self) {
         info!("Initiating graceful shutdown");
 
+        // Fail readiness probes before draining
+        self.memory_watchdog.set_draining();
+        self.ready.store(false, Ordering::SeqCst);
+
         // Signal shutdown
         let _ = self.shutdown_tx.send(true);
 
         // Signal all connections
         self.conn_manager.signal_shutdown();
 
-        // Drain connections (wait up to 30 seconds)
-        self.conn_manager.drain(Duration::from_secs(30)).await;
-
-        // Close endpoint
-        self.endpoint.close(VarInt::from_u32(0), b"server shutdown");
+        // Drain connections, closing what is left after the timeout
+        let timeout = Duration::from_secs(self.config().server.drain_timeout_secs);
+        self.conn_manager.drain(timeout).await;
 
+        self.close_endpoints();
         info!("Server shutdown complete");
     }
-}
 
-/// Build QUIC server configuration
-async fn build_server_config(config: &Config) -> Result<ServerConfig> {
-    // Load or generate certificates
-    let (certs, key) = load_or_generate_certs(config).await?;
+    /// Close every connection and endpoint at once, cutting short a
+    /// [`shutdown`](Self::shutdown) that is still draining
+    pub fn shutdown_now(&self) {
+        warn!("Closing all connections without draining");
+        self.memory_watchdog.set_draining();
+        self.ready.store(false, Ordering::SeqCst);
+        let _ = self.shutdown_tx.send(true);
+        self.conn_manager.close_all();
+        self.close_endpoints();
+    }
 
+    /// Close every endpoint with `server shutdown`
+    fn close_endpoints(&self) {
+        for endpoint in &self.endpoints {
+            let code = CloseCode::ServerShutdown;
+            endpoint.close(code.code(), code.reason().as_bytes());
+        }
+    }
+}
+
+/// Build QUIC server configuration presenting the certificate held by `certs`
+async fn build_server_config(config: &Config, certs: Arc<CertResolver>) -> Result<ServerConfig> {
     // Build rustls config
-    let mut rustls_config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .context("Failed to build TLS config")?;
+    crate::util::init_crypto();
+    let builder = rustls::ServerConfig::builder();
+    let builder = if config.tls.require_client_cert {
+        let ca_path = config
+            .tls
+            .client_ca_path
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("require_client_cert is set without client_ca_path"))?;
+        builder.with_client_cert_verifier(load_client_verifier(ca_path).await?)
+    } else {
+        builder.with_no_client_auth()
+    };
+    let mut rustls_config = builder.with_cert_resolver(certs);
 
     // Enable ALPN
     rustls_config.alpn_protocols = vec![b"mytunnel".to_vec(), b"h3".to_vec()];
 
+    // Accept early data from resumed sessions; QUIC requires the maximum
+    // value rather than a byte limit
+    if config.quic.enable_0rtt {
+        rustls_config.max_early_data_size = u32::MAX;
+    }
+
+    // Stateless tickets under a stable key, so sessions resume across restarts
+    if let Some(path) = &config.tls.session_ticket_key_path {
+        let ticketer = FileTicketer::load_or_create(std::path::Path::new(path))?;
+        rustls_config.ticketer = Arc::new(ticketer);
+    }
+
     // Create quinn server config
     let mut server_config = ServerConfig::with_crypto(Arc::new(
         quinn::crypto::rustls::QuicServerConfig::try_from(rustls_config)?,
@@ -203,9 +532,14 @@ async fn build_server_config(config: &Config) -> Result<ServerConfig> {
             .unwrap(),
     ));
 
-    // Enable datagrams for UDP relay
-    transport.datagram_receive_buffer_size(Some(65536));
-    transport.datagram_send_buffer_size(65536);
+    transport.congestion_controller_factory(congestion_controller(
+        &config.quic.congestion_control,
+    )?);
+
+    // Enable datagrams for UDP relay, buffering a bounded number of max-size payloads
+    let datagram_buffer = config.quic.max_udp_payload as usize * DATAGRAM_BUFFER_DEPTH;
+    transport.datagram_receive_buffer_size(Some(datagram_buffer));
+    transport.datagram_send_buffer_size(datagram_buffer);
 
     // Performance settings
     transport.initial_rtt(Duration::from_millis(100));
@@ -225,33 +559,88 @@ async fn build_server_config(config: &Config) -> Result<ServerConfig> {
     Ok(server_config)
 }
 
-/// Load certificates from files or generate self-signed
+/// Map a `quic.congestion_control` name to its quinn controller factory
+fn congestion_controller(name: &str) -> Result<Arc<dyn ControllerFactory + Send + Sync>> {
+    Ok(match name {
+        "bbr" => Arc::new(BbrConfig::default()),
+        "cubic" => Arc::new(CubicConfig::default()),
+        "newreno" => Arc::new(NewRenoConfig::default()),
+        other => anyhow::bail!("Unsupported congestion controller: {}", other),
+    })
+}
+
+/// Build the resolver for a `dns.mode`
+fn dns_resolver(config: &DnsConfig) -> Result<Arc<dyn Resolver>> {
+    let upstream = || {
+        config
+            .upstream
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("dns.mode = {:?} requires dns.upstream", config.mode))
+    };
+    let resolver: Arc<dyn Resolver> = match config.mode.as_str() {
+        "system" => return Ok(Arc::new(SystemResolver)),
+        "doh" => Arc::new(UpstreamResolver::doh(upstream()?)?),
+        "dot" => Arc::new(UpstreamResolver::dot(upstream()?)?),
+        other => anyhow::bail!("Unsupported DNS mode: {}", other),
+    };
+    info!(mode = %config.mode, upstream = upstream()?, "Resolving targets through DNS upstream");
+    Ok(resolver)
+}
+
+/// Build a verifier accepting client certificates that chain to the CA bundle
+async fn load_client_verifier(ca_path: &str) -> Result<Arc<dyn ClientCertVerifier>> {
+    info!(ca = %ca_path, "Requiring client certificates");
+
+    let ca_pem = tokio::fs::read(ca_path)
+        .await
+        .context("Failed to read client CA file")?;
+
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut ca_pem.as_slice()) {
+        roots
+            .add(cert.context("Failed to parse client CA certificate")?)
+            .context("Invalid client CA certificate")?;
+    }
+    if roots.is_empty() {
+        anyhow::bail!("No certificates found in client CA file {}", ca_path);
+    }
+
+    WebPkiClientVerifier::builder(Arc::new(roots))
+        .build()
+        .context("Failed to build client certificate verifier")
+}
+
+/// Load the certificate files, or generate a self-signed certificate
+///
+/// Certificate files, including `[[tls.certs]]` for SNI, come with a
+/// reloader watching them.
 async fn load_or_generate_certs(
     config: &Config,
-) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+) -> Result<(Arc<CertResolver>, Option<Arc<CertReloader>>)> {
     let cert_path = std::path::Path::new(&config.tls.cert_path);
     let key_path = std::path::Path::new(&config.tls.key_path);
 
-    if cert_path.exists() && key_path.exists() {
+    let mut files: Vec<CertFiles> = config
+        .tls
+        .certs
+        .iter()
+        .map(|c| CertFiles {
+            sni: Some(c.sni.clone()),
+            cert_path: c.cert_path.clone().into(),
+            key_path: c.key_path.clone().into(),
+        })
+        .collect();
+
+    let default = if cert_path.exists() && key_path.exists() {
         // Load from files
         info!(cert = %config.tls.cert_path, key = %config.tls.key_path, "Loading TLS certificates");
 
-        let cert_pem = tokio::fs::read(&config.tls.cert_path)
-            .await
-            .context("Failed to read certificate file")?;
-        let key_pem = tokio::fs::read(&config.tls.key_path)
-            .await
-            .context("Failed to read key file")?;
-
-        let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut cert_pem.as_slice())
-            .collect::<Result<Vec<_>, _>>()
-            .context("Failed to parse certificates")?;
-
-        let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
-            .context("Failed to parse private key")?
-            .ok_or_else(|| anyhow::anyhow!("No private key found in file"))?;
-
-        Ok((certs, key))
+        files.push(CertFiles {
+            sni: None,
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+        });
+        None
     } else if config.tls.auto_generate {
         // Generate self-signed certificate
         warn!("Generating self-signed certificate (not for production use)");
@@ -262,12 +651,565 @@ async fn load_or_generate_certs(
         let cert_der = CertificateDer::from(cert.cert);
         let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
 
-        Ok((vec![cert_der], key_der))
+        Some(certified_key_der(vec![cert_der], key_der)?)
     } else {
         anyhow::bail!(
             "TLS certificate not found at {} and auto_generate is disabled",
             config.tls.cert_path
         )
+    };
+
+    if files.is_empty() {
+        let default = default.expect("generated when no files are loaded");
+        return Ok((Arc::new(CertResolver::new(default)), None));
     }
+
+    let reloader = CertReloader::load(default, files).await?;
+    Ok((reloader.resolver(), Some(Arc::new(reloader))))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CONGESTION_CONTROLLERS;
+    use crate::connection::UserPolicy;
+    use crate::server::acceptor::{REQUEST_TCP_IPV4, STATUS_OK};
+    use crate::server::auth::{AuthFuture, AuthResult};
+    use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_congestion_controller() {
+        for name in CONGESTION_CONTROLLERS {
+            assert!(congestion_controller(name).is_ok(), "{}", name);
+        }
+        assert!(congestion_controller("vegas").is_err());
+    }
+
+    /// Scratch directory removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("mytunnel-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A loopback server with a generated certificate, and a client trusting it
+    struct TestServer {
+        server: Server,
+        client: Endpoint,
+        addr: std::net::SocketAddr,
+        cert: rcgen::CertifiedKey,
+        /// Holds the certificate files
+        dir: TempDir,
+    }
+
+    /// Start a server on `127.0.0.1:0`, with the sections in `extra` merged
+    /// key by key over the base config
+    async fn test_server(name: &str, extra: &str) -> TestServer {
+        crate::util::init_crypto();
+        let dir = TempDir::new(name);
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let base = format!(
+            r#"
+            [server]
+            bind_addr = "127.0.0.1:0"
+            [quic]
+            [tls]
+            cert_path = "{}"
+            key_path = "{}"
+            [pool]
+            [metrics]
+            [logging]
+        "#,
+            dir.write("server.pem", &cert.cert.pem()).display(),
+            dir.write("server.key", &cert.key_pair.serialize_pem()).display(),
+        );
+        let mut table: toml::Table = base.parse().unwrap();
+        for (section, values) in extra.parse::<toml::Table>().unwrap() {
+            match (table.get_mut(&section), values) {
+                (Some(toml::Value::Table(base)), toml::Value::Table(values)) => base.extend(values),
+                (_, values) => {
+                    table.insert(section, values);
+                }
+            }
+        }
+        let config: Config = toml::Value::Table(table).try_into().unwrap();
+
+        let server = Server::new(Arc::new(config)).await.unwrap();
+        let addr = server.local_addrs()[0];
+        let client = test_client(client_tls(&[cert.cert.der().clone()]));
+        TestServer { server, client, addr, cert, dir }
+    }
+
+    /// Client TLS config trusting `roots`, offering the server's ALPN
+    fn client_tls(roots: &[CertificateDer<'static>]) -> rustls::ClientConfig {
+        let mut store = rustls::RootCertStore::empty();
+        for root in roots {
+            store.add(root.clone()).unwrap();
+        }
+        let mut tls = rustls::ClientConfig::builder()
+            .with_root_certificates(store)
+            .with_no_client_auth();
+        tls.alpn_protocols = vec![b"mytunnel".to_vec()];
+        tls
+    }
+
+    /// Client endpoint on `127.0.0.1:0` connecting with `tls`
+    fn test_client(tls: rustls::ClientConfig) -> Endpoint {
+        let mut client = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        client.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+            quinn::crypto::rustls::QuicClientConfig::try_from(tls).unwrap(),
+        )));
+        client
+    }
+
+    fn mtls_config(dir: &TempDir, server_cert: &str, server_key: &str, ca: &Path) -> Config {
+        let toml = format!(
+            r#"
+            [server]
+            bind_addr = "127.0.0.1:0"
+            [quic]
+            [tls]
+            cert_path = "{}"
+            key_path = "{}"
+            require_client_cert = true
+            client_ca_path = "{}"
+            [pool]
+            [metrics]
+            [logging]
+        "#,
+            dir.write("server.pem", server_cert).display(),
+            dir.write("server.key", server_key).display(),
+            ca.display()
+        );
+        toml::from_str(&toml).unwrap()
+    }
+
+    /// Handshake against an mTLS server, returning whether the server accepted it
+    async fn mtls_handshake(
+        config: &Config,
+        server_cert: CertificateDer<'static>,
+        identity: Option<(CertificateDer<'static>, PrivateKeyDer<'static>)>,
+    ) -> bool {
+        let (certs, _) = load_or_generate_certs(config).await.unwrap();
+        let server_config = build_server_config(config, certs).await.unwrap();
+        let server = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(server_cert).unwrap();
+        let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+        let mut tls = match identity {
+            Some((cert, key)) => builder.with_client_auth_cert(vec![cert], key).unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        tls.alpn_protocols = vec![b"mytunnel".to_vec()];
+        let client = test_client(tls);
+
+        let connecting = client.connect(server.local_addr().unwrap(), "localhost").unwrap();
+        let (_client_conn, server_conn) = tokio::join!(connecting, async {
+            server.accept().await.unwrap().await
+        });
+        server_conn.is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls() {
+        // main() installs this; several rustls providers are linked in
+        crate::util::init_crypto();
+        let dir = TempDir::new("mtls");
+
+        // Client CA and a client certificate it signed
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().unwrap();
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+        let ca_path = dir.write("ca.pem", &ca_cert.pem());
+
+        let mut client_params = CertificateParams::new(vec!["client".to_string()]).unwrap();
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = client_params.signed_by(&client_key, &ca_cert, &ca_key).unwrap();
+
+        // A self-signed client certificate outside the CA
+        let rogue = rcgen::generate_simple_self_signed(vec!["client".to_string()]).unwrap();
+
+        let server = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let config = mtls_config(
+            &dir,
+            &server.cert.pem(),
+            &server.key_pair.serialize_pem(),
+            &ca_path,
+        );
+        let server_cert = server.cert.der().clone();
+
+        let trusted = (
+            client_cert.der().clone(),
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(client_key.serialize_der())),
+        );
+        let untrusted = (
+            rogue.cert.der().clone(),
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(rogue.key_pair.serialize_der())),
+        );
+
+        assert!(mtls_handshake(&config, server_cert.clone(), Some(trusted)).await);
+        assert!(!mtls_handshake(&config, server_cert.clone(), Some(untrusted)).await);
+        assert!(!mtls_handshake(&config, server_cert, None).await);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_bind_addrs() {
+        let extra = r#"
+            [server]
+            bind_addr = ["127.0.0.1:0", "127.0.0.2:0"]
+            reuseport_sockets = 2
+            [pool]
+            buffer_count_4k = 1
+            buffer_count_16k = 1
+            buffer_count_64k = 1
+            connection_slots = 8
+        "#;
+        let TestServer { server, client, .. } = test_server("bind-addrs", extra).await;
+        let addrs = server.local_addrs();
+        assert_eq!(addrs.len(), 4);
+
+        // SO_REUSEPORT sockets share their address's port
+        assert_eq!(addrs[0], addrs[1]);
+        assert_eq!(addrs[2], addrs[3]);
+        assert_ne!(addrs[0], addrs[2]);
+
+        let clients = async {
+            for addr in &addrs {
+                let conn = client.connect(*addr, "localhost").unwrap().await.unwrap();
+                conn.close(VarInt::from_u32(0), b"done");
+            }
+            server.shutdown().await;
+        };
+
+        // run() returns only once every endpoint is closed
+        let (result, ()) = tokio::join!(server.run(), clients);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reject_at_capacity() {
+        let extra = "[pool]\nconnection_slots = 1";
+        let TestServer { server, client, addr, .. } = test_server("capacity", extra).await;
+
+        let before = METRICS.connections_rejected_capacity.load(Ordering::Relaxed);
+        let clients = async {
+            let first = client.connect(addr, "localhost").unwrap().await.unwrap();
+            // Give the server time to register the first connection
+            while !server.conn_manager.is_full() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            let second = client.connect(addr, "localhost").unwrap().await.unwrap();
+            let reason = second.closed().await;
+            first.close(VarInt::from_u32(0), b"done");
+            server.shutdown().await;
+            reason
+        };
+
+        let (result, reason) = tokio::join!(server.run(), clients);
+        assert!(result.is_ok());
+        match reason {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                assert_eq!(close.error_code, CloseCode::Capacity.code());
+                assert_eq!(&close.reason[..], b"server at capacity");
+            }
+            other => panic!("unexpected close: {other:?}"),
+        }
+        assert!(METRICS.connections_rejected_capacity.load(Ordering::Relaxed) > before);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_limit_under_connect_burst() {
+        let extra = "[server]\nmax_concurrent_handshakes = 2";
+        let TestServer { server, client, addr, .. } = test_server("handshake-burst", extra).await;
+
+        let done = AtomicBool::new(false);
+        let sampler = async {
+            let mut peak = 0;
+            while !done.load(Ordering::Relaxed) {
+                peak = peak.max(server.handshake_limiter.in_progress());
+                tokio::task::yield_now().await;
+            }
+            peak
+        };
+        let clients = async {
+            let mut attempts = tokio::task::JoinSet::new();
+            for _ in 0..32 {
+                let connecting = client.connect(addr, "localhost").unwrap();
+                attempts.spawn(tokio::time::timeout(Duration::from_secs(10), connecting));
+            }
+            let mut results = Vec::new();
+            while let Some(result) = attempts.join_next().await {
+                results.push(result.unwrap());
+            }
+            done.store(true, Ordering::Relaxed);
+            server.shutdown().await;
+            results
+        };
+
+        let (result, peak, results) = tokio::join!(server.run(), sampler, clients);
+        assert!(result.is_ok());
+        assert!(peak <= 2, "{peak} handshakes ran at once");
+
+        // Every attempt settles: it either connected or was refused
+        let connected = results.iter().filter(|r| matches!(r, Ok(Ok(_)))).count();
+        let refused = results
+            .iter()
+            .filter(|r| matches!(r, Ok(Err(quinn::ConnectionError::ConnectionClosed(_)))))
+            .count();
+        assert!(connected >= 2, "only {connected} connected");
+        assert_eq!(connected + refused, 32);
+        assert_eq!(server.handshake_limiter.in_progress(), 0);
+    }
+
+    /// Allows only the token `alice`, on one connection at a time
+    struct AliceOnly;
+
+    impl Authenticator for AliceOnly {
+        fn authenticate<'a>(&'a self, token: &'a [u8], _: std::net::SocketAddr) -> AuthFuture<'a> {
+            let result = if token == b"alice" {
+                AuthResult::AllowWith(UserPolicy {
+                    user: "alice".to_string(),
+                    max_connections: Some(1),
+                    ..Default::default()
+                })
+            } else {
+                AuthResult::Deny
+            };
+            Box::pin(std::future::ready(result))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_authenticator() {
+        // No [auth] section: the supplied authenticator alone gates clients
+        let TestServer { server, client, addr, .. } = test_server("custom-auth", "").await;
+        let server = server.with_authenticator(Arc::new(AliceOnly));
+        let before = METRICS.connections_rejected_auth.load(Ordering::Relaxed);
+
+        let connect_as = |token: &'static [u8]| {
+            let client = client.clone();
+            async move {
+                let connection = client.connect(addr, "localhost").unwrap().await.unwrap();
+                let mut send = connection.open_uni().await.unwrap();
+                send.write_all(&[0x02, token.len() as u8]).await.unwrap();
+                send.write_all(token).await.unwrap();
+                send.finish().unwrap();
+                connection
+            }
+        };
+        let clients = async {
+            let eve = connect_as(b"eve").await;
+            let reason = eve.closed().await;
+
+            let alice = connect_as(b"alice").await;
+            while server.conn_manager.user_connection_count("alice") == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            // Her second connection is over her limit
+            let again = connect_as(b"alice").await.closed().await;
+            assert!(alice.close_reason().is_none());
+            alice.close(VarInt::from_u32(0), b"done");
+            server.shutdown().await;
+            (reason, again)
+        };
+
+        let (result, (reason, again)) = tokio::join!(server.run(), clients);
+        assert!(result.is_ok());
+        for (reason, code) in [(reason, CloseCode::AuthFailed), (again, CloseCode::Capacity)] {
+            match reason {
+                quinn::ConnectionError::ApplicationClosed(close) => {
+                    assert_eq!(close.error_code, code.code());
+                }
+                other => panic!("unexpected close: {other:?}"),
+            }
+        }
+        assert!(METRICS.connections_rejected_auth.load(Ordering::Relaxed) > before);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_slot_released_before_auth() {
+        let TestServer { server, client, addr, .. } = test_server("auth-slot", "").await;
+        let server = server.with_authenticator(Arc::new(AliceOnly));
+
+        let clients = async {
+            // Connected, but never sends its token
+            let connection = client.connect(addr, "localhost").unwrap().await.unwrap();
+            let released = async {
+                while server.handshake_limiter.in_progress() > 0 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            let waited = tokio::time::timeout(Duration::from_secs(2), released).await;
+            assert!(waited.is_ok(), "handshake slot held while waiting for auth");
+            assert!(connection.close_reason().is_none());
+
+            connection.close(VarInt::from_u32(0), b"done");
+            server.shutdown().await;
+        };
+
+        let (result, ()) = tokio::join!(server.run(), clients);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_accepts_0rtt() {
+        let extra = "[quic]\nenable_0rtt = true";
+        let TestServer { server, addr, cert, .. } = test_server("zero-rtt", extra).await;
+        let mut tls = client_tls(&[cert.cert.der().clone()]);
+        tls.enable_early_data = true;
+        let client = test_client(tls);
+
+        let clients = async {
+            // The first connection leaves the client a session ticket
+            let first = client.connect(addr, "localhost").unwrap().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            first.close(VarInt::from_u32(0), b"done");
+
+            let connecting = client.connect(addr, "localhost").unwrap();
+            let (connection, accepted) = match connecting.into_0rtt() {
+                Ok(resumed) => resumed,
+                Err(_) => panic!("no session ticket to resume with"),
+            };
+            assert!(accepted.await, "server rejected 0-RTT");
+            while server.conn_manager.list_connections().len() != 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert!(connection.close_reason().is_none());
+
+            connection.close(VarInt::from_u32(0), b"done");
+            server.shutdown().await;
+        };
+
+        let (result, ()) = tokio::join!(server.run(), clients);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        // Streams never time out, so only the drain deadline ends the one below
+        let extra = r#"
+            [server]
+            drain_timeout_secs = 1
+            [proxy]
+            stream_idle_timeout_secs = 0
+        "#;
+        let TestServer { server, client, addr, .. } = test_server("drain-timeout", extra).await;
+        // An origin that accepts and then stays silent
+        let origin = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin_port = origin.local_addr().unwrap().port();
+
+        let clients = async {
+            let connection = client.connect(addr, "localhost").unwrap().await.unwrap();
+            // A proxied stream that is open and idle when the shutdown starts
+            let (mut send, mut recv) = connection.open_bi().await.unwrap();
+            let mut request = vec![REQUEST_TCP_IPV4];
+            request.extend_from_slice(&origin_port.to_be_bytes());
+            request.extend_from_slice(&[127, 0, 0, 1]);
+            send.write_all(&request).await.unwrap();
+            let mut status = [0u8; 1];
+            recv.read_exact(&mut status).await.unwrap();
+            assert_eq!(status[0], STATUS_OK);
+
+            let start = std::time::Instant::now();
+            server.shutdown().await;
+            (start.elapsed(), connection.closed().await)
+        };
+
+        let (result, (elapsed, reason)) = tokio::join!(server.run(), clients);
+        assert!(result.is_ok());
+        assert!(elapsed >= Duration::from_millis(900), "drained in {elapsed:?}");
+        assert!(elapsed < Duration::from_secs(10), "drained in {elapsed:?}");
+        match reason {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                assert_eq!(close.error_code, CloseCode::ServerShutdown.code());
+            }
+            other => panic!("unexpected close: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cert_hot_reload() {
+        let TestServer { server, addr, cert: first, dir, .. } =
+            test_server("cert-reload", "").await;
+        let second = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+        let mut tls = client_tls(&[first.cert.der().clone(), second.cert.der().clone()]);
+        // A resumed session would report the certificate of the first handshake
+        tls.resumption = rustls::client::Resumption::disabled();
+        let client = test_client(tls);
+
+        let presented = |conn: &quinn::Connection| {
+            let chain = conn.peer_identity().unwrap();
+            chain.downcast::<Vec<CertificateDer<'static>>>().unwrap()[0].clone()
+        };
+
+        let clients = async {
+            let old = client.connect(addr, "localhost").unwrap().await.unwrap();
+            assert_eq!(presented(&old), *first.cert.der());
+
+            dir.write("server.pem", &second.cert.pem());
+            dir.write("server.key", &second.key_pair.serialize_pem());
+            let reloader = server.cert_reloader.as_ref().unwrap();
+            assert!(reloader.reload_if_changed().await.unwrap());
+
+            // New handshakes see the new certificate; the old connection lives on
+            let new = client.connect(addr, "localhost").unwrap().await.unwrap();
+            assert_eq!(presented(&new), *second.cert.der());
+            assert!(old.close_reason().is_none());
+
+            old.close(VarInt::from_u32(0), b"done");
+            new.close(VarInt::from_u32(0), b"done");
+            server.shutdown().await;
+        };
+
+        let (result, ()) = tokio::join!(server.run(), clients);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cert_reload_task_stops() {
+        let extra = "[tls]\nreload_interval_secs = 1";
+        let TestServer { server, .. } = test_server("cert-reload-stop", extra).await;
+        let reloader = server.cert_reloader.clone().unwrap();
+
+        let shutdown = async {
+            // The task holds its own reference while it runs
+            while Arc::strong_count(&reloader) < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            server.shutdown().await;
+        };
+        let (result, ()) = tokio::join!(server.run(), shutdown);
+        assert!(result.is_ok());
+
+        let stopped = async {
+            while Arc::strong_count(&reloader) > 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), stopped).await.unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+//! Memory watchdog
+//!
+//! Samples the process's resident memory and, while it is above
+//! `[limits] max_memory_mb`, fails readiness, refuses new connections and
+//! sheds idle ones until usage falls back.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::connection::ConnectionManager;
+use crate::metrics::METRICS;
+
+/// How often resident memory is sampled
+pub const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most idle connections closed per check while over the limit
+const SHED_BATCH: usize = 64;
+
+/// Pressure ends once usage falls below this share of the limit, so the
+/// server doesn't flap around the threshold
+const RECOVERY_RATIO: f64 = 0.9;
+
+/// Sheds load while the process uses more memory than allowed
+pub struct MemoryWatchdog {
+    /// Limit in bytes (0 = disabled)
+    limit_bytes: AtomicU64,
+    pressure: AtomicBool,
+    /// Set once the server starts draining; recovery then leaves `ready` alone
+    draining: AtomicBool,
+    conn_manager: Arc<ConnectionManager>,
+    /// Readiness flag cleared while under pressure
+    ready: Arc<AtomicBool>,
+}
+
+impl MemoryWatchdog {
+    /// Create a watchdog enforcing `limit_mb` (0 = disabled)
+    pub fn new(
+        limit_mb: usize,
+        conn_manager: Arc<ConnectionManager>,
+        ready: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            limit_bytes: AtomicU64::new(mb_to_bytes(limit_mb)),
+            pressure: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            conn_manager,
+            ready,
+        }
+    }
+
+    /// Change the limit; takes effect at the next check (0 = disabled)
+    pub fn set_limit(&self, limit_mb: usize) {
+        self.limit_bytes.store(mb_to_bytes(limit_mb), Ordering::Relaxed);
+    }
+
+    /// Stop restoring readiness on recovery; call before clearing `ready`
+    /// for shutdown
+    pub fn set_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether new connections should be refused
+    pub fn under_pressure(&self) -> bool {
+        self.pressure.load(Ordering::Acquire)
+    }
+
+    /// Check memory every [`MEMORY_CHECK_INTERVAL`] until the server shuts down
+    ///
+    /// Does nothing on platforms without `/proc/self/statm`.
+    pub fn spawn(self: Arc<Self>, shutdown_rx: watch::Receiver<bool>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(MEMORY_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                if *shutdown_rx.borrow() {
+                    return;
+                }
+                if let Some(resident) = resident_bytes() {
+                    self.check(resident);
+                }
+            }
+        })
+    }
+
+    /// Update the pressure state for `resident` bytes in use
+    fn check(&self, resident: u64) {
+        let limit = self.limit_bytes.load(Ordering::Relaxed);
+        let over = limit > 0 && resident > limit;
+        let resident_mb = resident / (1024 * 1024);
+
+        if !self.under_pressure() {
+            if !over {
+                return;
+            }
+            self.pressure.store(true, Ordering::Release);
+            self.ready.store(false, Ordering::Release);
+            METRICS.memory_pressure.store(1, Ordering::Relaxed);
+            warn!(
+                resident_mb,
+                limit_mb = limit / (1024 * 1024),
+                "Memory limit exceeded, refusing new connections"
+            );
+        } else if limit == 0 || (resident as f64) < limit as f64 * RECOVERY_RATIO {
+            self.pressure.store(false, Ordering::Release);
+            // Checked after the store so a drain starting meanwhile still
+            // ends with `ready` cleared
+            self.ready.store(true, Ordering::SeqCst);
+            if self.draining.load(Ordering::SeqCst) {
+                self.ready.store(false, Ordering::SeqCst);
+            }
+            METRICS.memory_pressure.store(0, Ordering::Relaxed);
+            info!(resident_mb, "Memory usage recovered, accepting connections");
+            return;
+        }
+
+        if over {
+            let shed = self.conn_manager.shed_idle(SHED_BATCH);
+            if shed > 0 {
+                warn!(shed, resident_mb, "Closed idle connections under memory pressure");
+            }
+        }
+    }
+}
+
+fn mb_to_bytes(mb: usize) -> u64 {
+    mb as u64 * 1024 * 1024
+}
+
+/// Resident set size of this process in bytes
+#[cfg(target_os = "linux")]
+pub fn resident_bytes() -> Option<u64> {
+    // statm: size resident shared text lib data dt, in pages
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE).ok()??;
+    Some(pages * page_size as u64)
+}
+
+/// Resident set size of this process in bytes
+#[cfg(not(target_os = "linux"))]
+pub fn resident_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionManagerConfig;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_pressure_transitions() {
+        let manager = ConnectionManager::new(ConnectionManagerConfig {
+            max_connections: 10,
+            idle_timeout: Duration::from_secs(30),
+        });
+        let ready = Arc::new(AtomicBool::new(true));
+        let watchdog = MemoryWatchdog::new(1000, manager, ready.clone());
+
+        watchdog.check(900 * MB);
+        assert!(!watchdog.under_pressure());
+
+        watchdog.check(1100 * MB);
+        assert!(watchdog.under_pressure());
+        assert!(!ready.load(Ordering::Acquire));
+
+        // Still within the recovery margin
+        watchdog.check(950 * MB);
+        assert!(watchdog.under_pressure());
+
+        watchdog.check(850 * MB);
+        assert!(!watchdog.under_pressure());
+        assert!(ready.load(Ordering::Acquire));
+
+        // Disabling the limit ends pressure too
+        watchdog.check(1100 * MB);
+        watchdog.set_limit(0);
+        watchdog.check(1100 * MB);
+        assert!(!watchdog.under_pressure());
+    }
+
+    #[test]
+    fn test_recovery_while_draining() {
+        let manager = ConnectionManager::new(ConnectionManagerConfig {
+            max_connections: 10,
+            idle_timeout: Duration::from_secs(30),
+        });
+        let ready = Arc::new(AtomicBool::new(true));
+        let watchdog = MemoryWatchdog::new(1000, manager, ready.clone());
+
+        watchdog.check(1100 * MB);
+        watchdog.set_draining();
+        ready.store(false, Ordering::SeqCst);
+
+        // Pressure ends, but a draining server stays unready
+        watchdog.check(500 * MB);
+        assert!(!watchdog.under_pressure());
+        assert!(!ready.load(Ordering::SeqCst));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_resident_bytes() {
+        assert!(resident_bytes().unwrap() > 0);
+    }
+}
@@ -3,8 +3,19 @@
 //! QUIC listener and connection handling.
 
 mod acceptor;
+mod auth;
+mod certs;
+mod limits;
 mod listener;
+mod memory;
+mod reverse;
+mod tickets;
 
 pub use listener::Server;
-pub use acceptor::ConnectionHandler;
+pub use acceptor::{reject_at_capacity, ConnectionHandler};
+pub use auth::{AuthFuture, AuthResult, Authenticator, StaticToken};
+pub use certs::{CertFiles, CertReloader, CertResolver};
+pub use limits::{HandshakeLimiter, HandshakePermit, PerIpBandwidth, StreamLimiter, StreamPermit};
+pub use memory::MemoryWatchdog;
+pub use tickets::FileTicketer;
 
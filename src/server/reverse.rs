@@ -0,0 +1,274 @@
+//! Reverse tunnels
+//!
+//! A client asks the server to listen on a TCP port; each connection made
+//! to it is handed to the client on a server-opened stream (like `ssh -R`).
+//! A one-shot bind instead carries the first connection on the request
+//! stream itself, as SOCKS5 BIND needs.
+
+use anyhow::{bail, Result};
+use quinn::{Connection, RecvStream, SendStream};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tracing::{debug, info};
+
+use crate::connection::{ConnectionId, ConnectionManager};
+use crate::proxy::{OriginConnection, TcpProxy};
+
+use super::acceptor::{
+    REASON_SERVER_BUSY, REASON_TIMEOUT, REASON_UNSPECIFIED, REQUEST_TCP_IPV4, REQUEST_TCP_IPV6,
+    STATUS_ERROR, STATUS_OK,
+};
+
+/// Stream request type: listen on a server port for the client
+pub(super) const REQUEST_BIND_REMOTE: u8 = 0x10;
+/// Server-opened stream type: a connection arrived on a bound port
+pub(super) const REQUEST_REVERSE_CONNECT: u8 = 0x11;
+/// Stream request type: listen on a server port for one connection and
+/// proxy it on the request stream
+pub(super) const REQUEST_BIND_ONCE: u8 = 0x12;
+/// One-shot bind peer type: take a connection from any address
+pub(super) const BIND_PEER_ANY: u8 = 0x00;
+
+/// Longest a one-shot bind waits for its connection
+const BIND_ONCE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Pause after a failed accept, so running out of fds doesn't spin the loop
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The client a reverse listener hands its connections to
+pub(super) struct ReverseClient {
+    pub conn_id: ConnectionId,
+    pub client_addr: SocketAddr,
+    pub connection: Connection,
+    pub conn_manager: Arc<ConnectionManager>,
+    /// Copies connections under the client's bandwidth budget and counters
+    pub proxy: TcpProxy,
+    /// Log each closed connection under the "access" target
+    pub access_log: bool,
+}
+
+impl ReverseClient {
+    /// Copy an accepted connection over `send` and `recv` until both sides
+    /// finish, recording its traffic like a tunneled stream
+    async fn proxy(
+        &self,
+        send: SendStream,
+        recv: RecvStream,
+        socket: OriginConnection,
+        peer: SocketAddr,
+        port: u16,
+    ) -> Result<()> {
+        let started = Instant::now();
+        let stats = self.proxy.proxy_connected(send, recv, socket).await?;
+        self.conn_manager.record_traffic(self.conn_id, stats.rx_bytes, stats.tx_bytes);
+
+        if self.access_log {
+            info!(
+                target: "access",
+                conn_id = %self.conn_id,
+                client_addr = %self.client_addr,
+                peer = %peer,
+                port,
+                bytes_in = stats.rx_bytes,
+                bytes_out = stats.tx_bytes,
+                duration_ms = started.elapsed().as_millis() as u64,
+                "Reverse stream closed"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A listener bound for one client
+pub(super) struct ReverseListener {
+    pub client: Arc<ReverseClient>,
+    pub listener: TcpListener,
+}
+
+impl ReverseListener {
+    /// Forward connections to the client until `control` ends, the QUIC
+    /// connection closes or the server shuts down
+    ///
+    /// Connections already forwarded keep running after the listener closes.
+    pub async fn serve(self, mut control: RecvStream) -> Result<()> {
+        let port = self.listener.local_addr()?.port();
+        let client = self.client;
+        let mut shutdown_rx = client.conn_manager.subscribe_shutdown();
+        let mut forwards = JoinSet::new();
+        let mut buf = [0u8; 64];
+
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => {
+                    let (socket, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            debug!(conn_id = %client.conn_id, error = %e, "Reverse accept error");
+                            tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                            continue;
+                        }
+                    };
+                    let client = client.clone();
+                    forwards.spawn(async move {
+                        if let Err(e) = forward(&client, socket, peer, port).await {
+                            let conn_id = client.conn_id;
+                            debug!(conn_id = %conn_id, %peer, error = %e, "Reverse stream error");
+                        }
+                    });
+                }
+                // Anything the client sends is ignored; only the end matters
+                read = control.read(&mut buf) => {
+                    if !matches!(read, Ok(Some(_))) {
+                        break;
+                    }
+                }
+                _ = client.connection.closed() => break,
+                _ = shutdown_rx.recv() => break,
+                Some(_) = forwards.join_next(), if !forwards.is_empty() => {}
+            }
+        }
+
+        forwards.detach_all();
+        Ok(())
+    }
+
+    /// Accept one connection from `expected` (None = any address) and
+    /// proxy it over `send` and `recv`
+    ///
+    /// Connections from other addresses are dropped and the wait goes on.
+    /// Once the peer connects, sends `[Status(1)][Type(1)][Address][Port(2)]`
+    /// with the peer address as a TCP connect request type would carry it.
+    /// Fails the stream with the timeout reason if nobody connects within
+    /// [`BIND_ONCE_TIMEOUT`], and gives up quietly if the client abandons
+    /// the stream, the QUIC connection closes or the server shuts down.
+    pub async fn serve_once(
+        self,
+        mut send: SendStream,
+        recv: RecvStream,
+        expected: Option<IpAddr>,
+    ) -> Result<()> {
+        let client = self.client;
+        let mut shutdown_rx = client.conn_manager.subscribe_shutdown();
+        let from_expected = async {
+            loop {
+                let (socket, peer) = self.listener.accept().await?;
+                let ip = peer.ip().to_canonical();
+                if expected.map_or(true, |expected| expected.to_canonical() == ip) {
+                    return io::Result::Ok((socket, peer));
+                }
+                debug!(conn_id = %client.conn_id, %peer, "Bind connection from unexpected peer");
+            }
+        };
+        let accepted = tokio::select! {
+            accepted = tokio::time::timeout(BIND_ONCE_TIMEOUT, from_expected) => accepted,
+            _ = send.stopped() => return Ok(()),
+            _ = client.connection.closed() => return Ok(()),
+            _ = shutdown_rx.recv() => return Ok(()),
+        };
+        let (socket, peer) = match accepted {
+            Ok(Ok(accepted)) => accepted,
+            Ok(Err(e)) => {
+                send.write_all(&[STATUS_ERROR, REASON_UNSPECIFIED]).await?;
+                let _ = send.finish();
+                return Err(e.into());
+            }
+            Err(_) => {
+                debug!(conn_id = %client.conn_id, "Bind timed out waiting for a connection");
+                send.write_all(&[STATUS_ERROR, REASON_TIMEOUT]).await?;
+                let _ = send.finish();
+                return Ok(());
+            }
+        };
+        let port = self.listener.local_addr()?.port();
+        drop(self.listener);
+
+        let socket = match client.proxy.adopt(socket).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                send.write_all(&[STATUS_ERROR, REASON_SERVER_BUSY]).await?;
+                let _ = send.finish();
+                return Err(e);
+            }
+        };
+        send.write_all(&encode_peer(peer)).await?;
+        debug!(conn_id = %client.conn_id, %peer, "Bind connection accepted");
+
+        client.proxy(send, recv, socket, peer, port).await
+    }
+}
+
+/// Bind a listener on `ip`, on `port` or for port 0 on any free port in `ports`
+///
+/// Port 0 takes the kernel's pick when it falls within `ports`, and
+/// otherwise the first free port in the range.
+pub(super) async fn bind_in_range(
+    ip: IpAddr,
+    port: u16,
+    ports: RangeInclusive<u16>,
+) -> io::Result<TcpListener> {
+    let listener = TcpListener::bind((ip, port)).await?;
+    if ports.contains(&listener.local_addr()?.port()) {
+        return Ok(listener);
+    }
+    drop(listener);
+
+    for port in ports.clone() {
+        match TcpListener::bind((ip, port)).await {
+            Ok(listener) => return Ok(listener),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("no free port in reverse range {}-{}", ports.start(), ports.end()),
+    ))
+}
+
+/// Encode the success reply carrying the peer of a one-shot bind
+fn encode_peer(peer: SocketAddr) -> Vec<u8> {
+    let mut reply = vec![STATUS_OK];
+    match peer.ip().to_canonical() {
+        IpAddr::V4(ip) => {
+            reply.push(REQUEST_TCP_IPV4);
+            reply.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            reply.push(REQUEST_TCP_IPV6);
+            reply.extend_from_slice(&ip.octets());
+        }
+    }
+    reply.extend_from_slice(&peer.port().to_be_bytes());
+    reply
+}
+
+/// Hand one inbound connection to the client and copy until both sides finish
+///
+/// The copy buffers are taken first, so a busy server drops the connection
+/// rather than opening a stream it can't serve.
+async fn forward(
+    client: &ReverseClient,
+    socket: TcpStream,
+    peer: SocketAddr,
+    port: u16,
+) -> Result<()> {
+    let socket = client.proxy.adopt(socket).await?;
+    let (mut send, mut recv) = client.connection.open_bi().await?;
+    let [hi, lo] = port.to_be_bytes();
+    send.write_all(&[REQUEST_REVERSE_CONNECT, hi, lo]).await?;
+
+    let status = recv.read_u8().await?;
+    if status != STATUS_OK {
+        let reason = recv.read_u8().await.unwrap_or(0);
+        bail!("client refused reverse connection: status {status:#04x}, reason {reason:#04x}");
+    }
+    debug!(conn_id = %client.conn_id, port, "Reverse connection established");
+
+    client.proxy(send, recv, socket, peer, port).await
+}
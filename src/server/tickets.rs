@@ -0,0 +1,235 @@
+//! Persistent TLS session ticket key
+//!
+//! rustls encrypts session tickets under keys that live only as long as the
+//! process, so a restart invalidates every ticket and clients fall back to
+//! full handshakes without 0-RTT. [`FileTicketer`] keeps a secret in a file
+//! instead, letting clients resume across restarts and rolling deploys.
+//!
+//! The ticket key itself rotates: each period's key is derived from the
+//! secret and the period number, so servers sharing the file rotate in
+//! step without talking to each other, and a leaked key only opens the
+//! tickets of its own period.
+
+use anyhow::{Context, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::hkdf::{Prk, HKDF_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::server::ProducesTickets;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Length of the key file contents
+const KEY_LEN: usize = 32;
+
+/// Seconds clients may hold a ticket, matching rustls' own ticketer
+const TICKET_LIFETIME: u32 = 60 * 60 * 12;
+
+/// Seconds each derived ticket key is issued under
+///
+/// Tickets are accepted for one more period after that, which covers
+/// their lifetime.
+const ROTATION_PERIOD: u64 = TICKET_LIFETIME as u64;
+
+/// Ticketer encrypting with ChaCha20-Poly1305 under keys derived from a
+/// secret kept on disk
+///
+/// Tickets are `[Nonce(12)][Ciphertext][Tag(16)]` with a random nonce.
+/// They are issued under the current period's key and accepted under the
+/// current or previous one.
+pub struct FileTicketer {
+    secret: Prk,
+    rng: SystemRandom,
+}
+
+impl FileTicketer {
+    /// Load the key at `path`, or generate one and write it there
+    ///
+    /// The file is created readable by the owner only. Servers sharing the
+    /// file can resume each other's sessions.
+    pub fn load_or_create(path: &Path) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let key_bytes = match std::fs::read(path) {
+            Ok(bytes) => {
+                if bytes.len() != KEY_LEN {
+                    anyhow::bail!(
+                        "Session ticket key {} is {} bytes, expected {}",
+                        path.display(),
+                        bytes.len(),
+                        KEY_LEN
+                    );
+                }
+                bytes
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut bytes = vec![0u8; KEY_LEN];
+                rng.fill(&mut bytes)
+                    .map_err(|_| anyhow::anyhow!("Failed to generate session ticket key"))?;
+                write_key(path, &bytes).with_context(|| {
+                    format!("Failed to write session ticket key {}", path.display())
+                })?;
+                info!(path = %path.display(), "Generated session ticket key");
+                bytes
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to read session ticket key {}", path.display())
+                })
+            }
+        };
+
+        Ok(Self {
+            secret: Prk::new_less_safe(HKDF_SHA256, &key_bytes),
+            rng,
+        })
+    }
+
+    /// Key for rotation period `period`
+    fn key(&self, period: u64) -> Option<LessSafeKey> {
+        let period = period.to_be_bytes();
+        let info = [&period[..]];
+        let okm = self.secret.expand(&info, &CHACHA20_POLY1305).ok()?;
+        Some(LessSafeKey::new(UnboundKey::from(okm)))
+    }
+
+    fn encrypt_at(&self, plain: &[u8], period: u64) -> Option<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).ok()?;
+
+        let mut ticket = Vec::with_capacity(NONCE_LEN + plain.len() + CHACHA20_POLY1305.tag_len());
+        ticket.extend_from_slice(&nonce);
+        let mut sealed = plain.to_vec();
+        self.key(period)?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .ok()?;
+        ticket.extend_from_slice(&sealed);
+        Some(ticket)
+    }
+
+    fn decrypt_at(&self, cipher: &[u8], period: u64) -> Option<Vec<u8>> {
+        if cipher.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, sealed) = cipher.split_at(NONCE_LEN);
+
+        // The tag only verifies under the key the ticket was sealed with
+        [period, period.saturating_sub(1)].into_iter().find_map(|period| {
+            let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+            let mut plain = sealed.to_vec();
+            let len = self.key(period)?.open_in_place(nonce, Aad::empty(), &mut plain).ok()?.len();
+            plain.truncate(len);
+            Some(plain)
+        })
+    }
+}
+
+/// The rotation period the clock is in now
+fn current_period() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.as_secs() / ROTATION_PERIOD
+}
+
+/// Create `path` with owner-only permissions; an existing file is an error,
+/// so two servers starting together can't overwrite each other's key
+fn write_key(path: &Path, key: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(key)?;
+    file.sync_all()
+}
+
+impl ProducesTickets for FileTicketer {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        TICKET_LIFETIME
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.encrypt_at(plain, current_period())
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        self.decrypt_at(cipher, current_period())
+    }
+}
+
+impl fmt::Debug for FileTicketer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileTicketer").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_reused_on_second_load() {
+        let dir = std::env::temp_dir()
+            .join(format!("mytunnel-tickets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ticket.key");
+        let _ = std::fs::remove_file(&path);
+
+        let first = FileTicketer::load_or_create(&path).unwrap();
+        let key = std::fs::read(&path).unwrap();
+        assert_eq!(key.len(), KEY_LEN);
+        let ticket = first.encrypt(b"session state").unwrap();
+
+        // A restarted server reads the same key and accepts the old ticket
+        let second = FileTicketer::load_or_create(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), key);
+        assert_eq!(second.decrypt(&ticket).unwrap(), b"session state");
+
+        // Tampered tickets are rejected
+        let mut tampered = ticket.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(second.decrypt(&tampered).is_none());
+        assert!(second.decrypt(&ticket[..4]).is_none());
+
+        std::fs::write(&path, b"short").unwrap();
+        assert!(FileTicketer::load_or_create(&path).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let dir = std::env::temp_dir()
+            .join(format!("mytunnel-tickets-rotation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ticket.key");
+        let _ = std::fs::remove_file(&path);
+        let ticketer = FileTicketer::load_or_create(&path).unwrap();
+
+        let period = current_period();
+        let ticket = ticketer.encrypt_at(b"session state", period).unwrap();
+        assert_eq!(ticketer.decrypt_at(&ticket, period).unwrap(), b"session state");
+
+        // Still accepted one period on, under the previous key
+        assert_eq!(ticketer.decrypt_at(&ticket, period + 1).unwrap(), b"session state");
+        // but issued under the new one
+        let newer = ticketer.encrypt_at(b"session state", period + 1).unwrap();
+        assert!(ticketer.decrypt_at(&newer, period).is_none());
+
+        // Rejected from two periods on
+        assert!(ticketer.decrypt_at(&ticket, period + 2).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
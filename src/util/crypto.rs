@@ -0,0 +1,25 @@
+//! rustls crypto provider setup
+
+use tracing::debug;
+
+/// Install the ring crypto provider as the process default for rustls
+///
+/// Safe to call more than once: a provider that is already installed, by
+/// an earlier call or by an embedding application, is kept.
+pub fn init_crypto() {
+    if rustls::crypto::ring::default_provider().install_default().is_err() {
+        debug!("rustls crypto provider already installed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_crypto_idempotent() {
+        init_crypto();
+        init_crypto();
+        assert!(rustls::crypto::CryptoProvider::get_default().is_some());
+    }
+}
@@ -1,41 +1,169 @@
 //! io_uring helpers for Linux
 //!
 //! This module provides io_uring integration for zero-copy I/O operations.
-//! Only available on Linux with kernel 5.6+.
+//! Only available on Linux with kernel 5.7+ (splice support).
 
 #![cfg(target_os = "linux")]
 
+use io_uring::{opcode, types, IoUring, Probe};
+use once_cell::sync::OnceCell;
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
 use std::os::unix::io::RawFd;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::net::TcpStream;
 
-/// Check if io_uring is available on this system
+/// Check if io_uring splice is available on this system
+///
+/// Probes the kernel once; seccomp filters or old kernels report false.
 pub fn is_available() -> bool {
-    // Try to probe for io_uring support
-    // In a real implementation, we would use the io_uring crate
-    // For now, check kernel version
-    if let Ok(uname) = nix::sys::utsname::uname() {
-        let release = uname.release().to_string_lossy();
-        if let Some(major_str) = release.split('.').next() {
-            if let Ok(major) = major_str.parse::<u32>() {
-                return major >= 5;
+    static AVAILABLE: OnceCell<bool> = OnceCell::new();
+    *AVAILABLE.get_or_init(|| probe_splice().unwrap_or(false))
+}
+
+fn probe_splice() -> io::Result<bool> {
+    let ring = IoUring::new(2)?;
+    let mut probe = Probe::new();
+    ring.submitter().register_probe(&mut probe)?;
+    Ok(probe.is_supported(opcode::Splice::CODE))
+}
+
+/// Reads a socket through a pipe using io_uring splice
+///
+/// Data moves from the socket into the pipe inside the kernel; only the
+/// final pipe read copies it to userspace.
+pub struct SpliceReader {
+    /// Ring whose fd becomes readable when completions are queued
+    ring: AsyncFd<IoUring>,
+    pipe_read: OwnedFd,
+    pipe_write: OwnedFd,
+    /// Tag for the next submission, so stale completions can be skipped
+    next_id: u64,
+    /// Submission still running after the read waiting on it was cancelled
+    in_flight: Option<u64>,
+    /// Bytes spliced into the pipe but not yet read out of it
+    buffered: usize,
+}
+
+impl SpliceReader {
+    /// Set up a ring and pipe
+    pub fn new() -> io::Result<Self> {
+        let ring = AsyncFd::new(IoUring::new(8)?)?;
+        let (pipe_read, pipe_write) = nix::unistd::pipe().map_err(io::Error::other)?;
+        Ok(Self {
+            ring,
+            pipe_read,
+            pipe_write,
+            next_id: 0,
+            in_flight: None,
+            buffered: 0,
+        })
+    }
+
+    /// Read up to `buf.len()` bytes from `socket`, waiting for data
+    ///
+    /// Returns 0 at EOF. `buf` must not be larger than the pipe capacity
+    /// (64KB by default).
+    ///
+    /// Cancel-safe: bytes a cancelled read already spliced stay in the pipe
+    /// and are returned by the next read, ahead of anything newer.
+    pub async fn read(&mut self, socket: &TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffered == 0 {
+            self.buffered = self.fill(socket, buf.len()).await?;
+        }
+
+        let n = self.buffered.min(buf.len());
+        let mut filled = 0;
+        while filled < n {
+            match nix::unistd::read(self.pipe_read.as_raw_fd(), &mut buf[filled..n]) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => filled += read,
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(e) => return Err(e.into()),
             }
         }
+        self.buffered -= n;
+
+        Ok(n)
     }
-    false
-}
 
-/// Placeholder for io_uring-based splice operation
-/// In production, this would use tokio-uring or io-uring crate
-pub async fn splice_async(
-    _fd_in: RawFd,
-    _fd_out: RawFd,
-    _len: usize,
-) -> std::io::Result<usize> {
-    // This is a placeholder - real implementation would use io_uring
-    // For MVP, we fall back to regular splice() in proxy/tcp.rs
-    Err(std::io::Error::new(
-        std::io::ErrorKind::Unsupported,
-        "io_uring splice not yet implemented, using sync splice",
-    ))
+    /// Splice up to `len` bytes from `socket` into the pipe, waiting for data
+    async fn fill(&mut self, socket: &TcpStream, len: usize) -> io::Result<usize> {
+        // A cancelled read's splice took the oldest bytes, so it goes first
+        if let Some(id) = self.in_flight {
+            match self.complete(id).await {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+        }
+
+        let pipe_write = self.pipe_write.as_raw_fd();
+        loop {
+            match self.splice_async(socket.as_raw_fd(), pipe_write, len).await {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // The socket is non-blocking; clear stale readiness and wait
+                    let _ = socket.try_io(Interest::READABLE, || {
+                        Err::<(), _>(io::ErrorKind::WouldBlock.into())
+                    });
+                    socket.readable().await?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Splice up to `len` bytes from `fd_in` to `fd_out` via io_uring
+    ///
+    /// One of the two must be a pipe. Fails with `WouldBlock` if `fd_in`
+    /// is a non-blocking socket with nothing to read.
+    pub async fn splice_async(
+        &mut self,
+        fd_in: RawFd,
+        fd_out: RawFd,
+        len: usize,
+    ) -> io::Result<usize> {
+        self.next_id += 1;
+        let id = self.next_id;
+
+        let entry = opcode::Splice::new(types::Fd(fd_in), -1, types::Fd(fd_out), -1, len as u32)
+            .build()
+            .user_data(id);
+
+        // SAFETY: splice only references the two fds, which the caller keeps open
+        unsafe { self.ring.get_mut().submission().push(&entry) }
+            .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+        self.ring.get_ref().submit()?;
+        self.in_flight = Some(id);
+
+        self.complete(id).await
+    }
+
+    /// Wait for the completion of submission `id`
+    async fn complete(&mut self, id: u64) -> io::Result<usize> {
+        loop {
+            let mut guard = self.ring.readable_mut().await?;
+
+            // Completions of other submissions are stale and skipped
+            let result = guard
+                .get_inner_mut()
+                .completion()
+                .find(|cqe| cqe.user_data() == id)
+                .map(|cqe| cqe.result());
+
+            match result {
+                Some(res) => {
+                    self.in_flight = None;
+                    return if res < 0 {
+                        Err(io::Error::from_raw_os_error(-res))
+                    } else {
+                        Ok(res as usize)
+                    };
+                }
+                None => guard.clear_ready(),
+            }
+        }
+    }
 }
 
 /// Placeholder for io_uring-based sendmmsg
@@ -49,3 +177,60 @@ pub async fn sendmmsg_async(
     ))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_splice_reader() {
+        if !is_available() {
+            return;
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut writer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut reader = SpliceReader::new().unwrap();
+        let mut buf = [0u8; 1024];
+
+        // Waits for data that isn't there yet
+        let read = tokio::spawn(async move {
+            let n = reader.read(&socket, &mut buf).await.unwrap();
+            (buf[..n].to_vec(), reader, socket)
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        writer.write_all(b"hello").await.unwrap();
+        let (data, mut reader, socket) = read.await.unwrap();
+        assert_eq!(data, b"hello");
+
+        drop(writer);
+        assert_eq!(reader.read(&socket, &mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_splice_reader_cancelled() {
+        if !is_available() {
+            return;
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut writer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let mut reader = SpliceReader::new().unwrap();
+        let mut buf = [0u8; 1024];
+
+        // Gives up on a read with nothing to splice yet
+        let read = reader.read(&socket, &mut buf);
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(50), read)
+            .await
+            .is_err());
+
+        writer.write_all(b"hello").await.unwrap();
+        let n = reader.read(&socket, &mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    }
+}
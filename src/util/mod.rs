@@ -1,11 +1,16 @@
 //! Utility modules
 
+mod crypto;
 mod socket;
 mod tracing_setup;
 
+pub use crypto::init_crypto;
 pub use socket::*;
 pub use tracing_setup::init_tracing;
 
 #[cfg(target_os = "linux")]
 pub mod io_uring;
 
+
+#[cfg(test)]
+pub(crate) mod testing;
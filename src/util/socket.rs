@@ -1,15 +1,25 @@
 //! Socket utilities and tuning
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use socket2::{Domain, Protocol, Socket, Type};
-use std::net::SocketAddr;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use tokio::net::{TcpSocket, TcpStream};
+use tracing::debug;
 
 /// Socket buffer sizes for high performance
 pub const RECV_BUFFER_SIZE: usize = 8 * 1024 * 1024; // 8MB
 pub const SEND_BUFFER_SIZE: usize = 8 * 1024 * 1024; // 8MB
 
 /// Create an optimized UDP socket for QUIC
-pub fn create_udp_socket(addr: SocketAddr, reuse_port: bool) -> Result<std::net::UdpSocket> {
+///
+/// `only_v6` disables IPv4-mapped traffic on IPv6 sockets.
+pub fn create_udp_socket(
+    addr: SocketAddr,
+    reuse_port: bool,
+    only_v6: bool,
+) -> Result<std::net::UdpSocket> {
     let domain = if addr.is_ipv4() {
         Domain::IPV4
     } else {
@@ -18,22 +28,31 @@ pub fn create_udp_socket(addr: SocketAddr, reuse_port: bool) -> Result<std::net:
 
     let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
 
-    // Enable address reuse
-    socket.set_reuse_address(true)?;
+    // Needed to bind "[::]" next to "0.0.0.0" on the same port
+    if only_v6 && addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+
+    // Share the port only when asked; otherwise a second server on the
+    // same port fails to bind instead of silently splitting the traffic
+    socket.set_reuse_address(reuse_port)?;
 
     // Enable port reuse for multi-core scaling (Unix only)
     #[cfg(all(unix, not(target_os = "macos")))]
     if reuse_port {
         use std::os::unix::io::AsRawFd;
-        unsafe {
-            let optval: libc::c_int = 1;
+        let optval: libc::c_int = 1;
+        let ret = unsafe {
             libc::setsockopt(
                 socket.as_raw_fd(),
                 libc::SOL_SOCKET,
                 libc::SO_REUSEPORT,
                 &optval as *const _ as *const libc::c_void,
                 std::mem::size_of::<libc::c_int>() as libc::socklen_t,
-            );
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to set SO_REUSEPORT");
         }
     }
 
@@ -80,9 +99,99 @@ pub fn create_tcp_socket(addr: SocketAddr) -> Result<Socket> {
     Ok(socket)
 }
 
+/// Create a socket for an origin connection to `target`
+///
+/// With `fast_open` the first write goes out in the SYN (TCP Fast Open).
+/// A kernel that refuses the option gets a normal connect instead.
+fn create_origin_socket(target: SocketAddr, fast_open: bool) -> Result<Socket> {
+    let socket = create_tcp_socket(target)?;
+    if fast_open {
+        if let Err(e) = set_fast_open_connect(&socket) {
+            debug!(error = %e, %target, "TCP Fast Open unavailable, connecting normally");
+        }
+    }
+    Ok(socket)
+}
+
+/// Connect to `target`, using TCP Fast Open if `fast_open` is set
+pub async fn connect_tcp(target: SocketAddr, fast_open: bool) -> Result<TcpStream> {
+    if !fast_open {
+        return Ok(TcpStream::connect(target).await?);
+    }
+    let socket = create_origin_socket(target, true)?;
+    let socket = TcpSocket::from_std_stream(std::net::TcpStream::from(socket));
+    Ok(socket.connect(target).await?)
+}
+
+/// Local address to bind when reaching `target`: `source_ip` if given,
+/// otherwise the unspecified address of the target's family
+pub fn local_bind_addr(target: SocketAddr, source_ip: Option<IpAddr>, port: u16) -> SocketAddr {
+    let ip = source_ip.unwrap_or(if target.is_ipv4() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    });
+    SocketAddr::new(ip, port)
+}
+
+/// Connect to `target` from the local address `source_ip`
+pub async fn connect_tcp_from(
+    target: SocketAddr,
+    source_ip: IpAddr,
+    fast_open: bool,
+) -> Result<TcpStream> {
+    let socket = create_origin_socket(target, fast_open)?;
+    socket.bind(&SocketAddr::new(source_ip, 0).into())?;
+
+    let socket = TcpSocket::from_std_stream(std::net::TcpStream::from(socket));
+    Ok(socket.connect(target).await?)
+}
+
+/// Connect to `target` from a local port within `ports`
+///
+/// The source IP is `source_ip` if given. Ports are tried in order until
+/// one can be bound and connected. Fails with a clear error if every
+/// port in the range is taken.
+pub async fn connect_tcp_in_port_range(
+    target: SocketAddr,
+    source_ip: Option<IpAddr>,
+    ports: RangeInclusive<u16>,
+    fast_open: bool,
+) -> Result<TcpStream> {
+    for port in ports.clone() {
+        let socket = create_origin_socket(target, fast_open)?;
+        // Source ports must be exclusive, otherwise every bind succeeds
+        socket.set_reuse_address(false)?;
+
+        match socket.bind(&local_bind_addr(target, source_ip, port).into()) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e.into()),
+        }
+
+        let socket = TcpSocket::from_std_stream(std::net::TcpStream::from(socket));
+        match socket.connect(target).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) if matches!(e.kind(), ErrorKind::AddrInUse | ErrorKind::AddrNotAvailable) => {
+                continue
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    anyhow::bail!(
+        "No free source port in egress range {}-{} for {}",
+        ports.start(),
+        ports.end(),
+        target
+    )
+}
+
 /// Apply socket optimizations for an existing socket
+///
+/// Packets are also marked with `dscp` if given.
 #[cfg(target_os = "linux")]
-pub fn optimize_socket_linux(fd: std::os::unix::io::RawFd) -> Result<()> {
+pub fn optimize_socket_linux(fd: std::os::unix::io::RawFd, dscp: Option<u8>) -> Result<()> {
     use nix::sys::socket::{setsockopt, sockopt};
     use std::os::fd::BorrowedFd;
 
@@ -91,11 +200,174 @@ pub fn optimize_socket_linux(fd: std::os::unix::io::RawFd) -> Result<()> {
     // Set priority for QoS
     let _ = setsockopt(&fd, sockopt::Priority, &6);
 
+    if let Some(dscp) = dscp {
+        set_dscp(&fd, dscp)?;
+    }
+
     Ok(())
 }
 
 #[cfg(not(target_os = "linux"))]
-pub fn optimize_socket_linux(_fd: std::os::unix::io::RawFd) -> Result<()> {
+pub fn optimize_socket_linux(_fd: std::os::unix::io::RawFd, _dscp: Option<u8>) -> Result<()> {
     Ok(())
 }
 
+/// Mark packets sent on `socket` with the DSCP value `dscp` (0-63)
+///
+/// IPv6 sockets get IPV6_TCLASS, plus IP_TOS for IPv4-mapped traffic.
+#[cfg(target_os = "linux")]
+pub fn set_dscp<S: std::os::fd::AsFd>(socket: &S, dscp: u8) -> Result<()> {
+    use nix::sys::socket::{getsockname, setsockopt, sockopt, SockaddrStorage};
+    use std::os::fd::AsRawFd;
+
+    // DSCP is the upper six bits of the TOS / traffic class byte
+    let tos = libc::c_int::from(dscp) << 2;
+    let local: SockaddrStorage = getsockname(socket.as_fd().as_raw_fd())?;
+    if local.as_sockaddr_in6().is_some() {
+        setsockopt(socket, sockopt::Ipv6TClass, &tos)?;
+        let _ = setsockopt(socket, sockopt::IpTos, &tos);
+    } else {
+        setsockopt(socket, sockopt::IpTos, &tos)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_dscp<S>(_socket: &S, _dscp: u8) -> Result<()> {
+    Ok(())
+}
+
+/// Send the first write on an unconnected TCP socket in its SYN
+/// (TCP_FASTOPEN_CONNECT)
+///
+/// `connect` then returns at once and the handshake waits for that write,
+/// so the peer must not be expected to speak first. Fails on kernels
+/// without client-side Fast Open.
+#[cfg(target_os = "linux")]
+pub fn set_fast_open_connect<S: std::os::fd::AsFd>(socket: &S) -> Result<()> {
+    use nix::sys::socket::{setsockopt, sockopt};
+
+    setsockopt(socket, sockopt::TcpFastOpenConnect, &true)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_fast_open_connect<S>(_socket: &S) -> Result<()> {
+    anyhow::bail!("TCP Fast Open is only supported on Linux")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Find a local port that is currently free
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[tokio::test]
+    async fn test_connect_in_port_range() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap();
+        let port = free_port();
+
+        let stream = connect_tcp_in_port_range(target, None, port..=port, false).await.unwrap();
+        assert_eq!(stream.local_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test]
+    async fn test_connect_from_source_ip() {
+        let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let target = SocketAddr::new("127.0.0.1".parse().unwrap(), listener.local_addr().unwrap().port());
+        let source: IpAddr = "127.0.0.2".parse().unwrap();
+
+        let stream = connect_tcp_from(target, source, false).await.unwrap();
+        assert_eq!(stream.local_addr().unwrap().ip(), source);
+
+        let port = free_port();
+        let stream = connect_tcp_in_port_range(target, Some(source), port..=port, false)
+            .await
+            .unwrap();
+        assert_eq!(stream.local_addr().unwrap(), SocketAddr::new(source, port));
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn test_udp_reuse_port() {
+        let first = create_udp_socket("127.0.0.1:0".parse().unwrap(), true, false).unwrap();
+        let addr = first.local_addr().unwrap();
+        let second = create_udp_socket(addr, true, false).unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+
+        // Without it the port stays exclusive
+        let exclusive = create_udp_socket("127.0.0.1:0".parse().unwrap(), false, false).unwrap();
+        assert!(create_udp_socket(exclusive.local_addr().unwrap(), false, false).is_err());
+        assert!(create_udp_socket(exclusive.local_addr().unwrap(), true, false).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_set_dscp() {
+        use nix::sys::socket::{getsockopt, sockopt};
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        set_dscp(&socket, 46).unwrap();
+        assert_eq!(getsockopt(&socket, sockopt::IpTos).unwrap(), 46 << 2);
+
+        let stream = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        optimize_socket_linux(std::os::fd::AsRawFd::as_raw_fd(&stream), Some(10)).unwrap();
+        assert_eq!(getsockopt(&stream, sockopt::IpTos).unwrap(), 10 << 2);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_set_fast_open_connect() {
+        use nix::sys::socket::{getsockopt, sockopt};
+
+        let socket = create_tcp_socket("127.0.0.1:80".parse().unwrap()).unwrap();
+        assert!(!getsockopt(&socket, sockopt::TcpFastOpenConnect).unwrap());
+        set_fast_open_connect(&socket).unwrap();
+        assert!(getsockopt(&socket, sockopt::TcpFastOpenConnect).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_connect_fast_open() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // The client writes first, which is when a Fast Open handshake happens
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap();
+        let echo = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let mut stream = connect_tcp(target, true).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut reply = [0u8; 5];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"hello");
+        echo.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_port_range_exhausted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap();
+        let port = free_port();
+
+        let _held = connect_tcp_in_port_range(target, None, port..=port, false).await.unwrap();
+        let err = connect_tcp_in_port_range(target, None, port..=port, false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No free source port"));
+    }
+}
@@ -0,0 +1,43 @@
+//! Shared helpers for unit tests
+
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::sync::Arc;
+
+/// A client/server QUIC connection pair over loopback
+pub(crate) struct QuicPair {
+    pub client: Connection,
+    pub server: Connection,
+    _client_endpoint: Endpoint,
+    _server_endpoint: Endpoint,
+}
+
+/// Establish a QUIC connection to a loopback endpoint using a throwaway cert
+pub(crate) async fn quic_pair() -> QuicPair {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_der = CertificateDer::from(cert.cert);
+    let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+
+    let server_config = ServerConfig::with_single_cert(vec![cert_der.clone()], key_der).unwrap();
+    let server_endpoint = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+    let server_addr = server_endpoint.local_addr().unwrap();
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert_der).unwrap();
+    let mut client_endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    client_endpoint.set_default_client_config(
+        ClientConfig::with_root_certificates(Arc::new(roots)).unwrap(),
+    );
+
+    let connecting = client_endpoint.connect(server_addr, "localhost").unwrap();
+    let (client, server) = tokio::join!(connecting, async {
+        server_endpoint.accept().await.unwrap().await
+    });
+
+    QuicPair {
+        client: client.unwrap(),
+        server: server.unwrap(),
+        _client_endpoint: client_endpoint,
+        _server_endpoint: server_endpoint,
+    }
+}